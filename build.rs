@@ -0,0 +1,56 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc::generate();
+}
+
+/// Generates the tonic client and server of `proto/payment_engine.proto`. There is no `protoc`
+/// requirement: the messages are written by hand in `src/grpc.rs` and only the service is
+/// described here, both must be kept in sync with the proto file.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    fn method(
+        name: &str,
+        route: &str,
+        input: &str,
+        output: &str,
+    ) -> tonic_build::manual::MethodBuilder {
+        Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("crate::grpc::proto::{}", input))
+            .output_type(format!("crate::grpc::proto::{}", output))
+            .codec_path("tonic_prost::ProstCodec")
+    }
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed=build.rs");
+        let service = Service::builder()
+            .name("PaymentEngine")
+            .package("payment_engine.v1")
+            .method(
+                method(
+                    "submit_transaction",
+                    "SubmitTransaction",
+                    "Transaction",
+                    "SubmitTransactionResponse",
+                )
+                .build(),
+            )
+            .method(method("get_account", "GetAccount", "GetAccountRequest", "Account").build())
+            .method(
+                method(
+                    "stream_report",
+                    "StreamReport",
+                    "StreamReportRequest",
+                    "Account",
+                )
+                .server_streaming()
+                .build(),
+            )
+            .method(method("get_metrics", "GetMetrics", "GetMetricsRequest", "Metrics").build())
+            .build();
+        Builder::new().compile(&[service]);
+    }
+}
@@ -0,0 +1,76 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use simple_payment_engine::{
+    config::{DisputePolicy, EngineConfig, LockedAccountPolicy},
+    decimal::Decimal,
+    engine::Engine,
+    transaction::{Transaction, TransactionType},
+};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    allow_admin: bool,
+    dispute_policy: u8,
+    locked_policy: u8,
+    steps: Vec<Step>,
+}
+
+/// A transaction of few clients and ids, so steps often reference earlier ones.
+#[derive(Arbitrary, Debug)]
+struct Step {
+    kind: u8,
+    client: u8,
+    tx: u8,
+    amount: Amount,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Amount {
+    Max,
+    Min,
+    Scaled(i64, u8),
+}
+
+impl Amount {
+    fn decimal(&self) -> Decimal {
+        match self {
+            Amount::Max => Decimal::MAX,
+            Amount::Min => Decimal::MIN,
+            Amount::Scaled(num, scale) => Decimal::new(*num, u32::from(*scale) % 29),
+        }
+    }
+}
+
+// Arbitrary transaction sequences: the engine must reject what it can't apply, e.g. amounts
+// overflowing the balances, without panicking and keep every account consistent.
+fuzz_target!(|input: Input| {
+    let mut engine = Engine::with_config(EngineConfig {
+        allow_admin: input.allow_admin,
+        dispute_policy: [
+            DisputePolicy::AllowNegative,
+            DisputePolicy::Reject,
+            DisputePolicy::PartialHold,
+        ][input.dispute_policy as usize % 3],
+        locked_account_policy: [
+            LockedAccountPolicy::Freeze,
+            LockedAccountPolicy::SettleOpen,
+            LockedAccountPolicy::Allow,
+        ][input.locked_policy as usize % 3],
+        ..EngineConfig::default()
+    });
+    for step in input.steps {
+        let kind = TransactionType::ALL[step.kind as usize % TransactionType::ALL.len()];
+        let Ok(transaction) = Transaction::new(
+            &kind.to_string(),
+            u16::from(step.client % 4),
+            u32::from(step.tx % 32),
+            step.amount.decimal(),
+        ) else {
+            continue;
+        };
+        let _ = engine.execute(transaction);
+        engine.check_invariants().unwrap();
+    }
+});
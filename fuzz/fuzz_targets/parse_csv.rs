@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_payment_engine::{
+    dialect::CsvDialect,
+    engine::Engine,
+    pipeline::{ErrorPolicy, Pipeline, RejectHandler},
+    source::CsvSource,
+};
+
+// Arbitrary bytes as CSV input, processed like the CLI does with `--on-error skip`: malformed rows
+// must be rejected by the error policy rather than fail the run or panic, and applying the rows
+// that parse must keep the accounts consistent.
+fuzz_target!(|data: &[u8]| {
+    let Ok((headers, records)) = CsvDialect::default().records(std::io::Cursor::new(data.to_vec()))
+    else {
+        return;
+    };
+    let mut source = CsvSource::new(headers.clone(), records);
+    let rejects = RejectHandler::new(ErrorPolicy::Skip, None, &headers).unwrap();
+    let mut pipeline = Pipeline::new(Engine::new(), rejects);
+    // Reading from memory never fails, an error here is malformed input ending the run
+    pipeline.process_source(&mut source).unwrap();
+    pipeline.engine.check_invariants().unwrap();
+});
@@ -0,0 +1,189 @@
+use clap::ValueEnum;
+
+use crate::{
+    client::Client,
+    currency::Currency,
+    decimal::{Decimal, RoundingStrategy},
+    transaction::{Transaction, TransactionRow},
+};
+
+/// How amounts are rounded to the configured precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Rounding {
+    /// Round half to even
+    #[default]
+    Bankers,
+    /// Round half away from zero
+    HalfUp,
+    /// Drop the extra digits
+    Truncate,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+/// Unit of the amounts of input rows and of the balances of the client report. The engine always
+/// keeps whole currency units.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum AmountUnit {
+    /// Whole currency units with a decimal fraction, e.g. `12.34`
+    #[default]
+    Major,
+    /// Integer counts of the currency's minor unit, e.g. `1234` cents for 12.34 USD
+    Minor,
+}
+
+impl AmountUnit {
+    /// The amount of this unit in whole currency units.
+    pub fn to_major(self, amount: Decimal, currency: Currency) -> Decimal {
+        match self {
+            AmountUnit::Major => amount,
+            AmountUnit::Minor => scale(amount, Decimal::new(1, currency.minor_digits())),
+        }
+    }
+
+    /// The amount in whole currency units in this unit.
+    pub fn from_major(self, amount: Decimal, currency: Currency) -> Decimal {
+        match self {
+            AmountUnit::Major => amount,
+            AmountUnit::Minor => {
+                scale(amount, Decimal::new(10_i64.pow(currency.minor_digits()), 0))
+            }
+        }
+    }
+
+    /// The input row with its amounts, including the amount of a dispute row, in whole currency
+    /// units. Rows without a currency are in `default_currency`.
+    pub fn row_to_major(self, row: TransactionRow, default_currency: Currency) -> TransactionRow {
+        if self == AmountUnit::Major {
+            return row;
+        }
+        let currency = row.currency.unwrap_or(default_currency);
+        TransactionRow {
+            transaction: row
+                .transaction
+                .map_amount(|amount| self.to_major(amount, currency)),
+            dispute_amount: row
+                .dispute_amount
+                .map(|amount| self.to_major(amount, currency)),
+            ..row
+        }
+    }
+}
+
+/// The amount times a power of ten, saturating like the balances do.
+fn scale(amount: Decimal, factor: Decimal) -> Decimal {
+    amount
+        .checked_mul(factor)
+        .unwrap_or(if amount < Decimal::ZERO {
+            Decimal::MIN
+        } else {
+            Decimal::MAX
+        })
+}
+
+/// Decimal places amounts are kept at and how extra places are rounded away. Applied to every
+/// amount entering the engine and to the balances of the client report.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmountPolicy {
+    pub precision: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for AmountPolicy {
+    fn default() -> Self {
+        AmountPolicy {
+            precision: 4,
+            rounding: Rounding::Bankers,
+        }
+    }
+}
+
+impl AmountPolicy {
+    pub fn round(&self, amount: Decimal) -> Decimal {
+        amount.round_dp_with_strategy(self.precision, self.rounding.strategy())
+    }
+
+    /// The transaction with its amount rounded, rows without an amount are unchanged.
+    pub fn round_transaction(&self, transaction: Transaction) -> Transaction {
+        transaction.map_amount(|amount| self.round(amount))
+    }
+
+    /// The account with its balances rounded for reporting. Balances of rounded amounts are
+    /// unchanged, only state restored from a more precise run is affected.
+    pub fn round_client(&self, client: &Client) -> Client {
+        Client {
+            available: self.round(client.available),
+            held: self.round(client.held),
+            total: self.round(client.total),
+            ..client.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding_modes() {
+        let amount: Decimal = "2.345".parse().unwrap();
+        let negative: Decimal = "-2.345".parse().unwrap();
+        for (rounding, expected, expected_negative) in [
+            (Rounding::Bankers, "2.34", "-2.34"),
+            (Rounding::HalfUp, "2.35", "-2.35"),
+            (Rounding::Truncate, "2.34", "-2.34"),
+        ] {
+            let policy = AmountPolicy {
+                precision: 2,
+                rounding,
+            };
+            assert_eq!(policy.round(amount), expected.parse().unwrap());
+            assert_eq!(policy.round(negative), expected_negative.parse().unwrap());
+        }
+        let truncate = AmountPolicy {
+            precision: 0,
+            rounding: Rounding::Truncate,
+        };
+        assert_eq!(
+            truncate.round_transaction(Transaction::Deposit(1, 1, "1.99".parse().unwrap())),
+            Transaction::Deposit(1, 1, Decimal::ONE)
+        );
+        assert_eq!(
+            truncate.round_transaction(Transaction::Dispute(1, 1)),
+            Transaction::Dispute(1, 1)
+        );
+    }
+
+    #[test]
+    fn test_minor_units() {
+        let row = TransactionRow {
+            transaction: Transaction::Deposit(1, 1, Decimal::new(1234, 0)),
+            timestamp: None,
+            currency: None,
+            dispute_amount: None,
+        };
+        let row = AmountUnit::Minor.row_to_major(row, Currency::USD);
+        assert_eq!(row.transaction.amount(), Some(Decimal::new(1234, 2)));
+        let yen: Currency = "JPY".parse().unwrap();
+        assert_eq!(
+            AmountUnit::Minor.to_major(Decimal::new(500, 0), yen),
+            Decimal::new(500, 0)
+        );
+        assert_eq!(
+            AmountUnit::Minor.from_major(Decimal::new(15, 1), Currency::USD),
+            Decimal::new(150, 0)
+        );
+        assert_eq!(
+            AmountUnit::Major.from_major(Decimal::new(15, 1), Currency::USD),
+            Decimal::new(15, 1)
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+use crate::{
+    currency::Currency,
+    transaction::{Transaction, TransactionRow},
+};
+
+const SEGMENT_PREFIX: &str = "segment-";
+
+#[derive(Clone, Debug)]
+pub struct ArchivePolicy {
+    /// Directory for the archive segment files. Must not contain segments of an earlier run.
+    pub dir: PathBuf,
+    /// Number of most recent transactions kept in memory. Older ones can no longer be disputed.
+    pub dispute_window: usize,
+    /// Transactions per archive segment.
+    pub segment_size: usize,
+}
+
+/// Cold storage for transactions that fell out of the dispute window.
+///
+/// Archived transactions are written to gzip-compressed CSV segments and removed from the
+/// in-memory log. Lookups scan the segments whose id range contains the transaction, so they are
+/// fast for ids outside the archived range and slow otherwise. Ids that grow with time, as usual
+/// for payment streams, never hit the slow path for new transactions.
+pub struct Archive {
+    policy: ArchivePolicy,
+    /// Logged transaction ids in execution order, oldest first.
+    order: VecDeque<u32>,
+    segments: Vec<Segment>,
+    /// Smallest and largest archived id.
+    range: Option<(u32, u32)>,
+}
+
+struct Segment {
+    path: PathBuf,
+    min_tx: u32,
+    max_tx: u32,
+}
+
+impl Archive {
+    pub fn new(policy: ArchivePolicy) -> io::Result<Self> {
+        fs::create_dir_all(&policy.dir)?;
+        for entry in fs::read_dir(&policy.dir)? {
+            if entry?
+                .file_name()
+                .to_string_lossy()
+                .starts_with(SEGMENT_PREFIX)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "archive directory {} already contains segments",
+                        policy.dir.display()
+                    ),
+                ));
+            }
+        }
+        Ok(Archive {
+            policy,
+            order: VecDeque::new(),
+            segments: Vec::new(),
+            range: None,
+        })
+    }
+
+    pub fn segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub(crate) fn track(&mut self, tx_id: u32) {
+        self.order.push_back(tx_id);
+    }
+
+    /// Takes the ids of the next segment to archive once a full segment fell out of the window.
+    pub(crate) fn next_candidates(&mut self) -> Option<Vec<u32>> {
+        if self.order.len() < self.policy.dispute_window + self.policy.segment_size.max(1) {
+            return None;
+        }
+        Some(
+            self.order
+                .drain(..self.policy.segment_size.max(1))
+                .collect(),
+        )
+    }
+
+    /// Puts back a candidate that must stay in memory, e.g. while it is disputed.
+    pub(crate) fn requeue(&mut self, tx_id: u32) {
+        self.order.push_back(tx_id);
+    }
+
+    pub(crate) fn write_segment(&mut self, entries: &[(Transaction, Currency)]) -> io::Result<()> {
+        let (Some(min_tx), Some(max_tx)) = (
+            entries.iter().map(|(t, _)| t.tx_id()).min(),
+            entries.iter().map(|(t, _)| t.tx_id()).max(),
+        ) else {
+            return Ok(());
+        };
+        let path = self.policy.dir.join(format!(
+            "{}{:06}.csv.gz",
+            SEGMENT_PREFIX,
+            self.segments.len()
+        ));
+        let file = BufWriter::new(File::create(&path)?);
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(GzEncoder::new(file, Compression::default()));
+        for (transaction, currency) in entries {
+            // Same column order as the input, with an empty timestamp
+            writer.write_record([
+                transaction.transaction_type().to_string(),
+                transaction.client_id().to_string(),
+                transaction.tx_id().to_string(),
+                transaction
+                    .amount()
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+                String::new(),
+                currency.to_string(),
+            ])?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .finish()?;
+        self.segments.push(Segment {
+            path,
+            min_tx,
+            max_tx,
+        });
+        self.range = Some(match self.range {
+            Some((min, max)) => (min.min(min_tx), max.max(max_tx)),
+            None => (min_tx, max_tx),
+        });
+        Ok(())
+    }
+
+    /// Looks up an archived transaction with its currency.
+    pub fn find(&self, tx_id: u32) -> io::Result<Option<(Transaction, Currency)>> {
+        if !self
+            .range
+            .is_some_and(|(min, max)| (min..=max).contains(&tx_id))
+        {
+            return Ok(None);
+        }
+        for segment in &self.segments {
+            if !(segment.min_tx..=segment.max_tx).contains(&tx_id) {
+                continue;
+            }
+            let file = BufReader::new(File::open(&segment.path)?);
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(GzDecoder::new(file));
+            for record in reader.deserialize::<TransactionRow>() {
+                let row = record.map_err(io::Error::other)?;
+                if row.transaction.tx_id() == tx_id {
+                    return Ok(Some((row.transaction, row.currency.unwrap_or_default())));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+
+    use super::*;
+
+    fn temp_policy(name: &str, dispute_window: usize, segment_size: usize) -> ArchivePolicy {
+        let dir = std::env::temp_dir().join(format!("archive-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ArchivePolicy {
+            dir,
+            dispute_window,
+            segment_size,
+        }
+    }
+
+    #[test]
+    fn test_segment_round_trip() {
+        let policy = temp_policy("round-trip", 0, 2);
+        let dir = policy.dir.clone();
+        let mut archive = Archive::new(policy.clone()).unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        archive
+            .write_segment(&[
+                (Transaction::Deposit(1, 10, Decimal::new(15, 1)), eur),
+                (
+                    Transaction::Withdrawal(2, 12, Decimal::new(5, 0)),
+                    Currency::USD,
+                ),
+            ])
+            .unwrap();
+        assert_eq!(
+            archive.find(10).unwrap(),
+            Some((Transaction::Deposit(1, 10, Decimal::new(15, 1)), eur))
+        );
+        assert_eq!(archive.find(11).unwrap(), None);
+        assert_eq!(archive.find(13).unwrap(), None);
+        // A second run must not mix its segments with the existing ones
+        assert!(Archive::new(policy).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_candidates_leave_dispute_window() {
+        let policy = temp_policy("candidates", 2, 2);
+        let dir = policy.dir.clone();
+        let mut archive = Archive::new(policy).unwrap();
+        for tx_id in 1..=3 {
+            archive.track(tx_id);
+        }
+        assert_eq!(archive.next_candidates(), None);
+        archive.track(4);
+        assert_eq!(archive.next_candidates(), Some(vec![1, 2]));
+        assert_eq!(archive.next_candidates(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use arrow_array::{Array, StringArray};
+use arrow_csv::{ReaderBuilder, reader::BufReader as BatchReader};
+use arrow_schema::{DataType, Field, Schema};
+use csv::StringRecord;
+
+use crate::transaction::TransactionRow;
+
+/// Reads CSV transactions into Arrow record batches instead of one `StringRecord` per row. The
+/// columns are taken by position like the row-by-row CSV input
+/// (`type, client, tx, amount[, timestamp[, currency]]`) and parsed straight from the batch
+/// columns, without serde.
+pub struct ArrowBatches<R: Read> {
+    batches: BatchReader<BufReader<R>>,
+    headers: StringRecord,
+}
+
+impl<R: Read> ArrowBatches<R> {
+    /// Reads the header from `reader`, the batches then hold up to `batch_rows` rows each.
+    pub fn new(reader: R, batch_rows: usize) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let headers = csv::Reader::from_reader(header.as_bytes())
+            .headers()?
+            .clone();
+        if headers.is_empty() {
+            anyhow::bail!("CSV input has no header");
+        }
+        // All columns are read as strings, so a malformed value rejects its row only
+        let fields: Vec<Field> = headers
+            .iter()
+            .map(|name| Field::new(name, DataType::Utf8, true))
+            .collect();
+        let batches = ReaderBuilder::new(Arc::new(Schema::new(fields)))
+            .with_header(false)
+            .with_batch_size(batch_rows)
+            .build_buffered(reader)?;
+        Ok(ArrowBatches { batches, headers })
+    }
+
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+}
+
+impl<R: Read> Iterator for ArrowBatches<R> {
+    type Item = Result<TransactionBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = match self.batches.next()? {
+            Ok(batch) => batch,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| {
+                column
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .cloned()
+                    .context("CSV column is not read as strings")
+            })
+            .collect::<Result<_>>();
+        Some(columns.map(|columns| TransactionBatch {
+            columns,
+            rows: batch.num_rows(),
+        }))
+    }
+}
+
+/// A batch of input rows with every column as strings, empty fields are nulls.
+pub struct TransactionBatch {
+    columns: Vec<StringArray>,
+    rows: usize,
+}
+
+impl TransactionBatch {
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// A non-empty field of the row, `None` if it's empty or the input has no such column.
+    fn field(&self, column: usize, row: usize) -> Option<&str> {
+        self.columns
+            .get(column)
+            .filter(|column| !column.is_null(row))
+            .map(|column| column.value(row))
+    }
+
+    /// Parses a row with the same rules as the `TransactionRow` deserialization.
+    pub fn row(&self, row: usize) -> Result<TransactionRow> {
+        let required = |column: usize, name: &str| {
+            self.field(column, row)
+                .with_context(|| format!("missing {}", name))
+        };
+        let amount = self
+            .field(3, row)
+            .map(|amount| amount.parse())
+            .transpose()?;
+        let timestamp = self
+            .field(4, row)
+            .map(|timestamp| timestamp.parse())
+            .transpose()?;
+        TransactionRow::new(
+            required(0, "type")?,
+            required(1, "client")?.parse()?,
+            required(2, "tx")?.parse()?,
+            amount,
+            timestamp,
+            self.field(5, row),
+        )
+    }
+
+    /// The input record of a row, for rejects and the audit trail.
+    pub fn record(&self, row: usize) -> StringRecord {
+        (0..self.columns.len())
+            .map(|column| self.field(column, row).unwrap_or_default())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn test_arrow_batches() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.5\n\
+                     dispute,1,1,\n\
+                     deposit,x,2,1.0\n\
+                     withdrawal,2,3,0.25\n";
+        let mut batches = ArrowBatches::new(input.as_bytes(), 3).unwrap();
+        assert_eq!(batches.headers().len(), 4);
+
+        let batch = batches.next().unwrap().unwrap();
+        assert_eq!(batch.len(), 3);
+        assert_eq!(
+            batch.row(0).unwrap().transaction,
+            Transaction::Deposit(1, 1, Decimal::new(15, 1))
+        );
+        let dispute = batch.row(1).unwrap();
+        assert_eq!(dispute.transaction, Transaction::Dispute(1, 1));
+        assert_eq!(dispute.dispute_amount, None);
+        assert!(batch.row(2).is_err());
+        assert_eq!(
+            batch.record(2),
+            StringRecord::from(vec!["deposit", "x", "2", "1.0"])
+        );
+
+        let batch = batches.next().unwrap().unwrap();
+        assert_eq!(
+            batch.row(0).unwrap().transaction,
+            Transaction::Withdrawal(2, 3, Decimal::new(25, 2))
+        );
+        assert!(batches.next().is_none());
+    }
+}
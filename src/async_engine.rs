@@ -0,0 +1,171 @@
+use std::{io, path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use csv::StringRecord;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::{
+    client::Client,
+    currency::Currency,
+    engine::{Engine, ExecutionError},
+    transaction::Transaction,
+};
+
+/// Records buffered between the blocking CSV reader and the async consumer.
+const RECORD_BUFFER: usize = 1024;
+
+/// Async facade over a shared engine for embedding into tokio services. Cheap to clone.
+///
+/// Transactions are applied under a `tokio::sync::Mutex`, so tasks waiting for the engine yield
+/// instead of blocking executor threads. Execution itself is CPU-only and short.
+#[derive(Clone)]
+pub struct AsyncEngine {
+    engine: Arc<Mutex<Engine>>,
+}
+
+impl AsyncEngine {
+    pub fn new(engine: Engine) -> Self {
+        AsyncEngine {
+            engine: Arc::new(Mutex::new(engine)),
+        }
+    }
+
+    pub async fn execute(&self, transaction: Transaction) -> Result<(), ExecutionError> {
+        self.engine.lock().await.execute(transaction)
+    }
+
+    pub async fn execute_in(
+        &self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+    ) -> Result<(), ExecutionError> {
+        self.engine.lock().await.execute_in(transaction, currency)
+    }
+
+    pub async fn client(&self, client_id: u16) -> Option<Client> {
+        self.engine.lock().await.client(client_id)
+    }
+
+    /// Copies all client accounts ordered by client id and currency.
+    pub async fn clients(&self) -> io::Result<Vec<Client>> {
+        self.engine.lock().await.clients().collect()
+    }
+
+    /// Runs a closure with exclusive access to the engine, e.g. to write the report.
+    pub async fn with_engine<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> R {
+        f(&mut *self.engine.lock().await)
+    }
+}
+
+/// CSV records read on tokio's blocking thread pool, so file IO never blocks executor threads.
+pub struct AsyncCsvRecords {
+    headers: StringRecord,
+    records: mpsc::Receiver<Result<StringRecord>>,
+}
+
+impl AsyncCsvRecords {
+    /// Opens a CSV file and starts reading it in the background. Reading stops early when the
+    /// records are dropped.
+    pub async fn from_path<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        let (headers_tx, headers_rx) = tokio::sync::oneshot::channel();
+        let (records_tx, records) = mpsc::channel(RECORD_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            let mut reader = match csv::Reader::from_path(path) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    let _ = headers_tx.send(Err(err));
+                    return;
+                }
+            };
+            if headers_tx.send(reader.headers().cloned()).is_err() {
+                return;
+            }
+            for record in reader.into_records() {
+                if records_tx
+                    .blocking_send(record.map_err(Into::into))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+        let headers = headers_rx.await??;
+        Ok(AsyncCsvRecords { headers, records })
+    }
+
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// Returns the next record, `None` at the end of the file.
+    pub async fn next(&mut self) -> Option<Result<StringRecord>> {
+        self.records.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+
+    use super::*;
+    use crate::transaction::TransactionRow;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_async_engine() {
+        block_on(async {
+            let engine = AsyncEngine::new(Engine::new());
+            let handles: Vec<_> = (1..=4)
+                .map(|tx_id| {
+                    let engine = engine.clone();
+                    tokio::spawn(async move {
+                        engine
+                            .execute(Transaction::Deposit(1, tx_id, Decimal::new(5, 0)))
+                            .await
+                    })
+                })
+                .collect();
+            for handle in handles {
+                assert!(handle.await.unwrap().is_ok());
+            }
+            assert_eq!(engine.client(1).await.unwrap().total, Decimal::new(20, 0));
+            assert_eq!(engine.clients().await.unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_async_csv_records() {
+        let path = std::env::temp_dir().join(format!("async-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,1.5\n",
+        )
+        .unwrap();
+        let transactions = block_on(async {
+            let mut records = AsyncCsvRecords::from_path(&path).await.unwrap();
+            assert_eq!(records.headers().len(), 4);
+            let mut transactions = Vec::new();
+            while let Some(record) = records.next().await {
+                let row: TransactionRow = record.unwrap().deserialize(None).unwrap();
+                transactions.push(row.transaction);
+            }
+            transactions
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            transactions,
+            vec![
+                Transaction::Deposit(1, 1, Decimal::new(20, 1)),
+                Transaction::Withdrawal(1, 2, Decimal::new(15, 1)),
+            ]
+        );
+        assert!(block_on(AsyncCsvRecords::from_path("/nonexistent/transactions.csv")).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::Result;
+use csv::StringRecord;
+
+use crate::{
+    client::Client,
+    config::DisputeAmountPolicy,
+    currency::Currency,
+    decimal::Decimal,
+    engine::{Engine, ExecutionError},
+    transaction::Transaction,
+};
+
+const HEADER: [&str; 13] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "outcome",
+    "reason",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "account",
+    "currency",
+    "amount_policy",
+];
+
+/// Append-only CSV audit trail with one row per input row: the transaction, whether it was
+/// applied or rejected with the reason, and the resulting balances of the affected account with
+/// its client id and currency. Amounts on dispute rows are recorded with how they were handled.
+pub struct AuditLog<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl AuditLog<File> {
+    /// Opens the audit file for appending. The header is written only when the file is new or empty.
+    pub fn append<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let write_header = file.metadata()?.len() == 0;
+        Self::new(file, write_header)
+    }
+}
+
+impl<W: Write> AuditLog<W> {
+    pub fn new(writer: W, write_header: bool) -> Result<Self> {
+        let mut writer = csv::Writer::from_writer(writer);
+        if write_header {
+            writer.write_record(HEADER)?;
+        }
+        Ok(AuditLog { writer })
+    }
+
+    /// Records an executed transaction with the engine state after execution. `currency` is the
+    /// currency the transaction was executed in, `dispute_amount` the amount of a dispute row
+    /// with the policy that handled it.
+    pub fn record(
+        &mut self,
+        engine: &Engine,
+        transaction: &Transaction,
+        currency: Option<Currency>,
+        dispute_amount: Option<(Decimal, DisputeAmountPolicy)>,
+        result: &Result<(), ExecutionError>,
+    ) -> Result<()> {
+        let (outcome, reason) = match result {
+            Ok(()) => ("applied", String::new()),
+            Err(err) => ("rejected", err.to_string()),
+        };
+        let client = engine.affected_account(transaction, currency);
+        let mut row = vec![
+            transaction.transaction_type().to_string(),
+            transaction.client_id().to_string(),
+            transaction.tx_id().to_string(),
+            transaction
+                .amount()
+                .or(dispute_amount.map(|(amount, _)| amount))
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+            outcome.to_string(),
+            reason,
+        ];
+        row.extend(balances(client.as_ref()));
+        row.push(
+            dispute_amount
+                .map(|(_, policy)| policy.outcome().to_string())
+                .unwrap_or_default(),
+        );
+        self.writer.write_record(&row)?;
+        Ok(())
+    }
+
+    /// Records an input row that could not be parsed into a transaction.
+    pub fn record_invalid(&mut self, record: &StringRecord, reason: &str) -> Result<()> {
+        let mut row: Vec<String> = (0..4)
+            .map(|i| record.get(i).unwrap_or_default().to_string())
+            .collect();
+        row.push("rejected".to_string());
+        row.push(reason.to_string());
+        row.extend(balances(None));
+        row.push(String::new());
+        self.writer.write_record(&row)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn balances(client: Option<&Client>) -> [String; 6] {
+    match client {
+        Some(client) => [
+            client.available.to_string(),
+            client.held.to_string(),
+            client.total.to_string(),
+            client.locked.to_string(),
+            client.id.to_string(),
+            client.currency.to_string(),
+        ],
+        None => Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EngineConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_audit_rows() {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_amounts: DisputeAmountPolicy::Honor,
+            ..EngineConfig::default()
+        });
+        let mut output = Vec::new();
+        {
+            let mut audit = AuditLog::new(&mut output, true).unwrap();
+            for (transaction, dispute_amount) in [
+                (Transaction::Deposit(1, 1, Decimal::new(10, 0)), None),
+                (Transaction::Withdrawal(1, 2, Decimal::new(20, 0)), None),
+                (Transaction::Dispute(1, 1), Some(Decimal::new(4, 0))),
+            ] {
+                let result = engine.execute_row(transaction.clone(), None, dispute_amount);
+                let dispute_amount =
+                    dispute_amount.map(|amount| (amount, DisputeAmountPolicy::Honor));
+                audit
+                    .record(&engine, &transaction, None, dispute_amount, &result)
+                    .unwrap();
+            }
+            let invalid = StringRecord::from(vec!["transfer", "1", "3", "1.0"]);
+            audit
+                .record_invalid(&invalid, "Unknown transaction type")
+                .unwrap();
+            audit.flush().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "type,client,tx,amount,outcome,reason,available,held,total,locked,account,currency,amount_policy
+deposit,1,1,10,applied,,10,0,10,false,1,USD,
+withdrawal,1,2,20,rejected,Insufficient funds,10,0,10,false,1,USD,
+dispute,1,1,4,applied,,6,4,10,false,1,USD,honored
+transfer,1,3,1.0,rejected,Unknown transaction type,,,,,,,
+"
+        );
+    }
+}
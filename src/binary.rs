@@ -0,0 +1,194 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decimal::Decimal,
+    source::{SourceError, TransactionSource},
+    transaction::TransactionRow,
+};
+
+/// Encoding of the binary transaction formats.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum BinaryFormat {
+    /// MessagePack rows, one after another
+    Msgpack,
+    /// bincode 1 rows with its default options, one after another
+    Bincode,
+}
+
+/// A transaction row of the binary formats, the columns of a CSV row in the same order:
+///
+/// | field       | type             |                                         |
+/// |-------------|------------------|-----------------------------------------|
+/// | `type`      | string           | transaction type, e.g. `deposit`        |
+/// | `client`    | u16              |                                         |
+/// | `tx`        | u32              |                                         |
+/// | `amount`    | optional string  | decimal so no digits are lost, `"1.5"`  |
+/// | `timestamp` | optional u64     | Unix seconds                            |
+/// | `currency`  | optional string  | ISO 4217 code                           |
+///
+/// A MessagePack row is an array of the six fields, `nil` for absent ones, or a map keyed by the
+/// field names. A bincode row is the fields in order: integers little-endian with their fixed
+/// width, strings as a u64 length followed by the UTF-8 bytes, optional fields as a 0 byte or a
+/// 1 byte followed by the value.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BinaryRow {
+    #[serde(rename = "type", alias = "ttype")]
+    pub ttype: String,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<String>,
+    pub timestamp: Option<u64>,
+    pub currency: Option<String>,
+}
+
+impl BinaryRow {
+    pub fn to_row(&self) -> Result<TransactionRow> {
+        let amount = self
+            .amount
+            .as_deref()
+            .map(|amount| amount.parse::<Decimal>())
+            .transpose()
+            .map_err(|err| anyhow::anyhow!("invalid amount: {}", err))?;
+        TransactionRow::new(
+            &self.ttype,
+            self.client,
+            self.tx,
+            amount,
+            self.timestamp,
+            self.currency.as_deref(),
+        )
+    }
+
+    /// The row in the CSV column order, for the rejects file.
+    fn to_record(&self) -> StringRecord {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        StringRecord::from(vec![
+            self.ttype.clone(),
+            self.client.to_string(),
+            self.tx.to_string(),
+            optional(self.amount.clone()),
+            optional(self.timestamp.map(|timestamp| timestamp.to_string())),
+            optional(self.currency.clone()),
+        ])
+    }
+}
+
+impl From<&TransactionRow> for BinaryRow {
+    fn from(row: &TransactionRow) -> Self {
+        BinaryRow {
+            ttype: row.transaction.transaction_type().to_string(),
+            client: row.transaction.client_id(),
+            tx: row.transaction.tx_id(),
+            amount: row
+                .transaction
+                .amount()
+                .or(row.dispute_amount)
+                .map(|amount| amount.to_string()),
+            timestamp: row.timestamp,
+            currency: row.currency.map(|currency| currency.to_string()),
+        }
+    }
+}
+
+/// Appends a row to a binary stream, MessagePack rows as arrays.
+pub fn write_row<W: Write>(format: BinaryFormat, writer: &mut W, row: &BinaryRow) -> Result<()> {
+    match format {
+        BinaryFormat::Msgpack => rmp_serde::encode::write(writer, row)?,
+        BinaryFormat::Bincode => bincode::serialize_into(writer, row)?,
+    }
+    Ok(())
+}
+
+/// Rows of a binary stream. A row that doesn't decode ends the input, as the rows after it can't
+/// be found, a decoded row that isn't a valid transaction is rejected like an invalid CSV row.
+pub struct BinarySource<R: BufRead> {
+    format: BinaryFormat,
+    reader: R,
+}
+
+impl<R: BufRead> BinarySource<R> {
+    pub fn new(format: BinaryFormat, reader: R) -> Self {
+        BinarySource { format, reader }
+    }
+
+    fn decode(&mut self) -> Result<BinaryRow> {
+        Ok(match self.format {
+            BinaryFormat::Msgpack => rmp_serde::decode::from_read(&mut self.reader)?,
+            BinaryFormat::Bincode => bincode::deserialize_from(&mut self.reader)?,
+        })
+    }
+}
+
+impl<R: BufRead + Send> TransactionSource for BinarySource<R> {
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>> {
+        match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {}
+            Err(err) => return Some(Err(SourceError::Read(err.into()))),
+        }
+        let row = match self.decode().context("undecodable binary row") {
+            Ok(row) => row,
+            Err(err) => return Some(Err(SourceError::Read(err))),
+        };
+        Some(row.to_row().map_err(|err| SourceError::Invalid {
+            record: row.to_record(),
+            reason: err.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn test_binary_round_trip() {
+        let rows = [
+            BinaryRow {
+                ttype: "deposit".to_string(),
+                client: 1,
+                tx: 1,
+                amount: Some("1.5".to_string()),
+                timestamp: Some(1700000000),
+                currency: Some("EUR".to_string()),
+            },
+            BinaryRow {
+                ttype: "bogus".to_string(),
+                client: 1,
+                tx: 2,
+                amount: None,
+                timestamp: None,
+                currency: None,
+            },
+        ];
+        for format in [BinaryFormat::Msgpack, BinaryFormat::Bincode] {
+            let mut encoded = Vec::new();
+            for row in &rows {
+                write_row(format, &mut encoded, row).unwrap();
+            }
+            let mut source = BinarySource::new(format, encoded.as_slice());
+            let deposit = source.next().unwrap().unwrap();
+            assert_eq!(
+                deposit.transaction,
+                Transaction::Deposit(1, 1, Decimal::new(15, 1))
+            );
+            assert_eq!(BinaryRow::from(&deposit), rows[0]);
+            assert!(matches!(
+                source.next(),
+                Some(Err(SourceError::Invalid { .. }))
+            ));
+            assert!(source.next().is_none());
+
+            // A truncated row ends the input with an error
+            let mut source = BinarySource::new(format, &encoded[..encoded.len() - 1]);
+            source.next().unwrap().unwrap();
+            assert!(matches!(source.next(), Some(Err(SourceError::Read(_)))));
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::anyhow;
+use csv::StringRecord;
+
+use crate::{
+    client::Client,
+    client_store::ClientStore,
+    currency::Currency,
+    source::{SourceError, TransactionSource},
+    transaction::{Transaction, TransactionRow},
+    tx_log::TransactionLog,
+};
+
+/// Increment of the SplitMix64 generator, also the salt forked sequences are derived with.
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Source of injected faults for testing recovery: each operation fails with probability `rate`.
+/// The faults are a deterministic sequence of the seed, so a failing run can be reproduced.
+#[derive(Debug)]
+pub struct Chaos {
+    seed: u64,
+    rate: f64,
+    state: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(seed: u64, rate: f64) -> Self {
+        Chaos {
+            seed,
+            rate,
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// An independent sequence for another component, so the faults of each component don't
+    /// depend on how its operations interleave with the others'.
+    pub fn fork(&self, component: u64) -> Chaos {
+        Chaos::new(
+            mix(self.seed ^ component.wrapping_mul(GOLDEN_GAMMA)),
+            self.rate,
+        )
+    }
+
+    /// Fails the next operation, described by `operation`, with the fault probability.
+    pub fn fault(&self, operation: &str) -> io::Result<()> {
+        let next = mix(self.state.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed));
+        // The top 53 bits as a uniform number in [0, 1)
+        if ((next >> 11) as f64 / (1u64 << 53) as f64) < self.rate {
+            return Err(io::Error::other(format!("injected fault in {}", operation)));
+        }
+        Ok(())
+    }
+}
+
+/// The SplitMix64 output function.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(GOLDEN_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A client store whose operations randomly fail before reaching the wrapped store.
+pub struct FaultInjectingStore {
+    inner: Box<dyn ClientStore>,
+    chaos: Chaos,
+}
+
+impl FaultInjectingStore {
+    pub fn new(inner: Box<dyn ClientStore>, chaos: Chaos) -> Self {
+        FaultInjectingStore { inner, chaos }
+    }
+}
+
+impl ClientStore for FaultInjectingStore {
+    fn get(&self, client_id: u16, currency: Currency) -> io::Result<Option<Client>> {
+        self.chaos.fault("client store get")?;
+        self.inner.get(client_id, currency)
+    }
+
+    fn get_mut(&mut self, client_id: u16, currency: Currency) -> io::Result<Option<&mut Client>> {
+        self.chaos.fault("client store get")?;
+        self.inner.get_mut(client_id, currency)
+    }
+
+    fn insert(&mut self, client: Client) -> io::Result<()> {
+        self.chaos.fault("client store insert")?;
+        self.inner.insert(client)
+    }
+
+    fn remove(&mut self, client_id: u16, currency: Currency) -> io::Result<()> {
+        self.chaos.fault("client store remove")?;
+        self.inner.remove(client_id, currency)
+    }
+
+    fn accounts(&self, client_id: u16) -> Box<dyn Iterator<Item = io::Result<Client>> + '_> {
+        Box::new(self.inner.accounts(client_id).map(|client| {
+            client.and_then(|client| self.chaos.fault("client store scan").map(|()| client))
+        }))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Client>> + '_> {
+        Box::new(self.inner.iter().map(|client| {
+            client.and_then(|client| self.chaos.fault("client store scan").map(|()| client))
+        }))
+    }
+}
+
+/// A transaction log whose operations randomly fail before reaching the wrapped log.
+pub struct FaultInjectingLog {
+    inner: Box<dyn TransactionLog>,
+    chaos: Chaos,
+}
+
+impl FaultInjectingLog {
+    pub fn new(inner: Box<dyn TransactionLog>, chaos: Chaos) -> Self {
+        FaultInjectingLog { inner, chaos }
+    }
+}
+
+impl TransactionLog for FaultInjectingLog {
+    fn get(&self, tx_id: u32) -> io::Result<Option<(Transaction, Currency)>> {
+        self.chaos.fault("transaction log get")?;
+        self.inner.get(tx_id)
+    }
+
+    fn insert(&mut self, transaction: &Transaction, currency: Currency) -> io::Result<()> {
+        self.chaos.fault("transaction log insert")?;
+        self.inner.insert(transaction, currency)
+    }
+
+    fn remove(&mut self, tx_id: u32) -> io::Result<()> {
+        self.chaos.fault("transaction log remove")?;
+        self.inner.remove(tx_id)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(Transaction, Currency)>> + '_> {
+        Box::new(self.inner.iter().map(|entry| {
+            entry.and_then(|entry| self.chaos.fault("transaction log scan").map(|()| entry))
+        }))
+    }
+}
+
+/// A transaction source whose reads randomly fail like a broken input, ending the run.
+pub struct FaultInjectingSource {
+    inner: Box<dyn TransactionSource>,
+    chaos: Chaos,
+}
+
+impl FaultInjectingSource {
+    pub fn new(inner: Box<dyn TransactionSource>, chaos: Chaos) -> Self {
+        FaultInjectingSource { inner, chaos }
+    }
+}
+
+impl TransactionSource for FaultInjectingSource {
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>> {
+        if let Err(err) = self.chaos.fault("input read") {
+            return Some(Err(SourceError::Read(anyhow!(err))));
+        }
+        self.inner.next()
+    }
+
+    fn headers(&self) -> StringRecord {
+        self.inner.headers()
+    }
+
+    fn record(&self, row: &TransactionRow) -> StringRecord {
+        self.inner.record(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client_store::MemoryStore,
+        decimal::Decimal,
+        engine::{Engine, ExecutionError},
+        snapshot::{read_snapshot, write_snapshot},
+        tx_log::MemoryLog,
+    };
+
+    #[test]
+    fn test_recovery_from_injected_faults() {
+        let faults = |chaos: &Chaos| {
+            (0..64)
+                .map(|_| chaos.fault("test").is_err())
+                .collect::<Vec<_>>()
+        };
+        let chaos = Chaos::new(7, 0.25);
+        assert_eq!(faults(&chaos), faults(&Chaos::new(7, 0.25)));
+        assert_ne!(faults(&chaos.fork(1)), faults(&chaos.fork(2)));
+        assert!(faults(&Chaos::new(7, 0.0)).iter().all(|fault| !fault));
+
+        let transactions: Vec<Transaction> = (1..=300)
+            .map(|tx| match tx % 5 {
+                0 => Transaction::Withdrawal((tx % 7) as u16, tx, Decimal::new(3, 0)),
+                4 => Transaction::Dispute((tx % 7) as u16, tx - 1),
+                _ => Transaction::Deposit((tx % 7) as u16, tx, Decimal::new(2, 0)),
+            })
+            .collect();
+        let mut clean = Engine::new();
+        for transaction in &transactions {
+            let _ = clean.execute(transaction.clone());
+        }
+
+        // Snapshots are taken every 10 rows until a fault, which ends the run like an IO error
+        let chaos = Chaos::new(7, 0.01);
+        let mut engine = Engine::new();
+        engine.set_client_store(Box::new(FaultInjectingStore::new(
+            Box::new(MemoryStore::new()),
+            chaos.fork(1),
+        )));
+        engine.set_transaction_log(Box::new(FaultInjectingLog::new(
+            Box::new(MemoryLog::new()),
+            chaos.fork(2),
+        )));
+        let (mut snapshot, mut applied) = (Vec::new(), 0);
+        write_snapshot(&Engine::new(), &mut snapshot).unwrap();
+        'run: for (chunk, rows) in transactions.chunks(10).enumerate() {
+            for transaction in rows {
+                if let Err(ExecutionError::StoreUnavailable | ExecutionError::LogUnavailable) =
+                    engine.execute(transaction.clone())
+                {
+                    break 'run;
+                }
+            }
+            let mut next = Vec::new();
+            if write_snapshot(&engine, &mut next).is_err() {
+                break;
+            }
+            (snapshot, applied) = (next, (chunk + 1) * 10);
+        }
+        assert!(applied < transactions.len());
+
+        // Replaying the rest on the last snapshot gives the state of the run without faults
+        let mut recovered = Engine::new();
+        read_snapshot(&mut recovered, snapshot.as_slice()).unwrap();
+        for transaction in &transactions[applied..] {
+            let _ = recovered.execute(transaction.clone());
+        }
+        let accounts = |engine: &Engine| engine.clients().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(accounts(&recovered), accounts(&clean));
+    }
+}
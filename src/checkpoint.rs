@@ -0,0 +1,166 @@
+use std::{
+    fs::File,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use csv::{ByteRecord, StringRecord};
+use sha2::{Digest, Sha256};
+
+use crate::dialect::CsvDialect;
+
+/// How far a run read its CSV input file: the number of rows applied, the byte offset just after
+/// the last one and the SHA-256 digest of the bytes before it. A later run over the same file, or
+/// the file with rows appended, resumes at the offset instead of applying the rows again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputCheckpoint {
+    pub rows: u64,
+    pub offset: u64,
+    /// Hex digest of the file up to `offset`, telling whether the file still starts the same.
+    pub sha256: String,
+}
+
+impl InputCheckpoint {
+    /// The checkpoint after the first `rows` rows of the CSV file at `path`, read in `dialect`.
+    pub fn scan(path: &Path, dialect: &CsvDialect, rows: u64) -> Result<Self> {
+        let mut reader = csv_reader(File::open(path)?, dialect);
+        if dialect.has_headers {
+            reader.byte_headers()?;
+        }
+        let mut record = ByteRecord::new();
+        for _ in 0..rows {
+            if !reader.read_byte_record(&mut record)? {
+                bail!("{} has fewer than {} rows", path.display(), rows);
+            }
+        }
+        let offset = reader.position().byte();
+        Ok(InputCheckpoint {
+            rows,
+            offset,
+            sha256: prefix_digest(&mut File::open(path)?, offset)?,
+        })
+    }
+
+    /// Opens the CSV file at `path` past the rows of the checkpoint, keeping its header. Fails if
+    /// the file doesn't start with the bytes the checkpoint was taken of.
+    pub fn resume(&self, path: &Path, dialect: &CsvDialect) -> Result<(impl Read + use<>, u64)> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size < self.offset || prefix_digest(&mut file, self.offset)? != self.sha256 {
+            bail!(
+                "{} doesn't start with the {} rows of the checkpoint",
+                path.display(),
+                self.rows
+            );
+        }
+        // The file is now at the offset, the header is read again from its start
+        let mut header = Vec::new();
+        if dialect.has_headers {
+            let mut reader = csv_reader(File::open(path)?, dialect);
+            reader.byte_headers()?;
+            let end = reader.position().byte();
+            File::open(path)?.take(end).read_to_end(&mut header)?;
+        }
+        let remaining = header.len() as u64 + size - self.offset;
+        Ok((Cursor::new(header).chain(file), remaining))
+    }
+
+    /// The `input,<rows>,<offset>,<sha256>` snapshot record.
+    pub fn record(&self) -> Vec<String> {
+        vec![
+            "input".to_string(),
+            self.rows.to_string(),
+            self.offset.to_string(),
+            self.sha256.clone(),
+        ]
+    }
+
+    /// Parses an `input` snapshot record, the record kind is not checked.
+    pub fn parse(record: &StringRecord) -> Result<Self> {
+        let field = |idx: usize| record.get(idx).context("missing field");
+        Ok(InputCheckpoint {
+            rows: field(1)?.parse()?,
+            offset: field(2)?.parse()?,
+            sha256: field(3)?.to_string(),
+        })
+    }
+}
+
+/// A CSV input file whose checkpoint is saved with the snapshot at the end of the run, after the
+/// `skipped` rows a resumed run didn't read again.
+#[derive(Clone, Debug)]
+pub struct CheckpointedInput {
+    pub path: PathBuf,
+    pub dialect: CsvDialect,
+    pub skipped: u64,
+}
+
+impl CheckpointedInput {
+    /// The checkpoint after `rows` more rows were taken from the input.
+    pub fn checkpoint(&self, rows: u64) -> Result<InputCheckpoint> {
+        InputCheckpoint::scan(&self.path, &self.dialect, self.skipped + rows)
+    }
+}
+
+fn csv_reader<R: Read>(reader: R, dialect: &CsvDialect) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .has_headers(dialect.has_headers)
+        .flexible(true)
+        .from_reader(reader)
+}
+
+/// Hex SHA-256 digest of the next `len` bytes of `reader`.
+fn prefix_digest<R: Read>(reader: &mut R, len: u64) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader.take(len), &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.csv", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        write!(
+            file,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n"
+        )
+        .unwrap();
+        let dialect = CsvDialect::default();
+        let checkpoint = InputCheckpoint::scan(&path, &dialect, 1).unwrap();
+        assert_eq!(checkpoint.offset, 38);
+        assert_eq!(
+            InputCheckpoint::parse(&StringRecord::from(checkpoint.record())).unwrap(),
+            checkpoint
+        );
+
+        // Appended rows are read after the checkpointed ones, under the header
+        writeln!(file, "withdrawal,1,3,0.5").unwrap();
+        let (reader, size) = checkpoint.resume(&path, &dialect).unwrap();
+        let (headers, records) = dialect.records(reader).unwrap();
+        assert_eq!(
+            headers,
+            StringRecord::from(vec!["type", "client", "tx", "amount"])
+        );
+        let txs: Vec<String> = records.map(|rec| rec.unwrap()[2].to_string()).collect();
+        assert_eq!(txs, ["2", "3"]);
+        assert_eq!(size, 22 + 16 + 19);
+        assert!(InputCheckpoint::scan(&path, &dialect, 4).is_err());
+
+        // A file that was rewritten doesn't resume
+        std::fs::write(&path, "type,client,tx,amount\ndeposit,2,1,1.0\n").unwrap();
+        assert!(checkpoint.resume(&path, &dialect).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
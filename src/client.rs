@@ -1,21 +1,84 @@
-use rust_decimal::Decimal;
+use std::sync::Arc;
 
+use crate::decimal::Decimal;
+use serde::{Serialize, Serializer};
+
+use crate::{currency::Currency, kyc::ClientMetadata};
+
+/// Balances of a client in one currency. A client with funds in several currencies has one
+/// `Client` per currency, all sharing the same `locked` state.
+///
+/// Serializes with the field names of the client report, `id` as `client`, and amounts as strings
+/// with their exact digits, so no format loses precision. The metadata isn't serialized.
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Client {
+    #[serde(rename = "client")]
     pub id: u16,
+    pub currency: Currency,
+    #[serde(serialize_with = "serialize_amount")]
     pub available: Decimal,
+    #[serde(serialize_with = "serialize_amount")]
     pub held: Decimal,
+    #[serde(serialize_with = "serialize_amount")]
     pub total: Decimal,
     pub locked: bool,
+    /// Closed by a `close_account` transaction, for good unlike a lock.
+    pub closed: bool,
+    /// Metadata of the client from the engine's client directory, if it lists the client.
+    #[serde(skip)]
+    pub metadata: Option<Arc<ClientMetadata>>,
+}
+
+fn serialize_amount<S: Serializer>(amount: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(amount)
 }
 
 impl Client {
     pub fn new(id: u16) -> Self {
+        Self::with_currency(id, Currency::default())
+    }
+
+    pub fn with_currency(id: u16, currency: Currency) -> Self {
         Client {
             id,
+            currency,
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             total: Decimal::ZERO,
             locked: false,
+            closed: false,
+            metadata: None,
         }
     }
+
+    /// Whether withdrawals took the available funds below zero, see `EngineConfig::overdrafts`.
+    pub fn is_overdrawn(&self) -> bool {
+        self.available < Decimal::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_serialization() {
+        let client = Client {
+            available: Decimal::new(15, 1),
+            total: Decimal::new(15, 1),
+            locked: true,
+            ..Client::with_currency(7, "EUR".parse().unwrap())
+        };
+        assert_eq!(
+            serde_json::to_string(&client).unwrap(),
+            "{\"client\":7,\"currency\":\"EUR\",\"available\":\"1.5\",\"held\":\"0\",\
+             \"total\":\"1.5\",\"locked\":true,\"closed\":false}"
+        );
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(&client).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "client,currency,available,held,total,locked,closed\n7,EUR,1.5,0,1.5,true,false\n"
+        );
+    }
 }
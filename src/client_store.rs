@@ -0,0 +1,288 @@
+use std::{collections::BTreeMap, io};
+
+use clap::ValueEnum;
+
+use crate::{client::Client, currency::Currency};
+
+/// Storage of the client accounts, keyed by client id and currency.
+pub trait ClientStore: Send + Sync {
+    fn get(&self, client_id: u16, currency: Currency) -> io::Result<Option<Client>>;
+    fn get_mut(&mut self, client_id: u16, currency: Currency) -> io::Result<Option<&mut Client>>;
+    /// Inserts or replaces the account of `client.id` in `client.currency`.
+    fn insert(&mut self, client: Client) -> io::Result<()>;
+    /// Removes the account of the client in the currency, if it has one.
+    fn remove(&mut self, client_id: u16, currency: Currency) -> io::Result<()>;
+    /// Iterates over the accounts of a client ordered by currency.
+    fn accounts(&self, client_id: u16) -> Box<dyn Iterator<Item = io::Result<Client>> + '_>;
+    /// Iterates over all accounts ordered by client id and currency.
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Client>> + '_>;
+}
+
+/// Client store selection for the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ClientStoreKind {
+    /// Keep all accounts in memory
+    #[default]
+    Memory,
+    /// Keep the accounts on disk with a cache of recently used ones (requires the `sled` feature)
+    Disk,
+}
+
+/// All accounts in memory, the fastest option while they fit.
+#[derive(Default)]
+pub struct MemoryStore {
+    accounts: BTreeMap<(u16, Currency), Client>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ClientStore for MemoryStore {
+    fn get(&self, client_id: u16, currency: Currency) -> io::Result<Option<Client>> {
+        Ok(self.accounts.get(&(client_id, currency)).cloned())
+    }
+
+    fn get_mut(&mut self, client_id: u16, currency: Currency) -> io::Result<Option<&mut Client>> {
+        Ok(self.accounts.get_mut(&(client_id, currency)))
+    }
+
+    fn insert(&mut self, client: Client) -> io::Result<()> {
+        self.accounts.insert((client.id, client.currency), client);
+        Ok(())
+    }
+
+    fn remove(&mut self, client_id: u16, currency: Currency) -> io::Result<()> {
+        self.accounts.remove(&(client_id, currency));
+        Ok(())
+    }
+
+    fn accounts(&self, client_id: u16) -> Box<dyn Iterator<Item = io::Result<Client>> + '_> {
+        Box::new(
+            self.accounts
+                .range((client_id, Currency::MIN)..=(client_id, Currency::MAX))
+                .map(|(_, client)| Ok(client.clone())),
+        )
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Client>> + '_> {
+        Box::new(self.accounts.values().cloned().map(Ok))
+    }
+}
+
+#[cfg(feature = "sled")]
+pub use disk::DiskStore;
+
+#[cfg(feature = "sled")]
+mod disk {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::decimal::Decimal;
+    use crate::memory::DISK_PAGE_CACHE;
+
+    /// Encoded key: client id and currency, ordered like the accounts.
+    const KEY_LEN: usize = 2 + 3;
+    /// Encoded account: available, held, total and the locked and closed flags.
+    const ENTRY_LEN: usize = 3 * 16 + 1;
+
+    /// Accounts in a sled database with a write-back cache of recently used accounts in memory,
+    /// for datasets with more clients than fit into memory. Updated accounts are written to the
+    /// database when the cache is full, so the database alone is not a consistent state.
+    ///
+    /// The database is a scratch area of a single run and is deleted when the store is dropped.
+    pub struct DiskStore {
+        db: sled::Db,
+        /// Every cached account is also in the database, possibly with older balances.
+        cache: BTreeMap<(u16, Currency), Client>,
+        cache_size: usize,
+    }
+
+    impl DiskStore {
+        /// Creates the store in `dir`, which must not contain files of another run. `cache_size`
+        /// is the number of accounts kept in memory.
+        pub fn open<P: AsRef<Path>>(dir: P, cache_size: usize) -> io::Result<Self> {
+            let dir = dir.as_ref();
+            if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("client store directory {} is not empty", dir.display()),
+                ));
+            }
+            let db = sled::Config::new()
+                .path(dir)
+                .temporary(true)
+                .cache_capacity(DISK_PAGE_CACHE)
+                .open()
+                .map_err(io::Error::other)?;
+            Ok(DiskStore {
+                db,
+                cache: BTreeMap::new(),
+                cache_size: cache_size.max(1),
+            })
+        }
+
+        /// Makes room for an account in the cache, writing all cached accounts back when it is
+        /// full. Dropping the whole cache is cheaper to track than recency and accounts of the
+        /// same clients are soon cached again.
+        fn reserve(&mut self) -> io::Result<()> {
+            if self.cache.len() < self.cache_size {
+                return Ok(());
+            }
+            let mut batch = sled::Batch::default();
+            for client in self.cache.values() {
+                batch.insert(&key(client.id, client.currency), &encode(client)[..]);
+            }
+            self.db.apply_batch(batch).map_err(io::Error::other)?;
+            self.cache.clear();
+            Ok(())
+        }
+
+        /// The account stored under an encoded key, preferring the cached balances.
+        fn account(&self, key: &[u8], entry: &[u8]) -> io::Result<Client> {
+            let client = decode(key, entry)?;
+            Ok(match self.cache.get(&(client.id, client.currency)) {
+                Some(cached) => cached.clone(),
+                None => client,
+            })
+        }
+    }
+
+    fn key(client_id: u16, currency: Currency) -> [u8; KEY_LEN] {
+        let mut key = [0; KEY_LEN];
+        key[..2].copy_from_slice(&client_id.to_be_bytes());
+        key[2..].copy_from_slice(currency.as_str().as_bytes());
+        key
+    }
+
+    fn encode(client: &Client) -> [u8; ENTRY_LEN] {
+        let mut entry = [0; ENTRY_LEN];
+        entry[..16].copy_from_slice(&client.available.serialize());
+        entry[16..32].copy_from_slice(&client.held.serialize());
+        entry[32..48].copy_from_slice(&client.total.serialize());
+        entry[48] = client.locked as u8 | (client.closed as u8) << 1;
+        entry
+    }
+
+    fn decode(key: &[u8], entry: &[u8]) -> io::Result<Client> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt client store entry");
+        let key: &[u8; KEY_LEN] = key.try_into().map_err(|_| invalid())?;
+        let entry: &[u8; ENTRY_LEN] = entry.try_into().map_err(|_| invalid())?;
+        let currency = std::str::from_utf8(&key[2..])
+            .map_err(|_| invalid())?
+            .parse()
+            .map_err(|_| invalid())?;
+        let amount = |at: usize| {
+            let mut amount = [0; 16];
+            amount.copy_from_slice(&entry[at..at + 16]);
+            Decimal::deserialize(amount)
+        };
+        Ok(Client {
+            available: amount(0),
+            held: amount(16),
+            total: amount(32),
+            locked: entry[48] & 1 != 0,
+            closed: entry[48] & 2 != 0,
+            ..Client::with_currency(u16::from_be_bytes([key[0], key[1]]), currency)
+        })
+    }
+
+    impl ClientStore for DiskStore {
+        fn get(&self, client_id: u16, currency: Currency) -> io::Result<Option<Client>> {
+            if let Some(client) = self.cache.get(&(client_id, currency)) {
+                return Ok(Some(client.clone()));
+            }
+            let key = key(client_id, currency);
+            let Some(entry) = self.db.get(key).map_err(io::Error::other)? else {
+                return Ok(None);
+            };
+            decode(&key, &entry).map(Some)
+        }
+
+        fn get_mut(
+            &mut self,
+            client_id: u16,
+            currency: Currency,
+        ) -> io::Result<Option<&mut Client>> {
+            if !self.cache.contains_key(&(client_id, currency)) {
+                let key = key(client_id, currency);
+                let Some(entry) = self.db.get(key).map_err(io::Error::other)? else {
+                    return Ok(None);
+                };
+                let client = decode(&key, &entry)?;
+                self.reserve()?;
+                self.cache.insert((client_id, currency), client);
+            }
+            Ok(self.cache.get_mut(&(client_id, currency)))
+        }
+
+        fn insert(&mut self, client: Client) -> io::Result<()> {
+            self.db
+                .insert(key(client.id, client.currency), &encode(&client)[..])
+                .map_err(io::Error::other)?;
+            let key = (client.id, client.currency);
+            if !self.cache.contains_key(&key) {
+                self.reserve()?;
+            }
+            self.cache.insert(key, client);
+            Ok(())
+        }
+
+        fn remove(&mut self, client_id: u16, currency: Currency) -> io::Result<()> {
+            self.db
+                .remove(key(client_id, currency))
+                .map_err(io::Error::other)?;
+            self.cache.remove(&(client_id, currency));
+            Ok(())
+        }
+
+        fn accounts(&self, client_id: u16) -> Box<dyn Iterator<Item = io::Result<Client>> + '_> {
+            Box::new(self.db.scan_prefix(client_id.to_be_bytes()).map(|item| {
+                let (key, entry) = item.map_err(io::Error::other)?;
+                self.account(&key, &entry)
+            }))
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = io::Result<Client>> + '_> {
+            // Big-endian ids iterate in account order
+            Box::new(self.db.iter().map(|item| {
+                let (key, entry) = item.map_err(io::Error::other)?;
+                self.account(&key, &entry)
+            }))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_disk_store() {
+            let dir = std::env::temp_dir().join(format!("client-store-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            let eur: Currency = "EUR".parse().unwrap();
+            let mut store = DiskStore::open(&dir, 1).unwrap();
+            store.insert(Client::with_currency(7, eur)).unwrap();
+            store.get_mut(7, eur).unwrap().unwrap().available = Decimal::new(-12345, 4);
+            // Caching client 2 writes the update of client 7 back to the database
+            store.insert(Client::new(2)).unwrap();
+            store.get_mut(2, Currency::USD).unwrap().unwrap().locked = true;
+            let client = store.get(7, eur).unwrap().unwrap();
+            assert_eq!(client.available, Decimal::new(-12345, 4));
+            assert_eq!(store.get(7, Currency::USD).unwrap(), None);
+            assert!(store.get_mut(3, Currency::USD).unwrap().is_none());
+
+            let accounts: Vec<(u16, bool)> = store
+                .iter()
+                .map(|client| client.map(|client| (client.id, client.locked)))
+                .collect::<io::Result<_>>()
+                .unwrap();
+            assert_eq!(accounts, vec![(2, true), (7, false)]);
+            assert_eq!(store.accounts(7).count(), 1);
+            drop(store);
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+
+/// Source of the current time in Unix seconds for time-dependent features, so tests and replays
+/// can run against deterministic time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+
+    /// Called with the timestamp of every input row that has one.
+    fn observe(&self, _timestamp: u64) {}
+}
+
+/// Wall-clock time.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// Time of the latest row timestamp seen, 0 before the first one. Processing the same input
+/// always sees the same time, no matter when it runs.
+#[derive(Debug, Default)]
+pub struct TransactionClock {
+    latest: AtomicU64,
+}
+
+impl TransactionClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Clock for TransactionClock {
+    fn now(&self) -> u64 {
+        self.latest.load(Ordering::Relaxed)
+    }
+
+    fn observe(&self, timestamp: u64) {
+        self.latest.fetch_max(timestamp, Ordering::Relaxed);
+    }
+}
+
+/// Time that only moves when told to, for tests.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        ManualClock {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+/// Clock selection for the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ClockSource {
+    /// Latest `timestamp` column value seen, reproducible across runs
+    #[default]
+    Transaction,
+    /// Wall-clock time
+    System,
+}
+
+impl ClockSource {
+    pub fn clock(self) -> Arc<dyn Clock> {
+        match self {
+            ClockSource::Transaction => Arc::new(TransactionClock::new()),
+            ClockSource::System => Arc::new(SystemClock),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_clock() {
+        let clock = TransactionClock::new();
+        assert_eq!(clock.now(), 0);
+        clock.observe(200);
+        clock.observe(100);
+        assert_eq!(clock.now(), 200);
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(10);
+        clock.observe(100);
+        assert_eq!(clock.now(), 10);
+        clock.advance(5);
+        assert_eq!(clock.now(), 15);
+        clock.set(1);
+        assert_eq!(clock.now(), 1);
+        assert!(SystemClock.now() > 1_600_000_000);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::BTreeMap, io};
+
+use crate::decimal::Decimal;
+use anyhow::{Result, bail};
+
+use crate::{
+    engine::{Engine, ExecutionError},
+    kyc::ClientDirectory,
+    transaction::Transaction,
+};
+
+/// Cohort label for clients missing from the metadata file.
+pub const UNKNOWN_COHORT: &str = "unknown";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CohortTotals {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub chargebacks: Decimal,
+}
+
+/// Aggregates deposits, withdrawals and chargebacks per client cohort.
+pub struct CohortReport {
+    cohorts: BTreeMap<u16, String>,
+    totals: BTreeMap<String, CohortTotals>,
+}
+
+impl CohortReport {
+    pub fn new(cohorts: BTreeMap<u16, String>) -> Self {
+        CohortReport {
+            cohorts,
+            totals: BTreeMap::new(),
+        }
+    }
+
+    /// The cohort of every client is its value in the `column` of the client metadata.
+    pub fn from_directory(directory: &ClientDirectory, column: &str) -> Result<Self> {
+        if !directory.has_column(column) {
+            bail!("client metadata has no `{}` column", column);
+        }
+        let cohorts = directory
+            .clients()
+            .map(|(client_id, metadata)| {
+                let cohort = metadata.columns.get(column).cloned().unwrap_or_default();
+                (client_id, cohort)
+            })
+            .collect();
+        Ok(Self::new(cohorts))
+    }
+
+    /// Accounts a successfully executed transaction. Chargeback and settled withdrawal amounts are
+    /// looked up in the engine, a chargeback counts the amount actually charged back. Fails with
+    /// `ExecutionError::Overflow` if a total overflows, leaving it unchanged.
+    pub fn record(
+        &mut self,
+        engine: &Engine,
+        transaction: &Transaction,
+    ) -> Result<(), ExecutionError> {
+        match *transaction {
+            Transaction::Deposit(client_id, _, amount) => {
+                add(&mut self.totals_mut(client_id).deposits, amount)
+            }
+            Transaction::Withdrawal(client_id, _, amount) => {
+                add(&mut self.totals_mut(client_id).withdrawals, amount)
+            }
+            Transaction::WithdrawalSettle(client_id, tx_id) => {
+                match engine.transaction(tx_id).and_then(|hold| hold.amount()) {
+                    Some(amount) => add(&mut self.totals_mut(client_id).withdrawals, amount),
+                    None => Ok(()),
+                }
+            }
+            Transaction::Chargeback(_, tx_id) => {
+                match (engine.transaction(tx_id), engine.charged_back(tx_id)) {
+                    (Some(Transaction::Deposit(client_id, ..)), Some(amount)) => {
+                        add(&mut self.totals_mut(client_id).chargebacks, amount)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn totals_mut(&mut self, client_id: u16) -> &mut CohortTotals {
+        let cohort = self
+            .cohorts
+            .get(&client_id)
+            .map(String::as_str)
+            .unwrap_or(UNKNOWN_COHORT);
+        self.totals.entry(cohort.to_string()).or_default()
+    }
+
+    pub fn totals(&self) -> &BTreeMap<String, CohortTotals> {
+        &self.totals
+    }
+
+    pub fn write_report<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["cohort", "deposits", "withdrawals", "chargebacks"])?;
+        for (cohort, totals) in &self.totals {
+            writer.write_record([
+                cohort.clone(),
+                totals.deposits.to_string(),
+                totals.withdrawals.to_string(),
+                totals.chargebacks.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn add(total: &mut Decimal, amount: Decimal) -> Result<(), ExecutionError> {
+    *total = total.checked_add(amount).ok_or(ExecutionError::Overflow)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DisputeAmountPolicy, EngineConfig};
+
+    #[test]
+    fn test_cohort_totals() {
+        let cohorts = BTreeMap::from([(1, "DE".to_string()), (2, "US".to_string())]);
+        let mut report = CohortReport::new(cohorts);
+        let mut engine = Engine::new();
+        let transactions = [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            Transaction::Deposit(2, 2, Decimal::new(20, 0)),
+            Transaction::Deposit(3, 3, Decimal::new(5, 0)),
+            Transaction::Withdrawal(1, 4, Decimal::new(3, 0)),
+            Transaction::Dispute(2, 2),
+            Transaction::Chargeback(2, 2),
+        ];
+        for transaction in transactions {
+            assert!(engine.execute(transaction.clone()).is_ok());
+            report.record(&engine, &transaction).unwrap();
+        }
+        let totals = report.totals();
+        assert_eq!(
+            totals["DE"],
+            CohortTotals {
+                deposits: Decimal::new(10, 0),
+                withdrawals: Decimal::new(3, 0),
+                chargebacks: Decimal::ZERO,
+            }
+        );
+        assert_eq!(totals["US"].chargebacks, Decimal::new(20, 0));
+        assert_eq!(totals[UNKNOWN_COHORT].deposits, Decimal::new(5, 0));
+    }
+
+    #[test]
+    fn test_cohorts_from_directory() {
+        let directory =
+            ClientDirectory::from_reader("client,kyc,country\n1,verified,DE\n2,,US\n".as_bytes())
+                .unwrap();
+        let report = CohortReport::from_directory(&directory, "country").unwrap();
+        assert_eq!(
+            report.cohorts,
+            BTreeMap::from([(1, "DE".to_string()), (2, "US".to_string())])
+        );
+        // The KYC status is a column like any other
+        let report = CohortReport::from_directory(&directory, "kyc").unwrap();
+        assert_eq!(report.cohorts[&2], "");
+        assert_eq!(
+            CohortReport::from_directory(&directory, "segment")
+                .err()
+                .unwrap()
+                .to_string(),
+            "client metadata has no `segment` column"
+        );
+    }
+
+    #[test]
+    fn test_partial_chargeback() {
+        let mut report = CohortReport::new(BTreeMap::from([(1, "DE".to_string())]));
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_amounts: DisputeAmountPolicy::Honor,
+            ..EngineConfig::default()
+        });
+        let deposit = Transaction::Deposit(1, 1, Decimal::new(10, 0));
+        engine.execute(deposit.clone()).unwrap();
+        report.record(&engine, &deposit).unwrap();
+        let partial = Some(Decimal::new(4, 0));
+        for transaction in [Transaction::Dispute(1, 1), Transaction::Chargeback(1, 1)] {
+            engine
+                .execute_row(transaction.clone(), None, partial)
+                .unwrap();
+            report.record(&engine, &transaction).unwrap();
+        }
+        assert_eq!(report.totals()["DE"].chargebacks, Decimal::new(4, 0));
+    }
+
+    #[test]
+    fn test_cohort_totals_overflow() {
+        let mut report = CohortReport::new(BTreeMap::new());
+        let mut engine = Engine::new();
+        let deposit = Transaction::Deposit(1, 1, Decimal::MAX);
+        engine.execute(deposit.clone()).unwrap();
+        report.record(&engine, &deposit).unwrap();
+        let deposit = Transaction::Deposit(2, 2, Decimal::ONE);
+        engine.execute(deposit.clone()).unwrap();
+        assert_eq!(
+            report.record(&engine, &deposit),
+            Err(ExecutionError::Overflow)
+        );
+        assert_eq!(report.totals()[UNKNOWN_COHORT].deposits, Decimal::MAX);
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression of an input file, decompressed on the fly while reading.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Compression {
+    /// Detected from the `.gz`/`.zst` extension or the leading magic bytes
+    #[default]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression of a file named `path` starting with `head`.
+    fn detect(path: &Path, head: &[u8]) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => return Compression::Gzip,
+            Some("zst") => return Compression::Zstd,
+            _ => {}
+        }
+        if head.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Compression of the file at `path`, detected from its name and first bytes for `Auto`.
+pub fn resolve(path: &Path, compression: Compression) -> io::Result<Compression> {
+    match compression {
+        Compression::Auto => {
+            let mut reader = BufReader::new(File::open(path)?);
+            Ok(Compression::detect(path, reader.fill_buf()?))
+        }
+        compression => Ok(compression),
+    }
+}
+
+/// Opens the file at `path` for reading its decompressed content.
+pub fn open(path: &Path, compression: Compression) -> io::Result<Box<dyn Read + Send>> {
+    decode(File::open(path)?, path, compression)
+}
+
+/// Reads the decompressed content of the file at `path` from `reader`, e.g. the file wrapped to
+/// count the bytes read.
+pub fn decode<R: Read + Send + 'static>(
+    reader: R,
+    path: &Path,
+    compression: Compression,
+) -> io::Result<Box<dyn Read + Send>> {
+    let mut reader = BufReader::new(reader);
+    let compression = match compression {
+        Compression::Auto => Compression::detect(path, reader.fill_buf()?),
+        compression => compression,
+    };
+    Ok(match compression {
+        Compression::Auto | Compression::None => Box::new(reader),
+        // Concatenated gzip members, e.g. from appending to an export, are read as one stream
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        #[cfg(not(target_arch = "wasm32"))]
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+        #[cfg(target_arch = "wasm32")]
+        Compression::Zstd => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "zstd input is not supported on WebAssembly",
+            ));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    fn read_all(path: &Path, compression: Compression) -> String {
+        let mut content = String::new();
+        open(path, compression)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn test_open_compressed() {
+        let content = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        let dir = std::env::temp_dir();
+        let prefix = format!("compression-{}", std::process::id());
+
+        let gzip = dir.join(format!("{}.csv.gz", prefix));
+        let mut encoder = GzEncoder::new(File::create(&gzip).unwrap(), Default::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let zstd = dir.join(format!("{}.csv.zst", prefix));
+        std::fs::write(&zstd, zstd::encode_all(content.as_bytes(), 0).unwrap()).unwrap();
+        // Detected from the magic bytes without an extension
+        let unnamed = dir.join(format!("{}-zstd", prefix));
+        std::fs::copy(&zstd, &unnamed).unwrap();
+        let plain = dir.join(format!("{}.csv", prefix));
+        std::fs::write(&plain, content).unwrap();
+
+        assert_eq!(read_all(&gzip, Compression::Auto), content);
+        assert_eq!(read_all(&zstd, Compression::Auto), content);
+        assert_eq!(read_all(&unnamed, Compression::Auto), content);
+        assert_eq!(read_all(&plain, Compression::Auto), content);
+        assert_eq!(read_all(&unnamed, Compression::Zstd), content);
+        assert!(
+            open(&plain, Compression::Gzip)
+                .unwrap()
+                .read_to_end(&mut Vec::new())
+                .is_err()
+        );
+        for path in [gzip, zstd, unnamed, plain] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
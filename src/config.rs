@@ -0,0 +1,264 @@
+use crate::decimal::Decimal;
+use clap::ValueEnum;
+
+use crate::{
+    amount::AmountPolicy, currency::Currency, engine::ExecutionError, fees::FeeSchedule,
+    kyc::ClientDirectory, ledger::Ledger, limits::WithdrawalLimits, transaction::Transaction,
+};
+
+/// Engine behavior switches. The defaults are the strict settings.
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    /// Reject dispute, resolve and chargeback rows whose client doesn't own the referenced transaction.
+    pub check_dispute_client: bool,
+    /// Treat a repeated deposit or withdrawal identical to the logged one as a no-op instead of
+    /// rejecting it as a duplicate.
+    pub idempotent_duplicates: bool,
+    pub validation: ValidationPolicy,
+    /// Precision and rounding of incoming amounts and reported balances.
+    pub amounts: AmountPolicy,
+    pub locked_account_policy: LockedAccountPolicy,
+    pub dispute_policy: DisputePolicy,
+    pub dispute_amounts: DisputeAmountPolicy,
+    pub pre_history: PreHistoryPolicy,
+    /// How the age of open disputes is measured for `Engine::expire_disputes`.
+    pub dispute_aging: DisputeAging,
+    /// Accept the administrative `unlock`, `adjust_credit`, `adjust_debit`, `set_overdraft` and
+    /// `close_account` transactions, and flag closed accounts in the report.
+    pub allow_admin: bool,
+    /// Unlock the client when a chargeback reversal re-credits a charged-back deposit.
+    pub reversal_unlocks: bool,
+    /// Keep separate balances per currency. Without it rows in another currency than the default
+    /// one are rejected.
+    pub multi_currency: bool,
+    /// Currency of rows without a `currency` column.
+    pub default_currency: Currency,
+    /// Ledger whose persistent state this engine reads and writes, `None` for the unnamespaced
+    /// layout of single-ledger deployments.
+    pub ledger: Option<Ledger>,
+    /// Fees of deposits and withdrawals, none without a schedule.
+    pub fees: Option<FeeSchedule>,
+    /// Limits of withdrawals and withdrawal holds, none without limits.
+    pub limits: Option<WithdrawalLimits>,
+    /// Metadata of the known clients, attached to their accounts.
+    pub clients: Option<ClientDirectory>,
+    /// Deposits above this amount are rejected unless the client's KYC status is verified.
+    /// Clients missing from `clients` are unverified.
+    pub kyc_deposit_limit: Option<Decimal>,
+    /// Let withdrawals and withdrawal holds take the available funds below zero up to the
+    /// client's overdraft limit, and flag overdrawn accounts in the report.
+    pub overdrafts: bool,
+    /// What closing an account with held funds does.
+    pub close_policy: ClosePolicy,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            check_dispute_client: true,
+            idempotent_duplicates: false,
+            validation: ValidationPolicy::default(),
+            amounts: AmountPolicy::default(),
+            locked_account_policy: LockedAccountPolicy::default(),
+            dispute_policy: DisputePolicy::default(),
+            dispute_amounts: DisputeAmountPolicy::default(),
+            pre_history: PreHistoryPolicy::default(),
+            dispute_aging: DisputeAging::default(),
+            allow_admin: false,
+            reversal_unlocks: false,
+            multi_currency: false,
+            default_currency: Currency::default(),
+            ledger: None,
+            fees: None,
+            limits: None,
+            clients: None,
+            kyc_deposit_limit: None,
+            overdrafts: false,
+            close_policy: ClosePolicy::default(),
+        }
+    }
+}
+
+/// Which dispute lifecycle operations a locked (charged back) account still accepts.
+/// Deposits and withdrawals are always rejected on locked accounts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum LockedAccountPolicy {
+    /// Reject disputes, resolves and chargebacks. Disputes opened before the lock stay held.
+    Freeze,
+    /// Allow resolving or charging back disputes opened before the lock, reject new disputes.
+    SettleOpen,
+    /// Allow the full dispute lifecycle, e.g. a second chargeback on a locked account.
+    #[default]
+    Allow,
+}
+
+/// What a `close_account` transaction does when the client has held funds. A closed client's
+/// accounts keep their final balances and reject every further transaction but balance
+/// assertions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ClosePolicy {
+    /// Reject the closure until the disputes are settled and the withdrawal holds finalized.
+    #[default]
+    Reject,
+    /// Resolve the client's open disputes and cancel its pending withdrawal holds, then close.
+    Release,
+}
+
+/// What a dispute does when the client no longer has the disputed amount available, e.g. after
+/// withdrawing a deposit that is disputed later.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum DisputePolicy {
+    /// Hold the full amount, available funds may go negative.
+    #[default]
+    AllowNegative,
+    /// Reject the dispute with an insufficient funds error.
+    Reject,
+    /// Hold only the available part of the amount. Resolve and chargeback move the held part.
+    PartialHold,
+}
+
+/// What happens to an amount on a dispute, resolve or chargeback row, which normally has none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum DisputeAmountPolicy {
+    /// Ignore the amount and settle the whole deposit.
+    #[default]
+    Ignore,
+    /// Ignore the amount, but print a warning.
+    Warn,
+    /// Reject the row.
+    Reject,
+    /// Dispute only the given part of the deposit. Resolve and chargeback rows must then give
+    /// the held amount.
+    Honor,
+}
+
+impl DisputeAmountPolicy {
+    /// How the amount of a row was handled, as recorded in the audit trail.
+    pub fn outcome(self) -> &'static str {
+        match self {
+            DisputeAmountPolicy::Ignore => "ignored",
+            DisputeAmountPolicy::Warn => "warned",
+            DisputeAmountPolicy::Reject => "rejected",
+            DisputeAmountPolicy::Honor => "honored",
+        }
+    }
+}
+
+/// What a dispute, resolve or chargeback does when it references an unknown transaction of a
+/// client seeded without history, e.g. from a previous run's report. Such transactions most
+/// likely predate the seeded state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum PreHistoryPolicy {
+    /// Reject the row with a pre-history error.
+    #[default]
+    Reject,
+    /// Accept the row without changing any balance.
+    Ignore,
+}
+
+/// Unit of the age of open disputes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum DisputeAging {
+    /// Seconds on the engine clock since the dispute was opened
+    #[default]
+    Clock,
+    /// Transactions executed since the dispute was opened, applied or rejected
+    Sequence,
+}
+
+/// Input validation rules for deposit, withdrawal and adjustment amounts.
+#[derive(Clone, Debug)]
+pub struct ValidationPolicy {
+    pub reject_zero_amounts: bool,
+    pub reject_negative_amounts: bool,
+    /// Upper bound for a single amount, unbounded if not set.
+    pub max_amount: Option<Decimal>,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        ValidationPolicy {
+            reject_zero_amounts: true,
+            reject_negative_amounts: true,
+            max_amount: None,
+        }
+    }
+}
+
+impl ValidationPolicy {
+    /// Disables all rules.
+    pub fn permissive() -> Self {
+        ValidationPolicy {
+            reject_zero_amounts: false,
+            reject_negative_amounts: false,
+            max_amount: None,
+        }
+    }
+
+    /// Checks the amount of deposits, withdrawals and adjustments. Expected balances of
+    /// assertions may be zero or negative, overdraft limits may be zero.
+    pub fn validate(&self, transaction: &Transaction) -> Result<(), ExecutionError> {
+        if transaction.is_assertion() {
+            return Ok(());
+        }
+        let Some(amount) = transaction.amount() else {
+            return Ok(());
+        };
+        let zero_allowed = matches!(transaction, Transaction::SetOverdraft(..));
+        if (self.reject_zero_amounts && amount.is_zero() && !zero_allowed)
+            || (self.reject_negative_amounts && amount.is_sign_negative() && !amount.is_zero())
+            || self.max_amount.is_some_and(|max| amount > max)
+        {
+            return Err(ExecutionError::InvalidAmount);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_validation_policy() {
+        let policy = ValidationPolicy::default();
+        assert!(
+            policy
+                .validate(&Transaction::Deposit(1, 1, Decimal::new(1, 4)))
+                .is_ok()
+        );
+        assert_eq!(
+            policy.validate(&Transaction::Deposit(1, 1, Decimal::ZERO)),
+            Err(ExecutionError::InvalidAmount)
+        );
+        assert_eq!(
+            policy.validate(&Transaction::Withdrawal(1, 1, Decimal::new(-1, 0))),
+            Err(ExecutionError::InvalidAmount)
+        );
+        assert!(policy.validate(&Transaction::Dispute(1, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_tuned_validation_policy() {
+        let policy = ValidationPolicy {
+            reject_zero_amounts: false,
+            max_amount: Some(Decimal::new(100, 0)),
+            ..ValidationPolicy::default()
+        };
+        assert!(
+            policy
+                .validate(&Transaction::Deposit(1, 1, Decimal::ZERO))
+                .is_ok()
+        );
+        assert_eq!(
+            policy.validate(&Transaction::Deposit(1, 1, Decimal::new(101, 0))),
+            Err(ExecutionError::InvalidAmount)
+        );
+        let permissive = ValidationPolicy::permissive();
+        assert!(
+            permissive
+                .validate(&Transaction::Withdrawal(1, 1, Decimal::new(-1, 0)))
+                .is_ok()
+        );
+    }
+}
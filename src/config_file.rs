@@ -0,0 +1,223 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    amount::Rounding,
+    config::{EngineConfig, ValidationPolicy},
+    decimal::Decimal,
+    fees::FeeSchedule,
+    limits::WithdrawalLimits,
+    pipeline::ErrorPolicy,
+    progress::ProgressMode,
+    report::{ReportFormat, ReportStyle},
+};
+
+/// Settings read from a TOML file, every one optional and named like its command line option.
+/// The engine settings, `precision`, `rounding`, `[validation]`, `[fees]` and `[limits]`, can be
+/// reloaded while the engine runs, the `[io]` options apply when a run starts.
+///
+/// ```toml
+/// precision = 2
+/// rounding = "half-up"
+///
+/// [validation]
+/// max_amount = "1000000"
+///
+/// [fees]
+/// account = 0
+/// withdrawal = { flat = "0.25" }
+///
+/// [limits.default]
+/// max_withdrawal = "5000"
+///
+/// [io]
+/// on_error = "collect"
+/// rejects = "rejects.csv"
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConfigFile {
+    pub precision: Option<u32>,
+    pub rounding: Option<Rounding>,
+    /// Validation rules, the ones missing from the table keep their defaults.
+    pub validation: Option<ValidationPolicy>,
+    /// Fee schedule, in the format of `FeeSchedule::from_toml`.
+    pub fees: Option<FeeSchedule>,
+    /// Withdrawal limits, in the format of `WithdrawalLimits::from_toml`.
+    pub limits: Option<WithdrawalLimits>,
+    pub io: IoSettings,
+}
+
+/// Input and output options of the `[io]` table.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IoSettings {
+    pub report_format: Option<ReportFormat>,
+    pub report_style: Option<ReportStyle>,
+    pub on_error: Option<ErrorPolicy>,
+    pub rejects: Option<PathBuf>,
+    pub audit: Option<PathBuf>,
+    pub progress: Option<ProgressMode>,
+    pub progress_interval: Option<u64>,
+}
+
+impl ConfigFile {
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        // Enums are spelled like their command line values, the fees and limits are parsed by
+        // their own loaders
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct File {
+            precision: Option<u32>,
+            rounding: Option<String>,
+            validation: Option<Validation>,
+            fees: Option<toml::Table>,
+            limits: Option<toml::Table>,
+            #[serde(default)]
+            io: Io,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Validation {
+            reject_zero_amounts: Option<bool>,
+            reject_negative_amounts: Option<bool>,
+            max_amount: Option<Decimal>,
+        }
+
+        #[derive(Default, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Io {
+            report_format: Option<String>,
+            report_style: Option<String>,
+            on_error: Option<String>,
+            rejects: Option<PathBuf>,
+            audit: Option<PathBuf>,
+            progress: Option<String>,
+            progress_interval: Option<u64>,
+        }
+
+        let file: File = toml::from_str(toml)?;
+        if file.precision.is_some_and(|precision| precision > 28) {
+            anyhow::bail!("The precision must be at most 28 decimal places");
+        }
+        if file.io.progress_interval == Some(0) {
+            anyhow::bail!("The progress interval must be at least 1 row");
+        }
+        let validation = file.validation.map(|validation| {
+            let defaults = ValidationPolicy::default();
+            ValidationPolicy {
+                reject_zero_amounts: validation
+                    .reject_zero_amounts
+                    .unwrap_or(defaults.reject_zero_amounts),
+                reject_negative_amounts: validation
+                    .reject_negative_amounts
+                    .unwrap_or(defaults.reject_negative_amounts),
+                max_amount: validation.max_amount,
+            }
+        });
+        let fees = file
+            .fees
+            .map(|table| {
+                FeeSchedule::from_toml(&toml::to_string(&table)?).context("invalid [fees]")
+            })
+            .transpose()?;
+        let limits = file
+            .limits
+            .map(|table| {
+                WithdrawalLimits::from_toml(&toml::to_string(&table)?).context("invalid [limits]")
+            })
+            .transpose()?;
+        Ok(ConfigFile {
+            precision: file.precision,
+            rounding: value("rounding", file.rounding)?,
+            validation,
+            fees,
+            limits,
+            io: IoSettings {
+                report_format: value("report_format", file.io.report_format)?,
+                report_style: value("report_style", file.io.report_style)?,
+                on_error: value("on_error", file.io.on_error)?,
+                rejects: file.io.rejects,
+                audit: file.io.audit,
+                progress: value("progress", file.io.progress)?,
+                progress_interval: file.io.progress_interval,
+            },
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        Self::from_toml(&toml).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    /// Applies the engine settings to `config`, but those `overridden` by name, e.g. `precision`
+    /// given on the command line. The validation rules have no command line option.
+    pub fn configure(&self, config: &mut EngineConfig, overridden: impl Fn(&str) -> bool) {
+        if let Some(precision) = self.precision
+            && !overridden("precision")
+        {
+            config.amounts.precision = precision;
+        }
+        if let Some(rounding) = self.rounding
+            && !overridden("rounding")
+        {
+            config.amounts.rounding = rounding;
+        }
+        if let Some(validation) = &self.validation {
+            config.validation = validation.clone();
+        }
+        if self.fees.is_some() && !overridden("fees") {
+            config.fees = self.fees.clone();
+        }
+        if self.limits.is_some() && !overridden("limits") {
+            config.limits = self.limits.clone();
+        }
+    }
+}
+
+/// Parses a value of a command line enum, e.g. `half-up` for `Rounding::HalfUp`.
+fn value<E: ValueEnum>(key: &str, name: Option<String>) -> Result<Option<E>> {
+    name.map(|name| E::from_str(&name, false).map_err(|_| anyhow!("invalid {} {:?}", key, name)))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_file() {
+        let file = ConfigFile::from_toml(
+            "precision = 2\n\
+             rounding = \"half-up\"\n\
+             [validation]\n\
+             max_amount = \"100\"\n\
+             [fees]\n\
+             account = 9\n\
+             withdrawal = { flat = \"1\" }\n\
+             [io]\n\
+             on_error = \"collect\"\n\
+             rejects = \"rejects.csv\"\n",
+        )
+        .unwrap();
+        assert_eq!(file.io.on_error, Some(ErrorPolicy::Collect));
+        assert_eq!(file.io.rejects, Some(PathBuf::from("rejects.csv")));
+
+        // The command line precision wins, the rest comes from the file
+        let mut config = EngineConfig::default();
+        file.configure(&mut config, |option| option == "precision");
+        assert_eq!(config.amounts.precision, 4);
+        assert_eq!(config.amounts.rounding, Rounding::HalfUp);
+        assert!(config.validation.reject_zero_amounts);
+        assert_eq!(config.validation.max_amount, Some(Decimal::new(100, 0)));
+        assert_eq!(config.fees.unwrap().account, 9);
+        assert!(config.limits.is_none());
+
+        assert!(ConfigFile::from_toml("rounding = \"up\"\n").is_err());
+        assert!(ConfigFile::from_toml("[io]\nthreads = 4\n").is_err());
+        assert!(ConfigFile::from_toml("[fees]\naccount = 1\nrefund = { flat = \"1\" }\n").is_err());
+    }
+}
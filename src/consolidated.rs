@@ -0,0 +1,290 @@
+use std::{collections::BTreeMap, io, path::Path};
+
+use crate::decimal::Decimal;
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+use crate::{amount::AmountPolicy, client::Client, currency::Currency};
+
+/// Conversion rates into a base currency, as the amount of base currency one unit of a currency
+/// is worth. The base currency itself converts at one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rates {
+    base: Currency,
+    rates: BTreeMap<Currency, Decimal>,
+}
+
+#[derive(Deserialize)]
+struct RateRow {
+    currency: String,
+    rate: Decimal,
+}
+
+impl Rates {
+    pub fn new(base: Currency, rates: BTreeMap<Currency, Decimal>) -> Self {
+        Rates { base, rates }
+    }
+
+    /// Loads the rates from a CSV with `currency` and `rate` columns. Rates must be positive and a
+    /// row of the base currency, if any, must have a rate of one.
+    pub fn load<P: AsRef<Path>>(base: Currency, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .with_context(|| format!("failed to open rates file {}", path.display()))?;
+        let mut rates = BTreeMap::new();
+        for (line, row) in reader.deserialize::<RateRow>().enumerate() {
+            // Line 1 is the header
+            let row = row.with_context(|| format!("invalid rates row on line {}", line + 2))?;
+            let currency = row.currency.parse::<Currency>()?;
+            if row.rate <= Decimal::ZERO {
+                bail!("rate of {} must be positive, got {}", currency, row.rate);
+            }
+            if currency == base && row.rate != Decimal::ONE {
+                bail!(
+                    "rate of the base currency {} must be 1, got {}",
+                    base,
+                    row.rate
+                );
+            }
+            if rates.insert(currency, row.rate).is_some() {
+                bail!("duplicate rate of {}", currency);
+            }
+        }
+        Ok(Self::new(base, rates))
+    }
+
+    pub fn base(&self) -> Currency {
+        self.base
+    }
+
+    /// The amount in `currency` converted into the base currency.
+    pub fn convert(&self, amount: Decimal, currency: Currency) -> Result<Decimal> {
+        if currency == self.base {
+            return Ok(amount);
+        }
+        let rate = self
+            .rates
+            .get(&currency)
+            .with_context(|| format!("no rate of {} into {}", currency, self.base))?;
+        amount
+            .checked_mul(*rate)
+            .with_context(|| format!("{} {} overflows in {}", amount, currency, self.base))
+    }
+}
+
+/// The balances of all accounts of a client in the base currency.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsolidatedAccount {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// Converts every account into the base currency and sums them per client. The sums are rounded
+/// like the client report, the total being the rounded available plus held funds.
+pub fn consolidate<I: IntoIterator<Item = io::Result<Client>>>(
+    clients: I,
+    rates: &Rates,
+    amounts: &AmountPolicy,
+) -> Result<BTreeMap<u16, ConsolidatedAccount>> {
+    let mut accounts = BTreeMap::<u16, ConsolidatedAccount>::new();
+    for client in clients {
+        let client = client?;
+        let available = rates.convert(client.available, client.currency)?;
+        let held = rates.convert(client.held, client.currency)?;
+        let account = accounts.entry(client.id).or_default();
+        account.available = account
+            .available
+            .checked_add(available)
+            .with_context(|| format!("available funds of client {} overflow", client.id))?;
+        account.held = account
+            .held
+            .checked_add(held)
+            .with_context(|| format!("held funds of client {} overflow", client.id))?;
+        account.locked |= client.locked;
+    }
+    for (client_id, account) in accounts.iter_mut() {
+        account.available = amounts.round(account.available);
+        account.held = amounts.round(account.held);
+        account.total = account
+            .available
+            .checked_add(account.held)
+            .with_context(|| format!("total funds of client {} overflow", client_id))?;
+    }
+    Ok(accounts)
+}
+
+/// Writes one row per client with its balances in the base currency.
+pub fn write_report<W: io::Write>(
+    accounts: &BTreeMap<u16, ConsolidatedAccount>,
+    base: Currency,
+    writer: W,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["client", "currency", "available", "held", "total", "locked"])?;
+    for (client_id, account) in accounts {
+        writer.write_record([
+            client_id.to_string(),
+            base.to_string(),
+            account.available.to_string(),
+            account.held.to_string(),
+            account.total.to_string(),
+            account.locked.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        code.parse().unwrap()
+    }
+
+    fn rates() -> Rates {
+        Rates::new(
+            currency("USD"),
+            BTreeMap::from([
+                (currency("EUR"), Decimal::new(11, 1)),
+                (currency("JPY"), Decimal::new(7, 3)),
+            ]),
+        )
+    }
+
+    fn account(id: u16, code: &str, available: Decimal, held: Decimal) -> io::Result<Client> {
+        Ok(Client {
+            available,
+            held,
+            total: available.checked_add(held).unwrap(),
+            ..Client::with_currency(id, currency(code))
+        })
+    }
+
+    #[test]
+    fn test_consolidate() {
+        let clients = vec![
+            account(1, "EUR", Decimal::new(10, 0), Decimal::new(2, 0)),
+            account(1, "JPY", Decimal::new(1000, 0), Decimal::ZERO),
+            account(1, "USD", Decimal::new(55, 1), Decimal::ZERO),
+            account(2, "EUR", Decimal::new(-3, 0), Decimal::ZERO),
+            Ok(Client {
+                locked: true,
+                ..Client::with_currency(2, currency("USD"))
+            }),
+        ];
+        let accounts = consolidate(clients, &rates(), &AmountPolicy::default()).unwrap();
+        assert_eq!(
+            accounts,
+            BTreeMap::from([
+                (
+                    1,
+                    ConsolidatedAccount {
+                        // 11 + 7 + 5.5
+                        available: Decimal::new(235, 1),
+                        held: Decimal::new(22, 1),
+                        total: Decimal::new(257, 1),
+                        locked: false,
+                    }
+                ),
+                (
+                    2,
+                    ConsolidatedAccount {
+                        available: Decimal::new(-33, 1),
+                        held: Decimal::ZERO,
+                        total: Decimal::new(-33, 1),
+                        locked: true,
+                    }
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_write_report() {
+        let accounts = BTreeMap::from([
+            (
+                1,
+                ConsolidatedAccount {
+                    available: Decimal::new(235, 1),
+                    held: Decimal::new(22, 1),
+                    total: Decimal::new(257, 1),
+                    locked: false,
+                },
+            ),
+            (
+                2,
+                ConsolidatedAccount {
+                    available: Decimal::new(-33, 1),
+                    total: Decimal::new(-33, 1),
+                    locked: true,
+                    ..ConsolidatedAccount::default()
+                },
+            ),
+        ]);
+        let mut report = Vec::new();
+        write_report(&accounts, currency("EUR"), &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,EUR,23.5,2.2,25.7,false\n\
+             2,EUR,-3.3,0,-3.3,true\n"
+        );
+    }
+
+    #[test]
+    fn test_consolidate_rounding() {
+        // 0.3031 EUR are 0.33341 USD, the total is the rounded available plus held
+        let clients = vec![account(
+            1,
+            "EUR",
+            Decimal::new(3031, 4),
+            Decimal::new(3031, 4),
+        )];
+        let accounts = consolidate(clients, &rates(), &AmountPolicy::default()).unwrap();
+        let account = &accounts[&1];
+        assert_eq!(account.available, Decimal::new(3334, 4));
+        assert_eq!(account.total, Decimal::new(6668, 4));
+    }
+
+    #[test]
+    fn test_missing_rate() {
+        let clients = vec![account(1, "GBP", Decimal::ONE, Decimal::ZERO)];
+        let err = consolidate(clients, &rates(), &AmountPolicy::default()).unwrap_err();
+        assert_eq!(err.to_string(), "no rate of GBP into USD");
+    }
+
+    #[test]
+    fn test_load_rates() {
+        let path = std::env::temp_dir().join(format!("rates-{}.csv", std::process::id()));
+        std::fs::write(&path, "currency,rate\nEUR, 1.1\nJPY,0.007\nUSD,1\n").unwrap();
+        let loaded = Rates::load(currency("USD"), &path).unwrap();
+        assert_eq!(loaded.base(), currency("USD"));
+        assert_eq!(
+            loaded.convert(Decimal::TWO, currency("EUR")).unwrap(),
+            Decimal::new(22, 1)
+        );
+
+        for (rates, error) in [
+            (
+                "currency,rate\nEUR,0\n",
+                "rate of EUR must be positive, got 0",
+            ),
+            (
+                "currency,rate\nUSD,2\n",
+                "rate of the base currency USD must be 1, got 2",
+            ),
+            ("currency,rate\nEUR,1.1\nEUR,1.2\n", "duplicate rate of EUR"),
+        ] {
+            std::fs::write(&path, rates).unwrap();
+            let err = Rates::load(currency("USD"), &path).unwrap_err();
+            assert_eq!(err.to_string(), error);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Serialize, Serializer};
+
+/// Three-letter currency code such as `EUR`, stored inline so it is cheap to copy and compare.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+
+    /// Bounds of all currency codes, used for range lookups of a client's accounts.
+    pub(crate) const MIN: Currency = Currency([0; 3]);
+    pub(crate) const MAX: Currency = Currency([u8::MAX; 3]);
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are accepted on parse
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+
+    /// Decimal places of the currency's minor unit per ISO 4217, e.g. 2 for the cents of `USD`
+    /// and 0 for `JPY`. Codes missing from the exceptions have two.
+    pub fn minor_digits(&self) -> u32 {
+        match &self.0 {
+            b"BIF" | b"CLP" | b"DJF" | b"GNF" | b"ISK" | b"JPY" | b"KMF" | b"KRW" | b"PYG"
+            | b"RWF" | b"UGX" | b"UYI" | b"VND" | b"VUV" | b"XAF" | b"XOF" | b"XPF" => 0,
+            b"BHD" | b"IQD" | b"JOD" | b"KWD" | b"LYD" | b"OMR" | b"TND" => 3,
+            _ => 2,
+        }
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::USD
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CurrencyError {
+    InvalidCode,
+}
+
+impl Display for CurrencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CurrencyError::InvalidCode => write!(f, "Currency code must be three letters"),
+        }
+    }
+}
+
+impl std::error::Error for CurrencyError {}
+
+impl FromStr for Currency {
+    type Err = CurrencyError;
+
+    /// Parses a case-insensitive three-letter code, surrounding whitespace is ignored.
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 3] = code
+            .trim()
+            .as_bytes()
+            .try_into()
+            .map_err(|_| CurrencyError::InvalidCode)?;
+        if !code.iter().all(u8::is_ascii_alphabetic) {
+            return Err(CurrencyError::InvalidCode);
+        }
+        Ok(Currency(code.map(|c| c.to_ascii_uppercase())))
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Serialized as its code, like the `currency` column.
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currency_parsing() {
+        assert_eq!("eur".parse::<Currency>().unwrap().to_string(), "EUR");
+        assert_eq!(" USD ".parse::<Currency>(), Ok(Currency::USD));
+        assert_eq!("EURO".parse::<Currency>(), Err(CurrencyError::InvalidCode));
+        assert_eq!("E1R".parse::<Currency>(), Err(CurrencyError::InvalidCode));
+        assert_eq!("".parse::<Currency>(), Err(CurrencyError::InvalidCode));
+    }
+}
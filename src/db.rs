@@ -0,0 +1,258 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::{
+    client::Client, decimal::Decimal, dispute::DisputeState, engine::Engine,
+    export::ExportedTransaction, transaction::Transaction,
+};
+
+/// Version of the database layout, bumped on incompatible changes.
+const DB_VERSION: &str = "1";
+
+/// Amounts are `TEXT` so they keep their exact decimal value, cast them to `REAL` for
+/// aggregates. `state` is the dispute state, see `DisputeState`, or `pending` for a withdrawal
+/// hold. `dispute_hold` is the amount held by an `open` dispute or the amount taken by a
+/// `charged_back` one. `refunded` is the amount refunded from a deposit.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT
+    );
+    CREATE TABLE IF NOT EXISTS clients (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx INTEGER PRIMARY KEY,
+        type TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        amount TEXT,
+        currency TEXT NOT NULL,
+        state TEXT,
+        dispute_hold TEXT,
+        refunded TEXT
+    );
+";
+
+/// Creates the tables, adding the columns of later versions to a database saved before them.
+fn create_schema(connection: &Connection) -> Result<()> {
+    connection.execute_batch(SCHEMA)?;
+    let refunded: i64 = connection.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('transactions') WHERE name = 'refunded'",
+        [],
+        |row| row.get(0),
+    )?;
+    if refunded == 0 {
+        connection.execute_batch("ALTER TABLE transactions ADD COLUMN refunded TEXT")?;
+    }
+    Ok(())
+}
+
+/// Restores the state saved by `save` into an empty engine. A database without saved state,
+/// e.g. a new file, restores nothing. The state of another ledger is rejected.
+pub fn load<P: AsRef<Path>>(engine: &mut Engine, path: P) -> Result<()> {
+    let path = path.as_ref();
+    let connection = Connection::open(path)
+        .with_context(|| format!("failed to open database {}", path.display()))?;
+    create_schema(&connection)?;
+    let meta = |key: &str| -> Result<Option<Option<String>>> {
+        Ok(connection
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    };
+    let Some(version) = meta("version")? else {
+        return Ok(());
+    };
+    if version.as_deref() != Some(DB_VERSION) {
+        bail!("unsupported database version {:?}", version);
+    }
+    let ledger = engine
+        .config()
+        .ledger
+        .as_ref()
+        .map(|ledger| ledger.as_str());
+    let saved_ledger = meta("ledger")?.flatten();
+    if saved_ledger.as_deref() != ledger {
+        bail!(
+            "database belongs to ledger {:?}, not {:?}",
+            saved_ledger,
+            ledger
+        );
+    }
+    if engine.clients().next().is_some() || engine.transactions().next().is_some() {
+        bail!("a database can only be loaded into an empty engine");
+    }
+
+    let mut select = connection.prepare("SELECT * FROM clients ORDER BY client, currency")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let client = Client {
+            available: row.get::<_, String>(2)?.parse()?,
+            held: row.get::<_, String>(3)?.parse()?,
+            total: row.get::<_, String>(4)?.parse()?,
+            locked: row.get(5)?,
+            ..Client::with_currency(row.get(0)?, row.get::<_, String>(1)?.parse()?)
+        };
+        engine.restore_account(client)?;
+    }
+
+    let mut select = connection.prepare("SELECT * FROM transactions ORDER BY tx")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let tx_id: u32 = row.get(0)?;
+        let amount = match row.get::<_, Option<String>>(3)? {
+            Some(amount) => amount.parse()?,
+            None => Decimal::ZERO,
+        };
+        let transaction = Transaction::new(&row.get::<_, String>(1)?, row.get(2)?, tx_id, amount)?;
+        let state: Option<String> = row.get(5)?;
+        let dispute_amount = row
+            .get::<_, Option<String>>(6)?
+            .map(|hold| hold.parse())
+            .transpose()?;
+        let (dispute, withdrawal_pending) = match state.as_deref() {
+            None => (DisputeState::None, false),
+            Some("pending") => (DisputeState::None, true),
+            Some(state) => (state.parse()?, false),
+        };
+        let refunded = row
+            .get::<_, Option<String>>(7)?
+            .map(|refunded| refunded.parse())
+            .transpose()?;
+        let entry = ExportedTransaction {
+            transaction,
+            currency: row.get::<_, String>(4)?.parse()?,
+            dispute,
+            dispute_amount,
+            withdrawal_pending,
+            refunded,
+        };
+        engine
+            .restore_transaction(&entry)
+            .with_context(|| format!("invalid transaction {} in database", tx_id))?;
+    }
+    Ok(())
+}
+
+/// Saves the accounts and the transaction log with their dispute states, replacing the state
+/// saved before, in a single SQLite transaction.
+pub fn save<P: AsRef<Path>>(engine: &Engine, path: P) -> Result<()> {
+    let path = path.as_ref();
+    let mut connection = Connection::open(path)
+        .with_context(|| format!("failed to open database {}", path.display()))?;
+    create_schema(&connection)?;
+    let tx = connection.transaction()?;
+    tx.execute_batch("DELETE FROM meta; DELETE FROM clients; DELETE FROM transactions;")?;
+    {
+        let mut insert = tx.prepare("INSERT INTO meta VALUES (?1, ?2)")?;
+        insert.execute(params!["version", DB_VERSION])?;
+        let ledger = engine
+            .config()
+            .ledger
+            .as_ref()
+            .map(|ledger| ledger.as_str());
+        insert.execute(params!["ledger", ledger])?;
+
+        let mut insert = tx.prepare("INSERT INTO clients VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        for client in engine.clients() {
+            let client = client?;
+            insert.execute(params![
+                client.id,
+                client.currency.as_str(),
+                client.available.to_string(),
+                client.held.to_string(),
+                client.total.to_string(),
+                client.locked,
+            ])?;
+        }
+
+        let mut insert =
+            tx.prepare("INSERT INTO transactions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+        for entry in engine.transactions() {
+            let (transaction, currency) = entry?;
+            let entry = engine.transaction_state(transaction, currency);
+            let state = match entry.dispute {
+                DisputeState::None if entry.withdrawal_pending => Some("pending".to_string()),
+                DisputeState::None => None,
+                state => Some(state.to_string()),
+            };
+            insert.execute(params![
+                entry.transaction.tx_id(),
+                entry.transaction.transaction_type().to_string(),
+                entry.transaction.client_id(),
+                entry.transaction.amount().map(|amount| amount.to_string()),
+                entry.currency.as_str(),
+                state,
+                entry.dispute_amount.map(|amount| amount.to_string()),
+                entry.refunded.map(|amount| amount.to_string()),
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::EngineConfig;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("payments-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut engine = Engine::new();
+        for tx_id in 1..=3 {
+            engine
+                .execute(Transaction::Deposit(1, tx_id, Decimal::new(15, 1)))
+                .unwrap();
+        }
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Dispute(1, 2)).unwrap();
+        engine.execute(Transaction::Resolve(1, 2)).unwrap();
+        save(&engine, &path).unwrap();
+
+        // Restarted on the database, the dispute states carry over
+        let mut restored = Engine::new();
+        load(&mut restored, &path).unwrap();
+        assert_eq!(restored.client(1), engine.client(1));
+        assert!(restored.execute(Transaction::Resolve(1, 1)).is_ok());
+        assert!(restored.execute(Transaction::Dispute(1, 2)).is_err());
+        assert!(
+            restored
+                .execute(Transaction::Deposit(1, 3, Decimal::ONE))
+                .is_err()
+        );
+        save(&restored, &path).unwrap();
+        let mut reloaded = Engine::new();
+        load(&mut reloaded, &path).unwrap();
+        assert_eq!(reloaded.client(1).unwrap().held, Decimal::ZERO);
+
+        let connection = Connection::open(&path).unwrap();
+        let total: f64 = connection
+            .query_row("SELECT SUM(CAST(total AS REAL)) FROM clients", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(total, 4.5);
+        drop(connection);
+
+        let mut other_ledger = Engine::with_config(EngineConfig {
+            ledger: Some("eu".parse().unwrap()),
+            ..EngineConfig::default()
+        });
+        assert!(load(&mut other_ledger, &path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,318 @@
+//! Money type of all amounts and balances, selected at compile time.
+//!
+//! By default amounts are `rust_decimal::Decimal`: 96-bit decimals that keep the scale of their
+//! input. The `fixed-point` feature swaps in `FixedPoint`, a 128-bit integer count of
+//! ten-thousandths that trades the flexible scale for cheaper arithmetic and a wider range.
+//! Either type converts from and to text only where rows are parsed and reports are written, so
+//! the engine is the same for both.
+
+#[cfg(not(feature = "fixed-point"))]
+pub use rust_decimal::{Decimal, RoundingStrategy, prelude::ToPrimitive};
+
+#[cfg(feature = "fixed-point")]
+pub use fixed::{FixedPoint as Decimal, RoundingStrategy, ToPrimitive};
+
+#[cfg(feature = "fixed-point")]
+mod fixed {
+    use std::{fmt::Display, ops::Neg, str::FromStr};
+
+    use serde::{Deserialize, Deserializer, de};
+
+    /// Decimal places of every `FixedPoint` amount.
+    const SCALE: u32 = 4;
+    const UNIT: i128 = 10_i128.pow(SCALE);
+
+    /// Amount with four decimal places, stored as a count of ten-thousandths. Mirrors the parts of
+    /// the `rust_decimal::Decimal` API the engine uses, so the two can be swapped. There are no
+    /// `+` and `-` operators, arithmetic goes through `checked_*` and `saturating_*` instead.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct FixedPoint(i128);
+
+    /// The rounding modes of `rust_decimal::RoundingStrategy` the engine uses.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum RoundingStrategy {
+        MidpointNearestEven,
+        MidpointAwayFromZero,
+        ToZero,
+    }
+
+    /// Conversion to floating point, for the SQL view.
+    pub trait ToPrimitive {
+        fn to_f64(&self) -> Option<f64>;
+    }
+
+    impl ToPrimitive for FixedPoint {
+        fn to_f64(&self) -> Option<f64> {
+            Some(self.0 as f64 / UNIT as f64)
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ParseError(&'static str);
+
+    impl Display for ParseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Invalid amount: {}", self.0)
+        }
+    }
+
+    impl std::error::Error for ParseError {}
+
+    /// Divides by a power of ten, rounding half to even like `Decimal::round_dp`.
+    fn div_round(value: i128, digits: u32) -> i128 {
+        div_round_with(value, digits, RoundingStrategy::MidpointNearestEven)
+    }
+
+    fn div_round_with(value: i128, digits: u32, strategy: RoundingStrategy) -> i128 {
+        if digits == 0 {
+            return value;
+        }
+        let divisor = 10_i128.pow(digits);
+        let (quotient, remainder) = (value / divisor, (value % divisor).abs());
+        let half = divisor / 2;
+        let away = match strategy {
+            RoundingStrategy::MidpointNearestEven => {
+                remainder > half || (remainder == half && quotient % 2 != 0)
+            }
+            RoundingStrategy::MidpointAwayFromZero => remainder >= half,
+            RoundingStrategy::ToZero => false,
+        };
+        if away {
+            quotient + value.signum()
+        } else {
+            quotient
+        }
+    }
+
+    impl FixedPoint {
+        pub const ZERO: FixedPoint = FixedPoint(0);
+        pub const ONE: FixedPoint = FixedPoint(UNIT);
+        pub const TWO: FixedPoint = FixedPoint(2 * UNIT);
+        /// Symmetric with `MIN`, so negation never overflows.
+        pub const MAX: FixedPoint = FixedPoint(i128::MAX);
+        pub const MIN: FixedPoint = FixedPoint(-i128::MAX);
+
+        /// `num` scaled down by `scale` decimal places, rounded to four places.
+        pub fn new(num: i64, scale: u32) -> Self {
+            let num = num as i128;
+            if scale <= SCALE {
+                FixedPoint(num * 10_i128.pow(SCALE - scale))
+            } else {
+                FixedPoint(div_round(num, scale - SCALE))
+            }
+        }
+
+        fn checked(value: Option<i128>) -> Option<Self> {
+            value.filter(|value| *value != i128::MIN).map(FixedPoint)
+        }
+
+        pub fn checked_add(self, other: Self) -> Option<Self> {
+            Self::checked(self.0.checked_add(other.0))
+        }
+
+        pub fn checked_sub(self, other: Self) -> Option<Self> {
+            Self::checked(self.0.checked_sub(other.0))
+        }
+
+        /// Product truncated to four places.
+        pub fn checked_mul(self, other: Self) -> Option<Self> {
+            Self::checked(self.0.checked_mul(other.0).map(|product| product / UNIT))
+        }
+
+        pub fn saturating_add(self, other: Self) -> Self {
+            self.checked_add(other)
+                .unwrap_or(if other.0 < 0 { Self::MIN } else { Self::MAX })
+        }
+
+        pub fn saturating_sub(self, other: Self) -> Self {
+            self.saturating_add(-other)
+        }
+
+        /// Always four, sums never drop digits.
+        pub fn scale(&self) -> u32 {
+            SCALE
+        }
+
+        pub fn is_zero(&self) -> bool {
+            self.0 == 0
+        }
+
+        pub fn is_sign_negative(&self) -> bool {
+            self.0 < 0
+        }
+
+        pub fn is_sign_positive(&self) -> bool {
+            self.0 >= 0
+        }
+
+        pub fn abs(&self) -> Self {
+            FixedPoint(self.0.abs())
+        }
+
+        /// Rounds half to even to `dp` decimal places.
+        pub fn round_dp(&self, dp: u32) -> Self {
+            self.round_dp_with_strategy(dp, RoundingStrategy::MidpointNearestEven)
+        }
+
+        /// Rounds to `dp` decimal places, a no-op from four places up.
+        pub fn round_dp_with_strategy(&self, dp: u32, strategy: RoundingStrategy) -> Self {
+            if dp >= SCALE {
+                return *self;
+            }
+            let digits = SCALE - dp;
+            FixedPoint(div_round_with(self.0, digits, strategy) * 10_i128.pow(digits))
+        }
+
+        /// Fixed-size representation for binary storage such as the disk transaction log.
+        pub fn serialize(&self) -> [u8; 16] {
+            self.0.to_be_bytes()
+        }
+
+        pub fn deserialize(bytes: [u8; 16]) -> Self {
+            FixedPoint(i128::from_be_bytes(bytes))
+        }
+    }
+
+    impl Display for FixedPoint {
+        /// Prints the significant digits only, e.g. `1.5` rather than `1.5000`.
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let sign = if self.0 < 0 { "-" } else { "" };
+            let (units, fraction) = (
+                self.0.unsigned_abs() / UNIT as u128,
+                self.0.unsigned_abs() % UNIT as u128,
+            );
+            if fraction == 0 {
+                return write!(f, "{}{}", sign, units);
+            }
+            let fraction = format!("{:04}", fraction);
+            write!(f, "{}{}.{}", sign, units, fraction.trim_end_matches('0'))
+        }
+    }
+
+    impl FromStr for FixedPoint {
+        type Err = ParseError;
+
+        /// Parses `[-+]digits[.digits]`, rounding half to even beyond four decimal places.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (negative, digits) = match s.as_bytes().first() {
+                Some(b'-') => (true, &s[1..]),
+                Some(b'+') => (false, &s[1..]),
+                _ => (false, s),
+            };
+            let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+            if integer.is_empty() && fraction.is_empty() {
+                return Err(ParseError("no digits"));
+            }
+            if !integer
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+            {
+                return Err(ParseError("not a decimal number"));
+            }
+            let overflow = || ParseError("out of range");
+            let mut value: i128 = 0;
+            for digit in integer.bytes().chain(fraction.bytes().take(SCALE as usize)) {
+                value = value
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add((digit - b'0') as i128))
+                    .ok_or_else(overflow)?;
+            }
+            let places = fraction.len().min(SCALE as usize) as u32;
+            value = value
+                .checked_mul(10_i128.pow(SCALE - places))
+                .ok_or_else(overflow)?;
+            // Rounds on the first dropped digit, looking further only to break a tie
+            let mut dropped = fraction.bytes().skip(SCALE as usize);
+            if let Some(first) = dropped.next() {
+                let rest_nonzero = dropped.any(|b| b != b'0');
+                if first > b'5' || (first == b'5' && (rest_nonzero || value % 2 != 0)) {
+                    value = value.checked_add(1).ok_or_else(overflow)?;
+                }
+            }
+            Ok(FixedPoint(if negative { -value } else { value }))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for FixedPoint {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct Visitor;
+
+            impl de::Visitor<'_> for Visitor {
+                type Value = FixedPoint;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    write!(f, "a decimal amount")
+                }
+
+                fn visit_str<E: de::Error>(self, value: &str) -> Result<FixedPoint, E> {
+                    value.trim().parse().map_err(E::custom)
+                }
+            }
+
+            // Read as text, a float would lose digits
+            deserializer.deserialize_str(Visitor)
+        }
+    }
+
+    impl Neg for FixedPoint {
+        type Output = FixedPoint;
+
+        fn neg(self) -> FixedPoint {
+            FixedPoint(-self.0)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_fixed_point_text() {
+            let parse = |s: &str| s.parse::<FixedPoint>().unwrap();
+            assert_eq!(parse("1.5"), FixedPoint::new(15, 1));
+            assert_eq!(parse("1.5").to_string(), "1.5");
+            assert_eq!(parse("-0.0001").to_string(), "-0.0001");
+            assert_eq!(parse("+3.").to_string(), "3");
+            assert_eq!(parse(".25"), FixedPoint::new(25, 2));
+            // Half to even beyond four places, like `round_dp`
+            assert_eq!(parse("0.00005"), FixedPoint::ZERO);
+            assert_eq!(parse("0.00015"), FixedPoint::new(2, 4));
+            assert_eq!(parse("0.000050001"), FixedPoint::new(1, 4));
+            assert_eq!(FixedPoint::new(15, 5), FixedPoint::new(2, 4));
+            for invalid in ["", "-", ".", "1e5", "1.2.3", "abc", "1 000"] {
+                assert!(invalid.parse::<FixedPoint>().is_err(), "{:?}", invalid);
+            }
+            assert!("1".repeat(40).parse::<FixedPoint>().is_err());
+        }
+
+        #[test]
+        fn test_fixed_point_arithmetic() {
+            assert_eq!(FixedPoint::MAX.checked_add(FixedPoint::new(1, 4)), None);
+            assert_eq!(FixedPoint::MIN.checked_sub(FixedPoint::new(1, 4)), None);
+            assert_eq!(
+                FixedPoint::MAX.saturating_add(FixedPoint::ONE),
+                FixedPoint::MAX
+            );
+            assert_eq!(FixedPoint::new(25, 1).round_dp(0), FixedPoint::TWO);
+            assert_eq!(FixedPoint::new(-35, 1).round_dp(0), FixedPoint::new(-4, 0));
+            let amount = FixedPoint::new(-125, 2);
+            for (strategy, rounded) in [
+                (RoundingStrategy::MidpointNearestEven, -12),
+                (RoundingStrategy::MidpointAwayFromZero, -13),
+                (RoundingStrategy::ToZero, -12),
+            ] {
+                assert_eq!(
+                    amount.round_dp_with_strategy(1, strategy),
+                    FixedPoint::new(rounded, 1)
+                );
+            }
+            assert_eq!(
+                FixedPoint::new(-129, 2).round_dp_with_strategy(1, RoundingStrategy::ToZero),
+                FixedPoint::new(-12, 1)
+            );
+            let amount = FixedPoint::new(-12345, 2);
+            assert_eq!(FixedPoint::deserialize(amount.serialize()), amount);
+        }
+    }
+}
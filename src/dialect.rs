@@ -0,0 +1,229 @@
+use std::{collections::BTreeMap, io::Read};
+
+use anyhow::Result;
+use csv::StringRecord;
+
+use crate::source::{COLUMNS, Records, SourceError};
+
+/// Layout of CSV transaction input: the field delimiter, whether the first record is a header, the
+/// header names of the columns and whether whitespace around fields is trimmed.
+///
+/// With a header, columns are found by their name, trimmed and in any case (`ttype` for `type`),
+/// in any order and among other columns. A header without the `type`, `client` and `tx` columns is
+/// read by position like a headerless input, in the order of `source::COLUMNS`, unless column names
+/// are given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub has_headers: bool,
+    /// Header names of the columns named differently, by column, e.g. `type` is `ttype`.
+    pub columns: BTreeMap<String, String>,
+    /// Trim whitespace around headers and fields, e.g. of `deposit, 1, 1, 1.0`.
+    pub trim: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: b',',
+            has_headers: true,
+            columns: BTreeMap::new(),
+            trim: true,
+        }
+    }
+}
+
+impl CsvDialect {
+    /// Reads CSV in this dialect and returns its header with an iterator over its records, both in
+    /// the order of `source::COLUMNS`. A record that isn't valid UTF-8 is an error wrapping a
+    /// `SourceError::Invalid`, the records after it are still read.
+    pub fn records<R: Read + Send + 'static>(&self, reader: R) -> Result<(StringRecord, Records)> {
+        if let Some(column) = self.columns.keys().find(|c| !COLUMNS.contains(&c.as_str())) {
+            anyhow::bail!(
+                "Unknown column {}, expected one of {}",
+                column,
+                COLUMNS.join(", ")
+            );
+        }
+        // Ragged rows are read as they are, e.g. dispute rows without the trailing amount field:
+        // missing columns are empty and fields past the last column are ignored
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .flexible(true)
+            .trim(if self.trim {
+                csv::Trim::All
+            } else {
+                csv::Trim::None
+            })
+            .from_reader(reader);
+        let headers = if self.has_headers {
+            reader.headers()?.clone()
+        } else if !self.columns.is_empty() {
+            anyhow::bail!("Column names require a header");
+        } else {
+            StringRecord::from(&COLUMNS[..])
+        };
+        let records = reader.into_byte_records().map(|rec| {
+            StringRecord::from_byte_record(rec?).map_err(|err| {
+                // Only this record is unreadable, the input goes on with the next one
+                let reason = err.utf8_error().to_string();
+                let record = err.into_byte_record();
+                SourceError::Invalid {
+                    record: record.iter().map(String::from_utf8_lossy).collect(),
+                    reason,
+                }
+                .into()
+            })
+        });
+        match self.positions(&headers)? {
+            Some(positions) => {
+                let headers = StringRecord::from(&COLUMNS[..positions.len()]);
+                let records = records.map(move |rec| rec.map(|rec| project(&positions, &rec)));
+                Ok((headers, Box::new(records)))
+            }
+            None => Ok((headers, Box::new(records))),
+        }
+    }
+
+    /// Positions of the columns in the header, up to the last one present. `None` if the records
+    /// are read as they are, the header is in the column order or isn't known.
+    fn positions(&self, headers: &StringRecord) -> Result<Option<Vec<Option<usize>>>> {
+        let mut positions: Vec<Option<usize>> = COLUMNS
+            .iter()
+            .map(|column| {
+                let names = match self.columns.get(*column) {
+                    Some(name) => vec![name.as_str()],
+                    None => aliases(column),
+                };
+                headers.iter().position(|header| {
+                    names
+                        .iter()
+                        .any(|name| header.trim().eq_ignore_ascii_case(name))
+                })
+            })
+            .collect();
+        if let Some(missing) = (0..3).find(|&idx| positions[idx].is_none()) {
+            if self.columns.is_empty() {
+                return Ok(None);
+            }
+            let column = COLUMNS[missing];
+            anyhow::bail!(
+                "The header has no {} column",
+                self.columns.get(column).map_or(column, String::as_str)
+            );
+        }
+        while positions.last() == Some(&None) {
+            positions.pop();
+        }
+        let in_order = positions
+            .iter()
+            .enumerate()
+            .all(|(idx, position)| *position == Some(idx));
+        Ok((!in_order).then_some(positions))
+    }
+}
+
+/// Header names a column is found by without a name given, `ttype` is the field name of the type
+/// in older exports.
+fn aliases(column: &'static str) -> Vec<&'static str> {
+    match column {
+        "type" => vec!["type", "ttype"],
+        _ => vec![column],
+    }
+}
+
+/// The fields of `record` at `positions`, empty for the columns the input doesn't have.
+fn project(positions: &[Option<usize>], record: &StringRecord) -> StringRecord {
+    positions
+        .iter()
+        .map(|position| position.and_then(|idx| record.get(idx)).unwrap_or(""))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Transaction, TransactionRow};
+
+    fn read(
+        dialect: &CsvDialect,
+        input: &'static str,
+    ) -> Result<(StringRecord, Vec<StringRecord>)> {
+        let (headers, records) = dialect.records(input.as_bytes())?;
+        Ok((headers, records.collect::<Result<_>>()?))
+    }
+
+    #[test]
+    fn test_csv_dialect() {
+        // Whitespace around fields is trimmed unless disabled
+        let input = "type, client, tx, amount\n deposit ,1,  1\t, 1.0\n";
+        let (headers, records) = read(&CsvDialect::default(), input).unwrap();
+        assert_eq!(headers, StringRecord::from(&COLUMNS[..4]));
+        assert_eq!(
+            records[0],
+            StringRecord::from(vec!["deposit", "1", "1", "1.0"])
+        );
+        let dialect = CsvDialect {
+            trim: false,
+            ..CsvDialect::default()
+        };
+        let (_, records) = read(&dialect, input).unwrap();
+        assert_eq!(&records[0][0], " deposit ");
+
+        let dialect = CsvDialect {
+            delimiter: b';',
+            columns: BTreeMap::from([("type".to_string(), "ttype".to_string())]),
+            ..CsvDialect::default()
+        };
+        let (headers, records) =
+            read(&dialect, "Amount;note;TX;Client;ttype\n1.5;x;7;2;deposit\n").unwrap();
+        assert_eq!(
+            headers,
+            StringRecord::from(vec!["type", "client", "tx", "amount"])
+        );
+        assert_eq!(
+            records[0],
+            StringRecord::from(vec!["deposit", "2", "7", "1.5"])
+        );
+        assert!(read(&dialect, "type;client;tx\n").is_err());
+
+        let dialect = CsvDialect {
+            has_headers: false,
+            ..CsvDialect::default()
+        };
+        let (headers, records) = read(&dialect, "deposit,1,1,1.0\n").unwrap();
+        assert_eq!(headers.len(), COLUMNS.len());
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn test_csv_dialect_ragged_rows() {
+        let input = "deposit,1,1,1.0\ndispute,1,1\ndeposit,1,2,2.0,1700000000,EUR\n";
+        let dialect = CsvDialect {
+            has_headers: false,
+            ..CsvDialect::default()
+        };
+        let (_, records) = read(&dialect, input).unwrap();
+        assert_eq!(
+            records.iter().map(StringRecord::len).collect::<Vec<_>>(),
+            [4, 3, 6]
+        );
+        let row: TransactionRow = records[1].deserialize(None).unwrap();
+        assert_eq!(row.transaction, Transaction::Dispute(1, 1));
+
+        // Columns found by name are empty where a row is short
+        let (headers, records) = read(
+            &CsvDialect::default(),
+            "client,type,tx,amount\n1,deposit,1,1.0\n1,dispute,1\n",
+        )
+        .unwrap();
+        assert_eq!(headers, StringRecord::from(&COLUMNS[..4]));
+        assert_eq!(
+            records[1],
+            StringRecord::from(vec!["dispute", "1", "1", ""])
+        );
+        let row: TransactionRow = records[1].deserialize(None).unwrap();
+        assert_eq!(row.transaction, Transaction::Dispute(1, 1));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+};
+
+use anyhow::Result;
+
+use crate::{
+    client::Client, config::EngineConfig, currency::Currency, decimal::Decimal, engine::Engine,
+    report::read_report, snapshot::read_snapshot,
+};
+
+/// Change of an account between two engine states, `None` on the side without the account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountDiff {
+    pub client_id: u16,
+    pub currency: Currency,
+    pub before: Option<Client>,
+    pub after: Option<Client>,
+}
+
+impl AccountDiff {
+    /// Change of the available, held and total funds, a missing account counting as empty.
+    pub fn deltas(&self) -> [Decimal; 3] {
+        let balances = |client: &Option<Client>| {
+            client.as_ref().map_or([Decimal::ZERO; 3], |client| {
+                [client.available, client.held, client.total]
+            })
+        };
+        let (before, after) = (balances(&self.before), balances(&self.after));
+        [0, 1, 2].map(|idx| after[idx].saturating_sub(before[idx]))
+    }
+
+    /// The account is locked after but wasn't before.
+    pub fn newly_locked(&self) -> bool {
+        let locked = |client: &Option<Client>| client.as_ref().is_some_and(|client| client.locked);
+        !locked(&self.before) && locked(&self.after)
+    }
+}
+
+/// Reads the accounts of a snapshot, as written by `snapshot::write_snapshot`, or of a CSV client
+/// report, told apart by the snapshot's first record. A snapshot is restored into an engine of
+/// `config`, which must be of the snapshot's ledger.
+pub fn read_accounts<R: BufRead>(mut reader: R, config: &EngineConfig) -> Result<Vec<Client>> {
+    if !reader.fill_buf()?.starts_with(b"snapshot,") {
+        return read_report(reader, config.default_currency);
+    }
+    let mut engine = Engine::with_config(config.clone());
+    read_snapshot(&mut engine, reader)?;
+    Ok(engine.clients().collect::<Result<_, _>>()?)
+}
+
+/// Compares two states of the accounts and returns the accounts whose balances, lock or closure
+/// changed, ordered by client id and currency.
+pub fn diff_accounts(before: &[Client], after: &[Client]) -> Vec<AccountDiff> {
+    let mut accounts: BTreeMap<(u16, Currency), AccountDiff> = BTreeMap::new();
+    for (client, is_after) in before
+        .iter()
+        .map(|client| (client, false))
+        .chain(after.iter().map(|client| (client, true)))
+    {
+        let diff = accounts
+            .entry((client.id, client.currency))
+            .or_insert_with(|| AccountDiff {
+                client_id: client.id,
+                currency: client.currency,
+                before: None,
+                after: None,
+            });
+        let side = if is_after {
+            &mut diff.after
+        } else {
+            &mut diff.before
+        };
+        *side = Some(client.clone());
+    }
+    accounts
+        .into_values()
+        .filter(|diff| {
+            let state = |client: &Option<Client>| {
+                client.as_ref().map(|client| {
+                    (
+                        client.available,
+                        client.held,
+                        client.total,
+                        client.locked,
+                        client.closed,
+                    )
+                })
+            };
+            state(&diff.before) != state(&diff.after)
+        })
+        .collect()
+}
+
+/// Writes the changed accounts as CSV with a
+/// `client,currency,available,held,total,newly_locked,status` header: the balance deltas, whether
+/// the account got locked and `new`, `removed`, `closed` or `changed`.
+pub fn write_diff<W: Write>(writer: W, diffs: &[AccountDiff]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "client",
+        "currency",
+        "available",
+        "held",
+        "total",
+        "newly_locked",
+        "status",
+    ])?;
+    for diff in diffs {
+        let [available, held, total] = diff.deltas();
+        let status = match (&diff.before, &diff.after) {
+            (None, _) => "new",
+            (_, None) => "removed",
+            (Some(before), Some(after)) if after.closed && !before.closed => "closed",
+            _ => "changed",
+        };
+        writer.write_record([
+            diff.client_id.to_string().as_str(),
+            diff.currency.to_string().as_str(),
+            available.to_string().as_str(),
+            held.to_string().as_str(),
+            total.to_string().as_str(),
+            diff.newly_locked().to_string().as_str(),
+            status,
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{snapshot::write_snapshot, transaction::Transaction};
+
+    #[test]
+    fn test_diff_accounts() {
+        let mut engine = Engine::new();
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            Transaction::Deposit(2, 2, Decimal::new(5, 0)),
+            Transaction::Deposit(3, 3, Decimal::new(1, 0)),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let mut snapshot = Vec::new();
+        write_snapshot(&engine, &mut snapshot).unwrap();
+        let before = read_accounts(snapshot.as_slice(), engine.config()).unwrap();
+        assert_eq!(before.len(), 3);
+
+        for transaction in [
+            Transaction::Withdrawal(1, 4, Decimal::new(25, 1)),
+            Transaction::Dispute(2, 2),
+            Transaction::Chargeback(2, 2),
+            Transaction::Deposit(4, 5, Decimal::ONE),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let report = "client,available,held,total,locked\n\
+                      1,7.5,0,7.5,false\n\
+                      2,0,0,0,true\n\
+                      3,1,0,1,false\n\
+                      4,1,0,1,false\n";
+        let after = read_accounts(report.as_bytes(), engine.config()).unwrap();
+
+        let diffs = diff_accounts(&before, &after);
+        assert_eq!(
+            diffs.iter().map(|diff| diff.client_id).collect::<Vec<_>>(),
+            [1, 2, 4]
+        );
+        let mut output = Vec::new();
+        write_diff(&mut output, &diffs).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,currency,available,held,total,newly_locked,status\n\
+             1,USD,-2.5,0,-2.5,false,changed\n\
+             2,USD,-5,0,-5,true,changed\n\
+             4,USD,1,0,1,false,new\n"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use crate::{decimal::Decimal, engine::ExecutionError};
+
+/// Where a deposit is in the dispute lifecycle:
+///
+/// ```text
+/// None --dispute--> Open --resolve--> Resolved
+///                   Open --chargeback--> ChargedBack --chargeback_reversal--> Reversed
+/// ```
+///
+/// `Resolved`, `ChargedBack` and `Reversed` are closed, a closed dispute can't be opened again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DisputeState {
+    /// Never disputed.
+    #[default]
+    None,
+    Open,
+    Resolved,
+    ChargedBack,
+    /// Charged back, then re-credited by a chargeback reversal.
+    Reversed,
+}
+
+/// A step of the dispute lifecycle, taken by a dispute, resolve, chargeback or chargeback
+/// reversal row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeAction {
+    Open,
+    Resolve,
+    Chargeback,
+    Reverse,
+}
+
+impl DisputeState {
+    /// The state after `action`, or the error rejecting the action in this state.
+    pub fn next(self, action: DisputeAction) -> Result<DisputeState, ExecutionError> {
+        match (self, action) {
+            (DisputeState::None, DisputeAction::Open) => Ok(DisputeState::Open),
+            (DisputeState::Open, DisputeAction::Resolve) => Ok(DisputeState::Resolved),
+            (DisputeState::Open, DisputeAction::Chargeback) => Ok(DisputeState::ChargedBack),
+            (DisputeState::ChargedBack, DisputeAction::Reverse) => Ok(DisputeState::Reversed),
+            (_, DisputeAction::Reverse) => Err(ExecutionError::NotChargedBack),
+            (state, _) if state.is_closed() => Err(ExecutionError::DisputeClosed),
+            (DisputeState::Open, _) => Err(ExecutionError::AlreadyDisputedTransaction),
+            (_, _) => Err(ExecutionError::NonDisputedTransaction),
+        }
+    }
+
+    pub fn is_closed(self) -> bool {
+        matches!(
+            self,
+            DisputeState::Resolved | DisputeState::ChargedBack | DisputeState::Reversed
+        )
+    }
+}
+
+impl Display for DisputeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DisputeState::None => "none",
+            DisputeState::Open => "open",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+            DisputeState::Reversed => "reversed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub struct DisputeStateError(String);
+
+impl Display for DisputeStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown dispute state {:?}", self.0)
+    }
+}
+
+impl std::error::Error for DisputeStateError {}
+
+impl FromStr for DisputeState {
+    type Err = DisputeStateError;
+
+    /// Parses the state names, and `closed` of state saved before charged-back and reversed
+    /// disputes were told apart as resolved.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DisputeState::None),
+            "open" => Ok(DisputeState::Open),
+            "resolved" | "closed" => Ok(DisputeState::Resolved),
+            "charged_back" => Ok(DisputeState::ChargedBack),
+            "reversed" => Ok(DisputeState::Reversed),
+            _ => Err(DisputeStateError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Dispute {
+    state: DisputeState,
+    /// Held while open, taken while charged back.
+    amount: Decimal,
+}
+
+/// Dispute states of the disputed transactions with their amounts, and the age of the open
+/// disputes for expiry.
+#[derive(Debug, Default)]
+pub(crate) struct Disputes {
+    disputes: BTreeMap<u32, Dispute>,
+    /// When each open dispute was opened, in the unit of the dispute aging.
+    opened: BTreeMap<u32, u64>,
+    /// Open disputes that can still expire, ordered by when they were opened.
+    by_age: BTreeSet<(u64, u32)>,
+}
+
+impl Disputes {
+    pub fn state(&self, tx_id: u32) -> DisputeState {
+        self.disputes
+            .get(&tx_id)
+            .map_or(DisputeState::None, |dispute| dispute.state)
+    }
+
+    /// Checks that the transaction's dispute can take `action`, without changing it.
+    pub fn check(&self, tx_id: u32, action: DisputeAction) -> Result<(), ExecutionError> {
+        self.state(tx_id).next(action).map(|_| ())
+    }
+
+    /// Amount held for an open dispute.
+    pub fn hold(&self, tx_id: u32) -> Option<Decimal> {
+        self.amount_in(tx_id, DisputeState::Open)
+    }
+
+    /// Amount taken by a chargeback that wasn't reversed.
+    pub fn charged_back(&self, tx_id: u32) -> Option<Decimal> {
+        self.amount_in(tx_id, DisputeState::ChargedBack)
+    }
+
+    fn amount_in(&self, tx_id: u32, state: DisputeState) -> Option<Decimal> {
+        self.disputes
+            .get(&tx_id)
+            .filter(|dispute| dispute.state == state)
+            .map(|dispute| dispute.amount)
+    }
+
+    pub fn opened(&self, tx_id: u32) -> Option<u64> {
+        self.opened.get(&tx_id).copied()
+    }
+
+    /// Opens a dispute holding `hold`, at `now` in the unit of the dispute aging.
+    pub fn open(&mut self, tx_id: u32, hold: Decimal, now: u64) -> Result<(), ExecutionError> {
+        self.check(tx_id, DisputeAction::Open)?;
+        self.disputes.insert(
+            tx_id,
+            Dispute {
+                state: DisputeState::Open,
+                amount: hold,
+            },
+        );
+        self.opened.insert(tx_id, now);
+        self.by_age.insert((now, tx_id));
+        Ok(())
+    }
+
+    /// Takes a resolve, chargeback or reversal step. A chargeback keeps the held amount as the
+    /// amount taken.
+    pub fn settle(&mut self, tx_id: u32, action: DisputeAction) -> Result<(), ExecutionError> {
+        let state = self.state(tx_id).next(action)?;
+        if let Some(dispute) = self.disputes.get_mut(&tx_id) {
+            dispute.state = state;
+        }
+        if let Some(opened) = self.opened.remove(&tx_id) {
+            self.by_age.remove(&(opened, tx_id));
+        }
+        Ok(())
+    }
+
+    /// Restores a dispute state from saved state, an open dispute starts aging at `now`.
+    pub fn restore(&mut self, tx_id: u32, state: DisputeState, amount: Decimal, now: u64) {
+        match state {
+            DisputeState::None => {}
+            DisputeState::Open => {
+                self.disputes.insert(tx_id, Dispute { state, amount });
+                self.opened.insert(tx_id, now);
+                self.by_age.insert((now, tx_id));
+            }
+            _ => {
+                self.disputes.insert(tx_id, Dispute { state, amount });
+            }
+        }
+    }
+
+    /// Ids of the transactions under an open dispute.
+    pub fn open_ids(&self) -> Vec<u32> {
+        self.opened.keys().copied().collect()
+    }
+
+    /// Open disputes opened before `cutoff` that can still expire, oldest first.
+    pub fn opened_before(&self, cutoff: u64) -> Vec<u32> {
+        self.by_age
+            .range(..(cutoff, 0))
+            .map(|(_, tx_id)| *tx_id)
+            .collect()
+    }
+
+    /// Keeps an open dispute from expiring, e.g. after its expiry failed.
+    pub fn stop_aging(&mut self, tx_id: u32) {
+        if let Some(opened) = self.opened.get(&tx_id) {
+            self.by_age.remove(&(*opened, tx_id));
+        }
+    }
+
+    /// Drops the state of a closed dispute, e.g. of an archived transaction.
+    pub fn forget(&mut self, tx_id: u32) {
+        if self.state(tx_id).is_closed() {
+            self.disputes.remove(&tx_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispute_transitions() {
+        use DisputeAction::*;
+
+        assert_eq!(DisputeState::None.next(Open), Ok(DisputeState::Open));
+        assert_eq!(
+            DisputeState::None.next(Resolve),
+            Err(ExecutionError::NonDisputedTransaction)
+        );
+        assert_eq!(
+            DisputeState::Open.next(Open),
+            Err(ExecutionError::AlreadyDisputedTransaction)
+        );
+        assert_eq!(
+            DisputeState::Open.next(Chargeback),
+            Ok(DisputeState::ChargedBack)
+        );
+        assert_eq!(
+            DisputeState::Open.next(Reverse),
+            Err(ExecutionError::NotChargedBack)
+        );
+        assert_eq!(
+            DisputeState::ChargedBack.next(Reverse),
+            Ok(DisputeState::Reversed)
+        );
+        for closed in [
+            DisputeState::Resolved,
+            DisputeState::ChargedBack,
+            DisputeState::Reversed,
+        ] {
+            for action in [Open, Resolve, Chargeback] {
+                assert_eq!(closed.next(action), Err(ExecutionError::DisputeClosed));
+            }
+        }
+
+        let mut disputes = Disputes::default();
+        disputes.open(7, Decimal::ONE, 100).unwrap();
+        assert_eq!(disputes.opened_before(101), [7]);
+        disputes.settle(7, Chargeback).unwrap();
+        assert_eq!(disputes.state(7), DisputeState::ChargedBack);
+        assert_eq!(disputes.hold(7), None);
+        assert_eq!(disputes.charged_back(7), Some(Decimal::ONE));
+        assert!(disputes.opened_before(101).is_empty());
+        disputes.settle(7, Reverse).unwrap();
+        assert_eq!(disputes.charged_back(7), None);
+        assert!(disputes.state(7).is_closed());
+    }
+}
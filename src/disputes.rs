@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, io::Write};
+
+use crate::transaction::Transaction;
+
+/// Tracks time-to-resolution of timestamped disputes.
+///
+/// Feed it every successfully executed transaction with its row timestamp. Disputes without a
+/// timestamp on either the opening or the closing row are not measured.
+#[derive(Default)]
+pub struct DisputeTracker {
+    open: BTreeMap<u32, u64>,
+    durations: Vec<u64>,
+}
+
+/// Dispute SLA metrics. Durations are in seconds.
+#[derive(Debug, PartialEq)]
+pub struct SlaMetrics {
+    pub closed: usize,
+    pub open: usize,
+    pub median: Option<u64>,
+    pub p95: Option<u64>,
+    /// Closed disputes that took longer than the threshold plus open disputes already older than it.
+    pub breaches: Option<usize>,
+}
+
+impl DisputeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, transaction: &Transaction, timestamp: Option<u64>) {
+        match *transaction {
+            Transaction::Dispute(_, tx_id) => {
+                if let Some(timestamp) = timestamp {
+                    self.open.insert(tx_id, timestamp);
+                }
+            }
+            Transaction::Resolve(_, tx_id) | Transaction::Chargeback(_, tx_id) => {
+                if let Some(opened_at) = self.open.remove(&tx_id)
+                    && let Some(closed_at) = timestamp
+                {
+                    self.durations.push(closed_at.saturating_sub(opened_at));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Computes the metrics, open disputes are aged until `now` from the engine clock.
+    pub fn sla_metrics(&self, threshold: Option<u64>, now: u64) -> SlaMetrics {
+        let mut durations = self.durations.clone();
+        durations.sort_unstable();
+        let breaches = threshold.map(|threshold| {
+            let closed = durations.iter().filter(|d| **d > threshold).count();
+            let open = self
+                .open
+                .values()
+                .filter(|opened_at| now.saturating_sub(**opened_at) > threshold)
+                .count();
+            closed + open
+        });
+        SlaMetrics {
+            closed: durations.len(),
+            open: self.open.len(),
+            median: percentile(&durations, 50),
+            p95: percentile(&durations, 95),
+            breaches,
+        }
+    }
+
+    /// Writes the SLA metrics as a `metric,value` CSV.
+    pub fn write_report<W: Write>(
+        &self,
+        writer: W,
+        threshold: Option<u64>,
+        now: u64,
+    ) -> csv::Result<()> {
+        let metrics = self.sla_metrics(threshold, now);
+        let optional = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["metric", "value"])?;
+        writer.write_record(["disputes_closed", &metrics.closed.to_string()])?;
+        writer.write_record(["disputes_open", &metrics.open.to_string()])?;
+        writer.write_record(["resolution_median_seconds", &optional(metrics.median)])?;
+        writer.write_record(["resolution_p95_seconds", &optional(metrics.p95)])?;
+        writer.write_record(["sla_threshold_seconds", &optional(threshold)])?;
+        writer.write_record([
+            "sla_breaches",
+            &optional(metrics.breaches.map(|breaches| breaches as u64)),
+        ])?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[u64], percent: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 50), None);
+        assert_eq!(percentile(&[7], 95), Some(7));
+        let values: Vec<u64> = (1..=20).collect();
+        assert_eq!(percentile(&values, 50), Some(10));
+        assert_eq!(percentile(&values, 95), Some(19));
+    }
+
+    #[test]
+    fn test_sla_metrics() {
+        let mut tracker = DisputeTracker::new();
+        tracker.record(&Transaction::Dispute(1, 1), Some(100));
+        tracker.record(&Transaction::Dispute(1, 2), Some(100));
+        tracker.record(&Transaction::Dispute(1, 3), Some(200));
+        tracker.record(&Transaction::Dispute(1, 4), None);
+        tracker.record(&Transaction::Resolve(1, 1), Some(150));
+        tracker.record(&Transaction::Chargeback(1, 2), Some(400));
+        tracker.record(&Transaction::Resolve(1, 4), Some(500));
+        assert_eq!(
+            tracker.sla_metrics(Some(200), 500),
+            SlaMetrics {
+                closed: 2,
+                open: 1,
+                median: Some(50),
+                p95: Some(300),
+                // Dispute 2 took 300s, dispute 3 has been open for 300s
+                breaches: Some(2),
+            }
+        );
+        assert_eq!(tracker.sla_metrics(Some(200), 300).breaches, Some(1));
+        assert_eq!(tracker.sla_metrics(None, 500).breaches, None);
+    }
+
+    #[test]
+    fn test_write_report() {
+        let mut tracker = DisputeTracker::new();
+        tracker.record(&Transaction::Dispute(1, 1), Some(100));
+        tracker.record(&Transaction::Resolve(1, 1), Some(160));
+        let mut output = Vec::new();
+        tracker.write_report(&mut output, Some(30), 160).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "metric,value
+disputes_closed,1
+disputes_open,0
+resolution_median_seconds,60
+resolution_p95_seconds,60
+sla_threshold_seconds,30
+sla_breaches,1
+"
+        );
+    }
+}
@@ -1,228 +1,1860 @@
 use std::{
+    cmp::Reverse,
     collections::{BTreeMap, BTreeSet},
+    fmt::Display,
     io,
+    sync::Arc,
 };
 
-use csv::Writer;
-use rust_decimal::Decimal;
+use clap::ValueEnum;
 
-use crate::{client::Client, transaction::Transaction};
+use crate::decimal::Decimal;
 
-pub(crate) struct Engine {
-    clients: BTreeMap<u16, Client>,
-    transaction_log: BTreeMap<u32, Transaction>,
-    disputed_transactions: BTreeSet<u32>,
+use crate::{
+    archive::Archive,
+    client::Client,
+    client_store::{ClientStore, MemoryStore},
+    clock::{Clock, TransactionClock},
+    config::{
+        ClosePolicy, DisputeAging, DisputeAmountPolicy, DisputePolicy, EngineConfig,
+        LockedAccountPolicy, PreHistoryPolicy,
+    },
+    currency::Currency,
+    dispute::{DisputeAction, DisputeState, Disputes},
+    events::{self, EventSink},
+    export::{ClientExport, ExportedTransaction, ImportError},
+    invariants::{InvariantViolation, check_account},
+    kyc::KycStatus,
+    limits::Velocity,
+    registry,
+    report::{AccountReportRow, CsvReportWriter, ReportColumns, ReportWriter},
+    transaction::{Transaction, TransactionType},
+    tx_log::{MemoryLog, TransactionLog},
+};
+
+/// Order of the clients in the report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ReportSort {
+    /// By client id
+    #[default]
+    Client,
+    /// By total funds, largest first
+    Total,
+    /// By available funds, largest first
+    Available,
+}
+
+/// Selection and order of the clients in the report. The default reports all clients by id.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReportOptions {
+    pub sort: ReportSort,
+    /// Only locked accounts.
+    pub only_locked: bool,
+    /// Only accounts with at least this total.
+    pub min_total: Option<Decimal>,
+    /// Only these clients, all if `None`.
+    pub clients: Option<BTreeSet<u16>>,
+}
+
+impl ReportOptions {
+    /// Whether `client` is reported.
+    pub fn includes(&self, client: &Client) -> bool {
+        (!self.only_locked || client.locked)
+            && self.min_total.is_none_or(|min| client.total >= min)
+            && self
+                .clients
+                .as_ref()
+                .is_none_or(|clients| clients.contains(&client.id))
+    }
+}
+
+/// Post-apply hook, called with the executed transaction and the resulting state of the affected client.
+pub type Hook = Box<dyn FnMut(&Transaction, &Client) + Send + Sync>;
+
+pub struct Engine {
+    config: EngineConfig,
+    clients: Box<dyn ClientStore>,
+    transaction_log: Box<dyn TransactionLog>,
+    /// Dispute state of each disputed transaction, with the amount held by an open dispute,
+    /// which the dispute policy may cap, or taken by a chargeback.
+    disputes: Disputes,
+    /// Number of transactions executed so far, the sequence number of dispute aging.
+    executed: u64,
+    /// Withdrawal holds awaiting settlement or cancellation with their held amount.
+    pending_withdrawals: BTreeMap<u32, Decimal>,
+    /// Amount refunded so far from each deposit.
+    refunds: BTreeMap<u32, Decimal>,
+    /// Ids of each client's logged transactions in the order they were logged, so a client's
+    /// history doesn't need a scan of the whole log.
+    client_transactions: BTreeMap<u16, Vec<u32>>,
+    /// Clients whose accounts were seeded without their history, see `seed_account`.
+    seeded_clients: BTreeSet<u16>,
+    /// Fees each account paid to the fee account of the fee schedule.
+    fees_paid: BTreeMap<(u16, Currency), Decimal>,
+    /// Recent withdrawals of each account for the withdrawal limits.
+    velocity: BTreeMap<(u16, Currency), Velocity>,
+    /// Overdraft limits set by `set_overdraft` transactions, overriding the withdrawal limits.
+    overdrafts: BTreeMap<u16, Decimal>,
+    /// Clients closed by `close_account` transactions, whose accounts carry `Client::closed`.
+    closed: BTreeSet<u16>,
+    /// Time on the engine clock of each client's last transaction, for `prune`.
+    last_active: BTreeMap<u16, u64>,
+    hooks: BTreeMap<TransactionType, Vec<Hook>>,
+    event_sinks: Vec<Box<dyn EventSink>>,
+    archive: Option<Archive>,
+    clock: Arc<dyn Clock>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ExecutionError {
     InsufficientFunds,
+    /// A withdrawal would take the available funds further below zero than the client's
+    /// overdraft limit.
+    OverdraftExceeded,
+    /// A withdrawal exceeds one of the client's withdrawal limits.
+    LimitExceeded,
     AccountLocked,
+    /// The client's accounts were closed by a `close_account` transaction.
+    AccountClosed,
+    /// An account can't be closed while it has held funds, see `ClosePolicy`.
+    FundsHeld,
     TransactionNotFound,
     IneligibleTransaction,
     NonDisputedTransaction,
     AlreadyDisputedTransaction,
+    ClientMismatch,
+    DuplicateTransaction,
+    InvalidAmount,
+    AdminNotAllowed,
+    CurrencyMismatch,
+    UnsupportedCurrency,
+    TransactionArchived,
+    ArchiveUnavailable,
+    DisputeClosed,
+    /// A settlement references no pending withdrawal hold, e.g. one already settled.
+    NoPendingWithdrawal,
+    /// A dispute, resolve or chargeback row carries an amount the dispute amount policy rejects.
+    UnexpectedAmount,
+    /// A dispute operation references an unknown transaction of a client seeded without history.
+    PreHistoryTransaction,
+    /// A chargeback reversal references a transaction that wasn't charged back or was already
+    /// reversed.
+    NotChargedBack,
+    /// A refund exceeds the part of the deposit that wasn't refunded or charged back yet.
+    RefundExceeded,
+    LogUnavailable,
+    StoreUnavailable,
+    /// A balance would exceed the range or precision of the decimal type.
+    Overflow,
+    /// A deposit exceeds the KYC deposit limit of a client that isn't verified.
+    KycRequired,
+    /// Another transaction of the batch failed validation, so the batch wasn't applied.
+    BatchRejected,
+    /// A balance assertion failed, with the expected and the actual available and total funds.
+    BalanceMismatch {
+        expected: Decimal,
+        actual: Decimal,
+        expected_total: Option<Decimal>,
+        total: Decimal,
+    },
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::InsufficientFunds => write!(f, "Insufficient funds"),
+            ExecutionError::OverdraftExceeded => write!(f, "Overdraft limit exceeded"),
+            ExecutionError::LimitExceeded => write!(f, "Withdrawal limit exceeded"),
+            ExecutionError::AccountLocked => write!(f, "Account is locked"),
+            ExecutionError::AccountClosed => write!(f, "Account is closed"),
+            ExecutionError::FundsHeld => write!(f, "Account has held funds"),
+            ExecutionError::TransactionNotFound => write!(f, "Transaction not found"),
+            ExecutionError::IneligibleTransaction => {
+                write!(f, "Transaction is not eligible for dispute")
+            }
+            ExecutionError::NonDisputedTransaction => write!(f, "Transaction is not disputed"),
+            ExecutionError::AlreadyDisputedTransaction => {
+                write!(f, "Transaction is already disputed")
+            }
+            ExecutionError::ClientMismatch => {
+                write!(f, "Transaction belongs to a different client")
+            }
+            ExecutionError::DuplicateTransaction => write!(f, "Duplicate transaction id"),
+            ExecutionError::InvalidAmount => write!(f, "Invalid transaction amount"),
+            ExecutionError::AdminNotAllowed => {
+                write!(f, "Administrative transactions are not allowed")
+            }
+            ExecutionError::CurrencyMismatch => {
+                write!(f, "Currency differs from the referenced transaction")
+            }
+            ExecutionError::UnsupportedCurrency => {
+                write!(f, "Currency is not supported without multi-currency")
+            }
+            ExecutionError::TransactionArchived => {
+                write!(f, "Transaction is archived and can no longer be disputed")
+            }
+            ExecutionError::ArchiveUnavailable => write!(f, "Transaction archive is unreadable"),
+            ExecutionError::DisputeClosed => {
+                write!(f, "Dispute of the transaction is already closed")
+            }
+            ExecutionError::NoPendingWithdrawal => {
+                write!(f, "No pending withdrawal hold with this id")
+            }
+            ExecutionError::UnexpectedAmount => {
+                write!(f, "Dispute operations don't take an amount")
+            }
+            ExecutionError::PreHistoryTransaction => {
+                write!(f, "Transaction predates the seeded account history")
+            }
+            ExecutionError::NotChargedBack => {
+                write!(f, "Transaction was not charged back or is already reversed")
+            }
+            ExecutionError::RefundExceeded => {
+                write!(f, "Refund exceeds the remaining amount of the deposit")
+            }
+            ExecutionError::LogUnavailable => write!(f, "Transaction log is unreadable"),
+            ExecutionError::StoreUnavailable => write!(f, "Client store is unreadable"),
+            ExecutionError::Overflow => write!(f, "Balance overflow"),
+            ExecutionError::KycRequired => {
+                write!(
+                    f,
+                    "Deposit exceeds the limit of clients without verified KYC"
+                )
+            }
+            ExecutionError::BatchRejected => {
+                write!(f, "Another transaction of the batch failed validation")
+            }
+            ExecutionError::BalanceMismatch {
+                expected,
+                actual,
+                expected_total,
+                total,
+            } => {
+                write!(
+                    f,
+                    "Balance assertion failed: expected available {}, actual {}",
+                    expected, actual
+                )?;
+                match expected_total {
+                    Some(expected_total) => {
+                        write!(f, ", expected total {}, actual {}", expected_total, total)
+                    }
+                    None => write!(f, ", total {}", total),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+impl ExecutionError {
+    /// Short snake_case name of the error, e.g. for structured log fields.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ExecutionError::InsufficientFunds => "insufficient_funds",
+            ExecutionError::OverdraftExceeded => "overdraft_exceeded",
+            ExecutionError::LimitExceeded => "limit_exceeded",
+            ExecutionError::AccountLocked => "account_locked",
+            ExecutionError::AccountClosed => "account_closed",
+            ExecutionError::FundsHeld => "funds_held",
+            ExecutionError::TransactionNotFound => "transaction_not_found",
+            ExecutionError::IneligibleTransaction => "ineligible_transaction",
+            ExecutionError::NonDisputedTransaction => "non_disputed_transaction",
+            ExecutionError::AlreadyDisputedTransaction => "already_disputed_transaction",
+            ExecutionError::ClientMismatch => "client_mismatch",
+            ExecutionError::DuplicateTransaction => "duplicate_transaction",
+            ExecutionError::InvalidAmount => "invalid_amount",
+            ExecutionError::AdminNotAllowed => "admin_not_allowed",
+            ExecutionError::CurrencyMismatch => "currency_mismatch",
+            ExecutionError::UnsupportedCurrency => "unsupported_currency",
+            ExecutionError::TransactionArchived => "transaction_archived",
+            ExecutionError::ArchiveUnavailable => "archive_unavailable",
+            ExecutionError::DisputeClosed => "dispute_closed",
+            ExecutionError::NoPendingWithdrawal => "no_pending_withdrawal",
+            ExecutionError::UnexpectedAmount => "unexpected_amount",
+            ExecutionError::PreHistoryTransaction => "pre_history_transaction",
+            ExecutionError::NotChargedBack => "not_charged_back",
+            ExecutionError::RefundExceeded => "refund_exceeded",
+            ExecutionError::LogUnavailable => "log_unavailable",
+            ExecutionError::StoreUnavailable => "store_unavailable",
+            ExecutionError::Overflow => "overflow",
+            ExecutionError::KycRequired => "kyc_required",
+            ExecutionError::BatchRejected => "batch_rejected",
+            ExecutionError::BalanceMismatch { .. } => "balance_mismatch",
+        }
+    }
+}
+
+/// Outcome of `Engine::execute_batch`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchResult {
+    /// Whether the batch passed validation and was applied.
+    pub applied: bool,
+    /// Outcome of each transaction in batch order. A batch that failed validation has the
+    /// validation errors of the offending transactions and `BatchRejected` for the others.
+    pub outcomes: Vec<Result<(), ExecutionError>>,
+}
+
+impl BatchResult {
+    /// Whether every transaction of the batch was applied.
+    pub fn is_ok(&self) -> bool {
+        self.applied && self.outcomes.iter().all(Result::is_ok)
+    }
+}
+
+/// The account after moving funds to its available and held balances, the total changing by
+/// their sum. Computed before anything changes, so an overflow rejects the transaction with
+/// `Overflow` instead of panicking and leaves the account untouched.
+fn moved(client: &Client, available: Decimal, held: Decimal) -> Result<Client, ExecutionError> {
+    // Near the range limits a sum drops fractional digits instead of failing, which would
+    // silently break `total == available + held`
+    let add = |balance: Decimal, delta: Decimal| {
+        balance
+            .checked_add(delta)
+            .filter(|sum| {
+                balance.is_zero()
+                    || delta.is_zero()
+                    || sum.scale() >= balance.scale().max(delta.scale())
+            })
+            .ok_or(ExecutionError::Overflow)
+    };
+    Ok(Client {
+        available: add(client.available, available)?,
+        held: add(client.held, held)?,
+        total: add(client.total, add(available, held)?)?,
+        ..client.clone()
+    })
+}
+
+/// Rejects debiting `amount` from the available funds of `client` beyond its `overdraft` limit.
+fn funded(client: &Client, amount: Decimal, overdraft: Decimal) -> Result<(), ExecutionError> {
+    if client.available >= amount {
+        return Ok(());
+    }
+    if overdraft.is_zero() {
+        return Err(ExecutionError::InsufficientFunds);
+    }
+    let room = client
+        .available
+        .checked_add(overdraft)
+        .ok_or(ExecutionError::Overflow)?;
+    if room < amount {
+        return Err(ExecutionError::OverdraftExceeded);
+    }
+    Ok(())
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         Engine {
-            clients: BTreeMap::new(),
-            transaction_log: BTreeMap::new(),
-            disputed_transactions: BTreeSet::new(),
+            config,
+            clients: Box::new(MemoryStore::new()),
+            transaction_log: Box::new(MemoryLog::new()),
+            disputes: Disputes::default(),
+            executed: 0,
+            pending_withdrawals: BTreeMap::new(),
+            refunds: BTreeMap::new(),
+            client_transactions: BTreeMap::new(),
+            seeded_clients: BTreeSet::new(),
+            fees_paid: BTreeMap::new(),
+            velocity: BTreeMap::new(),
+            overdrafts: BTreeMap::new(),
+            closed: BTreeSet::new(),
+            last_active: BTreeMap::new(),
+            hooks: BTreeMap::new(),
+            event_sinks: Vec::new(),
+            archive: None,
+            clock: Arc::new(TransactionClock::new()),
         }
     }
 
+    /// Enables archival of transactions that fell out of the archive's dispute window, see
+    /// `archive_old_transactions`. Only transactions logged afterwards are archived.
+    pub fn set_archive(&mut self, archive: Archive) {
+        self.archive = Some(archive);
+    }
+
+    /// Replaces the in-memory transaction log, e.g. with a disk-backed one. Call it before
+    /// executing transactions, the entries of the previous log are not moved over.
+    pub fn set_transaction_log(&mut self, transaction_log: Box<dyn TransactionLog>) {
+        self.transaction_log = transaction_log;
+    }
+
+    /// Replaces the in-memory client store, e.g. with a disk-backed one. Call it before executing
+    /// transactions, the accounts of the previous store are not moved over.
+    pub fn set_client_store(&mut self, clients: Box<dyn ClientStore>) {
+        self.clients = clients;
+    }
+
+    /// Replaces the clock of time-dependent features, transaction timestamps by default.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Registers a hook that runs after every successfully applied transaction of the given type.
+    /// Hooks run in registration order.
+    pub fn register_hook(&mut self, transaction_type: TransactionType, hook: Hook) {
+        self.hooks.entry(transaction_type).or_default().push(hook);
+    }
+
+    /// Adds a sink that receives the domain events of every applied transaction. Sinks run
+    /// after the hooks, in registration order.
+    pub fn add_event_sink(&mut self, sink: Box<dyn EventSink>) {
+        self.event_sinks.push(sink);
+    }
+
+    /// Executes a transaction in the default currency.
     pub fn execute(&mut self, transaction: Transaction) -> Result<(), ExecutionError> {
-        match transaction {
+        self.execute_in(transaction, None)
+    }
+
+    /// Executes a transaction in the given currency, `None` stands for the default currency.
+    /// Dispute operations act in the currency of the referenced transaction.
+    pub fn execute_in(
+        &mut self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+    ) -> Result<(), ExecutionError> {
+        self.execute_row(transaction, currency, None)
+    }
+
+    /// Validates all transactions of a batch, then applies them in order, e.g. for a request of an
+    /// API. A duplicate id, within the batch or of a logged transaction, an invalid amount or an
+    /// admin transaction the engine doesn't allow rejects the whole batch and nothing is applied.
+    /// Checks that depend on the balances, like insufficient funds, are made while applying, so
+    /// a validated batch can still have rejected transactions.
+    pub fn execute_batch(&mut self, transactions: Vec<Transaction>) -> BatchResult {
+        let mut ids = BTreeSet::new();
+        let validated: Vec<_> = transactions
+            .iter()
+            .map(|transaction| {
+                let introduces_id =
+                    !transaction.references_transaction() && !transaction.is_assertion();
+                if introduces_id && !ids.insert(transaction.tx_id()) {
+                    return Err(ExecutionError::DuplicateTransaction);
+                }
+                self.validate(transaction)
+            })
+            .collect();
+        if validated.iter().any(Result::is_err) {
+            let outcomes = validated
+                .into_iter()
+                .map(|outcome| outcome.and(Err(ExecutionError::BatchRejected)))
+                .collect();
+            return BatchResult {
+                applied: false,
+                outcomes,
+            };
+        }
+        let outcomes = transactions
+            .into_iter()
+            .map(|transaction| self.execute(transaction))
+            .collect();
+        BatchResult {
+            applied: true,
+            outcomes,
+        }
+    }
+
+    /// The checks of `run` that don't depend on the balances, for a transaction in the default
+    /// currency.
+    fn validate(&self, transaction: &Transaction) -> Result<(), ExecutionError> {
+        if transaction.is_admin() && !self.config.allow_admin {
+            return Err(ExecutionError::AdminNotAllowed);
+        }
+        let transaction = self.config.amounts.round_transaction(transaction.clone());
+        self.config.validation.validate(&transaction)?;
+        if !transaction.references_transaction()
+            && !transaction.is_assertion()
+            && let Some((logged, logged_currency)) = self.logged_or_archived(transaction.tx_id())?
+            && !(self.config.idempotent_duplicates
+                && logged == transaction
+                && logged_currency == self.config.default_currency)
+        {
+            return Err(ExecutionError::DuplicateTransaction);
+        }
+        Ok(())
+    }
+
+    /// Executes a transaction with the amount its input row gave a dispute, resolve or
+    /// chargeback, handled by the configured `DisputeAmountPolicy`. The amount is ignored for
+    /// other transactions.
+    pub fn execute_row(
+        &mut self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+        dispute_amount: Option<Decimal>,
+    ) -> Result<(), ExecutionError> {
+        self.executed += 1;
+        self.run(transaction, currency, dispute_amount)
+    }
+
+    /// Executes a transaction without counting it in the sequence of dispute aging.
+    fn run(
+        &mut self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+        dispute_amount: Option<Decimal>,
+    ) -> Result<(), ExecutionError> {
+        if transaction.is_admin() && !self.config.allow_admin {
+            return Err(ExecutionError::AdminNotAllowed);
+        }
+        if !self.config.multi_currency
+            && currency.is_some_and(|currency| currency != self.config.default_currency)
+        {
+            return Err(ExecutionError::UnsupportedCurrency);
+        }
+        let transaction = self.config.amounts.round_transaction(transaction);
+        self.config.validation.validate(&transaction)?;
+        let dispute_amount = match dispute_amount {
+            Some(amount) if transaction.is_dispute_operation() => match self.config.dispute_amounts
+            {
+                DisputeAmountPolicy::Ignore | DisputeAmountPolicy::Warn => None,
+                DisputeAmountPolicy::Reject => return Err(ExecutionError::UnexpectedAmount),
+                DisputeAmountPolicy::Honor => {
+                    let amount = self.config.amounts.round(amount);
+                    if amount <= Decimal::ZERO {
+                        return Err(ExecutionError::InvalidAmount);
+                    }
+                    Some(amount)
+                }
+            },
+            _ => None,
+        };
+        if !transaction.references_transaction()
+            && !transaction.is_assertion()
+            && let Some((logged, logged_currency)) = self.logged_or_archived(transaction.tx_id())?
+        {
+            if self.config.idempotent_duplicates
+                && logged == transaction
+                && logged_currency == currency.unwrap_or(self.config.default_currency)
+            {
+                return Ok(());
+            }
+            return Err(ExecutionError::DuplicateTransaction);
+        }
+        // The state before is only needed for events
+        let before =
+            (!self.event_sinks.is_empty()).then(|| self.affected_account(&transaction, currency));
+        let account = self.apply(&transaction, currency, dispute_amount)?;
+        if !transaction.is_assertion() {
+            self.last_active.insert(account.0, self.clock.now());
+        }
+        let hooks = self.hooks.contains_key(&transaction.transaction_type());
+        // An assertion on a client without an account has no client to pass
+        let Some(after) = (hooks || before.is_some())
+            .then(|| self.account(account.0, account.1))
+            .flatten()
+        else {
+            return Ok(());
+        };
+        if let Some(hooks) = self.hooks.get_mut(&transaction.transaction_type()) {
+            for hook in hooks.iter_mut() {
+                hook(&transaction, &after);
+            }
+        }
+        if let Some(before) = before {
+            let before = before.unwrap_or_else(|| Client::with_currency(account.0, account.1));
+            let dispute = self.dispute_state(transaction.tx_id());
+            for event in events::events(&transaction, &before, &after, dispute) {
+                for sink in self.event_sinks.iter_mut() {
+                    sink.emit(&event);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the transaction and returns the key of the affected account. `dispute_amount` is
+    /// the honored amount of a dispute operation.
+    fn apply(
+        &mut self,
+        transaction: &Transaction,
+        currency: Option<Currency>,
+        dispute_amount: Option<Decimal>,
+    ) -> Result<(u16, Currency), ExecutionError> {
+        let row_currency = currency;
+        let currency = currency.unwrap_or(self.config.default_currency);
+        if !self.closed.is_empty()
+            && !transaction.is_assertion()
+            && self.closed.contains(&self.affected_client_id(transaction))
+        {
+            return Err(ExecutionError::AccountClosed);
+        }
+        if transaction.is_dispute_operation()
+            && self.disputes.state(transaction.tx_id()).is_closed()
+        {
+            return Err(ExecutionError::DisputeClosed);
+        }
+        if transaction.is_dispute_operation()
+            && self.seeded_clients.contains(&transaction.client_id())
+            && self.logged_or_archived(transaction.tx_id())?.is_none()
+        {
+            return match self.config.pre_history {
+                PreHistoryPolicy::Reject => Err(ExecutionError::PreHistoryTransaction),
+                PreHistoryPolicy::Ignore => Ok((transaction.client_id(), currency)),
+            };
+        }
+        match *transaction {
             Transaction::Deposit(client_id, tx_id, amount) => {
-                let client = self.fetch_or_create_client_mut(client_id)?;
-                client.available += amount;
-                client.total += amount;
-                // Logging only deposits and withdrawals
-                self.transaction_log.insert(tx_id, transaction);
+                self.check_kyc(client_id, amount)?;
+                // A fee above the deposit takes all of it
+                let fee = self
+                    .fee(client_id, TransactionType::Deposit, amount)?
+                    .min(amount);
+                let client = self.fetch_or_create_client_mut(client_id, currency)?;
+                let net = amount.checked_sub(fee).ok_or(ExecutionError::Overflow)?;
+                let moved = moved(client, net, Decimal::ZERO)?;
+                let collected = self.collected_fee(client_id, currency, fee)?;
+                // Logging only deposits and withdrawals, before the balances change so a failed
+                // write leaves the state untouched
+                self.log_transaction(tx_id, transaction, currency)?;
+                *self.fetch_client_mut(client_id, currency)? = moved;
+                self.collect_fee(client_id, collected)?;
+                Ok((client_id, currency))
             }
             Transaction::Withdrawal(client_id, tx_id, amount) => {
-                let client = self.fetch_or_create_client_mut(client_id)?;
-                if client.available >= amount {
-                    client.available -= amount;
-                    client.total -= amount;
-                    // Logging only deposits and withdrawals
-                    self.transaction_log.insert(tx_id, transaction);
-                } else {
-                    return Err(ExecutionError::InsufficientFunds);
+                self.check_limits(client_id, currency, amount)?;
+                let fee = self.fee(client_id, TransactionType::Withdrawal, amount)?;
+                let debit = amount.checked_add(fee).ok_or(ExecutionError::Overflow)?;
+                let overdraft = self.overdraft(client_id);
+                let client = self.fetch_or_create_client_mut(client_id, currency)?;
+                funded(client, debit, overdraft)?;
+                let moved = moved(client, -debit, Decimal::ZERO)?;
+                let collected = self.collected_fee(client_id, currency, fee)?;
+                self.log_transaction(tx_id, transaction, currency)?;
+                *self.fetch_client_mut(client_id, currency)? = moved;
+                self.collect_fee(client_id, collected)?;
+                self.record_withdrawal(client_id, currency, amount);
+                Ok((client_id, currency))
+            }
+            Transaction::Dispute(client_id, tx_id) => {
+                self.disputes.check(tx_id, DisputeAction::Open)?;
+                let (src_client_id, src_amount, src_currency) =
+                    self.fetch_disputed_transaction(client_id, tx_id, row_currency)?;
+                // Only the part of the deposit that wasn't refunded can be disputed
+                let src_amount = src_amount
+                    .checked_sub(self.refunded(tx_id))
+                    .ok_or(ExecutionError::Overflow)?;
+                if src_amount <= Decimal::ZERO {
+                    return Err(ExecutionError::IneligibleTransaction);
+                }
+                let src_amount = match dispute_amount {
+                    Some(amount) if amount > src_amount => {
+                        return Err(ExecutionError::InvalidAmount);
+                    }
+                    Some(amount) => amount,
+                    None => src_amount,
+                };
+                let policy = self.config.dispute_policy;
+                let client = self.fetch_dispute_client_mut(src_client_id, src_currency, true)?;
+                let hold = match policy {
+                    DisputePolicy::AllowNegative => src_amount,
+                    DisputePolicy::Reject if client.available < src_amount => {
+                        return Err(ExecutionError::InsufficientFunds);
+                    }
+                    DisputePolicy::Reject => src_amount,
+                    DisputePolicy::PartialHold => {
+                        src_amount.min(client.available.max(Decimal::ZERO))
+                    }
+                };
+                *client = moved(client, -hold, hold)?;
+                let now = self.dispute_clock();
+                self.disputes.open(tx_id, hold, now)?;
+                Ok((src_client_id, src_currency))
+            }
+            Transaction::Resolve(client_id, tx_id) => {
+                self.disputes.check(tx_id, DisputeAction::Resolve)?;
+                let hold = self.disputes.hold(tx_id).unwrap_or(Decimal::ZERO);
+                if dispute_amount.is_some_and(|amount| amount != hold) {
+                    return Err(ExecutionError::InvalidAmount);
+                }
+                let (src_client_id, _, src_currency) =
+                    self.fetch_disputed_transaction(client_id, tx_id, row_currency)?;
+                let client = self.fetch_dispute_client_mut(src_client_id, src_currency, false)?;
+                *client = moved(client, hold, -hold)?;
+                self.disputes.settle(tx_id, DisputeAction::Resolve)?;
+                Ok((src_client_id, src_currency))
+            }
+            Transaction::Chargeback(client_id, tx_id) => {
+                self.disputes.check(tx_id, DisputeAction::Chargeback)?;
+                let hold = self.disputes.hold(tx_id).unwrap_or(Decimal::ZERO);
+                if dispute_amount.is_some_and(|amount| amount != hold) {
+                    return Err(ExecutionError::InvalidAmount);
+                }
+                let (src_client_id, _, src_currency) =
+                    self.fetch_disputed_transaction(client_id, tx_id, row_currency)?;
+                let client = self.fetch_dispute_client_mut(src_client_id, src_currency, false)?;
+                *client = moved(client, Decimal::ZERO, -hold)?;
+                // The lock applies to the accounts in all currencies
+                self.set_locked(src_client_id, true)?;
+                // The held amount stays with the dispute as the amount charged back
+                self.disputes.settle(tx_id, DisputeAction::Chargeback)?;
+                Ok((src_client_id, src_currency))
+            }
+            // Reversals re-credit funds taken from an account the chargeback locked, so they
+            // bypass the lock
+            Transaction::ChargebackReversal(client_id, tx_id) => {
+                self.disputes.check(tx_id, DisputeAction::Reverse)?;
+                let amount = self.disputes.charged_back(tx_id).unwrap_or(Decimal::ZERO);
+                let (src_client_id, _, src_currency) =
+                    self.fetch_disputed_transaction(client_id, tx_id, row_currency)?;
+                let client = self.fetch_client_mut(src_client_id, src_currency)?;
+                *client = moved(client, amount, Decimal::ZERO)?;
+                self.disputes.settle(tx_id, DisputeAction::Reverse)?;
+                if self.config.reversal_unlocks {
+                    self.set_locked(src_client_id, false)?;
                 }
+                Ok((src_client_id, src_currency))
             }
-            Transaction::Dispute(_, tx_id) => {
-                if self.disputed_transactions.contains(&tx_id) {
+            // Refunds reference the deposit like dispute operations but settle nothing, so an open
+            // dispute holding the funds blocks them
+            Transaction::Refund(client_id, tx_id, amount) => {
+                if amount <= Decimal::ZERO {
+                    return Err(ExecutionError::InvalidAmount);
+                }
+                let (src_client_id, src_amount, src_currency) =
+                    self.fetch_disputed_transaction(client_id, tx_id, row_currency)?;
+                if self.disputes.state(tx_id) == DisputeState::Open {
                     return Err(ExecutionError::AlreadyDisputedTransaction);
                 }
-                let (src_client_id, src_amount) = self.fetch_disputed_transaction(tx_id)?;
-                let client = self.fetch_or_create_client_mut(src_client_id)?;
-                client.available -= src_amount;
-                client.held += src_amount;
-                self.disputed_transactions.insert(tx_id);
-            }
-            Transaction::Resolve(_, tx_id) => {
-                if !self.disputed_transactions.contains(&tx_id) {
-                    return Err(ExecutionError::NonDisputedTransaction);
+                let refunded = self
+                    .refunded(tx_id)
+                    .checked_add(amount)
+                    .ok_or(ExecutionError::Overflow)?;
+                let charged_back = self.disputes.charged_back(tx_id).unwrap_or(Decimal::ZERO);
+                let refundable = src_amount
+                    .checked_sub(charged_back)
+                    .ok_or(ExecutionError::Overflow)?;
+                if refunded > refundable {
+                    return Err(ExecutionError::RefundExceeded);
+                }
+                let client = self.fetch_or_create_client_mut(src_client_id, src_currency)?;
+                if client.available < amount {
+                    return Err(ExecutionError::InsufficientFunds);
+                }
+                *client = moved(client, -amount, Decimal::ZERO)?;
+                self.refunds.insert(tx_id, refunded);
+                Ok((src_client_id, src_currency))
+            }
+            // Admin transactions bypass the account lock
+            Transaction::Unlock(client_id, tx_id) => {
+                self.log_transaction(tx_id, transaction, currency)?;
+                self.fetch_client_mut(client_id, currency)?;
+                self.set_locked(client_id, false)?;
+                Ok((client_id, currency))
+            }
+            Transaction::AdjustCredit(client_id, tx_id, amount) => {
+                let moved = moved(
+                    self.fetch_client_mut(client_id, currency)?,
+                    amount,
+                    Decimal::ZERO,
+                )?;
+                self.log_transaction(tx_id, transaction, currency)?;
+                *self.fetch_client_mut(client_id, currency)? = moved;
+                Ok((client_id, currency))
+            }
+            Transaction::SetOverdraft(client_id, tx_id, limit) => {
+                self.log_transaction(tx_id, transaction, currency)?;
+                self.fetch_client_mut(client_id, currency)?;
+                self.overdrafts.insert(client_id, limit);
+                Ok((client_id, currency))
+            }
+            // Closing bypasses the lock, the balances stay as the final settlement
+            Transaction::CloseAccount(client_id, tx_id) => {
+                if self.config.close_policy == ClosePolicy::Release {
+                    self.release_holds(client_id)?;
+                }
+                for client in self.clients.accounts(client_id) {
+                    let client = client.map_err(|_| ExecutionError::StoreUnavailable)?;
+                    if !client.held.is_zero() {
+                        return Err(ExecutionError::FundsHeld);
+                    }
+                }
+                self.log_transaction(tx_id, transaction, currency)?;
+                self.fetch_client_mut(client_id, currency)?;
+                self.set_closed(client_id)?;
+                Ok((client_id, currency))
+            }
+            Transaction::AdjustDebit(client_id, tx_id, amount) => {
+                let client = self.fetch_client_mut(client_id, currency)?;
+                if client.available < amount {
+                    return Err(ExecutionError::InsufficientFunds);
+                }
+                let moved = moved(client, -amount, Decimal::ZERO)?;
+                self.log_transaction(tx_id, transaction, currency)?;
+                *self.fetch_client_mut(client_id, currency)? = moved;
+                Ok((client_id, currency))
+            }
+            Transaction::WithdrawalHold(client_id, tx_id, amount) => {
+                self.check_limits(client_id, currency, amount)?;
+                let overdraft = self.overdraft(client_id);
+                let client = self.fetch_or_create_client_mut(client_id, currency)?;
+                funded(client, amount, overdraft)?;
+                let moved = moved(client, -amount, amount)?;
+                self.log_transaction(tx_id, transaction, currency)?;
+                *self.fetch_client_mut(client_id, currency)? = moved;
+                self.pending_withdrawals.insert(tx_id, amount);
+                self.record_withdrawal(client_id, currency, amount);
+                Ok((client_id, currency))
+            }
+            // Settlements finalize funds reserved before a lock, so they bypass it
+            Transaction::WithdrawalSettle(client_id, tx_id)
+            | Transaction::WithdrawalCancel(client_id, tx_id) => {
+                let Some(&amount) = self.pending_withdrawals.get(&tx_id) else {
+                    return Err(ExecutionError::NoPendingWithdrawal);
+                };
+                let Some((hold, hold_currency)) = self.logged(tx_id)? else {
+                    return Err(ExecutionError::TransactionNotFound);
+                };
+                if hold.client_id() != client_id {
+                    return Err(ExecutionError::ClientMismatch);
+                }
+                if row_currency.is_some_and(|currency| currency != hold_currency) {
+                    return Err(ExecutionError::CurrencyMismatch);
                 }
-                let (src_client_id, src_amount) = self.fetch_disputed_transaction(tx_id)?;
-                let client = self.fetch_or_create_client_mut(src_client_id)?;
-                client.available += src_amount;
-                client.held -= src_amount;
-                self.disputed_transactions.remove(&tx_id);
-            }
-            Transaction::Chargeback(_, tx_id) => {
-                if !self.disputed_transactions.contains(&tx_id) {
-                    return Err(ExecutionError::NonDisputedTransaction);
+                let released = match transaction {
+                    Transaction::WithdrawalCancel(..) => amount,
+                    _ => Decimal::ZERO,
+                };
+                let client = self.fetch_client_mut(client_id, hold_currency)?;
+                *client = moved(client, released, -amount)?;
+                self.pending_withdrawals.remove(&tx_id);
+                Ok((client_id, hold_currency))
+            }
+            Transaction::Custom(client_id, tx_id, amount, custom) => {
+                let client = self.fetch_or_create_client_mut(client_id, currency)?;
+                let (available, held) = registry::handler(custom).apply(client, amount)?;
+                let moved = moved(client, available, held)?;
+                self.log_transaction(tx_id, transaction, currency)?;
+                *self.fetch_client_mut(client_id, currency)? = moved;
+                Ok((client_id, currency))
+            }
+            Transaction::AssertBalance(client_id, _, expected, expected_total) => {
+                let (actual, total) = self
+                    .account(client_id, currency)
+                    .map(|client| (client.available, client.total))
+                    .unwrap_or_default();
+                if actual != expected || expected_total.is_some_and(|expected| total != expected) {
+                    return Err(ExecutionError::BalanceMismatch {
+                        expected,
+                        actual,
+                        expected_total,
+                        total,
+                    });
                 }
-                let (src_client_id, src_amount) = self.fetch_disputed_transaction(tx_id)?;
-                let client = self.fetch_or_create_client_mut(src_client_id)?;
-                client.held -= src_amount;
-                client.total -= src_amount;
-                client.locked = true;
-                self.disputed_transactions.remove(&tx_id);
+                Ok((client_id, currency))
             }
         }
-        Ok(())
     }
 
-    fn fetch_or_create_client_mut(
+    /// Fee of a deposit or withdrawal by the client, rounded to the amount precision. Zero
+    /// without a fee schedule and for the fee account itself.
+    fn fee(
+        &self,
+        client_id: u16,
+        transaction_type: TransactionType,
+        amount: Decimal,
+    ) -> Result<Decimal, ExecutionError> {
+        let Some(fees) = &self.config.fees else {
+            return Ok(Decimal::ZERO);
+        };
+        let fee = match transaction_type {
+            _ if client_id == fees.account => return Ok(Decimal::ZERO),
+            TransactionType::Deposit => fees.deposit,
+            TransactionType::Withdrawal => fees.withdrawal,
+            _ => return Ok(Decimal::ZERO),
+        };
+        fee.charge(amount)
+            .map(|fee| self.config.amounts.round(fee))
+            .ok_or(ExecutionError::Overflow)
+    }
+
+    /// The fee account after collecting the client's `fee` with the fees the client paid so far,
+    /// `None` if there is nothing to collect. Computed before anything changes, like `moved`. The
+    /// fee account collects fees even when locked.
+    fn collected_fee(
         &mut self,
         client_id: u16,
-    ) -> Result<&mut Client, ExecutionError> {
-        let client = self
-            .clients
-            .entry(client_id)
-            .or_insert(Client::new(client_id));
-        if client.locked {
-            return Err(ExecutionError::AccountLocked);
+        currency: Currency,
+        fee: Decimal,
+    ) -> Result<Option<(Client, Decimal)>, ExecutionError> {
+        let Some(account) = self.config.fees.as_ref().map(|fees| fees.account) else {
+            return Ok(None);
+        };
+        if fee.is_zero() {
+            return Ok(None);
         }
-        Ok(client)
+        let paid = self
+            .fees_paid
+            .get(&(client_id, currency))
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+            .checked_add(fee)
+            .ok_or(ExecutionError::Overflow)?;
+        let account = self.fetch_client_mut(account, currency)?;
+        Ok(Some((moved(account, fee, Decimal::ZERO)?, paid)))
     }
 
-    pub fn print_client_report(&self) {
-        let mut writer = Writer::from_writer(io::stdout());
+    fn collect_fee(
+        &mut self,
+        client_id: u16,
+        collected: Option<(Client, Decimal)>,
+    ) -> Result<(), ExecutionError> {
+        if let Some((collected, paid)) = collected {
+            let (account, currency) = (collected.id, collected.currency);
+            *self.fetch_client_mut(account, currency)? = collected;
+            self.fees_paid.insert((client_id, currency), paid);
+        }
+        Ok(())
+    }
 
-        // Write header
-        writer.write_record(&["client", "available", "held", "total", "locked"])
-            .expect("failed to write CSV header");
+    /// Rejects a deposit above the KYC deposit limit of a client that isn't verified.
+    fn check_kyc(&self, client_id: u16, amount: Decimal) -> Result<(), ExecutionError> {
+        let Some(limit) = self.config.kyc_deposit_limit else {
+            return Ok(());
+        };
+        let kyc = match &self.config.clients {
+            Some(clients) => clients.kyc(client_id),
+            None => KycStatus::default(),
+        };
+        if amount > limit && kyc != KycStatus::Verified {
+            return Err(ExecutionError::KycRequired);
+        }
+        Ok(())
+    }
 
-        // Write rows
-        for client in self.clients.values() {
-            writer.write_record(&[
-                client.id.to_string(),
-                client.available.to_string(),
-                client.held.to_string(),
-                client.total.to_string(),
-                client.locked.to_string(),
-            ])
-            .expect("failed to write CSV record");
+    /// Rejects a withdrawal of `amount` the client's withdrawal limits don't allow now.
+    fn check_limits(
+        &self,
+        client_id: u16,
+        currency: Currency,
+        amount: Decimal,
+    ) -> Result<(), ExecutionError> {
+        let Some(limits) = &self.config.limits else {
+            return Ok(());
+        };
+        let allowed = self
+            .velocity
+            .get(&(client_id, currency))
+            .unwrap_or(&Velocity::default())
+            .allows(&limits.client(client_id), amount, self.clock.now());
+        if !allowed {
+            return Err(ExecutionError::LimitExceeded);
         }
+        Ok(())
+    }
 
-        // Ensure all data is flushed
-        writer.flush().expect("failed to flush CSV writer");
+    /// How far the client's withdrawals may take its available funds below zero, zero without
+    /// overdrafts. A `set_overdraft` transaction overrides the withdrawal limits.
+    pub fn overdraft(&self, client_id: u16) -> Decimal {
+        if !self.config.overdrafts {
+            return Decimal::ZERO;
+        }
+        self.overdrafts
+            .get(&client_id)
+            .copied()
+            .or_else(|| {
+                let limits = self.config.limits.as_ref()?;
+                limits.client(client_id).overdraft
+            })
+            .unwrap_or(Decimal::ZERO)
     }
 
-    fn fetch_disputed_transaction(&self, tx_id: u32) -> Result<(u16, Decimal), ExecutionError> {
-        let transaction = self
-            .transaction_log
-            .get(&tx_id)
-            .ok_or(ExecutionError::TransactionNotFound)?;
-        match transaction {
-            Transaction::Deposit(client_id, _, amount) => Ok((*client_id, *amount)),
-            _ => Err(ExecutionError::IneligibleTransaction),
+    fn record_withdrawal(&mut self, client_id: u16, currency: Currency, amount: Decimal) {
+        if let Some(limits) = &self.config.limits {
+            let limits = limits.client(client_id);
+            self.velocity
+                .entry((client_id, currency))
+                .or_default()
+                .record(&limits, amount, self.clock.now());
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Current time of dispute aging, see `DisputeAging`.
+    fn dispute_clock(&self) -> u64 {
+        match self.config.dispute_aging {
+            DisputeAging::Clock => self.clock.now(),
+            DisputeAging::Sequence => self.executed,
+        }
+    }
 
-    #[test]
-    fn test_engine_creation() {
-        let engine = Engine::new();
-        assert!(engine.clients.is_empty());
+    /// Resolves the open disputes older than `max_age`, in the unit of the configured
+    /// `DisputeAging`, as if their owners had sent a resolve row. Hooks and event sinks see the
+    /// resolves like any other. Returns the applied resolves, oldest dispute first.
+    ///
+    /// A dispute the engine can't resolve, e.g. on a frozen account, stays open but doesn't
+    /// expire again.
+    pub fn expire_disputes(&mut self, max_age: u64) -> Result<Vec<Transaction>, ExecutionError> {
+        let Some(cutoff) = self.dispute_clock().checked_sub(max_age) else {
+            return Ok(Vec::new());
+        };
+        let mut resolved = Vec::new();
+        for tx_id in self.disputes.opened_before(cutoff) {
+            let Some((disputed, _)) = self.logged_or_archived(tx_id)? else {
+                self.disputes.stop_aging(tx_id);
+                continue;
+            };
+            let resolve = Transaction::Resolve(disputed.client_id(), tx_id);
+            match self.run(resolve.clone(), None, None) {
+                Ok(()) => resolved.push(resolve),
+                Err(err @ (ExecutionError::LogUnavailable | ExecutionError::StoreUnavailable)) => {
+                    return Err(err);
+                }
+                Err(_) => {
+                    self.disputes.stop_aging(tx_id);
+                }
+            }
+        }
+        Ok(resolved)
     }
 
-    #[test]
-    fn test_execution_deposit_and_withdraw() {
-        let mut engine = Engine::new();
-        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
-        assert!(engine.execute(deposit).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
-            assert_eq!(client1.available, Decimal::new(100000, 4));
-            assert_eq!(client1.total, Decimal::new(100000, 4));
-            assert!(!client1.locked);
+    /// Posts interest of `rate` times the available funds to every account that isn't locked
+    /// and has positive available funds, as deposits at the time `as_of`. The amounts are
+    /// rounded by the amount policy and the deposits are logged like input deposits, with ids
+    /// counting down from `u32::MAX` past the ids in use so they stay clear of the input's.
+    /// An account whose deposit the engine rejects, e.g. over the KYC deposit limit, gets no
+    /// interest. Returns the posted deposits with their currency.
+    pub fn accrue_interest(
+        &mut self,
+        rate: Decimal,
+        as_of: u64,
+    ) -> Result<Vec<(Transaction, Currency)>, ExecutionError> {
+        self.clock.observe(as_of);
+        let mut accounts = Vec::new();
+        for client in self.clients.iter() {
+            let client = client.map_err(|_| ExecutionError::StoreUnavailable)?;
+            if !client.locked && client.available > Decimal::ZERO {
+                accounts.push((client.id, client.currency, client.available));
+            }
         }
-        let withdrawal = Transaction::Withdrawal(1, 101, Decimal::new(50000, 4));
-        assert!(engine.execute(withdrawal).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
-            assert_eq!(client1.available, Decimal::new(50000, 4));
-            assert_eq!(client1.total, Decimal::new(50000, 4));
-            assert!(!client1.locked);
+        let mut posted = Vec::new();
+        let mut tx_id = u32::MAX;
+        for (client_id, currency, available) in accounts {
+            let interest = available
+                .checked_mul(rate)
+                .ok_or(ExecutionError::Overflow)?;
+            let interest = self.config.amounts.round(interest);
+            if interest <= Decimal::ZERO {
+                continue;
+            }
+            while self.logged_or_archived(tx_id)?.is_some() {
+                tx_id = tx_id
+                    .checked_sub(1)
+                    .ok_or(ExecutionError::DuplicateTransaction)?;
+            }
+            let deposit = Transaction::Deposit(client_id, tx_id, interest);
+            match self.run(deposit.clone(), Some(currency), None) {
+                Ok(()) => posted.push((deposit, currency)),
+                Err(err @ (ExecutionError::LogUnavailable | ExecutionError::StoreUnavailable)) => {
+                    return Err(err);
+                }
+                Err(_) => {}
+            }
         }
+        Ok(posted)
     }
 
-    #[test]
-    fn test_execution_deposit_and_resolved() {
-        let mut engine = Engine::new();
-        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
-        assert!(engine.execute(deposit).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
-            assert_eq!(client1.available, Decimal::new(100000, 4));
-            assert_eq!(client1.total, Decimal::new(100000, 4));
-            assert!(!client1.locked);
+    /// When the open dispute of a transaction was opened, in the unit of the dispute aging.
+    pub fn dispute_opened(&self, tx_id: u32) -> Option<u64> {
+        self.disputes.opened(tx_id)
+    }
+
+    fn log_transaction(
+        &mut self,
+        tx_id: u32,
+        transaction: &Transaction,
+        currency: Currency,
+    ) -> Result<(), ExecutionError> {
+        self.transaction_log
+            .insert(transaction, currency)
+            .map_err(|_| ExecutionError::LogUnavailable)?;
+        self.client_transactions
+            .entry(transaction.client_id())
+            .or_default()
+            .push(tx_id);
+        if let Some(archive) = self.archive.as_mut() {
+            archive.track(tx_id);
         }
-        let dispute = Transaction::Dispute(1, 100);
-        assert!(engine.execute(dispute).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
-            assert_eq!(client1.available, Decimal::new(0, 4));
-            assert_eq!(client1.held, Decimal::new(100000, 4));
-            assert_eq!(client1.total, Decimal::new(100000, 4));
-            assert!(!client1.locked);
+        Ok(())
+    }
+
+    fn logged(&self, tx_id: u32) -> Result<Option<(Transaction, Currency)>, ExecutionError> {
+        self.transaction_log
+            .get(tx_id)
+            .map_err(|_| ExecutionError::LogUnavailable)
+    }
+
+    /// Looks a transaction up in the log, falling back to the archive.
+    fn logged_or_archived(
+        &self,
+        tx_id: u32,
+    ) -> Result<Option<(Transaction, Currency)>, ExecutionError> {
+        if let Some(logged) = self.logged(tx_id)? {
+            return Ok(Some(logged));
         }
-        let resolve = Transaction::Resolve(1, 100);
-        assert!(engine.execute(resolve).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
-            assert_eq!(client1.available, Decimal::new(100000, 4));
-            assert_eq!(client1.held, Decimal::new(0, 4));
-            assert_eq!(client1.total, Decimal::new(100000, 4));
-            assert!(!client1.locked);
+        match &self.archive {
+            Some(archive) => archive
+                .find(tx_id)
+                .map_err(|_| ExecutionError::ArchiveUnavailable),
+            None => Ok(None),
         }
     }
 
-    #[test]
-    fn test_execution_deposit_and_chargeback() {
-        let mut engine = Engine::new();
-        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
-        assert!(engine.execute(deposit).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
-            assert_eq!(client1.available, Decimal::new(100000, 4));
-            assert_eq!(client1.total, Decimal::new(100000, 4));
-            assert!(!client1.locked);
+    /// Moves logged transactions that fell out of the dispute window to the archive. Disputed
+    /// transactions stay in memory until they are settled. Returns the number of archived transactions.
+    pub fn archive_old_transactions(&mut self) -> io::Result<usize> {
+        let Some(mut archive) = self.archive.take() else {
+            return Ok(0);
+        };
+        let mut archived = 0;
+        let mut result = Ok(());
+        while let Some(candidates) = archive.next_candidates() {
+            let mut entries = Vec::with_capacity(candidates.len());
+            for tx_id in candidates {
+                if self.disputes.state(tx_id) == DisputeState::Open
+                    || self.pending_withdrawals.contains_key(&tx_id)
+                {
+                    archive.requeue(tx_id);
+                } else {
+                    match self.transaction_log.get(tx_id) {
+                        Ok(Some(entry)) => entries.push(entry),
+                        Ok(None) => {}
+                        Err(err) => {
+                            result = Err(err);
+                            break;
+                        }
+                    }
+                }
+            }
+            if result.is_err() || entries.is_empty() {
+                // A failed read or only disputed transactions left outside the window
+                break;
+            }
+            // The log entries are removed only once the segment is written
+            if let Err(err) = archive.write_segment(&entries) {
+                result = Err(err);
+                break;
+            }
+            for (transaction, _) in &entries {
+                if let Err(err) = self.transaction_log.remove(transaction.tx_id()) {
+                    result = Err(err);
+                }
+                // Archived transactions can't be disputed, reversed or refunded anyway
+                self.disputes.forget(transaction.tx_id());
+                self.refunds.remove(&transaction.tx_id());
+            }
+            archived += entries.len();
         }
-        let dispute = Transaction::Dispute(1, 100);
-        assert!(engine.execute(dispute).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
-            assert_eq!(client1.available, Decimal::new(0, 4));
-            assert_eq!(client1.held, Decimal::new(100000, 4));
-            assert_eq!(client1.total, Decimal::new(100000, 4));
-            assert!(!client1.locked);
+        self.archive = Some(archive);
+        result.map(|()| archived)
+    }
+
+    /// Removes the clients without a transaction since `inactive_since` on the engine clock, with
+    /// their accounts and logged transactions, and returns their exports. With `zero_balance_only`
+    /// only clients whose accounts are empty are removed. Locked and closed clients and clients
+    /// with held funds, open disputes or pending withdrawal holds are kept. Clients restored
+    /// without activity times, e.g. from a database, count as last active at time zero. Archived
+    /// transactions of pruned clients stay in the archive.
+    pub fn prune(
+        &mut self,
+        inactive_since: u64,
+        zero_balance_only: bool,
+    ) -> Result<Vec<ClientExport>, ExecutionError> {
+        // Whether all of a client's accounts may go
+        let mut settled: BTreeMap<u16, bool> = BTreeMap::new();
+        for client in self.clients.iter() {
+            let client = client.map_err(|_| ExecutionError::StoreUnavailable)?;
+            let empty = !client.locked
+                && !client.closed
+                && client.held == Decimal::ZERO
+                && (!zero_balance_only || client.total == Decimal::ZERO);
+            *settled.entry(client.id).or_insert(true) &= empty;
         }
-        let resolve = Transaction::Chargeback(1, 100);
-        assert!(engine.execute(resolve).is_ok());
-        {
-            let client1 = engine.clients.get(&1).unwrap();
+        let mut pruned = Vec::new();
+        for (client_id, settled) in settled {
+            let active = self.last_active.get(&client_id).copied().unwrap_or(0);
+            let holds = self
+                .client_transactions
+                .get(&client_id)
+                .into_iter()
+                .flatten()
+                .any(|tx_id| self.is_disputed(*tx_id) || self.is_withdrawal_pending(*tx_id));
+            if !settled || holds || active >= inactive_since {
+                continue;
+            }
+            let export = self
+                .export_client(client_id)
+                .map_err(|_| ExecutionError::StoreUnavailable)?;
+            if let Some(export) = export {
+                self.remove_client(&export)?;
+                pruned.push(export);
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Forgets an exported client with its accounts and logged transactions.
+    fn remove_client(&mut self, export: &ClientExport) -> Result<(), ExecutionError> {
+        for entry in &export.transactions {
+            let tx_id = entry.transaction.tx_id();
+            self.transaction_log
+                .remove(tx_id)
+                .map_err(|_| ExecutionError::LogUnavailable)?;
+            self.disputes.forget(tx_id);
+            self.refunds.remove(&tx_id);
+        }
+        for account in &export.accounts {
+            self.clients
+                .remove(account.id, account.currency)
+                .map_err(|_| ExecutionError::StoreUnavailable)?;
+            self.fees_paid.remove(&(account.id, account.currency));
+            self.velocity.remove(&(account.id, account.currency));
+        }
+        self.client_transactions.remove(&export.client_id);
+        self.seeded_clients.remove(&export.client_id);
+        self.overdrafts.remove(&export.client_id);
+        self.last_active.remove(&export.client_id);
+        Ok(())
+    }
+
+    /// Time on the engine clock of the client's last transaction, `None` if it had none since
+    /// the engine was restored without activity times.
+    pub fn last_active(&self, client_id: u16) -> Option<u64> {
+        self.last_active.get(&client_id).copied()
+    }
+
+    /// Last activity of the clients that have one, ordered by client id.
+    pub(crate) fn activity(&self) -> impl Iterator<Item = (u16, u64)> + '_ {
+        self.last_active
+            .iter()
+            .map(|(client_id, time)| (*client_id, *time))
+    }
+
+    /// Restores the last activity of a client from a snapshot.
+    pub(crate) fn restore_activity(&mut self, client_id: u16, time: u64) {
+        self.last_active.insert(client_id, time);
+    }
+
+    /// Looks up a transaction in the archive, the slow path for transactions no longer in the log.
+    pub fn archived_transaction(&self, tx_id: u32) -> io::Result<Option<Transaction>> {
+        match &self.archive {
+            Some(archive) => Ok(archive.find(tx_id)?.map(|(transaction, _)| transaction)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Takes the settings of `config` that may change while the engine runs, for a reloaded
+    /// configuration file: amount precision and rounding, validation, fees and limits. Its other
+    /// settings are ignored, as the state already applied depends on them.
+    pub fn reload_config(&mut self, config: &EngineConfig) {
+        self.config.amounts = config.amounts;
+        self.config.validation = config.validation.clone();
+        self.config.fees = config.fees.clone();
+        self.config.limits = config.limits.clone();
+    }
+
+    /// Returns the client's account in the default currency.
+    pub fn client(&self, client_id: u16) -> Option<Client> {
+        self.account(client_id, self.config.default_currency)
+    }
+
+    /// Looks up an account. An account in an unreadable disk store is reported as missing.
+    pub fn account(&self, client_id: u16, currency: Currency) -> Option<Client> {
+        self.clients.get(client_id, currency).ok().flatten()
+    }
+
+    /// Iterates over the accounts of a client in all currencies, ordered by currency.
+    pub fn client_accounts(&self, client_id: u16) -> impl Iterator<Item = io::Result<Client>> {
+        self.clients.accounts(client_id)
+    }
+
+    /// Checks every account against `invariants::check_account`.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        for client in self.clients.iter() {
+            check_account(&client.map_err(|_| InvariantViolation::StoreUnavailable)?)?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over client accounts ordered by client id and currency.
+    pub fn clients(&self) -> impl Iterator<Item = io::Result<Client>> {
+        self.clients.iter()
+    }
+
+    /// Returns the client whose balances the transaction changes: the owner of the referenced
+    /// transaction for dispute and settlement rows, the row's client otherwise.
+    pub fn affected_client_id(&self, transaction: &Transaction) -> u16 {
+        if !transaction.references_transaction() {
+            return transaction.client_id();
+        }
+        match self.transaction_log.get(transaction.tx_id()) {
+            Ok(Some((logged, _))) => logged.client_id(),
+            _ => transaction.client_id(),
+        }
+    }
+
+    /// Returns the account whose balances the transaction changes, see `affected_client_id`.
+    pub fn affected_account(
+        &self,
+        transaction: &Transaction,
+        currency: Option<Currency>,
+    ) -> Option<Client> {
+        let currency = match self.transaction_log.get(transaction.tx_id()) {
+            Ok(Some((_, logged_currency))) if transaction.references_transaction() => {
+                logged_currency
+            }
+            _ => currency.unwrap_or(self.config.default_currency),
+        };
+        self.account(self.affected_client_id(transaction), currency)
+    }
+
+    /// Looks up a logged deposit or withdrawal.
+    /// A transaction in an unreadable disk log is reported as missing.
+    pub fn transaction(&self, tx_id: u32) -> Option<Transaction> {
+        self.transaction_log
+            .get(tx_id)
+            .ok()
+            .flatten()
+            .map(|(logged, _)| logged)
+    }
+
+    /// Currency of a logged deposit or withdrawal.
+    pub fn transaction_currency(&self, tx_id: u32) -> Option<Currency> {
+        self.transaction_log
+            .get(tx_id)
+            .ok()
+            .flatten()
+            .map(|(_, currency)| currency)
+    }
+
+    /// Iterates over logged deposits and withdrawals with their currency, ordered by id.
+    /// Archived transactions are not included.
+    pub fn transactions(&self) -> impl Iterator<Item = io::Result<(Transaction, Currency)>> {
+        self.transaction_log.iter()
+    }
+
+    /// Iterates over the client's logged deposits, withdrawals and admin transactions with their
+    /// currency, in the order they were applied. Archived transactions are read back from the
+    /// archive.
+    pub fn client_history(
+        &self,
+        client_id: u16,
+    ) -> impl Iterator<Item = io::Result<(Transaction, Currency)>> + '_ {
+        self.client_transactions
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|tx_id| {
+                self.logged_or_archived(*tx_id)
+                    .map_err(io::Error::other)
+                    .transpose()
+            })
+    }
+
+    /// Fees each account paid so far with the fee schedule, ordered by client and currency.
+    /// Fees paid before the engine was restored from saved state are not included.
+    pub fn fees_paid(&self) -> impl Iterator<Item = ((u16, Currency), Decimal)> + '_ {
+        self.fees_paid.iter().map(|(account, fee)| (*account, *fee))
+    }
+
+    /// Whether the transaction is currently under dispute.
+    pub fn is_disputed(&self, tx_id: u32) -> bool {
+        self.disputes.state(tx_id) == DisputeState::Open
+    }
+
+    /// Amount held for an open dispute. Less than the transaction amount if the dispute policy
+    /// capped the hold.
+    pub fn dispute_hold(&self, tx_id: u32) -> Option<Decimal> {
+        self.disputes.hold(tx_id)
+    }
+
+    /// Amount refunded so far from the deposit, zero if it wasn't refunded.
+    pub fn refunded(&self, tx_id: u32) -> Decimal {
+        self.refunds.get(&tx_id).copied().unwrap_or(Decimal::ZERO)
+    }
+
+    /// Amount still held by a withdrawal hold that was neither settled nor cancelled.
+    pub fn pending_withdrawal(&self, tx_id: u32) -> Option<Decimal> {
+        self.pending_withdrawals.get(&tx_id).copied()
+    }
+
+    pub fn is_withdrawal_pending(&self, tx_id: u32) -> bool {
+        self.pending_withdrawals.contains_key(&tx_id)
+    }
+
+    /// Where the transaction is in the dispute lifecycle, `DisputeState::None` if it was never
+    /// disputed.
+    pub fn dispute_state(&self, tx_id: u32) -> DisputeState {
+        self.disputes.state(tx_id)
+    }
+
+    /// Whether the transaction's dispute was resolved or charged back.
+    pub fn is_dispute_closed(&self, tx_id: u32) -> bool {
+        self.disputes.state(tx_id).is_closed()
+    }
+
+    /// Amount charged back from a deposit that a chargeback reversal would re-credit, `None`
+    /// once it was reversed.
+    pub fn charged_back(&self, tx_id: u32) -> Option<Decimal> {
+        self.disputes.charged_back(tx_id)
+    }
+
+    /// Exports the client's accounts and logged transactions with their dispute states, `None` if
+    /// the client has no account. Archived transactions are not part of the export.
+    pub fn export_client(&self, client_id: u16) -> io::Result<Option<ClientExport>> {
+        let accounts: Vec<Client> = self.client_accounts(client_id).collect::<io::Result<_>>()?;
+        if accounts.is_empty() {
+            return Ok(None);
+        }
+        let mut transactions = Vec::new();
+        for tx_id in self
+            .client_transactions
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+        {
+            let Some((transaction, currency)) = self.transaction_log.get(*tx_id)? else {
+                continue;
+            };
+            transactions.push(self.transaction_state(transaction, currency));
+        }
+        Ok(Some(ClientExport {
+            client_id,
+            accounts,
+            transactions,
+        }))
+    }
+
+    /// Checks that `import_client` would accept the export, without changing anything.
+    pub fn check_import(&self, export: &ClientExport) -> Result<(), ImportError> {
+        export.verify()?;
+        if self.client_accounts(export.client_id).next().is_some() {
+            return Err(ImportError::ClientExists(export.client_id));
+        }
+        let foreign_currency = export
+            .accounts
+            .iter()
+            .any(|account| account.currency != self.config.default_currency);
+        if foreign_currency && !self.config.multi_currency {
+            return Err(ExecutionError::UnsupportedCurrency.into());
+        }
+        for entry in &export.transactions {
+            let tx_id = entry.transaction.tx_id();
+            if self.logged_or_archived(tx_id)?.is_some()
+                || self.is_disputed(tx_id)
+                || self.is_dispute_closed(tx_id)
+                || self.is_withdrawal_pending(tx_id)
+            {
+                return Err(ExecutionError::DuplicateTransaction.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Imports a client exported from another engine. Nothing changes if the export is
+    /// inconsistent, the client already has accounts or a transaction id is already taken.
+    pub fn import_client(&mut self, export: &ClientExport) -> Result<(), ImportError> {
+        self.check_import(export)?;
+        for entry in &export.transactions {
+            self.restore_transaction(entry)?;
+        }
+        for account in &export.accounts {
+            self.restore_account(account.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Restores an account from a snapshot.
+    pub(crate) fn restore_account(&mut self, client: Client) -> Result<(), ExecutionError> {
+        if client.closed {
+            self.closed.insert(client.id);
+        }
+        self.clients
+            .insert(client)
+            .map_err(|_| ExecutionError::StoreUnavailable)
+    }
+
+    /// Sets an account's balances without its history, e.g. from a previous run's report. Dispute
+    /// operations of the client referencing unknown transactions are then handled by the
+    /// pre-history policy.
+    pub(crate) fn seed_account(&mut self, client: Client) -> Result<(), ExecutionError> {
+        self.seeded_clients.insert(client.id);
+        self.restore_account(client)
+    }
+
+    /// A logged transaction with the state of its dispute or withdrawal hold.
+    pub(crate) fn transaction_state(
+        &self,
+        transaction: Transaction,
+        currency: Currency,
+    ) -> ExportedTransaction {
+        let tx_id = transaction.tx_id();
+        ExportedTransaction {
+            transaction,
+            currency,
+            dispute: self.dispute_state(tx_id),
+            dispute_amount: self.dispute_hold(tx_id).or(self.charged_back(tx_id)),
+            withdrawal_pending: self.is_withdrawal_pending(tx_id),
+            refunded: self.refunds.get(&tx_id).copied(),
+        }
+    }
+
+    /// Restores a logged transaction and the state of its dispute or withdrawal hold from a
+    /// snapshot.
+    pub(crate) fn restore_transaction(
+        &mut self,
+        entry: &ExportedTransaction,
+    ) -> Result<(), ExecutionError> {
+        let tx_id = entry.transaction.tx_id();
+        // The age of a restored dispute starts over
+        let now = self.dispute_clock();
+        let amount = entry.dispute_amount.unwrap_or(Decimal::ZERO);
+        self.disputes.restore(tx_id, entry.dispute, amount, now);
+        if let (true, Some(amount)) = (entry.withdrawal_pending, entry.transaction.amount()) {
+            self.pending_withdrawals.insert(tx_id, amount);
+        }
+        if let Some(refunded) = entry.refunded {
+            self.refunds.insert(tx_id, refunded);
+        }
+        if let Transaction::SetOverdraft(client_id, _, limit) = entry.transaction {
+            self.overdrafts.insert(client_id, limit);
+        }
+        self.log_transaction(tx_id, &entry.transaction, entry.currency)?;
+        // Accounts restored before their closure, e.g. from a database, are closed here
+        if let Transaction::CloseAccount(client_id, _) = entry.transaction {
+            self.set_closed(client_id)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves the client's open disputes and cancels its pending withdrawal holds like input
+    /// rows would, for closing its accounts under `ClosePolicy::Release`. Releases applied before
+    /// one fails, e.g. a resolve on a frozen account, stay applied.
+    fn release_holds(&mut self, client_id: u16) -> Result<(), ExecutionError> {
+        let mut releases = Vec::new();
+        for tx_id in self.disputes.open_ids() {
+            if let Some((disputed, _)) = self.logged_or_archived(tx_id)?
+                && disputed.client_id() == client_id
+            {
+                releases.push(Transaction::Resolve(client_id, tx_id));
+            }
+        }
+        let pending: Vec<u32> = self.pending_withdrawals.keys().copied().collect();
+        for tx_id in pending {
+            if let Some((hold, _)) = self.logged(tx_id)?
+                && hold.client_id() == client_id
+            {
+                releases.push(Transaction::WithdrawalCancel(client_id, tx_id));
+            }
+        }
+        for release in releases {
+            self.run(release, None, None)?;
+        }
+        Ok(())
+    }
+
+    fn set_closed(&mut self, client_id: u16) -> Result<(), ExecutionError> {
+        let currencies = self
+            .clients
+            .accounts(client_id)
+            .map(|client| client.map(|client| client.currency))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|_| ExecutionError::StoreUnavailable)?;
+        for currency in currencies {
+            self.fetch_client_mut(client_id, currency)?.closed = true;
+        }
+        self.closed.insert(client_id);
+        Ok(())
+    }
+
+    fn set_locked(&mut self, client_id: u16, locked: bool) -> Result<(), ExecutionError> {
+        let currencies = self
+            .clients
+            .accounts(client_id)
+            .map(|client| client.map(|client| client.currency))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|_| ExecutionError::StoreUnavailable)?;
+        for currency in currencies {
+            self.fetch_client_mut(client_id, currency)?.locked = locked;
+        }
+        Ok(())
+    }
+
+    /// Fetches or creates an account. A new account inherits the lock and closure of the client's
+    /// other accounts.
+    fn fetch_client_mut(
+        &mut self,
+        client_id: u16,
+        currency: Currency,
+    ) -> Result<&mut Client, ExecutionError> {
+        let unavailable = |_| ExecutionError::StoreUnavailable;
+        if self
+            .clients
+            .get_mut(client_id, currency)
+            .map_err(unavailable)?
+            .is_none()
+        {
+            let (mut locked, mut closed) = (false, false);
+            for client in self.clients.accounts(client_id) {
+                let client = client.map_err(unavailable)?;
+                locked |= client.locked;
+                closed |= client.closed;
+            }
+            let metadata = self
+                .config
+                .clients
+                .as_ref()
+                .and_then(|clients| clients.get(client_id))
+                .cloned();
+            self.clients
+                .insert(Client {
+                    locked,
+                    closed,
+                    metadata,
+                    ..Client::with_currency(client_id, currency)
+                })
+                .map_err(unavailable)?;
+        }
+        self.clients
+            .get_mut(client_id, currency)
+            .map_err(unavailable)?
+            .ok_or(ExecutionError::StoreUnavailable)
+    }
+
+    fn fetch_or_create_client_mut(
+        &mut self,
+        client_id: u16,
+        currency: Currency,
+    ) -> Result<&mut Client, ExecutionError> {
+        let client = self.fetch_client_mut(client_id, currency)?;
+        if client.locked {
+            return Err(ExecutionError::AccountLocked);
+        }
+        Ok(client)
+    }
+
+    /// Fetches the owner of a disputed transaction, applying the locked account policy.
+    fn fetch_dispute_client_mut(
+        &mut self,
+        client_id: u16,
+        currency: Currency,
+        opens_dispute: bool,
+    ) -> Result<&mut Client, ExecutionError> {
+        let policy = self.config.locked_account_policy;
+        let client = self.fetch_client_mut(client_id, currency)?;
+        if client.locked {
+            let allowed = match policy {
+                LockedAccountPolicy::Freeze => false,
+                LockedAccountPolicy::SettleOpen => !opens_dispute,
+                LockedAccountPolicy::Allow => true,
+            };
+            if !allowed {
+                return Err(ExecutionError::AccountLocked);
+            }
+        }
+        Ok(client)
+    }
+
+    /// Prints the client report as CSV to stdout. Fails rather than panics if stdout is closed.
+    pub fn print_client_report(&self) -> anyhow::Result<()> {
+        let mut writer = CsvReportWriter::new(io::stdout().lock(), ReportColumns::of(&self.config));
+        self.write_report(&mut writer)
+    }
+
+    /// Writes all clients ordered by id to the report writer.
+    pub fn write_report(&self, writer: &mut dyn ReportWriter) -> anyhow::Result<()> {
+        self.write_report_with(writer, &ReportOptions::default())
+    }
+
+    /// Streams the client report in client id order, one row per account, for an embedder's own
+    /// sinks like a database or an HTTP response. Fails only if the client store can't be read.
+    pub fn report_iter(&self) -> impl Iterator<Item = io::Result<AccountReportRow>> + '_ {
+        self.clients.iter().map(|client| {
+            client.map(|client| AccountReportRow::from(&self.config.amounts.round_client(&client)))
+        })
+    }
+
+    /// Writes the clients `options` select in its order to the report writer. Sorting by a
+    /// balance collects the selected clients in memory, the id order streams them.
+    pub fn write_report_with(
+        &self,
+        writer: &mut dyn ReportWriter,
+        options: &ReportOptions,
+    ) -> anyhow::Result<()> {
+        let mut sorted = Vec::new();
+        for client in self.clients.iter() {
+            let client = self.config.amounts.round_client(&client?);
+            if !options.includes(&client) {
+                continue;
+            }
+            match options.sort {
+                ReportSort::Client => writer.write_client(&client)?,
+                ReportSort::Total | ReportSort::Available => sorted.push(client),
+            }
+        }
+        match options.sort {
+            ReportSort::Client => {}
+            // Stable, so equal balances stay ordered by id
+            ReportSort::Total => sorted.sort_by_key(|client| Reverse(client.total)),
+            ReportSort::Available => sorted.sort_by_key(|client| Reverse(client.available)),
+        }
+        for client in &sorted {
+            writer.write_client(client)?;
+        }
+        writer.finish()
+    }
+
+    /// Returns the owner, amount and currency of a disputable transaction. A row currency
+    /// different from the transaction currency is rejected.
+    fn fetch_disputed_transaction(
+        &self,
+        client_id: u16,
+        tx_id: u32,
+        currency: Option<Currency>,
+    ) -> Result<(u16, Decimal, Currency), ExecutionError> {
+        let Some((transaction, src_currency)) = self.logged(tx_id)? else {
+            if let Some(archive) = &self.archive
+                && archive
+                    .find(tx_id)
+                    .map_err(|_| ExecutionError::ArchiveUnavailable)?
+                    .is_some()
+            {
+                return Err(ExecutionError::TransactionArchived);
+            }
+            return Err(ExecutionError::TransactionNotFound);
+        };
+        match transaction {
+            Transaction::Deposit(src_client_id, _, amount) => {
+                if self.config.check_dispute_client && src_client_id != client_id {
+                    return Err(ExecutionError::ClientMismatch);
+                }
+                if currency.is_some_and(|currency| currency != src_currency) {
+                    return Err(ExecutionError::CurrencyMismatch);
+                }
+                Ok((src_client_id, amount, src_currency))
+            }
+            _ => Err(ExecutionError::IneligibleTransaction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use quickcheck::Arbitrary;
+
+    use super::*;
+    use crate::{
+        clock::ManualClock,
+        events::{Event, EventKind},
+        fees::FeeSchedule,
+        kyc::ClientDirectory,
+        limits::WithdrawalLimits,
+    };
+
+    #[test]
+    fn test_engine_creation() {
+        let engine = Engine::new();
+        assert!(engine.clients().next().is_none());
+    }
+
+    #[test]
+    fn test_execution_deposit_and_withdraw() {
+        let mut engine = Engine::new();
+        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
+        assert!(engine.execute(deposit).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
+            assert_eq!(client1.available, Decimal::new(100000, 4));
+            assert_eq!(client1.total, Decimal::new(100000, 4));
+            assert!(!client1.locked);
+        }
+        let withdrawal = Transaction::Withdrawal(1, 101, Decimal::new(50000, 4));
+        assert!(engine.execute(withdrawal).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
+            assert_eq!(client1.available, Decimal::new(50000, 4));
+            assert_eq!(client1.total, Decimal::new(50000, 4));
+            assert!(!client1.locked);
+        }
+    }
+
+    #[test]
+    fn test_execution_deposit_and_resolved() {
+        let mut engine = Engine::new();
+        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
+        assert!(engine.execute(deposit).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
+            assert_eq!(client1.available, Decimal::new(100000, 4));
+            assert_eq!(client1.total, Decimal::new(100000, 4));
+            assert!(!client1.locked);
+        }
+        let dispute = Transaction::Dispute(1, 100);
+        assert!(engine.execute(dispute).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
+            assert_eq!(client1.available, Decimal::new(0, 4));
+            assert_eq!(client1.held, Decimal::new(100000, 4));
+            assert_eq!(client1.total, Decimal::new(100000, 4));
+            assert!(!client1.locked);
+        }
+        let resolve = Transaction::Resolve(1, 100);
+        assert!(engine.execute(resolve).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
+            assert_eq!(client1.available, Decimal::new(100000, 4));
+            assert_eq!(client1.held, Decimal::new(0, 4));
+            assert_eq!(client1.total, Decimal::new(100000, 4));
+            assert!(!client1.locked);
+        }
+    }
+
+    #[test]
+    fn test_execution_deposit_and_chargeback() {
+        let mut engine = Engine::new();
+        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
+        assert!(engine.execute(deposit).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
+            assert_eq!(client1.available, Decimal::new(100000, 4));
+            assert_eq!(client1.total, Decimal::new(100000, 4));
+            assert!(!client1.locked);
+        }
+        let dispute = Transaction::Dispute(1, 100);
+        assert!(engine.execute(dispute).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
+            assert_eq!(client1.available, Decimal::new(0, 4));
+            assert_eq!(client1.held, Decimal::new(100000, 4));
+            assert_eq!(client1.total, Decimal::new(100000, 4));
+            assert!(!client1.locked);
+        }
+        let resolve = Transaction::Chargeback(1, 100);
+        assert!(engine.execute(resolve).is_ok());
+        {
+            let client1 = engine.client(1).unwrap();
             assert_eq!(client1.available, Decimal::new(0, 4));
             assert_eq!(client1.held, Decimal::new(0, 4));
             assert_eq!(client1.total, Decimal::new(0, 4));
@@ -283,4 +1915,1453 @@ mod tests {
             Some(ExecutionError::AlreadyDisputedTransaction)
         );
     }
+
+    #[test]
+    fn test_hooks_run_after_matching_transaction_type() {
+        let mut engine = Engine::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_hook = Arc::clone(&seen);
+        engine.register_hook(
+            TransactionType::Chargeback,
+            Box::new(move |transaction, client| {
+                seen_by_hook.lock().unwrap().push((
+                    transaction.clone(),
+                    client.total,
+                    client.locked,
+                ));
+            }),
+        );
+        assert!(
+            engine
+                .execute(Transaction::Deposit(1, 100, Decimal::new(100000, 4)))
+                .is_ok()
+        );
+        assert!(engine.execute(Transaction::Dispute(1, 100)).is_ok());
+        assert!(seen.lock().unwrap().is_empty());
+        assert!(engine.execute(Transaction::Chargeback(1, 100)).is_ok());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(Transaction::Chargeback(1, 100), Decimal::ZERO, true)]
+        );
+    }
+
+    #[test]
+    fn test_hooks_skip_rejected_transactions() {
+        let mut engine = Engine::new();
+        let calls = Arc::new(Mutex::new(0));
+        let calls_by_hook = Arc::clone(&calls);
+        engine.register_hook(
+            TransactionType::Withdrawal,
+            Box::new(move |_, _| *calls_by_hook.lock().unwrap() += 1),
+        );
+        let withdrawal = Transaction::Withdrawal(1, 100, Decimal::new(100000, 4));
+        assert_eq!(
+            engine.execute(withdrawal).err(),
+            Some(ExecutionError::InsufficientFunds)
+        );
+        assert_eq!(*calls.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_execution_dispute_client_mismatch() {
+        let mut engine = Engine::new();
+        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
+        assert!(engine.execute(deposit).is_ok());
+        assert_eq!(
+            engine.execute(Transaction::Dispute(2, 100)).err(),
+            Some(ExecutionError::ClientMismatch)
+        );
+        assert!(engine.execute(Transaction::Dispute(1, 100)).is_ok());
+        assert_eq!(
+            engine.execute(Transaction::Resolve(2, 100)).err(),
+            Some(ExecutionError::ClientMismatch)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Chargeback(2, 100)).err(),
+            Some(ExecutionError::ClientMismatch)
+        );
+        assert!(engine.client(2).is_none());
+    }
+
+    #[test]
+    fn test_execution_dispute_client_mismatch_permissive() {
+        let mut engine = Engine::with_config(EngineConfig {
+            check_dispute_client: false,
+            ..EngineConfig::default()
+        });
+        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
+        assert!(engine.execute(deposit).is_ok());
+        assert!(engine.execute(Transaction::Dispute(2, 100)).is_ok());
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.held, Decimal::new(100000, 4));
+    }
+
+    #[test]
+    fn test_execution_duplicate_transaction() {
+        let mut engine = Engine::new();
+        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
+        assert!(engine.execute(deposit.clone()).is_ok());
+        assert_eq!(
+            engine.execute(deposit).err(),
+            Some(ExecutionError::DuplicateTransaction)
+        );
+        let withdrawal = Transaction::Withdrawal(1, 100, Decimal::new(50000, 4));
+        assert_eq!(
+            engine.execute(withdrawal).err(),
+            Some(ExecutionError::DuplicateTransaction)
+        );
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.total, Decimal::new(100000, 4));
+    }
+
+    #[test]
+    fn test_execution_idempotent_duplicate_transaction() {
+        let mut engine = Engine::with_config(EngineConfig {
+            idempotent_duplicates: true,
+            ..EngineConfig::default()
+        });
+        let deposit = Transaction::Deposit(1, 100, Decimal::new(100000, 4));
+        assert!(engine.execute(deposit.clone()).is_ok());
+        assert!(engine.execute(deposit).is_ok());
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.total, Decimal::new(100000, 4));
+        let conflicting = Transaction::Deposit(1, 100, Decimal::new(1, 4));
+        assert_eq!(
+            engine.execute(conflicting).err(),
+            Some(ExecutionError::DuplicateTransaction)
+        );
+    }
+
+    #[test]
+    fn test_execution_invalid_amount() {
+        let mut engine = Engine::new();
+        assert!(
+            engine
+                .execute(Transaction::Deposit(1, 100, Decimal::new(100000, 4)))
+                .is_ok()
+        );
+        assert_eq!(
+            engine
+                .execute(Transaction::Deposit(1, 101, Decimal::ZERO))
+                .err(),
+            Some(ExecutionError::InvalidAmount)
+        );
+        assert_eq!(
+            engine
+                .execute(Transaction::Withdrawal(1, 102, Decimal::new(-50000, 4)))
+                .err(),
+            Some(ExecutionError::InvalidAmount)
+        );
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(100000, 4));
+        assert_eq!(client1.total, Decimal::new(100000, 4));
+        assert!(engine.transaction(101).is_none());
+    }
+
+    /// Deposits 10 on tx 1 and 20 on tx 2, disputes both, then charges back tx 1.
+    fn engine_with_locked_client_and_open_dispute(policy: LockedAccountPolicy) -> Engine {
+        let mut engine = Engine::with_config(EngineConfig {
+            locked_account_policy: policy,
+            ..EngineConfig::default()
+        });
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            Transaction::Deposit(1, 2, Decimal::new(20, 0)),
+            Transaction::Deposit(1, 3, Decimal::new(30, 0)),
+            Transaction::Dispute(1, 2),
+            Transaction::Dispute(1, 1),
+            Transaction::Chargeback(1, 1),
+        ] {
+            assert!(engine.execute(transaction).is_ok());
+        }
+        assert!(engine.client(1).unwrap().locked);
+        engine
+    }
+
+    #[test]
+    fn test_locked_account_policy_freeze() {
+        let mut engine = engine_with_locked_client_and_open_dispute(LockedAccountPolicy::Freeze);
+        assert_eq!(
+            engine.execute(Transaction::Chargeback(1, 2)).err(),
+            Some(ExecutionError::AccountLocked)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Resolve(1, 2)).err(),
+            Some(ExecutionError::AccountLocked)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 3)).err(),
+            Some(ExecutionError::AccountLocked)
+        );
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.held, Decimal::new(20, 0));
+        assert_eq!(client1.total, Decimal::new(50, 0));
+    }
+
+    #[test]
+    fn test_locked_account_policy_settle_open() {
+        let mut engine =
+            engine_with_locked_client_and_open_dispute(LockedAccountPolicy::SettleOpen);
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 3)).err(),
+            Some(ExecutionError::AccountLocked)
+        );
+        assert!(engine.execute(Transaction::Chargeback(1, 2)).is_ok());
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(30, 0));
+        assert_eq!(client1.held, Decimal::ZERO);
+        assert_eq!(client1.total, Decimal::new(30, 0));
+        assert!(client1.locked);
+    }
+
+    #[test]
+    fn test_locked_account_policy_allow() {
+        let mut engine = engine_with_locked_client_and_open_dispute(LockedAccountPolicy::Allow);
+        assert!(engine.execute(Transaction::Resolve(1, 2)).is_ok());
+        assert!(engine.execute(Transaction::Dispute(1, 3)).is_ok());
+        assert!(engine.execute(Transaction::Chargeback(1, 3)).is_ok());
+        assert_eq!(
+            engine
+                .execute(Transaction::Withdrawal(1, 4, Decimal::new(1, 0)))
+                .err(),
+            Some(ExecutionError::AccountLocked)
+        );
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(20, 0));
+        assert_eq!(client1.held, Decimal::ZERO);
+        assert_eq!(client1.total, Decimal::new(20, 0));
+    }
+
+    #[test]
+    fn test_locked_account_policy_default_is_allow() {
+        // Locked accounts only block deposits and withdrawals by default
+        assert_eq!(LockedAccountPolicy::default(), LockedAccountPolicy::Allow);
+        let mut engine = engine_with_locked_client_and_open_dispute(LockedAccountPolicy::default());
+        assert!(engine.execute(Transaction::Resolve(1, 2)).is_ok());
+        assert_eq!(
+            engine
+                .execute(Transaction::Deposit(1, 4, Decimal::new(1, 0)))
+                .err(),
+            Some(ExecutionError::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn test_admin_transactions_rejected_by_default() {
+        let mut engine = engine_with_locked_client_and_open_dispute(LockedAccountPolicy::Freeze);
+        assert_eq!(
+            engine.execute(Transaction::Unlock(1, 10)).err(),
+            Some(ExecutionError::AdminNotAllowed)
+        );
+        assert!(engine.client(1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_admin_unlock_and_adjustments() {
+        let mut engine = engine_with_locked_client_and_open_dispute(LockedAccountPolicy::Freeze);
+        engine.config.allow_admin = true;
+        // Adjustments apply to locked accounts as well
+        assert!(
+            engine
+                .execute(Transaction::AdjustCredit(1, 10, Decimal::new(5, 0)))
+                .is_ok()
+        );
+        assert!(engine.execute(Transaction::Unlock(1, 11)).is_ok());
+        assert!(!engine.client(1).unwrap().locked);
+        assert!(
+            engine
+                .execute(Transaction::Deposit(1, 12, Decimal::new(1, 0)))
+                .is_ok()
+        );
+        assert_eq!(
+            engine
+                .execute(Transaction::AdjustDebit(1, 13, Decimal::new(100, 0)))
+                .err(),
+            Some(ExecutionError::InsufficientFunds)
+        );
+        assert!(
+            engine
+                .execute(Transaction::AdjustDebit(1, 13, Decimal::new(6, 0)))
+                .is_ok()
+        );
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(30, 0));
+        assert_eq!(client1.held, Decimal::new(20, 0));
+        assert_eq!(client1.total, Decimal::new(50, 0));
+        // Admin transactions are logged and take part in the duplicate check
+        assert_eq!(engine.transaction(11), Some(Transaction::Unlock(1, 11)));
+        assert_eq!(
+            engine.execute(Transaction::Unlock(1, 11)).err(),
+            Some(ExecutionError::DuplicateTransaction)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 10)).err(),
+            Some(ExecutionError::IneligibleTransaction)
+        );
+    }
+
+    fn multi_currency_engine() -> Engine {
+        Engine::with_config(EngineConfig {
+            multi_currency: true,
+            ..EngineConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_multi_currency_balances() {
+        let mut engine = multi_currency_engine();
+        let eur = "EUR".parse().unwrap();
+        assert!(
+            engine
+                .execute_in(Transaction::Deposit(1, 1, Decimal::new(10, 0)), Some(eur))
+                .is_ok()
+        );
+        assert!(
+            engine
+                .execute(Transaction::Deposit(1, 2, Decimal::new(5, 0)))
+                .is_ok()
+        );
+        // Withdrawals only draw from the balance in their own currency
+        assert_eq!(
+            engine
+                .execute(Transaction::Withdrawal(1, 3, Decimal::new(7, 0)))
+                .err(),
+            Some(ExecutionError::InsufficientFunds)
+        );
+        assert!(
+            engine
+                .execute_in(Transaction::Withdrawal(1, 3, Decimal::new(7, 0)), Some(eur))
+                .is_ok()
+        );
+        assert_eq!(engine.account(1, eur).unwrap().total, Decimal::new(3, 0));
+        assert_eq!(engine.client(1).unwrap().total, Decimal::new(5, 0));
+        assert_eq!(engine.client_accounts(1).count(), 2);
+        assert_eq!(engine.transaction_currency(1), Some(eur));
+    }
+
+    #[test]
+    fn test_multi_currency_disputes() {
+        let mut engine = multi_currency_engine();
+        let eur = "EUR".parse().unwrap();
+        let deposit = Transaction::Deposit(1, 1, Decimal::new(10, 0));
+        assert!(engine.execute_in(deposit, Some(eur)).is_ok());
+        assert!(
+            engine
+                .execute(Transaction::Deposit(1, 2, Decimal::new(5, 0)))
+                .is_ok()
+        );
+        assert_eq!(
+            engine
+                .execute_in(Transaction::Dispute(1, 1), Some(Currency::USD))
+                .err(),
+            Some(ExecutionError::CurrencyMismatch)
+        );
+        // Without a currency the dispute acts in the currency of the deposit
+        assert!(engine.execute(Transaction::Dispute(1, 1)).is_ok());
+        assert_eq!(engine.account(1, eur).unwrap().held, Decimal::new(10, 0));
+        assert!(engine.execute(Transaction::Chargeback(1, 1)).is_ok());
+        // The chargeback locks the accounts in all currencies, including new ones
+        assert!(
+            engine
+                .client_accounts(1)
+                .all(|client| client.unwrap().locked)
+        );
+        assert_eq!(
+            engine
+                .execute_in(
+                    Transaction::Deposit(1, 3, Decimal::new(1, 0)),
+                    Some("GBP".parse().unwrap())
+                )
+                .err(),
+            Some(ExecutionError::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_currency() {
+        let mut engine = Engine::new();
+        let deposit = Transaction::Deposit(1, 1, Decimal::new(10, 0));
+        assert_eq!(
+            engine
+                .execute_in(deposit.clone(), Some("EUR".parse().unwrap()))
+                .err(),
+            Some(ExecutionError::UnsupportedCurrency)
+        );
+        assert!(engine.execute_in(deposit, Some(Currency::USD)).is_ok());
+    }
+
+    #[test]
+    fn test_archive_old_transactions() {
+        let dir = std::env::temp_dir().join(format!("engine-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut engine = Engine::new();
+        engine.set_archive(
+            Archive::new(crate::archive::ArchivePolicy {
+                dir: dir.clone(),
+                dispute_window: 2,
+                segment_size: 2,
+            })
+            .unwrap(),
+        );
+        for tx_id in 1..=3 {
+            let deposit = Transaction::Deposit(1, tx_id, Decimal::new(10, 0));
+            assert!(engine.execute(deposit).is_ok());
+        }
+        assert!(engine.execute(Transaction::Dispute(1, 1)).is_ok());
+        assert!(
+            engine
+                .execute(Transaction::Deposit(1, 4, Decimal::new(10, 0)))
+                .is_ok()
+        );
+        // Transaction 1 is disputed and stays in memory, transaction 2 is archived
+        assert_eq!(engine.archive_old_transactions().unwrap(), 1);
+        assert!(engine.transaction(1).is_some());
+        assert!(engine.transaction(2).is_none());
+        assert_eq!(
+            engine.archived_transaction(2).unwrap(),
+            Some(Transaction::Deposit(1, 2, Decimal::new(10, 0)))
+        );
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 2)).err(),
+            Some(ExecutionError::TransactionArchived)
+        );
+        assert_eq!(
+            engine
+                .execute(Transaction::Deposit(1, 2, Decimal::new(10, 0)))
+                .err(),
+            Some(ExecutionError::DuplicateTransaction)
+        );
+        assert!(engine.execute(Transaction::Resolve(1, 1)).is_ok());
+        assert_eq!(engine.client(1).unwrap().total, Decimal::new(40, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_closed_dispute_is_terminal() {
+        let mut engine = Engine::new();
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            Transaction::Deposit(1, 2, Decimal::new(10, 0)),
+            Transaction::Dispute(1, 1),
+            Transaction::Resolve(1, 1),
+            Transaction::Dispute(1, 2),
+            Transaction::Chargeback(1, 2),
+        ] {
+            assert!(engine.execute(transaction).is_ok());
+        }
+        for transaction in [
+            Transaction::Dispute(1, 1),
+            Transaction::Chargeback(1, 1),
+            Transaction::Resolve(1, 1),
+            Transaction::Dispute(1, 2),
+            Transaction::Resolve(1, 2),
+        ] {
+            assert_eq!(
+                engine.execute(transaction).err(),
+                Some(ExecutionError::DisputeClosed)
+            );
+        }
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.total, Decimal::new(10, 0));
+        assert_eq!(client1.held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_withdrawal_hold_settle_and_cancel() {
+        let mut engine = Engine::new();
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            Transaction::WithdrawalHold(1, 2, Decimal::new(4, 0)),
+            Transaction::WithdrawalHold(1, 3, Decimal::new(5, 0)),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::ONE);
+        assert_eq!(client1.held, Decimal::new(9, 0));
+        assert_eq!(client1.total, Decimal::new(10, 0));
+        assert_eq!(
+            engine.execute(Transaction::WithdrawalHold(1, 4, Decimal::TWO)),
+            Err(ExecutionError::InsufficientFunds)
+        );
+        assert_eq!(engine.pending_withdrawal(2), Some(Decimal::new(4, 0)));
+
+        engine.execute(Transaction::WithdrawalSettle(1, 2)).unwrap();
+        engine.execute(Transaction::WithdrawalCancel(1, 3)).unwrap();
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(6, 0));
+        assert_eq!(client1.held, Decimal::ZERO);
+        assert_eq!(client1.total, Decimal::new(6, 0));
+        assert!(!engine.is_withdrawal_pending(2));
+
+        // Each hold is finalized once, only by its own client and only holds can be settled
+        for (transaction, error) in [
+            (
+                Transaction::WithdrawalSettle(1, 2),
+                ExecutionError::NoPendingWithdrawal,
+            ),
+            (
+                Transaction::WithdrawalCancel(1, 2),
+                ExecutionError::NoPendingWithdrawal,
+            ),
+            (
+                Transaction::WithdrawalSettle(1, 1),
+                ExecutionError::NoPendingWithdrawal,
+            ),
+            (
+                Transaction::WithdrawalHold(1, 2, Decimal::ONE),
+                ExecutionError::DuplicateTransaction,
+            ),
+            (
+                Transaction::Dispute(1, 2),
+                ExecutionError::IneligibleTransaction,
+            ),
+        ] {
+            assert_eq!(engine.execute(transaction).err(), Some(error));
+        }
+        engine
+            .execute(Transaction::WithdrawalHold(1, 5, Decimal::ONE))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::WithdrawalSettle(2, 5)),
+            Err(ExecutionError::ClientMismatch)
+        );
+    }
+
+    /// Deposits 100, withdraws 70 and disputes the deposit under the given policy.
+    fn dispute_after_withdrawal(policy: DisputePolicy) -> (Engine, Result<(), ExecutionError>) {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_policy: policy,
+            ..EngineConfig::default()
+        });
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(100, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Withdrawal(1, 2, Decimal::new(70, 0)))
+            .unwrap();
+        let result = engine.execute(Transaction::Dispute(1, 1));
+        (engine, result)
+    }
+
+    #[test]
+    fn test_dispute_policy_allow_negative() {
+        let (mut engine, result) = dispute_after_withdrawal(DisputePolicy::AllowNegative);
+        assert!(result.is_ok());
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(-70, 0));
+        assert_eq!(client1.held, Decimal::new(100, 0));
+        assert!(engine.execute(Transaction::Chargeback(1, 1)).is_ok());
+        assert_eq!(engine.client(1).unwrap().total, Decimal::new(-70, 0));
+    }
+
+    #[test]
+    fn test_dispute_policy_reject() {
+        let (mut engine, result) = dispute_after_withdrawal(DisputePolicy::Reject);
+        assert_eq!(result, Err(ExecutionError::InsufficientFunds));
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(30, 0));
+        assert_eq!(client1.held, Decimal::ZERO);
+        // The dispute stays possible once the funds are back
+        engine
+            .execute(Transaction::Deposit(1, 3, Decimal::new(70, 0)))
+            .unwrap();
+        assert!(engine.execute(Transaction::Dispute(1, 1)).is_ok());
+        assert_eq!(engine.client(1).unwrap().held, Decimal::new(100, 0));
+    }
+
+    #[test]
+    fn test_dispute_policy_partial_hold() {
+        let (mut engine, result) = dispute_after_withdrawal(DisputePolicy::PartialHold);
+        assert!(result.is_ok());
+        assert_eq!(engine.dispute_hold(1), Some(Decimal::new(30, 0)));
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::ZERO);
+        assert_eq!(client1.held, Decimal::new(30, 0));
+        assert!(engine.execute(Transaction::Resolve(1, 1)).is_ok());
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(30, 0));
+        assert_eq!(client1.held, Decimal::ZERO);
+
+        let (mut engine, _) = dispute_after_withdrawal(DisputePolicy::PartialHold);
+        assert!(engine.execute(Transaction::Chargeback(1, 1)).is_ok());
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.total, Decimal::ZERO);
+        assert_eq!(client1.held, Decimal::ZERO);
+        assert!(client1.locked);
+    }
+
+    #[test]
+    fn test_expire_disputes() {
+        let clock = Arc::new(ManualClock::new(1000));
+        let mut engine = Engine::new();
+        engine.set_clock(clock.clone());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        engine.add_event_sink(Box::new(move |event: &Event| {
+            sink.lock().unwrap().push(event.kind);
+        }));
+        for tx_id in 1..=3 {
+            engine
+                .execute(Transaction::Deposit(1, tx_id, Decimal::ONE))
+                .unwrap();
+        }
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        clock.advance(50);
+        engine.execute(Transaction::Dispute(1, 2)).unwrap();
+        engine.execute(Transaction::Dispute(1, 3)).unwrap();
+        engine.execute(Transaction::Resolve(1, 3)).unwrap();
+        assert_eq!(engine.dispute_opened(2), Some(1050));
+        assert_eq!(engine.dispute_opened(3), None);
+
+        clock.advance(60);
+        assert_eq!(
+            engine.expire_disputes(100).unwrap(),
+            vec![Transaction::Resolve(1, 1)]
+        );
+        assert_eq!(engine.expire_disputes(100).unwrap(), vec![]);
+        assert!(!engine.is_disputed(1) && engine.is_dispute_closed(1));
+        assert!(engine.is_disputed(2));
+        assert_eq!(engine.client(1).unwrap().held, Decimal::ONE);
+        assert_eq!(
+            events.lock().unwrap().last(),
+            Some(&EventKind::DisputeResolved)
+        );
+
+        // Ages in executed transactions, expiry itself doesn't count
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_aging: DisputeAging::Sequence,
+            ..EngineConfig::default()
+        });
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::ONE))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        assert_eq!(engine.dispute_opened(1), Some(2));
+        assert!(engine.execute(Transaction::Resolve(1, 9)).is_err());
+        assert_eq!(engine.expire_disputes(1).unwrap(), vec![]);
+        assert!(engine.execute(Transaction::Resolve(1, 9)).is_err());
+        assert_eq!(engine.expire_disputes(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_chargeback_reversal() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(1, 2, Decimal::new(5, 0)))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::ChargebackReversal(1, 1)),
+            Err(ExecutionError::NotChargedBack)
+        );
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Chargeback(1, 1)).unwrap();
+        assert_eq!(engine.charged_back(1), Some(Decimal::new(10, 0)));
+        assert_eq!(
+            engine.execute(Transaction::ChargebackReversal(2, 1)),
+            Err(ExecutionError::ClientMismatch)
+        );
+        engine
+            .execute(Transaction::ChargebackReversal(1, 1))
+            .unwrap();
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available, Decimal::new(15, 0));
+        assert!(client.locked);
+        assert_eq!(
+            engine.execute(Transaction::ChargebackReversal(1, 1)),
+            Err(ExecutionError::NotChargedBack)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 1)),
+            Err(ExecutionError::DisputeClosed)
+        );
+
+        let mut engine = Engine::with_config(EngineConfig {
+            reversal_unlocks: true,
+            ..EngineConfig::default()
+        });
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::ONE))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Chargeback(1, 1)).unwrap();
+        engine
+            .execute(Transaction::ChargebackReversal(1, 1))
+            .unwrap();
+        assert!(!engine.client(1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_refund() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Withdrawal(1, 2, Decimal::ONE))
+            .unwrap();
+        engine
+            .execute(Transaction::Refund(1, 1, Decimal::new(3, 0)))
+            .unwrap();
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available, Decimal::new(6, 0));
+        assert_eq!(client.total, Decimal::new(6, 0));
+        assert!(!client.locked);
+        assert_eq!(engine.refunded(1), Decimal::new(3, 0));
+        assert_eq!(
+            engine.execute(Transaction::Refund(1, 1, Decimal::new(8, 0))),
+            Err(ExecutionError::RefundExceeded)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Refund(2, 1, Decimal::ONE)),
+            Err(ExecutionError::ClientMismatch)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Refund(1, 2, Decimal::ONE)),
+            Err(ExecutionError::IneligibleTransaction)
+        );
+
+        // A dispute holds only the part that wasn't refunded and blocks refunds while open
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        assert_eq!(engine.dispute_hold(1), Some(Decimal::new(7, 0)));
+        assert_eq!(
+            engine.execute(Transaction::Refund(1, 1, Decimal::ONE)),
+            Err(ExecutionError::AlreadyDisputedTransaction)
+        );
+        engine.execute(Transaction::Resolve(1, 1)).unwrap();
+
+        // Refunds add up across a snapshot
+        let mut snapshot = Vec::new();
+        crate::snapshot::write_snapshot(&engine, &mut snapshot).unwrap();
+        let mut engine = Engine::new();
+        crate::snapshot::read_snapshot(&mut engine, snapshot.as_slice()).unwrap();
+        engine
+            .execute(Transaction::Refund(1, 1, Decimal::new(5, 0)))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::Refund(1, 1, Decimal::new(3, 0))),
+            Err(ExecutionError::RefundExceeded)
+        );
+        assert_eq!(engine.client(1).unwrap().available, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut engine = Engine::with_config(EngineConfig {
+            fees: Some(
+                FeeSchedule::from_toml(
+                    "account = 0\n[deposit]\npercent = \"1\"\n[withdrawal]\nflat = \"2\"\n",
+                )
+                .unwrap(),
+            ),
+            ..EngineConfig::default()
+        });
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(100, 0)))
+            .unwrap();
+        assert_eq!(engine.client(1).unwrap().available, Decimal::new(99, 0));
+        // The fee counts towards the available funds a withdrawal needs
+        assert_eq!(
+            engine.execute(Transaction::Withdrawal(1, 2, Decimal::new(98, 0))),
+            Err(ExecutionError::InsufficientFunds)
+        );
+        engine
+            .execute(Transaction::Withdrawal(1, 3, Decimal::new(90, 0)))
+            .unwrap();
+        assert_eq!(engine.client(1).unwrap().available, Decimal::new(7, 0));
+        // The fee account pays no fees
+        engine
+            .execute(Transaction::Withdrawal(0, 4, Decimal::ONE))
+            .unwrap();
+        assert_eq!(engine.client(0).unwrap().total, Decimal::new(2, 0));
+        assert_eq!(
+            engine.fees_paid().collect::<Vec<_>>(),
+            [((1, Currency::default()), Decimal::new(3, 0))]
+        );
+    }
+
+    #[test]
+    fn test_fees_paid_overflow() {
+        let mut engine = Engine::with_config(EngineConfig {
+            fees: Some(FeeSchedule {
+                account: 0,
+                deposit: crate::fees::Fee {
+                    flat: Decimal::MAX,
+                    percent: Decimal::ZERO,
+                },
+                withdrawal: Default::default(),
+            }),
+            ..EngineConfig::default()
+        });
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::MAX))
+            .unwrap();
+        // The fee account spends the fee, so only the fees the client paid can overflow
+        engine
+            .execute(Transaction::Withdrawal(0, 2, Decimal::MAX))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::Deposit(1, 3, Decimal::MAX)),
+            Err(ExecutionError::Overflow)
+        );
+        assert_eq!(engine.client(0).unwrap().total, Decimal::ZERO);
+        assert!(engine.transaction(3).is_none());
+        assert_eq!(
+            engine.fees_paid().collect::<Vec<_>>(),
+            [((1, Currency::default()), Decimal::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_limits() {
+        let clock = Arc::new(ManualClock::new(0));
+        let mut engine = Engine::with_config(EngineConfig {
+            limits: Some(
+                WithdrawalLimits::from_toml("[default]\nmax_count = 1\nwindow = 10\n").unwrap(),
+            ),
+            ..EngineConfig::default()
+        });
+        engine.set_clock(clock.clone());
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::WithdrawalHold(1, 2, Decimal::ONE))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::Withdrawal(1, 3, Decimal::ONE)),
+            Err(ExecutionError::LimitExceeded)
+        );
+        clock.advance(10);
+        engine
+            .execute(Transaction::Withdrawal(1, 3, Decimal::ONE))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_dispute_amount_policies() {
+        let engine_with = |dispute_amounts| {
+            let mut engine = Engine::with_config(EngineConfig {
+                dispute_amounts,
+                ..EngineConfig::default()
+            });
+            engine
+                .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+                .unwrap();
+            engine
+        };
+        let amount = Some(Decimal::new(4, 0));
+        for policy in [DisputeAmountPolicy::Ignore, DisputeAmountPolicy::Warn] {
+            let mut engine = engine_with(policy);
+            assert!(
+                engine
+                    .execute_row(Transaction::Dispute(1, 1), None, amount)
+                    .is_ok()
+            );
+            assert_eq!(engine.dispute_hold(1), Some(Decimal::new(10, 0)));
+        }
+
+        let mut engine = engine_with(DisputeAmountPolicy::Reject);
+        assert_eq!(
+            engine.execute_row(Transaction::Dispute(1, 1), None, amount),
+            Err(ExecutionError::UnexpectedAmount)
+        );
+        // Rows without an amount are unaffected
+        assert!(
+            engine
+                .execute_row(Transaction::Dispute(1, 1), None, None)
+                .is_ok()
+        );
+
+        let mut engine = engine_with(DisputeAmountPolicy::Honor);
+        assert_eq!(
+            engine.execute_row(Transaction::Dispute(1, 1), None, Some(Decimal::new(11, 0))),
+            Err(ExecutionError::InvalidAmount)
+        );
+        assert!(
+            engine
+                .execute_row(Transaction::Dispute(1, 1), None, amount)
+                .is_ok()
+        );
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(6, 0));
+        assert_eq!(client1.held, Decimal::new(4, 0));
+        assert_eq!(
+            engine.execute_row(Transaction::Chargeback(1, 1), None, Some(Decimal::ONE)),
+            Err(ExecutionError::InvalidAmount)
+        );
+        assert!(
+            engine
+                .execute_row(Transaction::Chargeback(1, 1), None, amount)
+                .is_ok()
+        );
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.total, Decimal::new(6, 0));
+        assert!(client1.locked);
+
+        // A partial hold survives a snapshot and a resolve without an amount releases just it
+        let mut engine = engine_with(DisputeAmountPolicy::Honor);
+        engine
+            .execute_row(Transaction::Dispute(1, 1), None, amount)
+            .unwrap();
+        let mut snapshot = Vec::new();
+        crate::snapshot::write_snapshot(&engine, &mut snapshot).unwrap();
+        let mut engine = Engine::new();
+        crate::snapshot::read_snapshot(&mut engine, snapshot.as_slice()).unwrap();
+        assert_eq!(engine.dispute_hold(1), amount);
+        engine.execute(Transaction::Resolve(1, 1)).unwrap();
+        let client1 = engine.client(1).unwrap();
+        assert_eq!(client1.available, Decimal::new(10, 0));
+        assert_eq!(client1.held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_assert_balance() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        assert!(
+            engine
+                .execute(Transaction::AssertBalance(1, 2, Decimal::ZERO, None))
+                .is_ok()
+        );
+        assert_eq!(
+            engine.execute(Transaction::AssertBalance(1, 2, Decimal::new(10, 0), None)),
+            Err(ExecutionError::BalanceMismatch {
+                expected: Decimal::new(10, 0),
+                actual: Decimal::ZERO,
+                expected_total: None,
+                total: Decimal::new(10, 0),
+            })
+        );
+        assert!(
+            engine
+                .execute(Transaction::AssertBalance(
+                    1,
+                    2,
+                    Decimal::ZERO,
+                    Some(Decimal::new(10, 0))
+                ))
+                .is_ok()
+        );
+        // The available funds match but the held ones don't
+        let err = engine
+            .execute(Transaction::AssertBalance(
+                1,
+                2,
+                Decimal::ZERO,
+                Some(Decimal::ZERO),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ExecutionError::BalanceMismatch {
+                expected: Decimal::ZERO,
+                actual: Decimal::ZERO,
+                expected_total: Some(Decimal::ZERO),
+                total: Decimal::new(10, 0),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Balance assertion failed: expected available 0, actual 0, expected total 0, actual 10"
+        );
+        // Assertions don't log their id and don't create accounts
+        assert!(
+            engine
+                .execute(Transaction::AssertBalance(2, 1, Decimal::ZERO, None))
+                .is_ok()
+        );
+        assert!(engine.client(2).is_none());
+        assert_eq!(engine.client(1).unwrap().total, Decimal::new(10, 0));
+    }
+
+    #[test]
+    fn test_balance_overflow() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::MAX))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::Deposit(1, 2, Decimal::ONE)),
+            Err(ExecutionError::Overflow)
+        );
+        assert_eq!(engine.client(1).unwrap().total, Decimal::MAX);
+        // The rejected deposit wasn't logged
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 2)),
+            Err(ExecutionError::TransactionNotFound)
+        );
+
+        // A dispute of a spent deposit takes the available balance to its minimum, the held
+        // balance is at its maximum
+        engine
+            .execute(Transaction::Withdrawal(1, 3, Decimal::MAX))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine
+            .execute(Transaction::Deposit(1, 4, Decimal::ONE))
+            .unwrap();
+        let before = engine.client(1);
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 4)),
+            Err(ExecutionError::Overflow)
+        );
+        assert_eq!(engine.client(1), before);
+        assert!(!engine.is_disputed(4));
+    }
+
+    /// A transaction with an amount close to the decimal range limits.
+    #[derive(Clone, Debug)]
+    struct NearMax(Transaction);
+
+    impl Arbitrary for NearMax {
+        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+            let amount = *g
+                .choose(&[
+                    Decimal::MAX,
+                    Decimal::MAX.checked_sub(Decimal::ONE).unwrap(),
+                    Decimal::new(i64::MAX, 4),
+                    Decimal::new(1, 4),
+                    Decimal::ONE,
+                ])
+                .unwrap();
+            let client = u16::arbitrary(g) % 3;
+            let tx = u32::arbitrary(g) % 16;
+            let kind = *g
+                .choose(&["deposit", "withdrawal", "dispute", "resolve", "chargeback"])
+                .unwrap();
+            NearMax(Transaction::new(kind, client, tx, amount).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_near_max_amounts_never_panic() {
+        fn balances_stay_consistent(transactions: Vec<NearMax>) -> bool {
+            let mut engine = Engine::with_config(EngineConfig {
+                check_dispute_client: false,
+                ..EngineConfig::default()
+            });
+            for NearMax(transaction) in transactions {
+                let before: Vec<Client> = engine.clients().map(Result::unwrap).collect();
+                let result = engine.execute(transaction);
+                // An overflow leaves every account untouched
+                if result == Err(ExecutionError::Overflow)
+                    && !engine.clients().map(Result::unwrap).eq(before)
+                {
+                    return false;
+                }
+                if engine
+                    .clients()
+                    .map(Result::unwrap)
+                    .any(|client| client.available.checked_add(client.held) != Some(client.total))
+                {
+                    return false;
+                }
+            }
+            true
+        }
+        quickcheck::QuickCheck::new()
+            .tests(500)
+            .quickcheck(balances_stay_consistent as fn(Vec<NearMax>) -> bool);
+    }
+
+    #[test]
+    fn test_client_history() {
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_admin: true,
+            ..EngineConfig::default()
+        });
+        for transaction in [
+            Transaction::Deposit(1, 5, Decimal::new(10, 0)),
+            Transaction::Deposit(2, 1, Decimal::new(10, 0)),
+            Transaction::Withdrawal(1, 3, Decimal::new(4, 0)),
+            Transaction::Dispute(1, 5),
+            Transaction::Unlock(1, 7),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        // Rejected transactions are not part of the history
+        assert!(
+            engine
+                .execute(Transaction::Withdrawal(1, 8, Decimal::new(100, 0)))
+                .is_err()
+        );
+        let history: Vec<Transaction> = engine
+            .client_history(1)
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                Transaction::Deposit(1, 5, Decimal::new(10, 0)),
+                Transaction::Withdrawal(1, 3, Decimal::new(4, 0)),
+                Transaction::Unlock(1, 7),
+            ]
+        );
+        assert_eq!(engine.client_history(3).count(), 0);
+    }
+
+    #[test]
+    fn test_report_options() {
+        let mut engine = Engine::new();
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(5, 0)),
+            Transaction::Deposit(2, 2, Decimal::new(20, 0)),
+            Transaction::Deposit(3, 3, Decimal::new(10, 0)),
+            Transaction::Deposit(3, 4, Decimal::new(8, 0)),
+            Transaction::Dispute(3, 4),
+            Transaction::Chargeback(3, 4),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let report = |options: ReportOptions| {
+            let mut output = Vec::new();
+            engine
+                .write_report_with(
+                    &mut CsvReportWriter::new(&mut output, ReportColumns::default()),
+                    &options,
+                )
+                .unwrap();
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(report(ReportOptions::default()), ["1", "2", "3"]);
+        let by_total = ReportOptions {
+            sort: ReportSort::Total,
+            ..Default::default()
+        };
+        assert_eq!(report(by_total), ["2", "3", "1"]);
+        let filtered = ReportOptions {
+            sort: ReportSort::Available,
+            min_total: Some(Decimal::new(10, 0)),
+            clients: Some(BTreeSet::from([1, 3])),
+            ..Default::default()
+        };
+        assert_eq!(report(filtered), ["3"]);
+        let locked = ReportOptions {
+            only_locked: true,
+            ..Default::default()
+        };
+        assert_eq!(report(locked), ["3"]);
+
+        let rows: Vec<AccountReportRow> = engine.report_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            rows.iter().map(|row| row.client).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            rows[2],
+            AccountReportRow {
+                client: 3,
+                currency: Currency::default(),
+                available: Decimal::new(10, 0),
+                held: Decimal::ZERO,
+                total: Decimal::new(10, 0),
+                locked: true,
+                overdrawn: false,
+                closed: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_kyc_deposit_limit() {
+        let clients = ClientDirectory::from_reader(
+            "client,name,kyc\n1,Alice,verified\n2,Bob,pending\n".as_bytes(),
+        )
+        .unwrap();
+        let mut engine = Engine::with_config(EngineConfig {
+            clients: Some(clients),
+            kyc_deposit_limit: Some(Decimal::new(100, 0)),
+            ..EngineConfig::default()
+        });
+        let large = Decimal::new(500, 0);
+        assert!(engine.execute(Transaction::Deposit(1, 1, large)).is_ok());
+        assert_eq!(
+            engine.execute(Transaction::Deposit(2, 2, large)),
+            Err(ExecutionError::KycRequired)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Deposit(3, 3, large)),
+            Err(ExecutionError::KycRequired)
+        );
+        assert!(
+            engine
+                .execute(Transaction::Deposit(2, 4, Decimal::new(100, 0)))
+                .is_ok()
+        );
+        let alice = engine.client(1).unwrap();
+        assert_eq!(
+            alice
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.name.as_deref()),
+            Some("Alice")
+        );
+        assert_eq!(
+            engine.client(2).unwrap().metadata.unwrap().kyc,
+            KycStatus::Pending
+        );
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let mut engine = Engine::new();
+        let amount = |units| Decimal::new(units, 0);
+        engine
+            .execute(Transaction::Deposit(1, 1, amount(10)))
+            .unwrap();
+
+        let result = engine.execute_batch(vec![
+            Transaction::Deposit(1, 2, amount(5)),
+            Transaction::Deposit(2, 2, amount(5)),
+            Transaction::Withdrawal(1, 1, amount(1)),
+            Transaction::Withdrawal(1, 3, Decimal::ZERO),
+        ]);
+        assert!(!result.applied);
+        assert_eq!(
+            result.outcomes,
+            [
+                Err(ExecutionError::BatchRejected),
+                Err(ExecutionError::DuplicateTransaction),
+                Err(ExecutionError::DuplicateTransaction),
+                Err(ExecutionError::InvalidAmount),
+            ]
+        );
+        assert_eq!(engine.client(1).unwrap().available, amount(10));
+        assert!(engine.client(2).is_none());
+
+        // Balance checks are made while applying
+        let result = engine.execute_batch(vec![
+            Transaction::Deposit(2, 2, amount(5)),
+            Transaction::Dispute(2, 2),
+            Transaction::Withdrawal(2, 3, amount(1)),
+        ]);
+        assert!(result.applied && !result.is_ok());
+        assert_eq!(
+            result.outcomes,
+            [Ok(()), Ok(()), Err(ExecutionError::InsufficientFunds)]
+        );
+        assert_eq!(engine.client(2).unwrap().held, amount(5));
+    }
+
+    #[test]
+    fn test_overdraft() {
+        let limits = WithdrawalLimits::from_toml("[default]\noverdraft = \"50\"\n").unwrap();
+        let mut engine = Engine::with_config(EngineConfig {
+            limits: Some(limits),
+            overdrafts: true,
+            allow_admin: true,
+            ..EngineConfig::default()
+        });
+        let amount = |units| Decimal::new(units, 0);
+        engine
+            .execute(Transaction::Deposit(1, 1, amount(20)))
+            .unwrap();
+        assert!(
+            engine
+                .execute(Transaction::Withdrawal(1, 2, amount(60)))
+                .is_ok()
+        );
+        assert_eq!(engine.client(1).unwrap().available, amount(-40));
+        assert!(engine.client(1).unwrap().is_overdrawn());
+        assert_eq!(
+            engine.execute(Transaction::Withdrawal(1, 3, amount(20))),
+            Err(ExecutionError::OverdraftExceeded)
+        );
+
+        // An admin transaction overrides the configured limit, zero revokes it
+        engine
+            .execute(Transaction::SetOverdraft(1, 4, amount(100)))
+            .unwrap();
+        assert!(
+            engine
+                .execute(Transaction::WithdrawalHold(1, 5, amount(20)))
+                .is_ok()
+        );
+        engine
+            .execute(Transaction::SetOverdraft(2, 6, Decimal::ZERO))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::Withdrawal(2, 7, Decimal::ONE)),
+            Err(ExecutionError::InsufficientFunds)
+        );
+
+        // Without overdrafts the limits don't apply
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_admin: true,
+            ..EngineConfig::default()
+        });
+        engine
+            .execute(Transaction::SetOverdraft(1, 1, amount(100)))
+            .unwrap();
+        assert_eq!(
+            engine.execute(Transaction::Withdrawal(1, 2, Decimal::ONE)),
+            Err(ExecutionError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn test_close_account() {
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_admin: true,
+            ..EngineConfig::default()
+        });
+        let amount = |units| Decimal::new(units, 0);
+        for transaction in [
+            Transaction::Deposit(1, 1, amount(10)),
+            Transaction::Deposit(1, 2, amount(5)),
+            Transaction::Dispute(1, 2),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        assert_eq!(
+            engine.execute(Transaction::CloseAccount(1, 3)),
+            Err(ExecutionError::FundsHeld)
+        );
+        engine.execute(Transaction::Resolve(1, 2)).unwrap();
+        engine.execute(Transaction::CloseAccount(1, 3)).unwrap();
+        let client = engine.client(1).unwrap();
+        assert!(client.closed && !client.locked);
+        assert_eq!(client.total, amount(15));
+        for transaction in [
+            Transaction::Deposit(1, 4, amount(1)),
+            Transaction::Dispute(1, 1),
+            Transaction::Unlock(1, 5),
+            Transaction::CloseAccount(1, 6),
+        ] {
+            assert_eq!(
+                engine.execute(transaction),
+                Err(ExecutionError::AccountClosed)
+            );
+        }
+        assert!(
+            engine
+                .execute(Transaction::AssertBalance(1, 7, amount(15), None))
+                .is_ok()
+        );
+
+        // A restored account stays closed
+        let mut snapshot = Vec::new();
+        crate::snapshot::write_snapshot(&engine, &mut snapshot).unwrap();
+        let mut restored = Engine::with_config(engine.config().clone());
+        crate::snapshot::read_snapshot(&mut restored, snapshot.as_slice()).unwrap();
+        assert!(restored.client(1).unwrap().closed);
+        assert_eq!(
+            restored.execute(Transaction::Withdrawal(1, 8, amount(1))),
+            Err(ExecutionError::AccountClosed)
+        );
+
+        // Releasing resolves the open disputes and cancels the withdrawal holds first
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_admin: true,
+            close_policy: ClosePolicy::Release,
+            ..EngineConfig::default()
+        });
+        for transaction in [
+            Transaction::Deposit(2, 1, amount(10)),
+            Transaction::Dispute(2, 1),
+            Transaction::Deposit(2, 2, amount(10)),
+            Transaction::WithdrawalHold(2, 3, amount(4)),
+            Transaction::CloseAccount(2, 4),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let client = engine.client(2).unwrap();
+        assert!(client.closed);
+        assert_eq!((client.available, client.held), (amount(20), Decimal::ZERO));
+        assert_eq!(engine.dispute_state(1), DisputeState::Resolved);
+        assert!(!engine.is_withdrawal_pending(3));
+    }
+
+    #[test]
+    fn test_accrue_interest() {
+        let mut engine = Engine::new();
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(100, 0)),
+            Transaction::Deposit(2, 2, Decimal::new(50, 0)),
+            Transaction::Dispute(2, 2),
+            Transaction::Chargeback(2, 2),
+            Transaction::Deposit(3, u32::MAX, Decimal::new(10, 0)),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let posted = engine.accrue_interest(Decimal::new(15, 3), 1_000).unwrap();
+        let currency = Currency::default();
+        assert_eq!(
+            posted,
+            vec![
+                (
+                    Transaction::Deposit(1, u32::MAX - 1, Decimal::new(15, 1)),
+                    currency
+                ),
+                (
+                    Transaction::Deposit(3, u32::MAX - 2, Decimal::new(15, 2)),
+                    currency
+                ),
+            ]
+        );
+        assert_eq!(engine.client(1).unwrap().available, Decimal::new(1015, 1));
+        assert_eq!(engine.client(2).unwrap().available, Decimal::ZERO);
+        // The interest deposits are logged like any other and can be disputed
+        assert!(
+            engine
+                .execute(Transaction::Dispute(1, u32::MAX - 1))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let clock = Arc::new(ManualClock::new(100));
+        let mut engine = Engine::new();
+        engine.set_clock(clock.clone());
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            Transaction::Withdrawal(1, 2, Decimal::new(10, 0)),
+            Transaction::Deposit(2, 3, Decimal::new(5, 0)),
+            Transaction::Deposit(3, 4, Decimal::new(5, 0)),
+            Transaction::Dispute(3, 4),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        clock.set(200);
+        engine
+            .execute(Transaction::Deposit(4, 7, Decimal::ONE))
+            .unwrap();
+        engine
+            .execute(Transaction::Withdrawal(4, 8, Decimal::ONE))
+            .unwrap();
+        assert_eq!(engine.last_active(1), Some(100));
+
+        // Client 2 has funds, client 3 holds a disputed deposit and client 4 was active since
+        let pruned = engine.prune(150, true).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].client_id, 1);
+        assert_eq!(pruned[0].transactions.len(), 2);
+        assert!(engine.client(1).is_none());
+        assert_eq!(engine.last_active(1), None);
+        // The ids of pruned transactions can be taken again
+        assert!(
+            engine
+                .execute(Transaction::Deposit(5, 1, Decimal::ONE))
+                .is_ok()
+        );
+
+        let pruned = engine.prune(150, false).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].client_id, 2);
+        assert!(engine.client(3).is_some());
+        assert!(engine.client(4).is_some());
+    }
 }
@@ -0,0 +1,174 @@
+use std::fmt::Display;
+
+use crate::{client::Client, dispute::DisputeState, transaction::Transaction};
+
+/// What an applied transaction did to an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    FundsDeposited,
+    FundsWithdrawn,
+    DisputeOpened,
+    DisputeResolved,
+    ChargedBack,
+    /// Follows `ChargedBack` when the chargeback locked the client.
+    AccountLocked,
+    AccountUnlocked,
+    BalanceAdjusted,
+    WithdrawalHeld,
+    WithdrawalSettled,
+    WithdrawalCancelled,
+    ChargebackReversed,
+    AccountClosed,
+    FundsRefunded,
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EventKind::FundsDeposited => "FundsDeposited",
+            EventKind::FundsWithdrawn => "FundsWithdrawn",
+            EventKind::DisputeOpened => "DisputeOpened",
+            EventKind::DisputeResolved => "DisputeResolved",
+            EventKind::ChargedBack => "ChargedBack",
+            EventKind::AccountLocked => "AccountLocked",
+            EventKind::AccountUnlocked => "AccountUnlocked",
+            EventKind::BalanceAdjusted => "BalanceAdjusted",
+            EventKind::WithdrawalHeld => "WithdrawalHeld",
+            EventKind::WithdrawalSettled => "WithdrawalSettled",
+            EventKind::WithdrawalCancelled => "WithdrawalCancelled",
+            EventKind::ChargebackReversed => "ChargebackReversed",
+            EventKind::AccountClosed => "AccountClosed",
+            EventKind::FundsRefunded => "FundsRefunded",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Domain event of an applied transaction with the affected account before and after it. An
+/// account created by the transaction starts from zero balances.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub transaction: Transaction,
+    pub before: Client,
+    pub after: Client,
+    /// Dispute state of the transaction's `tx` after it was applied, e.g. `Open` after a dispute
+    /// or `Reversed` after a chargeback reversal.
+    pub dispute: DisputeState,
+}
+
+/// Receives the events of every applied transaction, in order, for building downstream
+/// projections without re-deriving state. Rejected transactions and balance assertions emit no
+/// events.
+pub trait EventSink: Send + Sync {
+    fn emit(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event) + Send + Sync> EventSink for F {
+    fn emit(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+/// Events of a transaction given the affected account before and after it and the dispute state
+/// of its `tx`.
+pub(crate) fn events(
+    transaction: &Transaction,
+    before: &Client,
+    after: &Client,
+    dispute: DisputeState,
+) -> Vec<Event> {
+    let event = |kind| Event {
+        kind,
+        transaction: transaction.clone(),
+        before: before.clone(),
+        after: after.clone(),
+        dispute,
+    };
+    match transaction {
+        Transaction::Deposit(..) => vec![event(EventKind::FundsDeposited)],
+        Transaction::Withdrawal(..) => vec![event(EventKind::FundsWithdrawn)],
+        Transaction::Dispute(..) => vec![event(EventKind::DisputeOpened)],
+        Transaction::Resolve(..) => vec![event(EventKind::DisputeResolved)],
+        Transaction::Chargeback(..) if !before.locked && after.locked => vec![
+            event(EventKind::ChargedBack),
+            event(EventKind::AccountLocked),
+        ],
+        Transaction::Chargeback(..) => vec![event(EventKind::ChargedBack)],
+        Transaction::Unlock(..) if before.locked => vec![event(EventKind::AccountUnlocked)],
+        Transaction::Unlock(..)
+        | Transaction::AssertBalance(..)
+        | Transaction::SetOverdraft(..) => Vec::new(),
+        Transaction::AdjustCredit(..) | Transaction::AdjustDebit(..) | Transaction::Custom(..) => {
+            vec![event(EventKind::BalanceAdjusted)]
+        }
+        Transaction::WithdrawalHold(..) => vec![event(EventKind::WithdrawalHeld)],
+        Transaction::WithdrawalSettle(..) => vec![event(EventKind::WithdrawalSettled)],
+        Transaction::WithdrawalCancel(..) => vec![event(EventKind::WithdrawalCancelled)],
+        Transaction::ChargebackReversal(..) if before.locked && !after.locked => vec![
+            event(EventKind::ChargebackReversed),
+            event(EventKind::AccountUnlocked),
+        ],
+        Transaction::ChargebackReversal(..) => vec![event(EventKind::ChargebackReversed)],
+        Transaction::CloseAccount(..) => vec![event(EventKind::AccountClosed)],
+        Transaction::Refund(..) => vec![event(EventKind::FundsRefunded)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::decimal::Decimal;
+
+    use super::*;
+    use crate::{config::EngineConfig, engine::Engine};
+
+    #[test]
+    fn test_event_sink() {
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&emitted);
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_admin: true,
+            ..EngineConfig::default()
+        });
+        engine.add_event_sink(Box::new(move |event: &Event| {
+            sink.lock().unwrap().push(event.clone())
+        }));
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        assert!(
+            engine
+                .execute(Transaction::Withdrawal(1, 2, Decimal::new(50, 0)))
+                .is_err()
+        );
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Chargeback(1, 1)).unwrap();
+        engine.execute(Transaction::Unlock(1, 3)).unwrap();
+
+        let emitted = emitted.lock().unwrap();
+        let kinds: Vec<EventKind> = emitted.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::FundsDeposited,
+                EventKind::DisputeOpened,
+                EventKind::ChargedBack,
+                EventKind::AccountLocked,
+                EventKind::AccountUnlocked,
+            ]
+        );
+        let deposit = &emitted[0];
+        assert_eq!(deposit.before.total, Decimal::ZERO);
+        assert_eq!(deposit.after.total, Decimal::new(10, 0));
+        let dispute = &emitted[1];
+        assert_eq!(
+            (dispute.before.held, dispute.after.held),
+            (Decimal::ZERO, Decimal::new(10, 0))
+        );
+        assert_eq!(dispute.dispute, DisputeState::Open);
+        assert_eq!(emitted[2].dispute, DisputeState::ChargedBack);
+        assert_eq!(deposit.dispute, DisputeState::None);
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use crate::decimal::Decimal;
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    client::Client,
+    currency::Currency,
+    dispute::DisputeState,
+    engine::ExecutionError,
+    snapshot::{account_record, parse_account, parse_transaction, transaction_record},
+    transaction::Transaction,
+};
+
+/// Version of the export format, stored in its first record.
+const EXPORT_VERSION: &str = "1";
+
+/// A logged transaction of an exported client with the state of its dispute or, for a
+/// withdrawal hold, whether it is still pending.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedTransaction {
+    pub transaction: Transaction,
+    pub currency: Currency,
+    pub dispute: DisputeState,
+    /// Amount held by an open dispute, or charged back by a chargeback that a chargeback
+    /// reversal can still re-credit.
+    pub dispute_amount: Option<Decimal>,
+    /// A withdrawal hold that was neither settled nor cancelled.
+    pub withdrawal_pending: bool,
+    /// Amount refunded so far from a deposit.
+    pub refunded: Option<Decimal>,
+}
+
+/// Self-contained record of one client, its accounts and its logged transactions, to migrate
+/// the client to another engine with `Engine::export_client` and `Engine::import_client`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientExport {
+    pub client_id: u16,
+    pub accounts: Vec<Client>,
+    pub transactions: Vec<ExportedTransaction>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    /// The export contradicts itself, e.g. a total that isn't available plus held.
+    Inconsistent(String),
+    /// The client already has accounts in the target engine.
+    ClientExists(u16),
+    /// The target engine can't take the client, e.g. a transaction id is already taken.
+    Rejected(ExecutionError),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Inconsistent(reason) => {
+                write!(f, "Inconsistent client export: {}", reason)
+            }
+            ImportError::ClientExists(client_id) => {
+                write!(f, "Client {} already has accounts", client_id)
+            }
+            ImportError::Rejected(err) => write!(f, "Client import rejected: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<ExecutionError> for ImportError {
+    fn from(err: ExecutionError) -> Self {
+        ImportError::Rejected(err)
+    }
+}
+
+impl ClientExport {
+    /// Checks that every account and transaction belongs to the client, that currencies aren't
+    /// repeated and that the balances add up.
+    pub fn verify(&self) -> Result<(), ImportError> {
+        let inconsistent = |reason: String| Err(ImportError::Inconsistent(reason));
+        let mut currencies = BTreeSet::new();
+        for account in &self.accounts {
+            if account.id != self.client_id {
+                return inconsistent(format!("account of client {}", account.id));
+            }
+            if !currencies.insert(account.currency) {
+                return inconsistent(format!("repeated {} account", account.currency));
+            }
+            if account.available.checked_add(account.held) != Some(account.total) {
+                return inconsistent(format!(
+                    "{} total is not available plus held",
+                    account.currency
+                ));
+            }
+        }
+        let mut tx_ids = BTreeSet::new();
+        for entry in &self.transactions {
+            let tx_id = entry.transaction.tx_id();
+            if entry.transaction.client_id() != self.client_id {
+                return inconsistent(format!("transaction {} of another client", tx_id));
+            }
+            if !tx_ids.insert(tx_id) {
+                return inconsistent(format!("repeated transaction {}", tx_id));
+            }
+            if !currencies.contains(&entry.currency) {
+                return inconsistent(format!("transaction {} without its account", tx_id));
+            }
+            let has_amount = matches!(
+                entry.dispute,
+                DisputeState::Open | DisputeState::ChargedBack
+            );
+            if entry.dispute_amount.is_some() != has_amount {
+                return inconsistent(format!(
+                    "transaction {} dispute amount in state {}",
+                    tx_id, entry.dispute
+                ));
+            }
+            if entry.withdrawal_pending
+                && !matches!(entry.transaction, Transaction::WithdrawalHold(..))
+            {
+                return inconsistent(format!("transaction {} pending but not a hold", tx_id));
+            }
+            if entry.refunded.is_some() && !matches!(entry.transaction, Transaction::Deposit(..)) {
+                return inconsistent(format!("transaction {} refunded but not a deposit", tx_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// The same export under another client id, e.g. to import a client whose id is taken.
+    pub fn with_client_id(&self, client_id: u16) -> ClientExport {
+        ClientExport {
+            client_id,
+            accounts: self
+                .accounts
+                .iter()
+                .map(|account| Client {
+                    id: client_id,
+                    ..account.clone()
+                })
+                .collect(),
+            transactions: self
+                .transactions
+                .iter()
+                .map(|entry| ExportedTransaction {
+                    transaction: entry.transaction.with_client_id(client_id),
+                    ..entry.clone()
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the export as headerless CSV records in the snapshot record format, starting with
+    /// `client_export,<version>,<client>` and ending with a `sha256,<digest>` record over all
+    /// preceding bytes.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut body = csv::WriterBuilder::new()
+            .flexible(true)
+            .has_headers(false)
+            .from_writer(Vec::new());
+        body.write_record(["client_export", EXPORT_VERSION, &self.client_id.to_string()])?;
+        for account in &self.accounts {
+            body.write_record(account_record(account))?;
+        }
+        for entry in &self.transactions {
+            body.write_record(transaction_record(entry))?;
+        }
+        let body = body.into_inner()?;
+        writer.write_all(&body)?;
+        writeln!(writer, "sha256,{}", hex_digest(&body))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads an export written by `write`, rejecting it if the digest doesn't match or it fails
+    /// `verify`. Windows line endings, e.g. from a checkout with line ending conversion, are
+    /// accepted.
+    pub fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.contains(&b'\r') {
+            data = String::from_utf8(data)?.replace("\r\n", "\n").into_bytes();
+        }
+        let content = data.strip_suffix(b"\n").unwrap_or(&data);
+        let split = content
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        let (body, trailer) = content.split_at(split);
+        match trailer.strip_prefix(b"sha256,") {
+            Some(digest) if digest == hex_digest(body).as_bytes() => {}
+            Some(_) => bail!("client export digest mismatch, the export was modified"),
+            None => bail!("client export has no digest"),
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .has_headers(false)
+            .from_reader(body);
+        let mut records = reader.records();
+        let client_id = match records.next().transpose()? {
+            Some(record) if record.get(0) == Some("client_export") => {
+                if record.get(1) != Some(EXPORT_VERSION) {
+                    bail!("unsupported client export version {:?}", record.get(1));
+                }
+                record.get(2).context("missing client id")?.parse()?
+            }
+            _ => bail!("not a client export"),
+        };
+        let mut export = ClientExport {
+            client_id,
+            accounts: Vec::new(),
+            transactions: Vec::new(),
+        };
+        for rec in records {
+            let record = rec?;
+            match record.get(0) {
+                Some("client") => export.accounts.push(parse_account(&record)?),
+                Some("tx") => export.transactions.push(parse_transaction(&record)?),
+                kind => bail!("unknown record kind {:?}", kind),
+            }
+        }
+        export.verify()?;
+        Ok(export)
+    }
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(1, 2, Decimal::new(5, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(2, 3, Decimal::new(7, 0)))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 2)).unwrap();
+        let export = engine.export_client(1).unwrap().unwrap();
+        assert_eq!(export.transactions.len(), 2);
+
+        let mut buffer = Vec::new();
+        export.write(&mut buffer).unwrap();
+        let read = ClientExport::read(buffer.as_slice()).unwrap();
+        assert_eq!(read, export);
+
+        let mut target = Engine::new();
+        target.import_client(&read).unwrap();
+        assert_eq!(target.client(1), engine.client(1));
+        // The open dispute moved along and can be resolved in the target
+        assert!(target.execute(Transaction::Resolve(1, 2)).is_ok());
+        assert_eq!(
+            target.import_client(&read),
+            Err(ImportError::ClientExists(1))
+        );
+        assert!(engine.export_client(3).unwrap().is_none());
+
+        let crlf = String::from_utf8(buffer.clone())
+            .unwrap()
+            .replace('\n', "\r\n");
+        assert_eq!(ClientExport::read(crlf.as_bytes()).unwrap(), export);
+
+        // Any change to the body breaks the digest
+        let tampered = String::from_utf8(buffer).unwrap().replacen("10", "99", 1);
+        assert!(ClientExport::read(tampered.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_import_rejects_conflicts() {
+        let mut source = Engine::new();
+        source
+            .execute(Transaction::Deposit(1, 1, Decimal::ONE))
+            .unwrap();
+        let export = source.export_client(1).unwrap().unwrap();
+
+        let mut target = Engine::new();
+        target
+            .execute(Transaction::Deposit(2, 1, Decimal::ONE))
+            .unwrap();
+        assert_eq!(
+            target.import_client(&export),
+            Err(ImportError::Rejected(ExecutionError::DuplicateTransaction))
+        );
+        assert!(target.client(1).is_none());
+
+        let mut broken = export.clone();
+        broken.accounts[0].held = Decimal::ONE;
+        assert!(matches!(
+            Engine::new().import_client(&broken),
+            Err(ImportError::Inconsistent(_))
+        ));
+    }
+}
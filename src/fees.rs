@@ -0,0 +1,111 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{decimal::Decimal, engine::Engine};
+
+/// Fee charged on one type of transaction: a flat amount plus a percentage of the transaction
+/// amount. Amounts are strings in the TOML file, e.g. `flat = "0.25"`, so no digits are lost.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fee {
+    #[serde(default)]
+    pub flat: Decimal,
+    #[serde(default)]
+    pub percent: Decimal,
+}
+
+impl Fee {
+    /// Fee on a transaction of `amount`, before rounding. `None` on overflow.
+    pub fn charge(&self, amount: Decimal) -> Option<Decimal> {
+        let percentage = amount
+            .checked_mul(self.percent)?
+            .checked_mul(Decimal::new(1, 2))?;
+        self.flat.checked_add(percentage)
+    }
+}
+
+/// Fees of deposits and withdrawals, collected into the account of a dedicated fee client.
+///
+/// ```toml
+/// account = 0
+///
+/// [deposit]
+/// percent = "1.5"
+///
+/// [withdrawal]
+/// flat = "0.25"
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    /// Client whose accounts collect the fees, in the currency of each transaction.
+    pub account: u16,
+    #[serde(default)]
+    pub deposit: Fee,
+    #[serde(default)]
+    pub withdrawal: Fee,
+}
+
+impl FeeSchedule {
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let schedule: FeeSchedule = toml::from_str(toml)?;
+        for (name, fee) in [
+            ("deposit", schedule.deposit),
+            ("withdrawal", schedule.withdrawal),
+        ] {
+            if fee.flat < Decimal::ZERO || fee.percent < Decimal::ZERO {
+                anyhow::bail!("The {} fee must not be negative", name);
+            }
+        }
+        Ok(schedule)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read fee schedule {}", path.display()))?;
+        Self::from_toml(&toml).with_context(|| format!("invalid fee schedule {}", path.display()))
+    }
+}
+
+/// Writes the fees each account paid, see `Engine::fees_paid`, as CSV with a
+/// `client,currency,fees` header. Balances in the client report are net of these fees.
+pub fn write_fees_paid<W: Write>(writer: W, engine: &Engine) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["client", "currency", "fees"])?;
+    for ((client_id, currency), fees) in engine.fees_paid() {
+        writer.write_record([
+            client_id.to_string(),
+            currency.to_string(),
+            fees.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_schedule() {
+        let schedule = FeeSchedule::from_toml(
+            "account = 9\n[deposit]\npercent = \"1.5\"\n[withdrawal]\nflat = \"0.25\"\npercent = \"1\"\n",
+        )
+        .unwrap();
+        assert_eq!(schedule.account, 9);
+        assert_eq!(
+            schedule.deposit.charge(Decimal::new(200, 0)),
+            Some(Decimal::new(3, 0))
+        );
+        assert_eq!(
+            schedule.withdrawal.charge(Decimal::new(10, 0)),
+            Some(Decimal::new(35, 2))
+        );
+
+        assert!(FeeSchedule::from_toml("account = 1\n[deposit]\nflat = \"-1\"\n").is_err());
+        assert!(FeeSchedule::from_toml("account = 1\n[refund]\nflat = \"1\"\n").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::{BTreeMap, btree_map::Entry},
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use csv::{StringRecord, Writer, WriterBuilder};
+use tracing::warn;
+
+use crate::transaction::{TransactionRow, TransactionType};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Inclusive range of client ids, written `N` or `N-M`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClientRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl ClientRange {
+    pub fn contains(&self, client_id: u16) -> bool {
+        (self.first..=self.last).contains(&client_id)
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientRangeError(String);
+
+impl Display for ClientRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid client range {:?}, expected N or N-M", self.0)
+    }
+}
+
+impl std::error::Error for ClientRangeError {}
+
+impl FromStr for ClientRange {
+    type Err = ClientRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ClientRangeError(s.to_string());
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let first = first.trim().parse().map_err(|_| invalid())?;
+        let last = last.trim().parse().map_err(|_| invalid())?;
+        if first > last {
+            return Err(invalid());
+        }
+        Ok(ClientRange { first, last })
+    }
+}
+
+/// Conditions a row must meet to be kept. Unset conditions match every row.
+#[derive(Clone, Debug, Default)]
+pub struct RowFilter {
+    pub clients: Option<ClientRange>,
+    /// Kept transaction types, all types if empty.
+    pub types: Vec<TransactionType>,
+    /// Earliest kept `timestamp`, inclusive. Rows without a timestamp don't match a time bound.
+    pub since: Option<u64>,
+    /// Latest kept `timestamp`, exclusive.
+    pub until: Option<u64>,
+}
+
+impl RowFilter {
+    pub fn matches(&self, row: &TransactionRow) -> bool {
+        let transaction = &row.transaction;
+        if self
+            .clients
+            .is_some_and(|clients| !clients.contains(transaction.client_id()))
+        {
+            return false;
+        }
+        if !self.types.is_empty() && !self.types.contains(&transaction.transaction_type()) {
+            return false;
+        }
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        row.timestamp.is_some_and(|timestamp| {
+            self.since.is_none_or(|since| timestamp >= since)
+                && self.until.is_none_or(|until| timestamp < until)
+        })
+    }
+}
+
+/// How kept rows are distributed over output files.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SplitBy {
+    /// One file per transaction type, e.g. `deposit.csv`
+    Type,
+    /// One file per UTC day of the `timestamp` column, e.g. `2024-03-01.csv`, rows without a
+    /// timestamp go to `undated.csv`
+    Day,
+}
+
+impl SplitBy {
+    /// Name of the output file of a row, without the extension.
+    fn part(self, row: &TransactionRow) -> String {
+        match self {
+            SplitBy::Type => row.transaction.transaction_type().to_string(),
+            SplitBy::Day => match row.timestamp {
+                Some(timestamp) => civil_date(timestamp / SECONDS_PER_DAY),
+                None => "undated".to_string(),
+            },
+        }
+    }
+}
+
+/// `YYYY-MM-DD` of a day counted from 1970-01-01, in the proleptic Gregorian calendar.
+fn civil_date(days: u64) -> String {
+    // Howard Hinnant's days_from_civil inverse, shifted so eras start on March 1st
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Destination of the kept rows. Rows are written as read, with the input's header, so the
+/// output parses exactly like the input.
+pub enum FilterOutput {
+    Single(Box<Writer<Box<dyn Write>>>),
+    Split {
+        by: SplitBy,
+        dir: PathBuf,
+        headers: StringRecord,
+        parts: BTreeMap<String, Writer<BufWriter<File>>>,
+    },
+}
+
+/// Rows may carry optional trailing columns the header doesn't have.
+fn writer_builder() -> WriterBuilder {
+    let mut builder = WriterBuilder::new();
+    builder.flexible(true);
+    builder
+}
+
+impl FilterOutput {
+    pub fn single(writer: Box<dyn Write>, headers: &StringRecord) -> Result<Self> {
+        let mut writer = writer_builder().from_writer(writer);
+        writer.write_record(headers)?;
+        Ok(FilterOutput::Single(Box::new(writer)))
+    }
+
+    /// Writes the parts into `dir`, creating it if needed. Existing parts are overwritten.
+    pub fn split(dir: PathBuf, by: SplitBy, headers: &StringRecord) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(FilterOutput::Split {
+            by,
+            dir,
+            headers: headers.clone(),
+            parts: BTreeMap::new(),
+        })
+    }
+
+    fn write(&mut self, record: &StringRecord, row: &TransactionRow) -> Result<()> {
+        match self {
+            FilterOutput::Single(writer) => writer.write_record(record)?,
+            FilterOutput::Split {
+                by,
+                dir,
+                headers,
+                parts,
+            } => {
+                let writer = match parts.entry(by.part(row)) {
+                    Entry::Occupied(part) => part.into_mut(),
+                    Entry::Vacant(part) => {
+                        let file = File::create(dir.join(format!("{}.csv", part.key())))?;
+                        let mut writer = writer_builder().from_writer(BufWriter::new(file));
+                        writer.write_record(&*headers)?;
+                        part.insert(writer)
+                    }
+                };
+                writer.write_record(record)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self {
+            FilterOutput::Single(writer) => writer.flush()?,
+            FilterOutput::Split { parts, .. } => {
+                for writer in parts.values_mut() {
+                    writer.flush()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Row counts of a filter run.
+#[derive(Debug, Default, PartialEq)]
+pub struct FilterStats {
+    pub read: u64,
+    pub written: u64,
+    /// Rows the parser rejects, dropped because no condition can be checked on them.
+    pub invalid: u64,
+}
+
+/// Parses every record with the engine's row parser and writes those matching `filter`.
+pub fn filter_records<I>(
+    records: I,
+    filter: &RowFilter,
+    output: &mut FilterOutput,
+) -> Result<FilterStats>
+where
+    I: IntoIterator<Item = Result<StringRecord>>,
+{
+    let mut stats = FilterStats::default();
+    for record in records {
+        let record = record?;
+        stats.read += 1;
+        let row: TransactionRow = match record.deserialize(None) {
+            Ok(row) => row,
+            Err(err) => {
+                warn!(row = ?record, "Dropped invalid row: {}", err);
+                stats.invalid += 1;
+                continue;
+            }
+        };
+        if filter.matches(&row) {
+            output.write(&record, &row)?;
+            stats.written += 1;
+        }
+    }
+    output.finish()?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "type,client,tx,amount,timestamp\n\
+                         deposit,1,1,1.0,86400\n\
+                         deposit,2,2,2.50,86401\n\
+                         withdrawal,1,3,0.5,172800\n\
+                         dispute,2,2,,\n\
+                         bogus,3,4,1.0,0\n\
+                         deposit,3,5,3.0000,951782400\n";
+
+    fn run(filter: &RowFilter, output: &mut FilterOutput) -> FilterStats {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(INPUT.as_bytes());
+        let records = reader.records().map(|record| record.map_err(Into::into));
+        filter_records(records, filter, output).unwrap()
+    }
+
+    #[test]
+    fn test_filter_keeps_rows_verbatim() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "timestamp"]);
+        let path = std::env::temp_dir().join(format!("filter-{}.csv", std::process::id()));
+        let mut output =
+            FilterOutput::single(Box::new(File::create(&path).unwrap()), &headers).unwrap();
+        let filter = RowFilter {
+            clients: Some("2-3".parse().unwrap()),
+            types: vec![TransactionType::Deposit, TransactionType::Dispute],
+            ..Default::default()
+        };
+        let stats = run(&filter, &mut output);
+        drop(output);
+        assert_eq!(
+            stats,
+            FilterStats {
+                read: 6,
+                written: 3,
+                invalid: 1
+            }
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "type,client,tx,amount,timestamp\n\
+             deposit,2,2,2.50,86401\n\
+             dispute,2,2,,\n\
+             deposit,3,5,3.0000,951782400\n"
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_filter_by_time_and_split_by_day() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "timestamp"]);
+        let dir = std::env::temp_dir().join(format!("filter-split-{}", std::process::id()));
+        let mut output = FilterOutput::split(dir.clone(), SplitBy::Day, &headers).unwrap();
+        let filter = RowFilter {
+            since: Some(86_400),
+            ..Default::default()
+        };
+        assert_eq!(run(&filter, &mut output).written, 4);
+        drop(output);
+        let mut parts: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        parts.sort();
+        assert_eq!(
+            parts,
+            ["1970-01-02.csv", "1970-01-03.csv", "2000-02-29.csv"]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("1970-01-02.csv")).unwrap(),
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,1.0,86400\n\
+             deposit,2,2,2.50,86401\n"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_client_range() {
+        assert_eq!(
+            "7".parse::<ClientRange>().unwrap(),
+            ClientRange { first: 7, last: 7 }
+        );
+        assert!("10-20".parse::<ClientRange>().unwrap().contains(20));
+        for invalid in ["", "5-", "20-10", "a-b", "70000"] {
+            assert!(invalid.parse::<ClientRange>().is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use std::{net::SocketAddr, pin::Pin};
+
+use crate::decimal::Decimal;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    client::Client,
+    currency::Currency,
+    service::{EngineService, ServiceError},
+    transaction::Transaction,
+    windowed::Window,
+};
+
+pub use proto::{
+    payment_engine_client::PaymentEngineClient, payment_engine_server::PaymentEngineServer,
+};
+
+/// Messages of `proto/payment_engine.proto`, written by hand so the build doesn't need `protoc`.
+/// Field tags must match the proto file.
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Transaction {
+        #[prost(string, tag = "1")]
+        pub r#type: String,
+        #[prost(uint32, tag = "2")]
+        pub client: u32,
+        #[prost(uint32, tag = "3")]
+        pub tx: u32,
+        #[prost(string, tag = "4")]
+        pub amount: String,
+        #[prost(uint64, tag = "5")]
+        pub timestamp: u64,
+        #[prost(string, tag = "6")]
+        pub currency: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubmitTransactionResponse {
+        #[prost(message, optional, tag = "1")]
+        pub account: Option<Account>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetAccountRequest {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+        #[prost(string, tag = "2")]
+        pub currency: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StreamReportRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Account {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+        #[prost(string, tag = "2")]
+        pub currency: String,
+        #[prost(string, tag = "3")]
+        pub available: String,
+        #[prost(string, tag = "4")]
+        pub held: String,
+        #[prost(string, tag = "5")]
+        pub total: String,
+        #[prost(bool, tag = "6")]
+        pub locked: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetMetricsRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Metrics {
+        #[prost(uint64, tag = "1")]
+        pub applied: u64,
+        #[prost(uint64, tag = "2")]
+        pub rejected: u64,
+        #[prost(uint64, tag = "3")]
+        pub window_seconds: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub windows: Vec<MetricsWindow>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MetricsWindow {
+        #[prost(uint64, tag = "1")]
+        pub start: u64,
+        #[prost(uint64, tag = "2")]
+        pub deposits: u64,
+        #[prost(string, tag = "3")]
+        pub deposit_volume: String,
+        #[prost(uint64, tag = "4")]
+        pub withdrawals: u64,
+        #[prost(string, tag = "5")]
+        pub withdrawal_volume: String,
+        #[prost(uint64, tag = "6")]
+        pub disputes: u64,
+        #[prost(uint64, tag = "7")]
+        pub chargebacks: u64,
+        #[prost(uint64, tag = "8")]
+        pub rejected: u64,
+    }
+
+    // Client and server generated by build.rs
+    include!(concat!(
+        env!("OUT_DIR"),
+        "/payment_engine.v1.PaymentEngine.rs"
+    ));
+}
+
+impl From<&Client> for proto::Account {
+    fn from(client: &Client) -> Self {
+        proto::Account {
+            client: client.id.into(),
+            currency: client.currency.to_string(),
+            available: client.available.to_string(),
+            held: client.held.to_string(),
+            total: client.total.to_string(),
+            locked: client.locked,
+        }
+    }
+}
+
+impl From<&Window> for proto::MetricsWindow {
+    fn from(window: &Window) -> Self {
+        proto::MetricsWindow {
+            start: window.start,
+            deposits: window.deposits,
+            deposit_volume: window.deposit_volume.to_string(),
+            withdrawals: window.withdrawals,
+            withdrawal_volume: window.withdrawal_volume.to_string(),
+            disputes: window.disputes,
+            chargebacks: window.chargebacks,
+            rejected: window.rejected,
+        }
+    }
+}
+
+fn parse_client(client: u32) -> Result<u16, Status> {
+    u16::try_from(client).map_err(|_| Status::invalid_argument("client id out of range"))
+}
+
+/// Empty stands for the default currency.
+fn parse_currency(currency: &str) -> Result<Option<Currency>, Status> {
+    match currency {
+        "" => Ok(None),
+        code => code
+            .parse()
+            .map(Some)
+            .map_err(|err| Status::invalid_argument(format!("{}", err))),
+    }
+}
+
+fn parse_transaction(message: &proto::Transaction) -> Result<Transaction, Status> {
+    let amount = match message.amount.as_str() {
+        "" => Decimal::ZERO,
+        amount => amount
+            .parse()
+            .map_err(|_| Status::invalid_argument(format!("invalid amount {:?}", amount)))?,
+    };
+    Transaction::new(
+        &message.r#type,
+        parse_client(message.client)?,
+        message.tx,
+        amount,
+    )
+    .map_err(|err| Status::invalid_argument(format!("{}", err)))
+}
+
+/// gRPC front end of an `EngineService`.
+pub struct GrpcService {
+    service: EngineService,
+}
+
+impl GrpcService {
+    pub fn new(service: EngineService) -> Self {
+        GrpcService { service }
+    }
+
+    /// Wraps the service into the tonic server, ready to be added to a `tonic::transport::Server`.
+    pub fn into_server(self) -> PaymentEngineServer<Self> {
+        PaymentEngineServer::new(self)
+    }
+}
+
+type AccountStream = Pin<Box<dyn Stream<Item = Result<proto::Account, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl proto::payment_engine_server::PaymentEngine for GrpcService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let message = request.into_inner();
+        let transaction = parse_transaction(&message)?;
+        let currency = parse_currency(&message.currency)?;
+        if message.timestamp > 0 {
+            self.service
+                .with_engine(|engine| engine.clock().observe(message.timestamp))
+                .await;
+        }
+        match self.service.submit(transaction, currency).await {
+            Ok(account) => Ok(Response::new(proto::SubmitTransactionResponse {
+                account: account.as_ref().map(Into::into),
+            })),
+            Err(ServiceError::Rejected(err)) => Err(Status::failed_precondition(err.to_string())),
+            Err(err @ ServiceError::Storage(_)) => Err(Status::internal(err.to_string())),
+            Err(ServiceError::RateLimited(limited)) => {
+                let mut status = Status::resource_exhausted(limited.to_string());
+                let retry_after = (limited.retry_after.as_millis() as u64).max(1);
+                status
+                    .metadata_mut()
+                    .insert("retry-after-ms", retry_after.into());
+                Err(status)
+            }
+        }
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let message = request.into_inner();
+        let client_id = parse_client(message.client)?;
+        let currency = parse_currency(&message.currency)?;
+        match self.service.account(client_id, currency).await {
+            Some(client) => Ok(Response::new((&client).into())),
+            None => Err(Status::not_found(format!(
+                "client {} has no account",
+                client_id
+            ))),
+        }
+    }
+
+    type StreamReportStream = AccountStream;
+
+    async fn stream_report(
+        &self,
+        _request: Request<proto::StreamReportRequest>,
+    ) -> Result<Response<Self::StreamReportStream>, Status> {
+        // A copy of the accounts so the engine isn't locked while the client reads the stream
+        let accounts: Vec<_> = self
+            .service
+            .clients()
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?
+            .iter()
+            .map(|client| Ok(client.into()))
+            .collect();
+        Ok(Response::new(Box::pin(tokio_stream::iter(accounts))))
+    }
+
+    async fn get_metrics(
+        &self,
+        _request: Request<proto::GetMetricsRequest>,
+    ) -> Result<Response<proto::Metrics>, Status> {
+        let metrics = self.service.metrics();
+        Ok(Response::new(proto::Metrics {
+            applied: metrics.applied(),
+            rejected: metrics.rejected(),
+            window_seconds: metrics.window_width().unwrap_or_default(),
+            windows: metrics.windows().iter().map(Into::into).collect(),
+        }))
+    }
+}
+
+/// Serves the engine on `addr` until `shutdown` completes.
+pub async fn serve(
+    service: EngineService,
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(GrpcService::new(service).into_server())
+        .serve_with_shutdown(addr, shutdown)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_stream::{StreamExt, wrappers::TcpListenerStream};
+    use tonic::Code;
+
+    use super::*;
+    use crate::{config::EngineConfig, windowed::WindowedMetrics};
+
+    fn transaction(r#type: &str, client: u32, tx: u32, amount: &str) -> proto::Transaction {
+        proto::Transaction {
+            r#type: r#type.to_string(),
+            client,
+            tx,
+            amount: amount.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_grpc_service() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let service = EngineService::builder(EngineConfig::default())
+                .windowed_metrics(WindowedMetrics::new(60, 10))
+                .build();
+            tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(GrpcService::new(service).into_server())
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+            let mut client = PaymentEngineClient::connect(format!("http://{}", addr))
+                .await
+                .unwrap();
+
+            let response = client
+                .submit_transaction(transaction("deposit", 1, 1, "10.5"))
+                .await
+                .unwrap()
+                .into_inner();
+            let account = response.account.unwrap();
+            assert_eq!((account.client, account.total.as_str()), (1, "10.5"));
+            client
+                .submit_transaction(transaction("deposit", 2, 2, "3"))
+                .await
+                .unwrap();
+
+            let rejected = client
+                .submit_transaction(transaction("withdrawal", 1, 3, "100"))
+                .await
+                .unwrap_err();
+            assert_eq!(rejected.code(), Code::FailedPrecondition);
+            let invalid = client
+                .submit_transaction(transaction("deposit", 70000, 4, "1"))
+                .await
+                .unwrap_err();
+            assert_eq!(invalid.code(), Code::InvalidArgument);
+
+            client
+                .submit_transaction(transaction("dispute", 1, 1, ""))
+                .await
+                .unwrap();
+            let account = client
+                .get_account(proto::GetAccountRequest {
+                    client: 1,
+                    currency: String::new(),
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                (
+                    account.available.parse::<Decimal>().unwrap(),
+                    account.held.parse::<Decimal>().unwrap()
+                ),
+                (Decimal::ZERO, Decimal::new(105, 1))
+            );
+            let missing = client
+                .get_account(proto::GetAccountRequest {
+                    client: 3,
+                    currency: String::new(),
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(missing.code(), Code::NotFound);
+
+            let report: Vec<u32> = client
+                .stream_report(proto::StreamReportRequest {})
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|account| account.unwrap().client)
+                .collect()
+                .await;
+            assert_eq!(report, vec![1, 2]);
+
+            let metrics = client
+                .get_metrics(proto::GetMetricsRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!((metrics.applied, metrics.rejected), (3, 1));
+            // Without timestamps all transactions fall into the first window
+            assert_eq!(metrics.windows.len(), 1);
+            assert_eq!(metrics.windows[0].deposit_volume, "13.5");
+        });
+    }
+}
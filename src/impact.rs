@@ -0,0 +1,165 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+
+use crate::decimal::Decimal;
+use anyhow::Result;
+
+use crate::{
+    currency::Currency,
+    engine::{Engine, ExecutionError},
+    transaction::{Transaction, TransactionType},
+};
+
+/// Most locked clients listed by id, the others are only counted.
+const LISTED_LOCKED: usize = 20;
+
+/// What a run would change, printed by `--dry-run` instead of the client report: the applied
+/// transactions by type, the value they moved, the accounts they locked and the rejected rows.
+pub struct ImpactSummary {
+    applied: BTreeMap<TransactionType, u64>,
+    moved: BTreeMap<Currency, Decimal>,
+    rejected: u64,
+    /// Accounts already locked before the run.
+    locked: BTreeSet<(u16, Currency)>,
+}
+
+impl ImpactSummary {
+    /// Starts from the state of `engine`, e.g. seeded from a snapshot.
+    pub fn new(engine: &Engine) -> Result<Self> {
+        let mut locked = BTreeSet::new();
+        for client in engine.clients() {
+            let client = client?;
+            if client.locked {
+                locked.insert((client.id, client.currency));
+            }
+        }
+        Ok(ImpactSummary {
+            applied: BTreeMap::new(),
+            moved: BTreeMap::new(),
+            rejected: 0,
+            locked,
+        })
+    }
+
+    /// Accounts an executed transaction. Deposits, withdrawals, withdrawal holds and adjustments
+    /// move their amount in the row's currency.
+    pub fn record(
+        &mut self,
+        engine: &Engine,
+        transaction: &Transaction,
+        currency: Option<Currency>,
+        result: &Result<(), ExecutionError>,
+    ) {
+        if result.is_err() {
+            self.rejected += 1;
+            return;
+        }
+        *self
+            .applied
+            .entry(transaction.transaction_type())
+            .or_default() += 1;
+        if let Some(amount) = transaction.amount()
+            && !matches!(
+                transaction,
+                Transaction::AssertBalance(..) | Transaction::SetOverdraft(..)
+            )
+        {
+            let currency = currency.unwrap_or(engine.config().default_currency);
+            let moved = self.moved.entry(currency).or_default();
+            *moved = moved.checked_add(amount).unwrap_or(*moved);
+        }
+    }
+
+    /// Accounts a row that didn't parse.
+    pub fn record_invalid(&mut self) {
+        self.rejected += 1;
+    }
+
+    /// Writes the summary with the accounts `engine` locked since the start.
+    pub fn write<W: Write>(&self, engine: &Engine, mut writer: W) -> Result<()> {
+        let mut newly_locked = Vec::new();
+        for client in engine.clients() {
+            let client = client?;
+            if client.locked && !self.locked.contains(&(client.id, client.currency)) {
+                newly_locked.push(client.id.to_string());
+            }
+        }
+        writeln!(writer, "Dry run, nothing was saved")?;
+        writeln!(
+            writer,
+            "Applied transactions: {}",
+            self.applied.values().sum::<u64>()
+        )?;
+        for (transaction_type, count) in &self.applied {
+            writeln!(writer, "  {}: {}", transaction_type, count)?;
+        }
+        for (currency, moved) in &self.moved {
+            writeln!(writer, "Value moved: {} {}", moved, currency)?;
+        }
+        writeln!(writer, "Rejected rows: {}", self.rejected)?;
+        write!(writer, "Accounts locked: {}", newly_locked.len())?;
+        if newly_locked.len() > LISTED_LOCKED {
+            let more = newly_locked.len() - LISTED_LOCKED;
+            newly_locked.truncate(LISTED_LOCKED);
+            write!(
+                writer,
+                " (clients {} and {} more)",
+                newly_locked.join(", "),
+                more
+            )?;
+        } else if !newly_locked.is_empty() {
+            write!(writer, " (clients {})", newly_locked.join(", "))?;
+        }
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_impact_summary() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(5, 0)))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Chargeback(1, 1)).unwrap();
+        let mut summary = ImpactSummary::new(&engine).unwrap();
+
+        for transaction in [
+            Transaction::Deposit(2, 2, Decimal::new(10, 0)),
+            Transaction::Withdrawal(2, 3, Decimal::new(4, 0)),
+            Transaction::Withdrawal(2, 4, Decimal::new(40, 0)),
+            Transaction::Dispute(2, 2),
+            Transaction::Chargeback(2, 2),
+        ] {
+            let result = engine.execute(transaction.clone());
+            summary.record(&engine, &transaction, None, &result);
+        }
+        summary.record_invalid();
+
+        let mut output = Vec::new();
+        summary.write(&engine, &mut output).unwrap();
+        let currency = Currency::default();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "Dry run, nothing was saved\n\
+                 Applied transactions: 4\n  \
+                 deposit: 1\n  \
+                 withdrawal: 1\n  \
+                 dispute: 1\n  \
+                 chargeback: 1\n\
+                 Value moved: 14 {}\n\
+                 Rejected rows: 2\n\
+                 Accounts locked: 1 (clients 2)\n",
+                currency
+            )
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use crate::{
+    client::Client, config::LockedAccountPolicy, decimal::Decimal, transaction::Transaction,
+};
+
+/// A broken accounting invariant, a bug in the engine or a corrupted store rather than a
+/// rejected transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvariantViolation {
+    /// The total of an account isn't the sum of its available and held funds.
+    TotalMismatch(Box<Client>),
+    /// An account holds a negative amount.
+    NegativeHeld(Box<Client>),
+    /// The balances of a locked account changed by a transaction that must not touch them.
+    LockedAccountChanged {
+        before: Box<Client>,
+        after: Box<Client>,
+    },
+    /// The accounts can't be read to be checked.
+    StoreUnavailable,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::TotalMismatch(client) => write!(
+                f,
+                "Total {} of client {} in {} is not available {} plus held {}",
+                client.total, client.id, client.currency, client.available, client.held
+            ),
+            InvariantViolation::NegativeHeld(client) => write!(
+                f,
+                "Client {} holds a negative amount {} in {}",
+                client.id, client.held, client.currency
+            ),
+            InvariantViolation::LockedAccountChanged { before, after } => write!(
+                f,
+                "Locked account of client {} in {} changed from {}/{} to {}/{} available/held",
+                before.id,
+                before.currency,
+                before.available,
+                before.held,
+                after.available,
+                after.held
+            ),
+            InvariantViolation::StoreUnavailable => write!(f, "Client store is unreadable"),
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// Checks the invariants every account satisfies at all times.
+pub fn check_account(client: &Client) -> Result<(), InvariantViolation> {
+    if client.available.checked_add(client.held) != Some(client.total) {
+        return Err(InvariantViolation::TotalMismatch(Box::new(client.clone())));
+    }
+    if client.held < Decimal::ZERO {
+        return Err(InvariantViolation::NegativeHeld(Box::new(client.clone())));
+    }
+    Ok(())
+}
+
+/// Checks the accounts of a client after `transaction` against the same accounts before it: each
+/// must satisfy `check_account` and an account that was locked keeps its balances unless the
+/// transaction may change them under the locked account `policy`.
+pub fn check_step(
+    before: &[Client],
+    after: &[Client],
+    transaction: &Transaction,
+    policy: LockedAccountPolicy,
+) -> Result<(), InvariantViolation> {
+    for client in after {
+        check_account(client)?;
+        let locked = before
+            .iter()
+            .find(|old| old.currency == client.currency && old.locked);
+        if let Some(old) = locked
+            && !changes_locked(transaction, policy)
+            && (old.available, old.held) != (client.available, client.held)
+        {
+            return Err(InvariantViolation::LockedAccountChanged {
+                before: Box::new(old.clone()),
+                after: Box::new(client.clone()),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether `transaction` may change the balances of a locked account: dispute operations as far
+/// as the locked account policy allows and the transactions settling funds held before the lock.
+fn changes_locked(transaction: &Transaction, policy: LockedAccountPolicy) -> bool {
+    match transaction {
+        Transaction::Dispute(..) => policy == LockedAccountPolicy::Allow,
+        Transaction::Resolve(..) | Transaction::Chargeback(..) => {
+            policy != LockedAccountPolicy::Freeze
+        }
+        Transaction::ChargebackReversal(..)
+        | Transaction::AdjustCredit(..)
+        | Transaction::AdjustDebit(..)
+        | Transaction::WithdrawalSettle(..)
+        | Transaction::WithdrawalCancel(..)
+        | Transaction::CloseAccount(..) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{config::EngineConfig, engine::Engine};
+
+    /// A transaction of a small set of clients and ids, so disputes often hit earlier rows.
+    fn step() -> impl Strategy<Value = Transaction> {
+        (
+            prop::sample::select(vec![
+                "deposit",
+                "withdrawal",
+                "dispute",
+                "resolve",
+                "chargeback",
+            ]),
+            0..4u16,
+            0..32u32,
+            1..=1000i64,
+        )
+            .prop_map(|(kind, client, tx, amount)| {
+                Transaction::new(kind, client, tx, Decimal::new(amount, 1)).unwrap()
+            })
+    }
+
+    /// Applies the stream and checks the invariants after every step, and that the totals of all
+    /// accounts are the deposits less the withdrawals and chargebacks, which take the disputed
+    /// deposit.
+    fn check_stream(
+        policy: LockedAccountPolicy,
+        steps: Vec<Transaction>,
+    ) -> Result<(), TestCaseError> {
+        let mut engine = Engine::with_config(EngineConfig {
+            locked_account_policy: policy,
+            ..EngineConfig::default()
+        });
+        let mut net = Decimal::ZERO;
+        for transaction in steps {
+            let client_id = transaction.client_id();
+            let accounts = |engine: &Engine| {
+                engine
+                    .client_accounts(client_id)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap()
+            };
+            let before = accounts(&engine);
+            let deposit = engine.transaction(transaction.tx_id());
+            if engine.execute(transaction.clone()).is_ok() {
+                let change = match (&transaction, deposit) {
+                    (Transaction::Deposit(_, _, amount), _) => *amount,
+                    (Transaction::Withdrawal(_, _, amount), _) => -*amount,
+                    (Transaction::Chargeback(..), Some(Transaction::Deposit(_, _, amount))) => {
+                        -amount
+                    }
+                    _ => Decimal::ZERO,
+                };
+                net = net.checked_add(change).unwrap();
+            }
+            prop_assert_eq!(
+                check_step(&before, &accounts(&engine), &transaction, policy),
+                Ok(())
+            );
+            prop_assert_eq!(engine.check_invariants(), Ok(()));
+            let total = engine
+                .clients()
+                .map(|client| client.unwrap().total)
+                .try_fold(Decimal::ZERO, Decimal::checked_add)
+                .unwrap();
+            prop_assert_eq!(total, net);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(300))]
+
+        #[test]
+        fn test_invariants_hold_after_every_step(
+            steps in prop::collection::vec(step(), 0..100),
+        ) {
+            check_stream(LockedAccountPolicy::default(), steps)?;
+        }
+
+        #[test]
+        fn test_invariants_hold_after_every_step_frozen(
+            steps in prop::collection::vec(step(), 0..100),
+        ) {
+            check_stream(LockedAccountPolicy::Freeze, steps)?;
+        }
+    }
+
+    #[test]
+    fn test_check_account() {
+        let broken = Client {
+            available: Decimal::ONE,
+            total: Decimal::ZERO,
+            ..Client::new(1)
+        };
+        assert_eq!(
+            check_account(&broken),
+            Err(InvariantViolation::TotalMismatch(Box::new(broken.clone())))
+        );
+    }
+}
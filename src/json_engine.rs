@@ -0,0 +1,98 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+
+use crate::{
+    config::EngineConfig,
+    engine::{Engine, ExecutionError},
+    report::{JsonReportWriter, ReportColumns},
+    transaction::TransactionRow,
+};
+
+/// Engine driven by JSON documents, the interface for bindings to other runtimes such as a
+/// WebAssembly build for browser-based tools. Rows go through the same rules as in batch mode.
+pub struct JsonEngine {
+    engine: Engine,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JsonEngineError {
+    /// The document is not a valid transaction row.
+    Invalid(String),
+    /// The engine rejected the transaction.
+    Rejected(ExecutionError),
+}
+
+impl Display for JsonEngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonEngineError::Invalid(reason) => write!(f, "Invalid transaction: {}", reason),
+            JsonEngineError::Rejected(err) => write!(f, "Failed to execute transaction: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for JsonEngineError {}
+
+impl JsonEngine {
+    pub fn new(config: EngineConfig) -> Self {
+        JsonEngine {
+            engine: Engine::with_config(config),
+        }
+    }
+
+    /// Executes a transaction given as a JSON object with the columns of an input row: `type`,
+    /// `client`, `tx` and the optional `amount`, `timestamp` and `currency`. Amounts are strings
+    /// so they keep their exact decimal value.
+    pub fn execute_json(&mut self, tx_json: &str) -> Result<(), JsonEngineError> {
+        let row: TransactionRow = serde_json::from_str(tx_json)
+            .map_err(|err| JsonEngineError::Invalid(err.to_string()))?;
+        if let Some(timestamp) = row.timestamp {
+            self.engine.clock().observe(timestamp);
+        }
+        self.engine
+            .execute_row(row.transaction, row.currency, row.dispute_amount)
+            .map_err(JsonEngineError::Rejected)
+    }
+
+    /// The client report as a JSON array, as written by `--report-format json`.
+    pub fn report_json(&self) -> Result<String> {
+        let mut report = Vec::new();
+        let columns = ReportColumns::of(self.engine.config());
+        self.engine
+            .write_report(&mut JsonReportWriter::new(&mut report, columns))?;
+        Ok(String::from_utf8(report)?)
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_engine() {
+        let mut engine = JsonEngine::new(EngineConfig::default());
+        engine
+            .execute_json(r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#)
+            .unwrap();
+        engine
+            .execute_json(r#"{"type": "dispute", "client": 1, "tx": 1, "timestamp": 100}"#)
+            .unwrap();
+        assert_eq!(
+            engine.execute_json(r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "1"}"#),
+            Err(JsonEngineError::Rejected(ExecutionError::InsufficientFunds))
+        );
+        assert!(matches!(
+            engine.execute_json(r#"{"type": "transfer", "client": 1, "tx": 3}"#),
+            Err(JsonEngineError::Invalid(_))
+        ));
+        let report: serde_json::Value =
+            serde_json::from_str(&engine.report_json().unwrap()).unwrap();
+        assert_eq!(report[0]["client"], 1);
+        assert_eq!(report[0]["held"].to_string(), "2.5");
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use csv::StringRecord;
+use rdkafka::{
+    ClientConfig,
+    consumer::{BaseConsumer, Consumer},
+    message::Message,
+};
+
+use crate::{
+    pipeline::Pipeline,
+    rate_limit::{RateLimit, RateLimiter},
+    report::{ReportFormat, write_report_file},
+};
+
+const POLL_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub struct ConsumerOptions {
+    pub brokers: String,
+    pub topic: String,
+    pub group_id: String,
+    pub report_path: PathBuf,
+    pub report_format: ReportFormat,
+    pub report_interval: Duration,
+    /// Limit of the rows of all clients together, unlimited if not set.
+    pub rate_limit: Option<RateLimit>,
+    /// Limit of the rows of each client, unlimited if not set.
+    pub client_rate_limit: Option<RateLimit>,
+}
+
+/// Consumes transactions from a Kafka topic until an error occurs.
+///
+/// Each message carries one or more headerless CSV rows (`type,client,tx,amount[,timestamp]`).
+/// The offset of a message is stored for commit only after all its rows were applied or rejected
+/// and the rejects and audit files were flushed, so a crash never skips unprocessed messages.
+/// Rows over the rate limits wait until the limits admit them, the topic keeps the messages
+/// behind.
+pub fn consume(options: &ConsumerOptions, pipeline: &mut Pipeline) -> Result<()> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &options.brokers)
+        .set("group.id", &options.group_id)
+        .set("enable.auto.commit", "true")
+        .set("enable.auto.offset.store", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()?;
+    consumer.subscribe(&[&options.topic])?;
+    let mut limiter = RateLimiter::new(options.rate_limit, options.client_rate_limit);
+
+    let mut last_report = Instant::now();
+    loop {
+        if let Some(message) = consumer.poll(POLL_TIMEOUT) {
+            let message = message?;
+            if let Some(payload) = message.payload() {
+                for record in payload_records(payload) {
+                    let record = record?;
+                    throttle(&mut limiter, &record);
+                    pipeline.process(&record)?;
+                }
+            }
+            pipeline.flush()?;
+            consumer.store_offset_from_message(&message)?;
+        }
+        if last_report.elapsed() >= options.report_interval {
+            write_report_file(
+                &pipeline.engine,
+                &options.report_path,
+                options.report_format,
+            )?;
+            last_report = Instant::now();
+        }
+    }
+}
+
+/// Waits until the rate limits admit the row. Rows without a valid client id are left for the
+/// pipeline to reject.
+fn throttle(limiter: &mut RateLimiter, record: &StringRecord) {
+    let Some(client_id) = record.get(1).and_then(|client| client.trim().parse().ok()) else {
+        return;
+    };
+    while let Err(limited) = limiter.acquire(client_id, Instant::now()) {
+        std::thread::sleep(limited.retry_after);
+    }
+}
+
+/// Splits a message payload into headerless CSV records.
+fn payload_records(payload: &[u8]) -> impl Iterator<Item = csv::Result<StringRecord>> + '_ {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(payload)
+        .into_records()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_records() {
+        let records: Vec<StringRecord> =
+            payload_records(b"deposit,1,1,1.0\ndispute,1,1,,1700000000\n")
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0],
+            StringRecord::from(vec!["deposit", "1", "1", "1.0"])
+        );
+        assert_eq!(records[1].len(), 5);
+    }
+}
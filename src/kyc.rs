@@ -0,0 +1,187 @@
+use std::{collections::BTreeMap, fmt::Display, io::Read, path::Path, str::FromStr, sync::Arc};
+
+use anyhow::{Context, Result};
+
+/// Know-your-customer verification state of a client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KycStatus {
+    #[default]
+    Unverified,
+    Pending,
+    Verified,
+    Rejected,
+}
+
+impl Display for KycStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            KycStatus::Unverified => "unverified",
+            KycStatus::Pending => "pending",
+            KycStatus::Verified => "verified",
+            KycStatus::Rejected => "rejected",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for KycStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "unverified" => Ok(KycStatus::Unverified),
+            "pending" => Ok(KycStatus::Pending),
+            "verified" => Ok(KycStatus::Verified),
+            "rejected" => Ok(KycStatus::Rejected),
+            _ => anyhow::bail!("unknown kyc status {}", s),
+        }
+    }
+}
+
+/// Descriptive data of a client, shared by all its accounts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientMetadata {
+    pub name: Option<String>,
+    /// Id of the client in another system, e.g. the CRM.
+    pub external_id: Option<String>,
+    pub kyc: KycStatus,
+    /// Every column but `client` as it is in the file, e.g. `country` for the cohort report.
+    pub columns: BTreeMap<String, String>,
+}
+
+/// Metadata of the clients listed in the client metadata CSV, with a `client` column, the
+/// optional `name`, `external_id` and `kyc` columns and any others:
+///
+/// ```csv
+/// client,name,external_id,kyc,country
+/// 1,Alice,crm-0017,verified,DE
+/// 2,Bob,,pending,US
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientDirectory {
+    headers: Vec<String>,
+    clients: BTreeMap<u16, Arc<ClientMetadata>>,
+}
+
+impl ClientDirectory {
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+        let client_idx = headers
+            .iter()
+            .position(|header| header == "client")
+            .context("client metadata has no `client` column")?;
+        let mut clients = BTreeMap::new();
+        for (line, record) in reader.records().enumerate() {
+            // Line 1 is the header
+            let record = record?;
+            let client_id: u16 = record
+                .get(client_idx)
+                .unwrap_or_default()
+                .parse()
+                .with_context(|| format!("invalid client id on line {}", line + 2))?;
+            let columns: BTreeMap<String, String> = headers
+                .iter()
+                .zip(record.iter())
+                .filter(|(header, _)| *header != "client")
+                .map(|(header, value)| (header.clone(), value.to_string()))
+                .collect();
+            let text = |column: &str| {
+                columns
+                    .get(column)
+                    .filter(|value| !value.is_empty())
+                    .cloned()
+            };
+            let kyc = match text("kyc") {
+                Some(kyc) => kyc
+                    .parse()
+                    .with_context(|| format!("invalid kyc status on line {}", line + 2))?,
+                None => KycStatus::default(),
+            };
+            let metadata = ClientMetadata {
+                name: text("name"),
+                external_id: text("external_id"),
+                kyc,
+                columns,
+            };
+            clients.insert(client_id, Arc::new(metadata));
+        }
+        Ok(ClientDirectory { headers, clients })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to read client metadata {}", path.display()))?;
+        Self::from_reader(file)
+            .with_context(|| format!("invalid client metadata {}", path.display()))
+    }
+
+    /// Whether the file has the column, e.g. the one the cohort report groups by.
+    pub fn has_column(&self, column: &str) -> bool {
+        self.headers.iter().any(|header| header == column)
+    }
+
+    /// The listed clients with their metadata, by id.
+    pub fn clients(&self) -> impl Iterator<Item = (u16, &ClientMetadata)> {
+        self.clients
+            .iter()
+            .map(|(client_id, metadata)| (*client_id, metadata.as_ref()))
+    }
+
+    pub fn get(&self, client_id: u16) -> Option<&Arc<ClientMetadata>> {
+        self.clients.get(&client_id)
+    }
+
+    /// KYC status of the client, unverified if it isn't listed.
+    pub fn kyc(&self, client_id: u16) -> KycStatus {
+        self.get(client_id)
+            .map(|metadata| metadata.kyc)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_directory() {
+        let directory = ClientDirectory::from_reader(
+            "client,name,external_id,kyc,country\n1,Alice,crm-0017,verified,DE\n\
+             2,Bob,, pending,US\n3,,,,\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            directory.get(1).map(|metadata| metadata.as_ref()),
+            Some(&ClientMetadata {
+                name: Some("Alice".to_string()),
+                external_id: Some("crm-0017".to_string()),
+                kyc: KycStatus::Verified,
+                columns: BTreeMap::from([
+                    ("country".to_string(), "DE".to_string()),
+                    ("external_id".to_string(), "crm-0017".to_string()),
+                    ("kyc".to_string(), "verified".to_string()),
+                    ("name".to_string(), "Alice".to_string()),
+                ]),
+            })
+        );
+        assert_eq!(directory.get(2).unwrap().external_id, None);
+        assert_eq!(directory.kyc(2), KycStatus::Pending);
+        assert_eq!(directory.kyc(3), KycStatus::Unverified);
+        assert_eq!(directory.kyc(4), KycStatus::Unverified);
+        assert!(directory.has_column("country"));
+        assert!(!directory.has_column("client_type"));
+        assert_eq!(directory.clients().count(), 3);
+
+        // Only the client column is required
+        let directory = ClientDirectory::from_reader("client,country\n1,DE\n".as_bytes()).unwrap();
+        assert_eq!(directory.kyc(1), KycStatus::Unverified);
+        assert_eq!(directory.get(1).unwrap().columns["country"], "DE");
+
+        assert!(ClientDirectory::from_reader("client,kyc\n1,approved\n".as_bytes()).is_err());
+        assert!(ClientDirectory::from_reader("id,kyc\n1,verified\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const MAX_LEN: usize = 64;
+
+/// Name of a logical ledger, e.g. `staging` or a customer id. Persistent state of different
+/// ledgers is kept apart, so they can share one archive directory or database.
+///
+/// Names are 1 to 64 ASCII letters, digits, `-` or `_`, safe to use as a directory or table name.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ledger(String);
+
+impl Ledger {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The ledger's own directory inside a shared base directory.
+    pub fn dir(&self, base: &Path) -> PathBuf {
+        base.join(&self.0)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LedgerError {
+    InvalidName,
+}
+
+impl Display for LedgerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LedgerError::InvalidName => write!(
+                f,
+                "Ledger name must be 1 to {} letters, digits, `-` or `_`",
+                MAX_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+impl FromStr for Ledger {
+    type Err = LedgerError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let valid = (1..=MAX_LEN).contains(&name.len())
+            && name
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+        if !valid {
+            return Err(LedgerError::InvalidName);
+        }
+        Ok(Ledger(name.to_string()))
+    }
+}
+
+impl Display for Ledger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_names() {
+        let ledger: Ledger = "customer_42-eu".parse().unwrap();
+        assert_eq!(
+            ledger.dir(Path::new("/var/archive")),
+            PathBuf::from("/var/archive/customer_42-eu")
+        );
+        for invalid in ["", "../prod", "a b", "ledger/1", &"x".repeat(65)] {
+            assert_eq!(invalid.parse::<Ledger>(), Err(LedgerError::InvalidName));
+        }
+    }
+}
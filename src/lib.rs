@@ -0,0 +1,76 @@
+pub mod amount;
+pub mod archive;
+#[cfg(feature = "arrow")]
+pub mod arrow_source;
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod audit;
+#[cfg(feature = "binary")]
+pub mod binary;
+pub mod chaos;
+pub mod checkpoint;
+pub mod client;
+pub mod client_store;
+pub mod clock;
+pub mod cohorts;
+pub mod compression;
+pub mod config;
+pub mod config_file;
+pub mod consolidated;
+pub mod currency;
+#[cfg(feature = "sql")]
+pub mod db;
+pub mod decimal;
+pub mod dialect;
+pub mod diff;
+pub mod dispute;
+pub mod disputes;
+pub mod engine;
+pub mod events;
+pub mod export;
+pub mod fees;
+pub mod filter;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod impact;
+pub mod invariants;
+pub mod json_engine;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod kyc;
+pub mod ledger;
+pub mod limits;
+pub mod memory;
+pub mod merge;
+pub mod parallel;
+#[cfg(feature = "parquet")]
+pub mod parquet_source;
+pub mod pipeline;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod progress;
+pub mod query;
+pub mod rate_limit;
+pub mod registry;
+pub mod replica;
+pub mod report;
+#[cfg(feature = "async")]
+pub mod service;
+pub mod shadow;
+pub mod shared;
+pub mod shutdown;
+pub mod snapshot;
+pub mod source;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod statement;
+pub mod summary;
+pub mod tenants;
+pub mod topk;
+pub mod transaction;
+pub mod tx_log;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod webhook;
+pub mod windowed;
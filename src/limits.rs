@@ -0,0 +1,203 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::decimal::Decimal;
+
+const DAY: u64 = 86_400;
+
+/// Limits of one client's withdrawals, unset limits don't apply. Amounts are strings in the
+/// TOML file, e.g. `max_withdrawal = "500"`, so no digits are lost.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Largest single withdrawal.
+    pub max_withdrawal: Option<Decimal>,
+    /// Largest sum of withdrawals on one UTC day of the clock.
+    pub max_daily_total: Option<Decimal>,
+    /// Most withdrawals within `window` seconds.
+    pub max_count: Option<u32>,
+    /// Seconds of the `max_count` window, a day if not given.
+    pub window: Option<u64>,
+    /// How far withdrawals may take the available funds below zero, when the engine allows
+    /// overdrafts.
+    pub overdraft: Option<Decimal>,
+}
+
+impl Limits {
+    /// These limits with the unset ones taken from `defaults`.
+    fn or(self, defaults: Limits) -> Limits {
+        Limits {
+            max_withdrawal: self.max_withdrawal.or(defaults.max_withdrawal),
+            max_daily_total: self.max_daily_total.or(defaults.max_daily_total),
+            max_count: self.max_count.or(defaults.max_count),
+            window: self.window.or(defaults.window),
+            overdraft: self.overdraft.or(defaults.overdraft),
+        }
+    }
+
+    fn window(&self) -> u64 {
+        self.window.unwrap_or(DAY)
+    }
+}
+
+/// Withdrawal limits of all clients, with overrides per client id on top of the defaults.
+///
+/// ```toml
+/// [default]
+/// max_withdrawal = "1000"
+/// max_count = 5
+/// window = 3600
+///
+/// [clients.42]
+/// max_withdrawal = "25000"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WithdrawalLimits {
+    pub default: Limits,
+    /// Overrides by client id, limits a client doesn't override are the defaults.
+    pub clients: BTreeMap<u16, Limits>,
+}
+
+impl WithdrawalLimits {
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        // TOML table keys are strings
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct File {
+            #[serde(default)]
+            default: Limits,
+            #[serde(default)]
+            clients: BTreeMap<String, Limits>,
+        }
+
+        let file: File = toml::from_str(toml)?;
+        let mut clients = BTreeMap::new();
+        for (client_id, limits) in file.clients {
+            let client_id = client_id
+                .parse()
+                .with_context(|| format!("invalid client id {:?}", client_id))?;
+            clients.insert(client_id, limits);
+        }
+        Ok(WithdrawalLimits {
+            default: file.default,
+            clients,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read withdrawal limits {}", path.display()))?;
+        Self::from_toml(&toml)
+            .with_context(|| format!("invalid withdrawal limits {}", path.display()))
+    }
+
+    /// The limits of the client, its overrides on top of the defaults.
+    pub fn client(&self, client_id: u16) -> Limits {
+        match self.clients.get(&client_id) {
+            Some(limits) => limits.or(self.default),
+            None => self.default,
+        }
+    }
+}
+
+/// Recent withdrawals of an account, for the daily total and the count limits.
+#[derive(Clone, Debug, Default)]
+pub struct Velocity {
+    day: u64,
+    day_total: Decimal,
+    /// Times of the withdrawals of the last count window, oldest first.
+    recent: VecDeque<u64>,
+}
+
+impl Velocity {
+    /// Whether a withdrawal of `amount` at `now` stays within the limits.
+    pub fn allows(&self, limits: &Limits, amount: Decimal, now: u64) -> bool {
+        if limits.max_withdrawal.is_some_and(|max| amount > max) {
+            return false;
+        }
+        if let Some(max) = limits.max_daily_total {
+            let day_total = if now / DAY == self.day {
+                self.day_total
+            } else {
+                Decimal::ZERO
+            };
+            if day_total
+                .checked_add(amount)
+                .is_none_or(|total| total > max)
+            {
+                return false;
+            }
+        }
+        if let Some(max) = limits.max_count {
+            let window = limits.window();
+            let in_window = self
+                .recent
+                .iter()
+                .filter(|time| now.saturating_sub(**time) < window)
+                .count();
+            if in_window >= max as usize {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Counts an applied withdrawal of `amount` at `now`.
+    pub fn record(&mut self, limits: &Limits, amount: Decimal, now: u64) {
+        if now / DAY != self.day {
+            self.day = now / DAY;
+            self.day_total = Decimal::ZERO;
+        }
+        self.day_total = self.day_total.saturating_add(amount);
+        let window = limits.window();
+        while self
+            .recent
+            .front()
+            .is_some_and(|time| now.saturating_sub(*time) >= window)
+        {
+            self.recent.pop_front();
+        }
+        if limits.max_count.is_some() {
+            self.recent.push_back(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_withdrawal_limits() {
+        let limits = WithdrawalLimits::from_toml(
+            "[default]\nmax_withdrawal = \"100\"\nmax_daily_total = \"150\"\n\
+             [clients.7]\nmax_withdrawal = \"1000\"\nmax_count = 2\nwindow = 60\n",
+        )
+        .unwrap();
+        assert_eq!(limits.client(1).max_count, None);
+        let vip = limits.client(7);
+        assert_eq!(vip.max_withdrawal, Some(Decimal::new(1000, 0)));
+        assert_eq!(vip.max_daily_total, Some(Decimal::new(150, 0)));
+
+        let default = limits.client(1);
+        let mut velocity = Velocity::default();
+        assert!(!velocity.allows(&default, Decimal::new(101, 0), 0));
+        velocity.record(&default, Decimal::new(100, 0), 0);
+        assert!(!velocity.allows(&default, Decimal::new(60, 0), 10));
+        // The daily total starts over on the next day
+        assert!(velocity.allows(&default, Decimal::new(60, 0), DAY));
+
+        let mut velocity = Velocity::default();
+        velocity.record(&vip, Decimal::ONE, 0);
+        velocity.record(&vip, Decimal::ONE, 30);
+        assert!(!velocity.allows(&vip, Decimal::ONE, 59));
+        assert!(velocity.allows(&vip, Decimal::ONE, 60));
+
+        assert!(WithdrawalLimits::from_toml("[clients.x]\nmax_count = 1\n").is_err());
+    }
+}
@@ -1,49 +1,2646 @@
-use std::time::Instant;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{
+    ArgAction, ArgGroup, ArgMatches, Args as ClapArgs, CommandFactory, FromArgMatches, Parser,
+    Subcommand, ValueEnum, parser::ValueSource,
+};
+use csv::StringRecord;
+use simple_payment_engine::decimal::Decimal;
+use tracing::{info, info_span, warn};
+use tracing_subscriber::EnvFilter;
 
-use crate::transaction::Transaction;
+use simple_payment_engine::{
+    amount::{AmountPolicy, AmountUnit, Rounding},
+    archive::{Archive, ArchivePolicy},
+    audit::AuditLog,
+    chaos::{Chaos, FaultInjectingLog, FaultInjectingSource, FaultInjectingStore},
+    checkpoint::CheckpointedInput,
+    client_store::{ClientStore, ClientStoreKind, MemoryStore},
+    clock::ClockSource,
+    cohorts::CohortReport,
+    compression::{self, Compression},
+    config::{
+        ClosePolicy, DisputeAging, DisputeAmountPolicy, DisputePolicy, EngineConfig,
+        LockedAccountPolicy, PreHistoryPolicy,
+    },
+    config_file::ConfigFile,
+    consolidated::{self, Rates, consolidate},
+    currency::Currency,
+    dialect::CsvDialect,
+    diff::{diff_accounts, read_accounts, write_diff},
+    dispute::DisputeState,
+    disputes::DisputeTracker,
+    engine::{Engine, ReportOptions, ReportSort},
+    fees::{FeeSchedule, write_fees_paid},
+    filter::{ClientRange, FilterOutput, RowFilter, SplitBy, filter_records},
+    impact::ImpactSummary,
+    kyc::ClientDirectory,
+    ledger::Ledger,
+    limits::WithdrawalLimits,
+    memory::{ByteSize, MemoryPlan},
+    merge::TimestampMerge,
+    parallel::{Rejection, ShardedEngine, verify_determinism},
+    pipeline::{ErrorPolicy, Pipeline, RejectHandler},
+    progress::{Progress, ProgressMode, log_writer},
+    query::{AccountQuery, disputed_transactions, write_disputed},
+    replica::Replica,
+    report::{
+        ReportColumns, ReportFormat, ReportStyle, read_report, report_writer, seed_from_report,
+        write_report_file,
+    },
+    shadow::Shadow,
+    shutdown,
+    snapshot::{self, BalanceDelta},
+    source::{
+        COLUMNS, Chain, CsvSource, JsonLinesSource, MemorySource, ReadAhead, Records, SourceError,
+        TransactionSource,
+    },
+    statement::{Statement, StatementFormat},
+    summary::{EXIT_CLEAN, RunSummary},
+    tenants::{CollisionPolicy, MergeReport, merge_engines},
+    topk::Exposures,
+    transaction::{TransactionRow, TransactionType},
+    tx_log::{MemoryLog, TransactionLog, TransactionLogKind},
+    verify::{verify, write_discrepancies},
+    webhook::{WebhookConfig, WebhookNotifier},
+    windowed::WindowedMetrics,
+};
 
-mod client;
-mod engine;
-mod transaction;
+#[cfg(feature = "arrow")]
+use simple_payment_engine::arrow_source::ArrowBatches;
+#[cfg(feature = "binary")]
+use simple_payment_engine::binary::{BinaryFormat, BinaryRow, BinarySource, write_row};
+#[cfg(feature = "kafka")]
+use simple_payment_engine::kafka::{ConsumerOptions, consume};
+#[cfg(feature = "parquet")]
+use simple_payment_engine::parquet_source::ParquetRecords;
+#[cfg(feature = "plugins")]
+use simple_payment_engine::plugin::Plugin;
+#[cfg(any(feature = "grpc", feature = "kafka"))]
+use simple_payment_engine::rate_limit::RateLimit;
+#[cfg(feature = "sql")]
+use simple_payment_engine::sql::SqlView;
+#[cfg(feature = "sled")]
+use simple_payment_engine::{client_store::DiskStore, tx_log::DiskLog};
+#[cfg(feature = "grpc")]
+use simple_payment_engine::{grpc, rate_limit::RateLimiter, service::EngineService};
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// Input CSV file containing transactions
-    #[clap(value_parser)]
-    input: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input CSV files containing transactions, processed one after another into the same state.
+    /// Glob patterns such as `'batch-*.csv'` expand to the matching files in name order
+    #[clap(value_parser, required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Merge the input files by their `timestamp` column instead of processing them one after another
+    #[clap(long)]
+    merge_by_timestamp: bool,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input files
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+
+    /// Read the CSV input into Arrow record batches and apply them a batch at a time, without
+    /// deserializing every row on its own (requires the `arrow` feature)
+    #[clap(long, conflicts_with_all = ["merge_by_timestamp", "shards", "input_plugin"])]
+    arrow: bool,
+
+    /// Apply transactions on this many threads, each owning the clients whose id modulo the
+    /// number of shards is its index. Rows of a client are applied in input order
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), conflicts_with_all = [
+        "audit",
+        "archive_dir",
+        "snapshot",
+        "save_snapshot",
+        "db",
+        "disputes_report",
+        "windowed_metrics",
+        "cohort_report",
+        "consolidated_report",
+        "dispute_ttl",
+        "fees",
+        "paranoid",
+    ])]
+    shards: u16,
+
+    /// Also apply the rows on a single engine and fail if its balances or rejections differ from
+    /// those of the `--shards`, printing the differing accounts like `diff` does
+    #[clap(long)]
+    verify_determinism: bool,
+
+    /// Rows read and parsed ahead on a reader thread while the engine applies the previous ones,
+    /// 0 reads them on the engine thread. By default 4096 if more than one CPU is available
+    #[clap(long)]
+    read_ahead: Option<usize>,
+
+    /// Memory budget, e.g. `512M`: the transaction log is kept on disk, caching only as many
+    /// transactions as fit, the read-ahead is capped and the run refuses to start if the estimated
+    /// working set doesn't fit (requires the `sled` feature)
+    #[clap(long, value_name = "SIZE", conflicts_with_all = ["shards", "dry_run"])]
+    max_memory: Option<ByteSize>,
+
+    /// Read the input file from where the run that saved `--snapshot` left off, checking the file
+    /// still starts with the rows it applied. Requires a single uncompressed CSV input file
+    #[clap(long, requires = "snapshot", conflicts_with_all = ["arrow", "input_plugin"])]
+    resume: bool,
+
+    /// Plugin library that provides the transactions CSV, a single INPUT is passed to it as its config
+    /// (requires the `plugins` feature)
+    #[clap(long)]
+    input_plugin: Option<String>,
+
+    /// Plugin library that receives the client report instead of stdout (requires the `plugins` feature)
+    #[clap(long)]
+    report_plugin: Option<String>,
+
+    /// Config string passed to the report plugin
+    #[clap(long, default_value = "", requires = "report_plugin")]
+    report_plugin_config: String,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Process the input and print a summary of what it would change instead of the client
+    /// report, without saving any state
+    #[clap(long, conflicts_with_all = ["shards", "save_snapshot", "db"])]
+    dry_run: bool,
+
+    /// Write the JSON run summary to this file instead of stderr
+    #[clap(long)]
+    summary_file: Option<PathBuf>,
+
+    /// Format of the log lines on stderr
+    #[clap(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Least severe level logged, or a filter like `RUST_LOG` such as
+    /// `warn,simple_payment_engine::progress=info`
+    #[clap(long, global = true, default_value = "info")]
+    log_level: String,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+// Selection and order of the clients in the client report. Not a doc comment, clap would take it
+// for the help text of the program.
+#[derive(Debug, ClapArgs)]
+struct ReportArgs {
+    /// Order of the clients in the report, balances largest first
+    #[clap(long, value_enum, default_value_t = ReportSort::Client)]
+    sort: ReportSort,
 
-    let mut engine = engine::Engine::new();
+    /// Report locked accounts only
+    #[clap(long)]
+    only_locked: bool,
 
-    let mut reader = csv::Reader::from_path(args.input)?;
-    let mut counter = 0u64;
-    let start = Instant::now();
-    for rec in reader.records() {
-        let record = rec?;
-        let transaction = record.deserialize(None);
-        if let Err(err) = transaction {
-            eprintln!("Failed to deserialize transaction: {}", err);
+    /// Report accounts with at least this total only
+    #[clap(long)]
+    min_total: Option<Decimal>,
+
+    /// Report these comma-separated client ids only
+    #[clap(long, value_delimiter = ',')]
+    clients: Vec<u16>,
+}
+
+impl ReportArgs {
+    fn options(&self) -> ReportOptions {
+        ReportOptions {
+            sort: self.sort,
+            only_locked: self.only_locked,
+            min_total: self.min_total,
+            clients: (!self.clients.is_empty()).then(|| self.clients.iter().copied().collect()),
+        }
+    }
+}
+
+// Layout of the CSV input, see `CsvDialect`. Not a doc comment, clap would take it for the
+// help text of the program.
+#[derive(Debug, ClapArgs)]
+struct DialectArgs {
+    /// Field delimiter of the CSV input, a single ASCII character such as `;` or `\t` for tabs
+    #[clap(long, default_value = ",", value_parser = parse_delimiter)]
+    delimiter: u8,
+
+    /// The CSV input has no header row, its columns are in the order type, client, tx, amount,
+    /// timestamp, currency
+    #[clap(long)]
+    no_headers: bool,
+
+    /// Header name of a column as COLUMN=NAME, e.g. `type=kind`. Repeat for more columns
+    #[clap(long = "column", value_name = "COLUMN=NAME", value_parser = parse_column,
+           conflicts_with = "no_headers")]
+    columns: Vec<(String, String)>,
+
+    /// Trim whitespace around the CSV headers and fields, as in `deposit, 1, 1, 1.0`
+    #[clap(long, default_value_t = true, action = ArgAction::Set)]
+    trim: bool,
+}
+
+impl DialectArgs {
+    fn dialect(&self) -> CsvDialect {
+        CsvDialect {
+            delimiter: self.delimiter,
+            has_headers: !self.no_headers,
+            columns: self.columns.iter().cloned().collect(),
+            trim: self.trim,
+        }
+    }
+}
+
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err("expected a single ASCII character".to_string()),
+    }
+}
+
+fn parse_column(value: &str) -> Result<(String, String), String> {
+    let (column, name) = value
+        .split_once('=')
+        .ok_or_else(|| "expected COLUMN=NAME".to_string())?;
+    if !COLUMNS.contains(&column) {
+        return Err(format!(
+            "unknown column {}, expected one of {}",
+            column,
+            COLUMNS.join(", ")
+        ));
+    }
+    Ok((column.to_string(), name.to_string()))
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Apply transactions from a Kafka topic continuously (requires the `kafka` feature)
+    Consume(ConsumeArgs),
+    /// Process a transactions file and run an SQL query over the resulting engine state instead of
+    /// printing the client report (requires the `sql` feature)
+    Sql(SqlArgs),
+    /// Serve the engine over gRPC until interrupted (requires the `grpc` feature). Rejected
+    /// transactions are returned to the caller, options for input rows and side reports don't apply
+    Grpc(GrpcArgs),
+    /// Process a transactions file and print one client's logged transactions as CSV instead of
+    /// the client report
+    History(HistoryArgs),
+    /// Process a transactions file and print one client's account statement with running
+    /// balances instead of the client report
+    Statement(StatementArgs),
+    /// Write the rows of a transactions file that match the given conditions, unchanged, to a new
+    /// CSV file or split over several
+    Filter(FilterArgs),
+    /// Merge the clients of other tenants' snapshots into a snapshot, remapping or rejecting client
+    /// ids that exist on both sides with different histories
+    Merge(MergeArgs),
+    /// Keep a read-only copy of a primary's accounts by following its `--audit` trail and
+    /// periodically write the client report from it
+    Replica(ReplicaArgs),
+    /// Process a transactions file and compare the balances with an expected client report,
+    /// printing the differences as CSV and failing if there are any
+    Verify(VerifyArgs),
+    /// Print the accounts or open disputes of a saved snapshot or database that match the given
+    /// conditions, without reprocessing any input
+    Query(QueryArgs),
+    /// Process a transactions file, or only the state of `--snapshot` or `--db`, post interest
+    /// on the available funds of every account that isn't locked and print the client report
+    Accrue(AccrueArgs),
+    /// Process a transactions file, or only the state of `--snapshot` or `--db`, remove the
+    /// dormant clients with empty accounts and their logged transactions and print the client
+    /// report of the remaining ones
+    Prune(PruneArgs),
+    /// Convert a transactions file to the MessagePack or bincode input format (requires the
+    /// `binary` feature)
+    Convert(ConvertArgs),
+    /// Compare two saved snapshots or client reports and print the accounts whose balances
+    /// changed, with their deltas and whether they got locked, as CSV
+    Diff(DiffArgs),
+}
+
+impl Command {
+    /// The processing options of the subcommands that process transactions.
+    fn processing_mut(&mut self) -> Option<&mut ProcessingArgs> {
+        match self {
+            Command::Consume(args) => Some(&mut args.processing),
+            Command::Sql(args) => Some(&mut args.processing),
+            Command::Grpc(args) => Some(&mut args.processing),
+            Command::History(args) => Some(&mut args.processing),
+            Command::Statement(args) => Some(&mut args.processing),
+            Command::Verify(args) => Some(&mut args.processing),
+            Command::Accrue(args) => Some(&mut args.processing),
+            Command::Prune(args) => Some(&mut args.processing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, ClapArgs)]
+struct ConsumeArgs {
+    /// Kafka bootstrap servers
+    #[clap(long)]
+    brokers: String,
+
+    /// Topic with one or more headerless CSV transaction rows per message
+    #[clap(long)]
+    topic: String,
+
+    /// Consumer group id
+    #[clap(long, default_value = "simple-payment-engine")]
+    group_id: String,
+
+    /// File the client report is periodically written to
+    #[clap(long)]
+    report_path: PathBuf,
+
+    /// Seconds between client report writes
+    #[clap(long, default_value_t = 60)]
+    report_interval: u64,
+
+    #[command(flatten)]
+    rate_limits: RateLimitArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+/// Token bucket limits of the transactions a server accepts
+#[derive(Debug, ClapArgs)]
+struct RateLimitArgs {
+    /// Transactions per second accepted from all clients together, unlimited by default
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit: Option<u32>,
+
+    /// Transactions accepted at once within `--rate-limit`, by default one second's worth
+    #[clap(long, requires = "rate_limit", value_parser = clap::value_parser!(u32).range(1..))]
+    rate_burst: Option<u32>,
+
+    /// Transactions per second accepted from each client, unlimited by default
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    client_rate_limit: Option<u32>,
+
+    /// Transactions accepted at once within `--client-rate-limit`, by default one second's worth
+    #[clap(long, requires = "client_rate_limit", value_parser = clap::value_parser!(u32).range(1..))]
+    client_rate_burst: Option<u32>,
+}
+
+#[cfg(any(feature = "grpc", feature = "kafka"))]
+impl RateLimitArgs {
+    fn global(&self) -> Option<RateLimit> {
+        self.rate_limit.map(|rate| RateLimit {
+            rate,
+            burst: self.rate_burst.unwrap_or(rate),
+        })
+    }
+
+    fn client(&self) -> Option<RateLimit> {
+        self.client_rate_limit.map(|rate| RateLimit {
+            rate,
+            burst: self.client_rate_burst.unwrap_or(rate),
+        })
+    }
+}
+
+#[derive(Debug, ClapArgs)]
+struct SqlArgs {
+    /// Input file containing transactions
+    input: PathBuf,
+
+    /// Query over the `clients`, `transactions`, `disputes` and `client_metadata` tables, the
+    /// result is printed as CSV
+    query: String,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct HistoryArgs {
+    /// Input file containing transactions
+    input: PathBuf,
+
+    /// Client whose transactions are printed
+    #[clap(long)]
+    client: u16,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct StatementArgs {
+    /// Input file containing transactions
+    input: PathBuf,
+
+    /// Client whose statement is printed
+    #[clap(long)]
+    client: u16,
+
+    /// Statement output format
+    #[clap(long, value_enum, default_value_t = StatementFormat::Csv)]
+    statement_format: StatementFormat,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct VerifyArgs {
+    /// Input file containing transactions
+    input: PathBuf,
+
+    /// CSV client report the recomputed balances must match, with or without a currency column
+    #[clap(long)]
+    expected: PathBuf,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct FilterArgs {
+    /// Input file containing transactions
+    input: PathBuf,
+
+    /// Output CSV file, stdout if not given. With `--split-by`, the directory of the parts
+    #[clap(long)]
+    output: Option<PathBuf>,
+
+    /// Keep rows of clients in this range, e.g. `100-199`
+    #[clap(long)]
+    clients: Option<ClientRange>,
+
+    /// Keep rows of these transaction types, e.g. `dispute,resolve,chargeback`
+    #[clap(long = "type", value_delimiter = ',')]
+    types: Vec<TransactionType>,
+
+    /// Keep rows with a `timestamp` at or after this Unix time
+    #[clap(long)]
+    since: Option<u64>,
+
+    /// Keep rows with a `timestamp` before this Unix time
+    #[clap(long)]
+    until: Option<u64>,
+
+    /// Split the kept rows over files in the `--output` directory
+    #[clap(long, value_enum, requires = "output")]
+    split_by: Option<SplitBy>,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct MergeArgs {
+    /// Snapshot the other snapshots are merged into
+    target: PathBuf,
+
+    /// Snapshots whose clients are merged into the target, in order
+    #[clap(required = true)]
+    sources: Vec<PathBuf>,
+
+    /// Where the merged snapshot is saved
+    #[clap(long)]
+    output: PathBuf,
+
+    /// What to do with a client id that has different histories on both sides
+    #[clap(long, value_enum, default_value_t = CollisionPolicy::Reject)]
+    on_collision: CollisionPolicy,
+
+    /// Output CSV file listing the colliding client ids and their ids in the merged snapshot,
+    /// stdout if not given
+    #[clap(long)]
+    remap_report: Option<PathBuf>,
+
+    /// The snapshots keep balances per currency
+    #[clap(long)]
+    multi_currency: bool,
+
+    /// Ledger of the snapshots
+    #[clap(long)]
+    ledger: Option<Ledger>,
+}
+
+#[derive(Debug, ClapArgs)]
+#[clap(group(ArgGroup::new("state").required(true).args(["snapshot", "db"])))]
+struct QueryArgs {
+    /// Snapshot to query, as saved with `--save-snapshot`
+    #[clap(long)]
+    snapshot: Option<PathBuf>,
+
+    /// SQLite database to query, as saved with `--db` (requires the `sql` feature)
+    #[clap(long)]
+    db: Option<PathBuf>,
+
+    /// Only the accounts of this client
+    #[clap(long)]
+    client: Option<u16>,
+
+    /// Only locked accounts
+    #[clap(long)]
+    locked_only: bool,
+
+    /// Only accounts whose total is above this amount
+    #[clap(long)]
+    balance_above: Option<Decimal>,
+
+    /// List the transactions under an open dispute as CSV instead of the accounts
+    #[clap(long, conflicts_with_all = ["locked_only", "balance_above"])]
+    disputed_txs: bool,
+
+    /// Output format of the accounts
+    #[clap(long, value_enum, default_value_t = ReportFormat::Csv)]
+    report_format: ReportFormat,
+
+    /// The snapshot keeps balances per currency
+    #[clap(long)]
+    multi_currency: bool,
+
+    /// Ledger of the snapshot
+    #[clap(long)]
+    ledger: Option<Ledger>,
+}
+
+#[derive(Debug, ClapArgs)]
+struct DiffArgs {
+    /// Earlier state, a snapshot saved with `--save-snapshot` or a CSV client report
+    before: PathBuf,
+
+    /// Later state, a snapshot or a CSV client report
+    after: PathBuf,
+
+    /// Output CSV file, stdout if not given
+    #[clap(long)]
+    output: Option<PathBuf>,
+
+    /// The snapshots keep balances per currency
+    #[clap(long)]
+    multi_currency: bool,
+
+    /// Ledger of the snapshots
+    #[clap(long)]
+    ledger: Option<Ledger>,
+}
+
+#[derive(Debug, ClapArgs)]
+struct AccrueArgs {
+    /// Input file containing the transactions applied before the interest
+    input: Option<PathBuf>,
+
+    /// Interest as a fraction of the available funds, e.g. `0.0025`
+    #[clap(long)]
+    rate: Decimal,
+
+    /// Time of the interest deposits, in seconds of the `--clock`
+    #[clap(long)]
+    as_of: u64,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct PruneArgs {
+    /// Input file containing the transactions applied before pruning
+    input: Option<PathBuf>,
+
+    /// Remove the clients without a transaction since this time, in seconds of the `--clock`
+    #[clap(long)]
+    inactive_since: u64,
+
+    /// Also remove dormant clients with funds left in their accounts
+    #[clap(long)]
+    any_balance: bool,
+
+    /// Write the removed clients to this snapshot file instead of dropping them, e.g. to bring
+    /// them back with `merge`
+    #[clap(long)]
+    pruned: Option<PathBuf>,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the CSV input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct ConvertArgs {
+    /// Input file containing transactions, `-` reads stdin
+    input: PathBuf,
+
+    /// Binary format to write
+    #[clap(long, value_enum)]
+    to: ConvertFormat,
+
+    /// Output file, stdout if not given
+    #[clap(long)]
+    output: Option<PathBuf>,
+
+    /// Input file format
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    format: InputFormat,
+
+    /// Compression of the input file
+    #[clap(long, value_enum, default_value_t = Compression::Auto)]
+    compression: Compression,
+
+    #[command(flatten)]
+    dialect: DialectArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum ConvertFormat {
+    Msgpack,
+    Bincode,
+}
+
+#[derive(Debug, ClapArgs)]
+struct GrpcArgs {
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:50051")]
+    listen: SocketAddr,
+
+    #[command(flatten)]
+    rate_limits: RateLimitArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, ClapArgs)]
+struct ReplicaArgs {
+    /// Audit trail written by the primary with `--audit`
+    audit: PathBuf,
+
+    /// File the client report is periodically written to
+    #[clap(long)]
+    report_path: PathBuf,
+
+    /// Seconds between client report writes, the report is only rewritten when accounts changed
+    #[clap(long, default_value_t = 60)]
+    report_interval: u64,
+
+    /// Milliseconds between checks for new audit rows
+    #[clap(long, default_value_t = 500)]
+    poll_interval: u64,
+
+    /// Client report output format
+    #[clap(long, value_enum, default_value_t = ReportFormat::Csv)]
+    report_format: ReportFormat,
+
+    /// Report one row per client and currency, for primaries running with `--multi-currency`
+    #[clap(long)]
+    multi_currency: bool,
+}
+
+// Options shared by every input mode
+#[derive(Clone, Debug, ClapArgs)]
+struct ProcessingArgs {
+    /// TOML file of engine settings and IO options, overridden by the options given on the
+    /// command line. The grpc server reloads its engine settings on SIGHUP
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Settings read from `--config`
+    #[clap(skip)]
+    config_file: Option<ConfigFile>,
+
+    /// Ids of the options given on the command line, which `--config` doesn't override
+    #[clap(skip)]
+    command_line: Vec<String>,
+
+    /// Client report output format
+    #[clap(long, value_enum, default_value_t = ReportFormat::Csv)]
+    report_format: ReportFormat,
+
+    /// How the client report writes amounts
+    #[clap(long, value_enum, default_value_t = ReportStyle::Raw)]
+    report_style: ReportStyle,
+
+    /// Unit of the input amounts and the reported balances, `minor` for integer cents
+    #[clap(long, value_enum, default_value_t = AmountUnit::Major)]
+    amount_unit: AmountUnit,
+
+    /// What to do with rows that fail to parse or execute
+    #[clap(long, value_enum, default_value_t = ErrorPolicy::Skip)]
+    on_error: ErrorPolicy,
+
+    /// Output CSV file for rejected rows, used with `--on-error collect`
+    #[clap(long, required_if_eq("on_error", "collect"))]
+    rejects: Option<PathBuf>,
+
+    /// Append every transaction with its outcome and the resulting client balances to this CSV file
+    #[clap(long)]
+    audit: Option<PathBuf>,
+
+    /// How progress is reported on stderr
+    #[clap(long, value_enum, default_value_t = ProgressMode::Log)]
+    progress: ProgressMode,
+
+    /// Rows between two lines of `--progress log`
+    #[clap(long, default_value_t = 1_000_000, value_parser = clap::value_parser!(u64).range(1..))]
+    progress_interval: u64,
+
+    /// Number of biggest exposures to print in the stats report, 0 disables tracking
+    #[clap(long, default_value_t = 10)]
+    top_k: usize,
+
+    /// Reject dispute, resolve and chargeback rows whose client doesn't own the referenced transaction
+    #[clap(long)]
+    check_dispute_client: bool,
+
+    /// What a dispute does when the disputed amount is no longer available
+    #[clap(long, value_enum, default_value_t = DisputePolicy::AllowNegative)]
+    dispute_policy: DisputePolicy,
+
+    /// What to do with an amount on a dispute, resolve or chargeback row
+    #[clap(long, value_enum, default_value_t = DisputeAmountPolicy::Ignore)]
+    dispute_amounts: DisputeAmountPolicy,
+
+    /// Which dispute operations locked accounts still accept
+    #[clap(long, value_enum, default_value_t = LockedAccountPolicy::Allow)]
+    locked_disputes: LockedAccountPolicy,
+
+    /// Resolve disputes open for longer than AGE, in seconds on the `--clock` time or in
+    /// transactions with `--dispute-aging sequence`
+    #[clap(long, value_name = "AGE", conflicts_with = "shadow")]
+    dispute_ttl: Option<u64>,
+
+    /// How the age of disputes is measured for `--dispute-ttl`
+    #[clap(long, value_enum, default_value_t = DisputeAging::Clock, requires = "dispute_ttl")]
+    dispute_aging: DisputeAging,
+
+    /// Stop with an error on a failed `assert_balance` row instead of applying `--on-error`
+    #[clap(long)]
+    strict_assertions: bool,
+
+    /// Check the accounts of every row's client against the accounting invariants after the row,
+    /// and all accounts at the end, stopping at the first violation
+    #[clap(long)]
+    paranoid: bool,
+
+    /// Accept the administrative `unlock`, `adjust_credit`, `adjust_debit`, `set_overdraft` and
+    /// `close_account` transactions, and add a `closed` column to the report
+    #[clap(long)]
+    allow_admin: bool,
+
+    /// What a `close_account` row does when the client has held funds
+    #[clap(long, value_enum, default_value_t = ClosePolicy::Reject)]
+    close_policy: ClosePolicy,
+
+    /// Unlock the client when a `chargeback_reversal` re-credits a charged-back deposit
+    #[clap(long)]
+    reversal_unlocks: bool,
+
+    /// Keep balances per currency given in the optional `currency` column and report one row per client and currency
+    #[clap(long)]
+    multi_currency: bool,
+
+    /// Currency of rows without a currency
+    #[clap(long, default_value_t = Currency::default())]
+    default_currency: Currency,
+
+    /// Decimal places of amounts and reported balances, extra places are rounded with
+    /// `--rounding`
+    #[clap(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(0..=28))]
+    precision: u32,
+
+    /// How amounts with more decimal places than `--precision` are rounded
+    #[clap(long, value_enum, default_value_t = Rounding::Bankers)]
+    rounding: Rounding,
+
+    /// Ledger name that keeps this run's archive and snapshots apart from other ledgers sharing
+    /// the same storage
+    #[clap(long)]
+    ledger: Option<Ledger>,
+
+    /// Directory for archive segments of transactions that fell out of the dispute window
+    #[clap(long)]
+    archive_dir: Option<PathBuf>,
+
+    /// Number of most recent transactions kept in memory and open for disputes, used with `--archive-dir`
+    #[clap(long, default_value_t = 1_000_000, requires = "archive_dir")]
+    dispute_window: usize,
+
+    /// Transactions per archive segment file
+    #[clap(long, default_value_t = 100_000, requires = "archive_dir")]
+    archive_segment_size: usize,
+
+    /// Where the log of deposits and withdrawals referenced by disputes is kept
+    #[clap(long, value_enum, default_value_t = TransactionLogKind::Memory)]
+    tx_log: TransactionLogKind,
+
+    /// Scratch directory of the disk transaction log, a new directory in the system temp
+    /// directory by default
+    #[clap(long)]
+    tx_log_dir: Option<PathBuf>,
+
+    /// Number of recent transactions the disk transaction log caches in memory
+    #[clap(long, default_value_t = 1_000_000)]
+    tx_log_cache: usize,
+
+    /// Where the client accounts are kept
+    #[clap(long, value_enum, default_value_t = ClientStoreKind::Memory)]
+    client_store: ClientStoreKind,
+
+    /// Scratch directory of the disk client store, a new directory in the system temp directory
+    /// by default
+    #[clap(long)]
+    client_store_dir: Option<PathBuf>,
+
+    /// Number of recently used accounts the disk client store caches in memory
+    #[clap(long, default_value_t = 100_000)]
+    client_store_cache: usize,
+
+    /// Start from the state saved with `--save-snapshot`, the input then only holds new transactions
+    #[clap(long, conflicts_with = "archive_dir")]
+    snapshot: Option<PathBuf>,
+
+    /// Start from the balances and locks of a CSV client report written by an earlier run, without
+    /// its transaction history
+    #[clap(long, conflicts_with = "snapshot")]
+    warm_start: Option<PathBuf>,
+
+    /// What disputes of unknown transactions of clients from `--warm-start` do
+    #[clap(long, value_enum, default_value_t = PreHistoryPolicy::Reject, requires = "warm_start")]
+    pre_history: PreHistoryPolicy,
+
+    /// Save the final state as a gzip-compressed snapshot for the next incremental run
+    #[clap(long, conflicts_with = "archive_dir")]
+    save_snapshot: Option<PathBuf>,
+
+    /// SQLite database the state is loaded from, if it has any, and saved to after the run
+    /// (requires the `sql` feature)
+    #[clap(long, conflicts_with_all = ["archive_dir", "snapshot", "warm_start"])]
+    db: Option<PathBuf>,
+
+    /// Output file for the report of accounts whose balances changed since `--snapshot`
+    #[clap(long, requires = "snapshot")]
+    delta_report: Option<PathBuf>,
+
+    /// Treat repeated deposits and withdrawals identical to an already applied one as no-ops
+    #[clap(long)]
+    idempotent: bool,
+
+    /// Check every row against a simple reference implementation of the accounting rules and
+    /// compare all balances every N rows, stopping at the first divergence
+    #[clap(long, value_name = "N", conflicts_with_all = ["snapshot", "warm_start", "archive_dir"],
+           value_parser = clap::value_parser!(u64).range(1..))]
+    shadow: Option<u64>,
+
+    /// Output CSV file for dispute resolution SLA metrics, measured from the `timestamp` column
+    #[clap(long)]
+    disputes_report: Option<PathBuf>,
+
+    /// Time source of time-dependent features such as aging open disputes for `--dispute-sla`
+    #[clap(long, value_enum, default_value_t = ClockSource::Transaction)]
+    clock: ClockSource,
+
+    /// Dispute resolution time in seconds above which a dispute counts as an SLA breach
+    #[clap(long)]
+    dispute_sla: Option<u64>,
+
+    /// Output CSV file for per-window aggregates of deposit volume, disputes and rejections
+    #[clap(long)]
+    windowed_metrics: Option<PathBuf>,
+
+    /// Width of the metrics windows in seconds on the `--clock` time
+    #[clap(long, default_value_t = 60)]
+    metrics_window: u64,
+
+    /// Client metadata CSV with a `client` column and the optional `name`, `external_id` and
+    /// `kyc` columns, used for KYC checks, cohort reporting and as the `client_metadata` table of
+    /// the `sql` command
+    #[clap(long, alias = "clients-file")]
+    client_metadata: Option<PathBuf>,
+
+    /// Client metadata column to group the cohort report by, e.g. `country`
+    #[clap(long, requires_all = ["client_metadata", "cohort_report"])]
+    cohort_by: Option<String>,
+
+    /// Output CSV file for per-cohort totals of deposits, withdrawals and chargebacks
+    #[clap(long, requires = "cohort_by")]
+    cohort_report: Option<PathBuf>,
+
+    /// Output CSV file of the balances of every client converted into `--base-currency` and
+    /// summed over its currencies, one row per client
+    #[clap(long, requires_all = ["multi_currency", "base_currency", "rates"])]
+    consolidated_report: Option<PathBuf>,
+
+    /// Currency the consolidated report converts the balances into
+    #[clap(long, requires = "consolidated_report")]
+    base_currency: Option<Currency>,
+
+    /// CSV of `currency` and `rate` columns, the rate being the amount of `--base-currency` one
+    /// unit of the currency is worth
+    #[clap(long, requires = "consolidated_report")]
+    rates: Option<PathBuf>,
+
+    /// TOML fee schedule of deposits and withdrawals, the fees are collected into the account of
+    /// its fee client
+    #[clap(long)]
+    fees: Option<PathBuf>,
+
+    /// Output CSV file for the fees each client paid
+    #[clap(long, requires = "fees")]
+    fee_report: Option<PathBuf>,
+
+    /// TOML file of withdrawal limits with overrides per client, measured on the `--clock` time
+    #[clap(long)]
+    limits: Option<PathBuf>,
+
+    /// Let withdrawals take the available funds below zero up to the client's overdraft limit,
+    /// from `--limits` or a `set_overdraft` row, and add an `overdrawn` column to the report
+    #[clap(long)]
+    overdrafts: bool,
+
+    /// Reject deposits above this amount of clients whose KYC status in `--client-metadata` isn't
+    /// `verified`
+    #[clap(long)]
+    kyc_deposit_limit: Option<Decimal>,
+
+    /// URL the account notifications are posted to as JSON, can be repeated. Only `http://` URLs
+    /// are supported
+    #[clap(long = "webhook", value_name = "URL")]
+    webhooks: Vec<String>,
+
+    /// Total balance whose crossing is notified to the webhooks, can be repeated
+    #[clap(
+        long = "webhook-threshold",
+        value_name = "AMOUNT",
+        requires = "webhooks"
+    )]
+    webhook_thresholds: Vec<Decimal>,
+
+    /// Retries of a failed webhook delivery before it is dropped
+    #[clap(long, default_value_t = 3, requires = "webhooks")]
+    webhook_retries: u32,
+
+    /// Milliseconds before the first retry of a webhook delivery, doubled for each further one
+    #[clap(long, default_value_t = 500, requires = "webhooks")]
+    webhook_backoff: u64,
+
+    /// Developer option: fail client store, transaction log and input operations at random with
+    /// this probability, e.g. `0.001`, to test recovering from saved state
+    #[clap(long, hide = true, value_name = "RATE")]
+    chaos: Option<f64>,
+
+    /// Seed of the `--chaos` faults, the same seed fails the same operations of the same run
+    #[clap(long, hide = true, default_value_t = 0, requires = "chaos")]
+    chaos_seed: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum InputFormat {
+    Csv,
+    /// Requires the `parquet` feature
+    Parquet,
+    /// JSON lines, one object per line with the columns of a CSV row
+    Jsonl,
+    /// MessagePack rows, see `binary::BinaryRow` (requires the `binary` feature)
+    Msgpack,
+    /// bincode rows, see `binary::BinaryRow` (requires the `binary` feature)
+    Bincode,
+}
+
+impl InputFormat {
+    /// Formats read as CSV-shaped records, the others are parsed into rows directly.
+    fn has_records(self) -> bool {
+        matches!(self, InputFormat::Csv | InputFormat::Parquet)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line with the fields of the event and its spans
+    Json,
+}
+
+/// Sends the log to stderr, the report keeps stdout to itself.
+fn init_logging(format: LogFormat, level: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(level).context("Invalid --log-level")?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(log_writer)
+        .with_target(false);
+    match format {
+        LogFormat::Text => builder.with_ansi(io::stderr().is_terminal()).init(),
+        LogFormat::Json => builder.json().with_current_span(false).init(),
+    }
+    Ok(())
+}
+
+/// Opens the input and returns its header with an iterator over raw records. CSV input is
+/// decompressed on the fly and read in `dialect`, `-` reads CSV from stdin.
+fn open_input(
+    path: &Path,
+    format: InputFormat,
+    compression: Compression,
+    dialect: &CsvDialect,
+    progress: &mut Progress,
+) -> Result<(StringRecord, Records)> {
+    match format {
+        InputFormat::Csv if path == Path::new("-") => dialect.records(io::stdin()),
+        InputFormat::Csv => {
+            let file = File::open(path)?;
+            let size = file.metadata()?.len();
+            dialect.records(compression::decode(
+                progress.track(file, size),
+                path,
+                compression,
+            )?)
+        }
+        #[cfg(feature = "parquet")]
+        InputFormat::Parquet => {
+            let records = ParquetRecords::from_path(path)?;
+            Ok((records.headers().clone(), Box::new(records)))
+        }
+        #[cfg(not(feature = "parquet"))]
+        InputFormat::Parquet => {
+            anyhow::bail!("Parquet input requires building with the `parquet` feature")
+        }
+        InputFormat::Jsonl => anyhow::bail!("JSON lines input can't be read as CSV records"),
+        InputFormat::Msgpack | InputFormat::Bincode => {
+            anyhow::bail!("Binary input can't be read as CSV records")
+        }
+    }
+}
+
+/// Opens the input as a source of parsed rows, `-` reads stdin.
+fn open_source(
+    path: &Path,
+    format: InputFormat,
+    compression: Compression,
+    dialect: &CsvDialect,
+    progress: &mut Progress,
+) -> Result<Box<dyn TransactionSource>> {
+    if format.has_records() {
+        let (headers, records) = open_input(path, format, compression, dialect, progress)?;
+        return Ok(Box::new(CsvSource::new(headers, records)));
+    }
+    let reader: Box<dyn BufRead + Send> = if path == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let reader = compression::decode(progress.track(file, size), path, compression)?;
+        Box::new(BufReader::new(reader))
+    };
+    match format {
+        InputFormat::Jsonl => Ok(Box::new(JsonLinesSource::new(reader))),
+        _ => binary_source(format, reader),
+    }
+}
+
+#[cfg(feature = "binary")]
+fn binary_source(
+    format: InputFormat,
+    reader: Box<dyn BufRead + Send>,
+) -> Result<Box<dyn TransactionSource>> {
+    let format = match format {
+        InputFormat::Bincode => BinaryFormat::Bincode,
+        _ => BinaryFormat::Msgpack,
+    };
+    Ok(Box::new(BinarySource::new(format, reader)))
+}
+
+#[cfg(not(feature = "binary"))]
+fn binary_source(
+    _format: InputFormat,
+    _reader: Box<dyn BufRead + Send>,
+) -> Result<Box<dyn TransactionSource>> {
+    anyhow::bail!("MessagePack and bincode input requires building with the `binary` feature")
+}
+
+/// Expands glob patterns among the input arguments, other arguments are taken as paths.
+fn expand_inputs(patterns: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for path in patterns {
+        // Non-UTF-8 paths can't be patterns
+        let Some(pattern) = path.to_str().filter(|path| path.contains(['*', '?', '['])) else {
+            paths.push(path.clone());
             continue;
+        };
+        let matches = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            anyhow::bail!("No input files match {}", pattern);
         }
-        let transaction: Transaction = transaction.unwrap();
-        if let Err(err) = engine.execute(transaction) {
-            eprintln!("Failed to execute transaction: {:?}", err);
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+/// Opens all inputs as one source, concatenated or merged by timestamp. The header of the first
+/// input is the header of the source.
+fn open_inputs(
+    patterns: &[PathBuf],
+    format: InputFormat,
+    compression: Compression,
+    dialect: &CsvDialect,
+    merge_by_timestamp: bool,
+    progress: &mut Progress,
+) -> Result<Box<dyn TransactionSource>> {
+    let paths = expand_inputs(patterns)?;
+    if !format.has_records() {
+        if merge_by_timestamp {
+            anyhow::bail!("--merge-by-timestamp requires CSV or Parquet input");
         }
-        counter += 1;
-        if counter % 1000000 == 0 {
-            eprintln!("Processed {} transactions...", counter);
+        let sources = paths
+            .iter()
+            .map(|path| {
+                open_source(path, format, compression, dialect, progress)
+                    .with_context(|| format!("Failed to open {}", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Box::new(Chain::new(sources)));
+    }
+    let mut inputs = paths
+        .iter()
+        .map(|path| {
+            open_input(path, format, compression, dialect, progress)
+                .with_context(|| format!("Failed to open {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if inputs.len() == 1 {
+        let (headers, records) = inputs.remove(0);
+        return Ok(Box::new(CsvSource::new(headers, records)));
+    }
+    let headers = inputs[0].0.clone();
+    if merge_by_timestamp {
+        let sources = inputs
+            .into_iter()
+            .zip(&paths)
+            .map(|((headers, records), path)| {
+                let timestamp_idx = headers
+                    .iter()
+                    .position(|column| column.trim() == "timestamp")
+                    .with_context(|| {
+                        format!("{} has no timestamp column to merge by", path.display())
+                    })?;
+                Ok((records, timestamp_idx))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let records = Box::new(TimestampMerge::new(sources));
+        return Ok(Box::new(CsvSource::new(headers, records)));
+    }
+    let records = inputs.into_iter().flat_map(|(_, records)| records);
+    Ok(Box::new(CsvSource::new(headers, Box::new(records))))
+}
+
+/// Engine settings of the options, with the engine settings of `file` that weren't given on the
+/// command line.
+fn engine_config(args: &ProcessingArgs, file: Option<&ConfigFile>) -> Result<EngineConfig> {
+    let mut config = EngineConfig {
+        check_dispute_client: args.check_dispute_client,
+        idempotent_duplicates: args.idempotent,
+        locked_account_policy: args.locked_disputes,
+        dispute_policy: args.dispute_policy,
+        dispute_amounts: args.dispute_amounts,
+        pre_history: args.pre_history,
+        dispute_aging: args.dispute_aging,
+        allow_admin: args.allow_admin,
+        reversal_unlocks: args.reversal_unlocks,
+        multi_currency: args.multi_currency,
+        default_currency: args.default_currency,
+        ledger: args.ledger.clone(),
+        amounts: AmountPolicy {
+            precision: args.precision,
+            rounding: args.rounding,
+        },
+        fees: args.fees.as_deref().map(FeeSchedule::load).transpose()?,
+        limits: args
+            .limits
+            .as_deref()
+            .map(WithdrawalLimits::load)
+            .transpose()?,
+        clients: args
+            .client_metadata
+            .as_deref()
+            .map(ClientDirectory::load)
+            .transpose()?,
+        kyc_deposit_limit: args.kyc_deposit_limit,
+        overdrafts: args.overdrafts,
+        close_policy: args.close_policy,
+        ..EngineConfig::default()
+    };
+    if let Some(file) = file {
+        file.configure(&mut config, |option| {
+            args.command_line.iter().any(|id| id == option)
+        });
+    }
+    Ok(config)
+}
+
+/// Reads `--config`, taking its IO options that weren't given on the command line into `args`.
+/// Its engine settings are applied by `engine_config`.
+fn load_config(args: &mut ProcessingArgs, matches: &ArgMatches) -> Result<()> {
+    fn set<T>(option: &mut T, value: Option<T>, given: bool) {
+        if let Some(value) = value
+            && !given
+        {
+            *option = value;
         }
     }
+
+    let Some(path) = &args.config else {
+        return Ok(());
+    };
+    let file = ConfigFile::load(path)?;
+    let command_line: Vec<String> = matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        .map(|id| id.to_string())
+        .collect();
+    let given = |option: &str| command_line.iter().any(|id| id == option);
+    let io = file.io.clone();
+    set(
+        &mut args.report_format,
+        io.report_format,
+        given("report_format"),
+    );
+    set(
+        &mut args.report_style,
+        io.report_style,
+        given("report_style"),
+    );
+    set(&mut args.on_error, io.on_error, given("on_error"));
+    set(&mut args.rejects, io.rejects.map(Some), given("rejects"));
+    set(&mut args.audit, io.audit.map(Some), given("audit"));
+    set(&mut args.progress, io.progress, given("progress"));
+    set(
+        &mut args.progress_interval,
+        io.progress_interval,
+        given("progress_interval"),
+    );
+    if args.on_error == ErrorPolicy::Collect && args.rejects.is_none() {
+        anyhow::bail!("--on-error collect requires --rejects or `rejects` in --config");
+    }
+    args.config_file = Some(file);
+    args.command_line = command_line;
+    Ok(())
+}
+
+/// Builds the engine with its storage, restored from `--snapshot` if given.
+fn build_engine(args: &ProcessingArgs) -> Result<Engine> {
+    let mut engine = Engine::with_config(engine_config(args, args.config_file.as_ref())?);
+    engine.set_clock(args.clock.clock());
+    let mut tx_log: Box<dyn TransactionLog> = match args.tx_log {
+        TransactionLogKind::Memory => Box::new(MemoryLog::new()),
+        TransactionLogKind::Disk => open_disk_log(args)?,
+    };
+    let mut client_store: Box<dyn ClientStore> = match args.client_store {
+        ClientStoreKind::Memory => Box::new(MemoryStore::new()),
+        ClientStoreKind::Disk => open_disk_store(args)?,
+    };
+    if let Some(chaos) = chaos(args, CHAOS_TX_LOG) {
+        tx_log = Box::new(FaultInjectingLog::new(tx_log, chaos));
+    }
+    if let Some(chaos) = chaos(args, CHAOS_CLIENT_STORE) {
+        client_store = Box::new(FaultInjectingStore::new(client_store, chaos));
+    }
+    engine.set_transaction_log(tx_log);
+    engine.set_client_store(client_store);
+    if let Some(path) = &args.snapshot {
+        snapshot::load(&mut engine, path)?;
+    }
+    if let Some(path) = &args.db {
+        load_db(&mut engine, path)?;
+    }
+    if let Some(path) = &args.warm_start {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open report {}", path.display()))?;
+        seed_from_report(&mut engine, BufReader::new(file))?;
+    }
+    if let Some(dir) = &args.archive_dir {
+        let dir = Path::new(dir);
+        engine.set_archive(Archive::new(ArchivePolicy {
+            dir: match &args.ledger {
+                Some(ledger) => ledger.dir(dir),
+                None => dir.into(),
+            },
+            dispute_window: args.dispute_window,
+            segment_size: args.archive_segment_size,
+        })?);
+    }
+    // Attached last, so restoring the saved state notifies nothing
+    if !args.webhooks.is_empty() {
+        engine.add_event_sink(Box::new(WebhookNotifier::new(WebhookConfig {
+            urls: args.webhooks.clone(),
+            thresholds: args.webhook_thresholds.clone(),
+            retries: args.webhook_retries,
+            backoff: Duration::from_millis(args.webhook_backoff),
+        })?));
+    }
+    Ok(engine)
+}
+
+/// Components failed by `--chaos`, each with its own sequence of faults.
+const CHAOS_TX_LOG: u64 = 1;
+const CHAOS_CLIENT_STORE: u64 = 2;
+const CHAOS_INPUT: u64 = 3;
+
+/// The `--chaos` faults of a component.
+fn chaos(args: &ProcessingArgs, component: u64) -> Option<Chaos> {
+    args.chaos
+        .map(|rate| Chaos::new(args.chaos_seed, rate).fork(component))
+}
+
+fn progress(args: &ProcessingArgs) -> Progress {
+    Progress::new(args.progress, args.progress_interval)
+}
+
+/// Builds the engine with its trackers and sinks. `headers` is the input header used for the rejects file.
+fn build_pipeline(args: &ProcessingArgs, headers: &StringRecord) -> Result<Pipeline> {
+    let mut engine = build_engine(args)?;
+    let exposures = (args.top_k > 0).then(|| Exposures::track(&mut engine, args.top_k));
+    let rejects = RejectHandler::new(args.on_error, args.rejects.as_deref(), headers)?;
+    let mut pipeline = Pipeline::new(engine, rejects);
+    pipeline.exposures = exposures;
+    pipeline.strict_assertions = args.strict_assertions;
+    pipeline.paranoid = args.paranoid;
+    pipeline.amount_unit = args.amount_unit;
+    pipeline.dispute_ttl = args.dispute_ttl;
+    pipeline.audit = args.audit.as_ref().map(AuditLog::append).transpose()?;
+    pipeline.windows = args
+        .windowed_metrics
+        .as_ref()
+        .map(|path| WindowedMetrics::new(args.metrics_window, 0).write_to(File::create(path)?))
+        .transpose()?;
+    pipeline.disputes = args.disputes_report.as_ref().map(|_| DisputeTracker::new());
+    // The metadata is loaded once, with the engine config
+    pipeline.cohorts = match (&pipeline.engine.config().clients, &args.cohort_by) {
+        (Some(directory), Some(column)) => Some(CohortReport::from_directory(directory, column)?),
+        _ => None,
+    };
+    pipeline.rates = match (&args.base_currency, &args.rates) {
+        (Some(base), Some(path)) => Some(Rates::load(*base, path)?),
+        _ => None,
+    };
+    pipeline.shadow = args
+        .shadow
+        .map(|interval| Shadow::new(pipeline.engine.config(), interval))
+        .transpose()?;
+    Ok(pipeline)
+}
+
+/// Prints the stats report and writes the side reports.
+fn finish(
+    args: &ProcessingArgs,
+    mut pipeline: Pipeline,
+    start: Instant,
+) -> Result<(Engine, RunSummary)> {
+    if let Some(windows) = pipeline.windows.as_mut() {
+        windows.finish()?;
+    }
+    pipeline.flush()?;
+    if let Some(shadow) = &pipeline.shadow {
+        shadow.compare(&pipeline.engine)?;
+    }
+    if pipeline.paranoid {
+        pipeline.engine.check_invariants()?;
+    }
+    if let Some(impact) = &pipeline.impact {
+        impact.write(&pipeline.engine, io::stdout().lock())?;
+    }
     let duration = start.elapsed();
-    eprintln!("Processed {} transactions in {:?}", counter, duration);
+    pipeline.summary.finish(duration);
+    info!(
+        processed = pipeline.processed(),
+        ?duration,
+        "Processed {} transactions in {:?}",
+        pipeline.processed(),
+        duration
+    );
+    if let Some(exposures) = pipeline.exposures.take() {
+        print_exposures(
+            &exposures
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+    }
+    if let (Some(disputes), Some(path)) = (pipeline.disputes.take(), &args.disputes_report) {
+        let now = pipeline.engine.clock().now();
+        disputes.write_report(File::create(path)?, args.dispute_sla, now)?;
+    }
+    if let (Some(cohorts), Some(path)) = (pipeline.cohorts.take(), &args.cohort_report) {
+        cohorts.write_report(File::create(path)?)?;
+    }
+    if let (Some(rates), Some(path)) = (pipeline.rates.take(), &args.consolidated_report) {
+        let accounts = consolidate(
+            pipeline.engine.clients(),
+            &rates,
+            &pipeline.engine.config().amounts,
+        )?;
+        consolidated::write_report(&accounts, rates.base(), File::create(path)?)?;
+    }
+    Ok((pipeline.engine, pipeline.summary))
+}
+
+fn print_exposures(exposures: &Exposures) {
+    for (currency, exposures) in exposures.by_currency() {
+        print_top_k("total", currency, &exposures.by_total.top());
+        print_top_k("disputed amount", currency, &exposures.by_disputed.top());
+    }
+}
+
+fn print_top_k(label: &str, currency: Currency, top: &[(u16, Decimal)]) {
+    if top.is_empty() {
+        return;
+    }
+    info!("Top {} clients by {} in {}:", top.len(), label, currency);
+    for (rank, (client_id, value)) in top.iter().enumerate() {
+        info!(rank = rank + 1, client = client_id, %value, "client {}: {}", client_id, value);
+    }
+}
+
+#[cfg(feature = "sled")]
+fn open_disk_log(args: &ProcessingArgs) -> Result<Box<dyn TransactionLog>> {
+    let dir = match &args.tx_log_dir {
+        Some(dir) => dir.into(),
+        None => std::env::temp_dir().join(format!(
+            "simple-payment-engine-tx-log-{}",
+            std::process::id()
+        )),
+    };
+    let dir = match &args.ledger {
+        Some(ledger) => ledger.dir(&dir),
+        None => dir,
+    };
+    Ok(Box::new(DiskLog::open(dir, args.tx_log_cache)?))
+}
+
+#[cfg(not(feature = "sled"))]
+fn open_disk_log(_args: &ProcessingArgs) -> Result<Box<dyn TransactionLog>> {
+    anyhow::bail!("The disk transaction log requires building with the `sled` feature")
+}
+
+#[cfg(feature = "sled")]
+fn open_disk_store(args: &ProcessingArgs) -> Result<Box<dyn ClientStore>> {
+    let dir = match &args.client_store_dir {
+        Some(dir) => dir.into(),
+        None => std::env::temp_dir().join(format!(
+            "simple-payment-engine-clients-{}",
+            std::process::id()
+        )),
+    };
+    let dir = match &args.ledger {
+        Some(ledger) => ledger.dir(&dir),
+        None => dir,
+    };
+    Ok(Box::new(DiskStore::open(dir, args.client_store_cache)?))
+}
+
+#[cfg(not(feature = "sled"))]
+fn open_disk_store(_args: &ProcessingArgs) -> Result<Box<dyn ClientStore>> {
+    anyhow::bail!("The disk client store requires building with the `sled` feature")
+}
+
+#[cfg(feature = "sql")]
+fn load_db(engine: &mut Engine, path: &Path) -> Result<()> {
+    simple_payment_engine::db::load(engine, path)
+}
+
+#[cfg(not(feature = "sql"))]
+fn load_db(_engine: &mut Engine, _path: &Path) -> Result<()> {
+    anyhow::bail!("--db requires building with the `sql` feature")
+}
+
+#[cfg(feature = "sql")]
+fn save_db(engine: &Engine, path: &Path) -> Result<()> {
+    simple_payment_engine::db::save(engine, path)
+}
+
+#[cfg(not(feature = "sql"))]
+fn save_db(_engine: &Engine, _path: &Path) -> Result<()> {
+    anyhow::bail!("--db requires building with the `sql` feature")
+}
+
+#[cfg(feature = "plugins")]
+fn open_plugin_input(plugin: &str, config: &str) -> Result<Box<dyn TransactionSource>> {
+    let source = CsvSource::from_reader(Plugin::load(plugin)?.source(config)?)?;
+    Ok(Box::new(source))
+}
+
+#[cfg(not(feature = "plugins"))]
+fn open_plugin_input(_plugin: &str, _config: &str) -> Result<Box<dyn TransactionSource>> {
+    anyhow::bail!("Input plugins require building with the `plugins` feature")
+}
+
+#[cfg(feature = "plugins")]
+fn plugin_report_output(plugin: &str, config: &str) -> Result<Box<dyn io::Write>> {
+    Ok(Box::new(Plugin::load(plugin)?.sink(config)?))
+}
+
+#[cfg(not(feature = "plugins"))]
+fn plugin_report_output(_plugin: &str, _config: &str) -> Result<Box<dyn io::Write>> {
+    anyhow::bail!("Report plugins require building with the `plugins` feature")
+}
+
+/// Applies all input rows and writes the side reports.
+fn process_records(
+    args: &ProcessingArgs,
+    source: Box<dyn TransactionSource>,
+    progress: Progress,
+) -> Result<(Engine, RunSummary)> {
+    let pipeline = build_pipeline(args, &source.headers())?;
+    run_pipeline(args, pipeline, source, progress)
+}
+
+/// Rows per batch, the unit of the `batch` log span and of the `--arrow` record batches.
+const BATCH_ROWS: usize = 8192;
+
+/// Default of `--read-ahead`, rows parsed by the reader thread before it waits for the engine.
+const READ_AHEAD_ROWS: usize = 4096;
+
+/// Rows to read ahead, none on a single CPU where the reader thread would only compete with the
+/// engine.
+fn read_ahead(batch: &Args) -> usize {
+    batch
+        .read_ahead
+        .unwrap_or_else(|| match thread::available_parallelism().map(usize::from) {
+            Ok(cpus) if cpus > 1 => READ_AHEAD_ROWS,
+            _ => 0,
+        })
+}
+
+/// Shrinks the transaction log cache and read-ahead of a batch run to fit `--max-memory`, moving
+/// the transaction log to disk.
+fn fit_memory(batch: &mut Args) -> Result<()> {
+    let Some(limit) = batch.max_memory else {
+        return Ok(());
+    };
+    if !cfg!(feature = "sled") {
+        anyhow::bail!(
+            "--max-memory keeps the transaction log on disk, which requires building with the \
+             `sled` feature"
+        );
+    }
+    let args = &batch.processing;
+    let plan = MemoryPlan {
+        tx_log_cache: args.tx_log_cache,
+        client_store_cache: (args.client_store == ClientStoreKind::Disk)
+            .then_some(args.client_store_cache),
+        read_ahead: read_ahead(batch),
+    }
+    .fit(limit)?;
+    info!(
+        "Fitting {} into {}: caching {} transactions, reading {} rows ahead",
+        ByteSize(plan.estimate()),
+        limit,
+        plan.tx_log_cache,
+        plan.read_ahead
+    );
+    batch.read_ahead = Some(plan.read_ahead);
+    batch.processing.tx_log = TransactionLogKind::Disk;
+    batch.processing.tx_log_cache = plan.tx_log_cache;
+    Ok(())
+}
+
+/// Columns of the engine's client report in the `--report-style`.
+fn report_columns(args: &ProcessingArgs, engine: &Engine) -> ReportColumns {
+    ReportColumns {
+        style: args.report_style,
+        unit: args.amount_unit,
+        ..ReportColumns::of(engine.config())
+    }
+}
+
+/// Applies all input rows through a built pipeline and writes the side reports.
+fn run_pipeline(
+    args: &ProcessingArgs,
+    pipeline: Pipeline,
+    mut source: Box<dyn TransactionSource>,
+    progress: Progress,
+) -> Result<(Engine, RunSummary)> {
+    drive_pipeline(args, pipeline, progress, |pipeline, progress| {
+        loop {
+            let _batch = info_span!("batch", first_row = pipeline.processed() + 1).entered();
+            for _ in 0..BATCH_ROWS {
+                if stop_requested(pipeline.processed())
+                    || !pipeline.process_next(source.as_mut())?
+                {
+                    return Ok(());
+                }
+                progress.update(pipeline.processed());
+            }
+        }
+    })
+}
+
+/// Feeds the input to a built pipeline with `apply` and writes the side reports.
+fn drive_pipeline(
+    args: &ProcessingArgs,
+    mut pipeline: Pipeline,
+    mut progress: Progress,
+    apply: impl FnOnce(&mut Pipeline, &mut Progress) -> Result<()>,
+) -> Result<(Engine, RunSummary)> {
+    let delta = args
+        .delta_report
+        .as_ref()
+        .map(|_| BalanceDelta::new(&pipeline.engine))
+        .transpose()?;
+    let start = Instant::now();
+    apply(&mut pipeline, &mut progress)?;
+    progress.finish(pipeline.processed());
+    let checkpoint = match (&pipeline.input, &args.save_snapshot) {
+        (Some(input), Some(_)) => Some(input.checkpoint(pipeline.consumed())?),
+        _ => None,
+    };
+    let (engine, summary) = finish(args, pipeline, start)?;
+    if let (Some(delta), Some(path)) = (delta, &args.delta_report) {
+        let file = BufWriter::new(File::create(path)?);
+        let mut report = report_writer(args.report_format, file, report_columns(args, &engine));
+        delta.write_report(&engine, report.as_mut())?;
+    }
+    if let Some(path) = &args.fee_report {
+        write_fees_paid(BufWriter::new(File::create(path)?), &engine)?;
+    }
+    if let Some(path) = &args.save_snapshot {
+        snapshot::save_with(&engine, checkpoint.as_ref(), path)?;
+    }
+    if let Some(path) = &args.db {
+        save_db(&engine, path)?;
+    }
+    Ok((engine, summary))
+}
+
+/// Whether a stop signal arrived, checked before every input row.
+fn stop_requested(processed: u64) -> bool {
+    let Some(signal) = shutdown::received() else {
+        return false;
+    };
+    warn!(
+        signal,
+        processed,
+        "Stopping on signal {} after {} transactions, the report covers them only",
+        signal,
+        processed
+    );
+    true
+}
+
+/// Applies all input records on `shards` threads. Rows are routed by client id on this thread,
+/// rejections are printed as with `--on-error skip`. With `verify` the rows are also applied on a
+/// single engine and the run fails if its outcome differs.
+fn process_sharded(
+    args: &ProcessingArgs,
+    shards: u16,
+    verify: bool,
+    mut source: Box<dyn TransactionSource>,
+    mut progress: Progress,
+) -> Result<(Engine, RunSummary)> {
+    if args.on_error != ErrorPolicy::Skip
+        || args.tx_log != TransactionLogKind::Memory
+        || args.client_store != ClientStoreKind::Memory
+    {
+        anyhow::bail!(
+            "--shards requires --on-error skip, the memory transaction log and client store"
+        );
+    }
+    if args.audit.is_some()
+        || args
+            .config_file
+            .as_ref()
+            .is_some_and(|file| file.fees.is_some())
+    {
+        anyhow::bail!("--shards doesn't support the audit trail or fees of --config");
+    }
+    let mut rejects = RejectHandler::new(args.on_error, None, &source.headers())?;
+    let engines = (0..shards)
+        .map(|_| build_engine(args))
+        .collect::<Result<Vec<_>>>()?;
+    let default_currency = engines[0].config().default_currency;
+    // Shards apply rows asynchronously, so the shadow is only compared once they finished
+    let mut shadow = args
+        .shadow
+        .map(|interval| Shadow::new(engines[0].config(), interval))
+        .transpose()?;
+    let mut single = verify.then(|| build_engine(args)).transpose()?;
+    let mut single_rejections = Vec::new();
+    let mut sharded = ShardedEngine::new(engines);
+    let mut summary = RunSummary::new();
+    let mut rejected = Vec::new();
+    let mut print = |summary: &mut RunSummary, rejection: Rejection| {
+        summary.reject(rejection.error.kind());
+        warn!(
+            kind = rejection.error.kind(),
+            client = rejection.transaction.client_id(),
+            tx = rejection.transaction.tx_id(),
+            "Failed to execute transaction: {}",
+            rejection.error
+        );
+        if verify {
+            rejected.push(rejection);
+        }
+    };
+    let start = Instant::now();
+    let mut processed = 0u64;
+    while !stop_requested(processed) {
+        let Some(row) = source.next() else {
+            break;
+        };
+        let row = match row {
+            Ok(row) => row,
+            Err(SourceError::Invalid { record, reason }) => {
+                summary.reject("invalid");
+                rejects.reject(
+                    &record,
+                    "invalid",
+                    format!("Failed to deserialize transaction: {}", reason),
+                )?;
+                continue;
+            }
+            Err(SourceError::Read(err)) => return Err(err),
+        };
+        let row = args.amount_unit.row_to_major(row, default_currency);
+        let seq = processed;
+        processed += 1;
+        summary.record(row.transaction.transaction_type());
+        if let Some(shadow) = shadow.as_mut() {
+            let _ = shadow.execute(&row.transaction, row.currency);
+        }
+        if let Some(single) = single.as_mut() {
+            if let Some(timestamp) = row.timestamp {
+                single.clock().observe(timestamp);
+            }
+            if let Err(error) =
+                single.execute_row(row.transaction.clone(), row.currency, row.dispute_amount)
+            {
+                single_rejections.push(Rejection {
+                    seq,
+                    transaction: row.transaction.clone(),
+                    error,
+                });
+            }
+        }
+        sharded.submit_row(row)?;
+        sharded
+            .rejections()
+            .for_each(|rejection| print(&mut summary, rejection));
+        progress.update(processed);
+    }
+    progress.finish(processed);
+    let (engine, rejections) = sharded.finish()?;
+    rejections
+        .into_iter()
+        .for_each(|rejection| print(&mut summary, rejection));
+    if let Some(shadow) = &shadow {
+        shadow.compare(&engine)?;
+    }
+    if let Some(single) = &single {
+        verify_determinism(
+            single,
+            &single_rejections,
+            &engine,
+            &rejected,
+            io::stderr().lock(),
+        )?;
+    }
+    summary.finish(start.elapsed());
+    info!(
+        processed,
+        duration = ?start.elapsed(),
+        shards,
+        "Processed {} transactions in {:?} on {} shards",
+        processed,
+        start.elapsed(),
+        shards
+    );
+    if args.top_k > 0 {
+        let mut exposures = Exposures::new(args.top_k);
+        for client in engine.clients() {
+            exposures.update(&client?);
+        }
+        print_exposures(&exposures);
+    }
+    Ok((engine, summary))
+}
+
+/// Applies the CSV inputs one after another as Arrow record batches.
+#[cfg(feature = "arrow")]
+fn process_arrow(batch: &Args, mut progress: Progress) -> Result<(Engine, RunSummary)> {
+    if batch.format != InputFormat::Csv {
+        anyhow::bail!("--arrow reads CSV input only");
+    }
+    if batch.dialect.dialect() != CsvDialect::default() {
+        anyhow::bail!("--arrow reads comma-separated CSV with a header in the column order only");
+    }
+    let mut inputs = Vec::new();
+    for path in expand_inputs(&batch.inputs)? {
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        let reader = compression::decode(progress.track(file, size), &path, batch.compression)?;
+        let batches = ArrowBatches::new(reader, BATCH_ROWS)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        inputs.push(batches);
+    }
+    let pipeline = batch_pipeline(batch, inputs[0].headers())?;
+    drive_pipeline(
+        &batch.processing,
+        pipeline,
+        progress,
+        |pipeline, progress| {
+            for batches in inputs {
+                for transactions in batches {
+                    let transactions = transactions?;
+                    let _batch = info_span!(
+                        "batch",
+                        first_row = pipeline.processed() + 1,
+                        rows = transactions.len()
+                    )
+                    .entered();
+                    for idx in 0..transactions.len() {
+                        if stop_requested(pipeline.processed()) {
+                            return Ok(());
+                        }
+                        match transactions.row(idx) {
+                            Ok(row) => pipeline.process_row(row, || transactions.record(idx))?,
+                            Err(err) => pipeline
+                                .reject_invalid(&transactions.record(idx), &err.to_string())?,
+                        }
+                    }
+                    progress.update(pipeline.processed());
+                }
+            }
+            Ok(())
+        },
+    )
+}
+
+#[cfg(not(feature = "arrow"))]
+fn process_arrow(_batch: &Args, _progress: Progress) -> Result<(Engine, RunSummary)> {
+    anyhow::bail!("--arrow requires building with the `arrow` feature")
+}
+
+/// Builds the pipeline of a batch run, which tracks the impact summary of `--dry-run`.
+fn batch_pipeline(batch: &Args, headers: &StringRecord) -> Result<Pipeline> {
+    let mut pipeline = build_pipeline(&batch.processing, headers)?;
+    if batch.dry_run {
+        pipeline.impact = Some(ImpactSummary::new(&pipeline.engine)?);
+    }
+    Ok(pipeline)
+}
+
+/// The input of a batch run whose read position can be checkpointed, a single uncompressed CSV
+/// file.
+fn checkpointed_input(batch: &Args) -> Result<Option<PathBuf>> {
+    if batch.format != InputFormat::Csv || batch.input_plugin.is_some() || batch.arrow {
+        return Ok(None);
+    }
+    match expand_inputs(&batch.inputs)?.as_slice() {
+        [path]
+            if path != Path::new("-")
+                && compression::resolve(path, batch.compression)? == Compression::None =>
+        {
+            Ok(Some(path.clone()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Opens the input file after the rows applied by the run that saved `--snapshot`, as recorded in
+/// the snapshot's input checkpoint, and returns the source with the number of rows skipped.
+fn resume_input(
+    batch: &Args,
+    path: &Path,
+    progress: &mut Progress,
+) -> Result<(Box<dyn TransactionSource>, u64)> {
+    let snapshot = batch
+        .processing
+        .snapshot
+        .as_ref()
+        .context("--resume requires --snapshot")?;
+    let Some(checkpoint) = snapshot::load_checkpoint(snapshot)? else {
+        anyhow::bail!(
+            "Snapshot {} has no input checkpoint to resume from",
+            snapshot.display()
+        );
+    };
+    let dialect = batch.dialect.dialect();
+    let (reader, size) = checkpoint.resume(path, &dialect)?;
+    info!(
+        rows = checkpoint.rows,
+        offset = checkpoint.offset,
+        "Resuming {} after {} rows",
+        path.display(),
+        checkpoint.rows
+    );
+    let (headers, records) = dialect.records(progress.track(reader, size))?;
+    Ok((Box::new(CsvSource::new(headers, records)), checkpoint.rows))
+}
+
+fn run_batch(batch: &Args) -> Result<()> {
+    let args = &batch.processing;
+    if batch.dry_run
+        && (args.tx_log != TransactionLogKind::Memory
+            || args.client_store != ClientStoreKind::Memory)
+    {
+        anyhow::bail!(
+            "--dry-run requires the memory transaction log and client store, the disk ones are \
+             written as rows apply"
+        );
+    }
+    shutdown::install()?;
+    let mut progress = progress(args);
+    let output: Box<dyn io::Write> = match &batch.report_plugin {
+        Some(plugin) => plugin_report_output(plugin, &batch.report_plugin_config)?,
+        None => Box::new(io::stdout().lock()),
+    };
+    let (engine, summary) = if batch.arrow {
+        process_arrow(batch, progress)?
+    } else {
+        let input = match checkpointed_input(batch)? {
+            Some(path) if batch.resume || args.save_snapshot.is_some() => Some(path),
+            _ if batch.resume => {
+                anyhow::bail!("--resume requires a single uncompressed CSV input file")
+            }
+            _ => None,
+        };
+        let mut skipped = 0;
+        let source = match (&batch.input_plugin, &input) {
+            (Some(plugin), _) => match batch.inputs.as_slice() {
+                [config] => open_plugin_input(plugin, &config.to_string_lossy())?,
+                _ => anyhow::bail!("--input-plugin takes a single INPUT config"),
+            },
+            (None, Some(path)) if batch.resume => {
+                let (source, rows) = resume_input(batch, path, &mut progress)?;
+                skipped = rows;
+                source
+            }
+            (None, _) => open_inputs(
+                &batch.inputs,
+                batch.format,
+                batch.compression,
+                &batch.dialect.dialect(),
+                batch.merge_by_timestamp,
+                &mut progress,
+            )?,
+        };
+        let source = match chaos(args, CHAOS_INPUT) {
+            Some(chaos) => Box::new(FaultInjectingSource::new(source, chaos)),
+            None => source,
+        };
+        let source = match read_ahead(batch) {
+            0 => source,
+            rows => Box::new(ReadAhead::spawn(source, rows)?),
+        };
+        if batch.shards > 1 {
+            process_sharded(
+                args,
+                batch.shards,
+                batch.verify_determinism,
+                source,
+                progress,
+            )?
+        } else if batch.verify_determinism {
+            anyhow::bail!("--verify-determinism requires --shards above 1");
+        } else {
+            let mut pipeline = batch_pipeline(batch, &source.headers())?;
+            pipeline.input = input.map(|path| CheckpointedInput {
+                path,
+                dialect: batch.dialect.dialect(),
+                skipped,
+            });
+            run_pipeline(args, pipeline, source, progress)?
+        }
+    };
+
+    if !batch.dry_run {
+        let mut report = report_writer(args.report_format, output, report_columns(args, &engine));
+        engine.write_report_with(report.as_mut(), &batch.report.options())?;
+    }
+    match &batch.summary_file {
+        Some(path) => summary.write_json(File::create(path)?)?,
+        None => summary.write_json(io::stderr().lock())?,
+    }
+    if let Some(signal) = shutdown::received() {
+        // The outputs are complete for the rows read, the status tells the run was cut short
+        std::process::exit(shutdown::exit_code(signal));
+    }
+    if summary.exit_code() != EXIT_CLEAN {
+        std::process::exit(summary.exit_code());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "kafka")]
+fn run_consume(args: &ConsumeArgs) -> Result<()> {
+    let headers = StringRecord::from(vec!["type", "client", "tx", "amount", "timestamp"]);
+    let mut pipeline = build_pipeline(&args.processing, &headers)?;
+    let options = ConsumerOptions {
+        brokers: args.brokers.clone(),
+        topic: args.topic.clone(),
+        group_id: args.group_id.clone(),
+        report_path: args.report_path.clone(),
+        report_format: args.processing.report_format,
+        report_interval: std::time::Duration::from_secs(args.report_interval),
+        rate_limit: args.rate_limits.global(),
+        client_rate_limit: args.rate_limits.client(),
+    };
+    consume(&options, &mut pipeline)
+}
+
+#[cfg(not(feature = "kafka"))]
+fn run_consume(_args: &ConsumeArgs) -> Result<()> {
+    anyhow::bail!("The consume command requires building with the `kafka` feature")
+}
+
+#[cfg(feature = "sql")]
+fn run_sql(args: &SqlArgs) -> Result<()> {
+    let mut progress = progress(&args.processing);
+    let source = open_source(
+        &args.input,
+        args.format,
+        args.compression,
+        &args.dialect.dialect(),
+        &mut progress,
+    )?;
+    let (engine, _) = process_records(&args.processing, source, progress)?;
+    let mut view = SqlView::from_engine(&engine)?;
+    if let Some(path) = &args.processing.client_metadata {
+        view.load_client_metadata(path)?;
+    }
+    view.query(&args.query, io::stdout().lock())
+}
+
+#[cfg(not(feature = "sql"))]
+fn run_sql(_args: &SqlArgs) -> Result<()> {
+    anyhow::bail!("The sql command requires building with the `sql` feature")
+}
 
-    engine.print_client_report();
+/// Number of recent metrics windows the gRPC service returns.
+#[cfg(feature = "grpc")]
+const METRICS_RETENTION: usize = 60;
 
+fn run_history(args: &HistoryArgs) -> Result<()> {
+    let mut progress = progress(&args.processing);
+    let source = open_source(
+        &args.input,
+        args.format,
+        args.compression,
+        &args.dialect.dialect(),
+        &mut progress,
+    )?;
+    let (engine, _) = process_records(&args.processing, source, progress)?;
+    let mut writer = csv::Writer::from_writer(io::stdout().lock());
+    writer.write_record(["type", "client", "tx", "amount", "currency", "state"])?;
+    for entry in engine.client_history(args.client) {
+        let (transaction, currency) = entry?;
+        let tx_id = transaction.tx_id();
+        let state = match engine.dispute_state(tx_id) {
+            DisputeState::None if engine.is_withdrawal_pending(tx_id) => "pending".to_string(),
+            DisputeState::None => String::new(),
+            state => state.to_string(),
+        };
+        writer.write_record([
+            transaction.transaction_type().to_string(),
+            transaction.client_id().to_string(),
+            tx_id.to_string(),
+            transaction
+                .amount()
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+            currency.to_string(),
+            state,
+        ])?;
+    }
+    writer.flush()?;
     Ok(())
 }
+
+fn run_statement(args: &StatementArgs) -> Result<()> {
+    let mut progress = progress(&args.processing);
+    let source = open_source(
+        &args.input,
+        args.format,
+        args.compression,
+        &args.dialect.dialect(),
+        &mut progress,
+    )?;
+    let mut pipeline = build_pipeline(&args.processing, &source.headers())?;
+    let statement = Statement::track(&mut pipeline.engine, args.client)?;
+    let (engine, _) = run_pipeline(&args.processing, pipeline, source, progress)?;
+    let statement = statement
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    statement.write(&engine, args.statement_format, io::stdout().lock())
+}
+
+fn run_verify(args: &VerifyArgs) -> Result<()> {
+    let mut progress = progress(&args.processing);
+    let source = open_source(
+        &args.input,
+        args.format,
+        args.compression,
+        &args.dialect.dialect(),
+        &mut progress,
+    )?;
+    let pipeline = build_pipeline(&args.processing, &source.headers())?;
+    let (engine, _) = run_pipeline(&args.processing, pipeline, source, progress)?;
+    let expected = File::open(&args.expected)
+        .with_context(|| format!("failed to open report {}", args.expected.display()))?;
+    let expected = read_report(BufReader::new(expected), engine.config().default_currency)?;
+    let discrepancies = verify(&engine, &expected)?;
+    if discrepancies.is_empty() {
+        info!("Verified {} accounts", expected.len());
+        return Ok(());
+    }
+    write_discrepancies(io::stdout().lock(), &discrepancies)?;
+    anyhow::bail!(
+        "{} differences between the expected report and the recomputed balances",
+        discrepancies.len()
+    )
+}
+
+fn run_diff(args: &DiffArgs) -> Result<()> {
+    let config = EngineConfig {
+        multi_currency: args.multi_currency,
+        ledger: args.ledger.clone(),
+        ..EngineConfig::default()
+    };
+    let read = |path: &PathBuf| {
+        let reader = compression::open(path, Compression::Auto)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        read_accounts(BufReader::new(reader), &config)
+            .with_context(|| format!("failed to read {}", path.display()))
+    };
+    let diffs = diff_accounts(&read(&args.before)?, &read(&args.after)?);
+    match &args.output {
+        Some(path) => write_diff(BufWriter::new(File::create(path)?), &diffs)?,
+        None => write_diff(io::stdout().lock(), &diffs)?,
+    }
+    info!(
+        "{} accounts changed, {} newly locked",
+        diffs.len(),
+        diffs.iter().filter(|diff| diff.newly_locked()).count()
+    );
+    Ok(())
+}
+
+fn run_filter(args: &FilterArgs) -> Result<()> {
+    let (headers, records) = open_input(
+        &args.input,
+        args.format,
+        args.compression,
+        &args.dialect.dialect(),
+        &mut Progress::new(ProgressMode::None, 1),
+    )?;
+    let filter = RowFilter {
+        clients: args.clients,
+        types: args.types.clone(),
+        since: args.since,
+        until: args.until,
+    };
+    let mut output = match (args.split_by, &args.output) {
+        (Some(by), Some(dir)) => FilterOutput::split(dir.clone(), by, &headers)?,
+        (_, Some(path)) => {
+            FilterOutput::single(Box::new(BufWriter::new(File::create(path)?)), &headers)?
+        }
+        (_, None) => FilterOutput::single(Box::new(io::stdout().lock()), &headers)?,
+    };
+    let stats = filter_records(records, &filter, &mut output)?;
+    info!(
+        "Kept {} of {} rows, dropped {} invalid rows",
+        stats.written, stats.read, stats.invalid
+    );
+    Ok(())
+}
+
+fn run_merge(args: &MergeArgs) -> Result<()> {
+    let load = |path: &Path| -> Result<Engine> {
+        let mut engine = Engine::with_config(EngineConfig {
+            multi_currency: args.multi_currency,
+            ledger: args.ledger.clone(),
+            ..EngineConfig::default()
+        });
+        snapshot::load(&mut engine, path)?;
+        Ok(engine)
+    };
+    let mut target = load(&args.target)?;
+    let mut report = MergeReport::default();
+    for path in &args.sources {
+        let source = load(path)?;
+        let merged = merge_engines(&mut target, &source, args.on_collision)
+            .with_context(|| format!("Failed to merge {}", path.display()))?;
+        report.imported += merged.imported;
+        report.collisions.extend(merged.collisions);
+    }
+    snapshot::save(&target, &args.output)?;
+    info!(
+        "Merged {} clients, {} colliding client ids",
+        report.imported,
+        report.collisions.len()
+    );
+    match &args.remap_report {
+        Some(path) => report.write_csv(BufWriter::new(File::create(path)?)),
+        None => report.write_csv(io::stdout().lock()),
+    }
+}
+
+fn run_query(args: &QueryArgs) -> Result<()> {
+    let mut engine = Engine::with_config(EngineConfig {
+        multi_currency: args.multi_currency,
+        ledger: args.ledger.clone(),
+        ..EngineConfig::default()
+    });
+    match (&args.snapshot, &args.db) {
+        (Some(path), _) => snapshot::load(&mut engine, path)?,
+        (None, Some(path)) => load_db(&mut engine, path)?,
+        (None, None) => unreachable!("clap requires --snapshot or --db"),
+    }
+    if args.disputed_txs {
+        return write_disputed(
+            io::stdout().lock(),
+            &disputed_transactions(&engine, args.client)?,
+        );
+    }
+    let query = AccountQuery {
+        client: args.client,
+        locked_only: args.locked_only,
+        balance_above: args.balance_above,
+    };
+    let mut report = report_writer(
+        args.report_format,
+        io::stdout().lock(),
+        ReportColumns::of(engine.config()),
+    );
+    for client in query.accounts(&engine)? {
+        report.write_client(&client)?;
+    }
+    report.finish()
+}
+
+fn run_accrue(args: &AccrueArgs) -> Result<()> {
+    let mut progress = progress(&args.processing);
+    let mut source: Box<dyn TransactionSource> = match &args.input {
+        Some(path) => open_source(
+            path,
+            args.format,
+            args.compression,
+            &args.dialect.dialect(),
+            &mut progress,
+        )?,
+        None => Box::new(MemorySource::from(Vec::<TransactionRow>::new())),
+    };
+    let pipeline = build_pipeline(&args.processing, &source.headers())?;
+    let (engine, _) = drive_pipeline(
+        &args.processing,
+        pipeline,
+        progress,
+        |pipeline, progress| {
+            while pipeline.process_next(source.as_mut())? {
+                progress.update(pipeline.processed());
+            }
+            let posted = pipeline.accrue_interest(args.rate, args.as_of)?;
+            info!(posted, "Posted interest to {} accounts", posted);
+            Ok(())
+        },
+    )?;
+    let mut report = report_writer(
+        args.processing.report_format,
+        io::stdout().lock(),
+        report_columns(&args.processing, &engine),
+    );
+    engine.write_report(report.as_mut())
+}
+
+fn run_prune(args: &PruneArgs) -> Result<()> {
+    let mut progress = progress(&args.processing);
+    let mut source: Box<dyn TransactionSource> = match &args.input {
+        Some(path) => open_source(
+            path,
+            args.format,
+            args.compression,
+            &args.dialect.dialect(),
+            &mut progress,
+        )?,
+        None => Box::new(MemorySource::from(Vec::<TransactionRow>::new())),
+    };
+    let pipeline = build_pipeline(&args.processing, &source.headers())?;
+    let (engine, _) = drive_pipeline(
+        &args.processing,
+        pipeline,
+        progress,
+        |pipeline, progress| {
+            while pipeline.process_next(source.as_mut())? {
+                progress.update(pipeline.processed());
+            }
+            let pruned = pipeline
+                .engine
+                .prune(args.inactive_since, !args.any_balance)?;
+            info!(
+                pruned = pruned.len(),
+                "Pruned {} dormant clients",
+                pruned.len()
+            );
+            if let Some(path) = &args.pruned {
+                let mut archive = Engine::with_config(pipeline.engine.config().clone());
+                for export in &pruned {
+                    archive.import_client(export)?;
+                }
+                snapshot::save(&archive, path)?;
+            }
+            Ok(())
+        },
+    )?;
+    let mut report = report_writer(
+        args.processing.report_format,
+        io::stdout().lock(),
+        report_columns(&args.processing, &engine),
+    );
+    engine.write_report(report.as_mut())
+}
+
+#[cfg(feature = "binary")]
+fn run_convert(args: &ConvertArgs) -> Result<()> {
+    let mut source = open_source(
+        &args.input,
+        args.format,
+        args.compression,
+        &args.dialect.dialect(),
+        &mut Progress::new(ProgressMode::None, 1),
+    )?;
+    let format = match args.to {
+        ConvertFormat::Msgpack => BinaryFormat::Msgpack,
+        ConvertFormat::Bincode => BinaryFormat::Bincode,
+    };
+    let mut output: Box<dyn io::Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut converted = 0u64;
+    while let Some(row) = source.next() {
+        // A row the binary formats can't represent would be lost silently
+        let row = row.with_context(|| format!("Failed to convert row {}", converted + 1))?;
+        write_row(format, &mut output, &BinaryRow::from(&row))?;
+        converted += 1;
+    }
+    output.flush()?;
+    info!("Converted {} rows", converted);
+    Ok(())
+}
+
+#[cfg(not(feature = "binary"))]
+fn run_convert(_args: &ConvertArgs) -> Result<()> {
+    anyhow::bail!("The convert command requires building with the `binary` feature")
+}
+
+#[cfg(feature = "grpc")]
+fn run_grpc(args: &GrpcArgs) -> Result<()> {
+    let processing = &args.processing;
+    if processing.amount_unit != AmountUnit::Major {
+        anyhow::bail!("The grpc server takes amounts in whole currency units only");
+    }
+    let mut windows = WindowedMetrics::new(processing.metrics_window, METRICS_RETENTION);
+    if let Some(path) = &processing.windowed_metrics {
+        windows = windows.write_to(BufWriter::new(File::create(path)?))?;
+    }
+    let mut builder = EngineService::from_engine(build_engine(processing)?)
+        .top_k(processing.top_k)
+        .windowed_metrics(windows);
+    let rate_limits = &args.rate_limits;
+    if rate_limits.rate_limit.is_some() || rate_limits.client_rate_limit.is_some() {
+        builder = builder.rate_limit(RateLimiter::new(rate_limits.global(), rate_limits.client()));
+    }
+    if let Some(path) = &processing.audit {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let write_header = file.metadata()?.len() == 0;
+        builder = builder.audit(BufWriter::new(file), write_header)?;
+    }
+    let service = builder.build();
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        info!("Serving gRPC on {}", args.listen);
+        let hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        let reload = tokio::spawn(reload_on_hangup(
+            hangups,
+            service.clone(),
+            processing.clone(),
+        ));
+        let shutdown = async {
+            let _ = tokio::signal::ctrl_c().await;
+        };
+        let served = grpc::serve(service.clone(), args.listen, shutdown).await;
+        reload.abort();
+        served?;
+        service.finish().await
+    })?;
+    let metrics = service.metrics();
+    info!(
+        "Applied {} transactions, rejected {}, throttled {}",
+        metrics.applied(),
+        metrics.rejected(),
+        metrics.throttled()
+    );
+    for (currency, top) in metrics.top_by_total() {
+        print_top_k("total", currency, &top);
+    }
+    for (currency, top) in metrics.top_by_disputed() {
+        print_top_k("disputed amount", currency, &top);
+    }
+    if let Some(path) = &processing.save_snapshot {
+        runtime.block_on(service.with_engine(|engine| snapshot::save(engine, path)))?;
+    }
+    if let Some(path) = &processing.db {
+        runtime.block_on(service.with_engine(|engine| save_db(engine, path)))?;
+    }
+    Ok(())
+}
+
+/// Reloads the engine settings of `--config` on every SIGHUP. A file that fails to load is
+/// logged and the engine keeps its settings.
+#[cfg(feature = "grpc")]
+async fn reload_on_hangup(
+    mut hangups: tokio::signal::unix::Signal,
+    service: EngineService,
+    args: ProcessingArgs,
+) {
+    let Some(path) = &args.config else {
+        return;
+    };
+    while hangups.recv().await.is_some() {
+        match ConfigFile::load(path).and_then(|file| engine_config(&args, Some(&file))) {
+            Ok(config) => {
+                service
+                    .with_engine(|engine| engine.reload_config(&config))
+                    .await;
+                info!("Reloaded the engine settings of {}", path.display());
+            }
+            Err(err) => warn!("Keeping the engine settings, failed to reload: {:#}", err),
+        }
+    }
+}
+
+#[cfg(not(feature = "grpc"))]
+fn run_grpc(_args: &GrpcArgs) -> Result<()> {
+    anyhow::bail!("The grpc command requires building with the `grpc` feature")
+}
+
+fn run_replica(args: &ReplicaArgs) -> Result<()> {
+    let mut replica = Replica::follow(&args.audit, args.multi_currency);
+    let report_interval = Duration::from_secs(args.report_interval);
+    let mut last_report: Option<Instant> = None;
+    let mut changed = true;
+    loop {
+        changed |= replica.poll()? > 0;
+        if changed && last_report.is_none_or(|last| last.elapsed() >= report_interval) {
+            write_report_file(replica.engine(), &args.report_path, args.report_format)?;
+            last_report = Some(Instant::now());
+            changed = false;
+        }
+        std::thread::sleep(Duration::from_millis(args.poll_interval));
+    }
+}
+
+/// Whether the error comes from stdout closed early, e.g. by `| head`.
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    let broken = |kind: io::ErrorKind| kind == io::ErrorKind::BrokenPipe;
+    err.chain().any(|cause| {
+        if let Some(err) = cause.downcast_ref::<io::Error>() {
+            return broken(err.kind());
+        }
+        if let Some(err) = cause.downcast_ref::<csv::Error>() {
+            return matches!(err.kind(), csv::ErrorKind::Io(err) if broken(err.kind()));
+        }
+        if let Some(err) = cause.downcast_ref::<serde_json::Error>() {
+            return err.io_error_kind().is_some_and(broken);
+        }
+        false
+    })
+}
+
+fn main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    init_logging(args.log_format, &args.log_level)?;
+    let processing = match &mut args.command {
+        Some(command) => command.processing_mut(),
+        None => Some(&mut args.processing),
+    };
+    if let Some(processing) = processing {
+        let matches = matches
+            .subcommand()
+            .map_or(&matches, |(_, matches)| matches);
+        load_config(processing, matches)?;
+    }
+    if args.command.is_none() {
+        fit_memory(&mut args)?;
+    }
+
+    let result = match &args.command {
+        Some(Command::Consume(consume_args)) => run_consume(consume_args),
+        Some(Command::Sql(sql_args)) => run_sql(sql_args),
+        Some(Command::Grpc(grpc_args)) => run_grpc(grpc_args),
+        Some(Command::History(history_args)) => run_history(history_args),
+        Some(Command::Statement(statement_args)) => run_statement(statement_args),
+        Some(Command::Filter(filter_args)) => run_filter(filter_args),
+        Some(Command::Merge(merge_args)) => run_merge(merge_args),
+        Some(Command::Replica(replica_args)) => run_replica(replica_args),
+        Some(Command::Verify(verify_args)) => run_verify(verify_args),
+        Some(Command::Query(query_args)) => run_query(query_args),
+        Some(Command::Accrue(accrue_args)) => run_accrue(accrue_args),
+        Some(Command::Prune(prune_args)) => run_prune(prune_args),
+        Some(Command::Convert(convert_args)) => run_convert(convert_args),
+        Some(Command::Diff(diff_args)) => run_diff(diff_args),
+        None => run_batch(&args),
+    };
+    match result {
+        // The reader of the report went away, like `head` after enough lines. Not an error.
+        Err(err) if is_broken_pipe(&err) => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,175 @@
+use std::{fmt, mem::size_of, str::FromStr};
+
+use csv::StringRecord;
+
+use crate::{
+    client::Client,
+    currency::Currency,
+    transaction::{Transaction, TransactionRow},
+};
+
+/// Memory of the process outside the estimated structures: code, parsers, buffers and the state
+/// that grows with the input, like open disputes.
+pub const BASE_BYTES: u64 = 32 << 20;
+
+/// Page cache of each sled database of the disk transaction log and client store.
+pub const DISK_PAGE_CACHE: u64 = 16 << 20;
+
+/// Estimated bytes of a cached transaction log entry, with the cache's per entry bookkeeping.
+pub const TX_LOG_ENTRY_BYTES: u64 =
+    (size_of::<u32>() + size_of::<(Transaction, Currency)>() + 64) as u64;
+
+/// Estimated bytes of an account in memory, with the map's per entry bookkeeping.
+pub const ACCOUNT_BYTES: u64 = (size_of::<(u16, Currency)>() + size_of::<Client>() + 32) as u64;
+
+/// Estimated bytes of a row waiting in the read-ahead channel, with the fields of its record.
+pub const ROW_BYTES: u64 = (size_of::<TransactionRow>() + size_of::<StringRecord>() + 64) as u64;
+
+/// Fewest transactions the transaction log caches, below which disputes mostly hit the disk.
+pub const MIN_TX_LOG_CACHE: usize = 10_000;
+
+/// Accounts of a memory client store with every client id in use, in a single currency.
+const MAX_ACCOUNTS: u64 = u16::MAX as u64 + 1;
+
+/// A number of bytes, parsed from a number with an optional `K`, `M` or `G` suffix in powers of
+/// 1024, e.g. `512M` or `2GiB`.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let digits = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        let (number, unit) = value.split_at(digits);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid size {}, expected e.g. 512M", value))?;
+        let shift = match unit.trim_end_matches("iB").trim_end_matches('B') {
+            "" => 0,
+            "K" | "k" => 10,
+            "M" | "m" => 20,
+            "G" | "g" => 30,
+            _ => return Err(format!("unknown size unit {}, expected K, M or G", unit)),
+        };
+        number
+            .checked_mul(1 << shift)
+            .map(ByteSize)
+            .ok_or_else(|| format!("size {} is too large", value))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            bytes if bytes >= 1 << 30 => write!(f, "{:.1}GiB", bytes as f64 / (1u64 << 30) as f64),
+            bytes if bytes >= 1 << 20 => write!(f, "{:.1}MiB", bytes as f64 / (1u64 << 20) as f64),
+            bytes => write!(f, "{}B", bytes),
+        }
+    }
+}
+
+/// Sizes of the structures that hold a configurable number of entries in memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryPlan {
+    /// Transactions the disk transaction log caches.
+    pub tx_log_cache: usize,
+    /// Accounts the disk client store caches, `None` for the memory client store.
+    pub client_store_cache: Option<usize>,
+    /// Rows read ahead of the engine.
+    pub read_ahead: usize,
+}
+
+impl MemoryPlan {
+    /// Estimated bytes of the process with these sizes.
+    pub fn estimate(&self) -> u64 {
+        let accounts = match self.client_store_cache {
+            Some(cache) => cache as u64 * ACCOUNT_BYTES + DISK_PAGE_CACHE,
+            None => MAX_ACCOUNTS * ACCOUNT_BYTES,
+        };
+        BASE_BYTES
+            + DISK_PAGE_CACHE
+            + self.tx_log_cache as u64 * TX_LOG_ENTRY_BYTES
+            + accounts
+            + self.read_ahead as u64 * ROW_BYTES
+    }
+
+    /// The sizes shrunk to fit `limit`: the read-ahead to an eighth of it and the transaction log
+    /// cache to what is left after everything else. Fails if even the smallest transaction log
+    /// cache doesn't fit, as the estimate then says the run would be killed for its memory.
+    pub fn fit(self, limit: ByteSize) -> Result<MemoryPlan, MemoryError> {
+        let read_ahead = self.read_ahead.min((limit.0 / 8 / ROW_BYTES) as usize);
+        let smallest = MemoryPlan {
+            tx_log_cache: self.tx_log_cache.min(MIN_TX_LOG_CACHE),
+            read_ahead,
+            ..self
+        };
+        let estimate = smallest.estimate();
+        if estimate > limit.0 {
+            return Err(MemoryError::Exceeded {
+                estimate: ByteSize(estimate),
+                limit,
+            });
+        }
+        let spare = (limit.0 - estimate) / TX_LOG_ENTRY_BYTES;
+        Ok(MemoryPlan {
+            tx_log_cache: self
+                .tx_log_cache
+                .min(smallest.tx_log_cache.saturating_add(spare as usize)),
+            ..smallest
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MemoryError {
+    Exceeded { estimate: ByteSize, limit: ByteSize },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::Exceeded { estimate, limit } => write!(
+                f,
+                "The estimated working set of {} exceeds the memory limit of {}",
+                estimate, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_plan() {
+        assert_eq!("512M".parse(), Ok(ByteSize(512 << 20)));
+        assert_eq!("2GiB".parse(), Ok(ByteSize(2 << 30)));
+        assert_eq!("4096".parse(), Ok(ByteSize(4096)));
+        assert!("12X".parse::<ByteSize>().is_err());
+
+        let wanted = MemoryPlan {
+            tx_log_cache: 1_000_000,
+            client_store_cache: None,
+            read_ahead: 4096,
+        };
+        // A generous limit keeps the sizes
+        assert_eq!(wanted.fit(ByteSize(8 << 30)), Ok(wanted));
+        // A tight one shrinks the cache to what is left
+        let limit = ByteSize(wanted.estimate() - 1000 * TX_LOG_ENTRY_BYTES);
+        let plan = wanted.fit(limit).unwrap();
+        assert_eq!(plan.tx_log_cache, 1_000_000 - 1000);
+        assert!(plan.estimate() <= limit.0);
+        // A limit below the fixed part refuses to start
+        assert!(matches!(
+            wanted.fit(ByteSize(48 << 20)),
+            Err(MemoryError::Exceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use csv::StringRecord;
+
+struct Source<I> {
+    records: I,
+    /// Position of the `timestamp` column.
+    timestamp_idx: usize,
+    head: Option<Result<StringRecord>>,
+    /// Timestamp of the latest row, used for rows without one.
+    last_timestamp: u64,
+}
+
+impl<I: Iterator<Item = Result<StringRecord>>> Source<I> {
+    fn advance(&mut self) {
+        self.head = self.records.next();
+    }
+
+    /// Sort key of the head row. Rows without a valid timestamp stay right behind their
+    /// predecessor.
+    fn head_timestamp(&self) -> Option<u64> {
+        match &self.head {
+            Some(Ok(record)) => Some(
+                record
+                    .get(self.timestamp_idx)
+                    .and_then(|timestamp| timestamp.trim().parse().ok())
+                    .unwrap_or(self.last_timestamp),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Merges records of several timestamp-ordered inputs into one stream ordered by the
+/// `timestamp` column. Rows with equal timestamps keep the order of the inputs.
+pub struct TimestampMerge<I> {
+    sources: Vec<Source<I>>,
+}
+
+impl<I: Iterator<Item = Result<StringRecord>>> TimestampMerge<I> {
+    /// Takes the inputs with the position of their `timestamp` column.
+    pub fn new(inputs: impl IntoIterator<Item = (I, usize)>) -> Self {
+        let sources = inputs
+            .into_iter()
+            .map(|(records, timestamp_idx)| {
+                let mut source = Source {
+                    records,
+                    timestamp_idx,
+                    head: None,
+                    last_timestamp: 0,
+                };
+                source.advance();
+                source
+            })
+            .collect();
+        TimestampMerge { sources }
+    }
+}
+
+impl<I: Iterator<Item = Result<StringRecord>>> Iterator for TimestampMerge<I> {
+    type Item = Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A read error is passed on as soon as it is seen
+        if let Some(source) = self
+            .sources
+            .iter_mut()
+            .find(|source| matches!(source.head, Some(Err(_))))
+        {
+            let head = source.head.take();
+            source.advance();
+            return head;
+        }
+        let (idx, timestamp) = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, source)| source.head_timestamp().map(|ts| (idx, ts)))
+            .min_by_key(|(idx, timestamp)| (*timestamp, *idx))?;
+        let source = &mut self.sources[idx];
+        source.last_timestamp = timestamp;
+        let head = source.head.take();
+        source.advance();
+        head
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(rows: &[&str]) -> std::vec::IntoIter<Result<StringRecord>> {
+        rows.iter()
+            .map(|row| Ok(StringRecord::from(row.split(',').collect::<Vec<_>>())))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_timestamp_merge() {
+        let first = records(&[
+            "deposit,1,1,1.0,100",
+            "dispute,1,1,,",
+            "deposit,1,3,1.0,300",
+        ]);
+        let second = records(&["200,deposit,2,2,1.0", "300,withdrawal,2,4,1.0"]);
+        let merged: Vec<String> = TimestampMerge::new([(first, 4), (second, 0)])
+            .map(|record| record.unwrap().iter().collect::<Vec<_>>().join(","))
+            .collect();
+        assert_eq!(
+            merged,
+            vec![
+                "deposit,1,1,1.0,100",
+                // No timestamp, stays behind its predecessor
+                "dispute,1,1,,",
+                "200,deposit,2,2,1.0",
+                // Equal timestamps keep the input order
+                "deposit,1,3,1.0,300",
+                "300,withdrawal,2,4,1.0",
+            ]
+        );
+    }
+}
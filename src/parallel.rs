@@ -0,0 +1,552 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Result, anyhow};
+use tracing::{info, warn};
+
+use crate::{
+    currency::Currency,
+    decimal::Decimal,
+    diff::{diff_accounts, write_diff},
+    engine::{Engine, ExecutionError},
+    transaction::{Transaction, TransactionRow},
+};
+
+/// Rows queued per shard before the router blocks, bounding memory when a shard falls behind.
+const SHARD_QUEUE: usize = 4096;
+
+enum Job {
+    Row {
+        seq: u64,
+        transaction: Transaction,
+        currency: Option<Currency>,
+        /// Latest timestamp of the rows submitted up to this one, so the shard's clock reads what
+        /// a single engine's clock would.
+        timestamp: Option<u64>,
+        dispute_amount: Option<Decimal>,
+    },
+    /// Asks whether the shard logged the transaction id, answered once the rows queued before
+    /// are applied.
+    Logged(u32, SyncSender<bool>),
+}
+
+/// A transaction a shard rejected.
+#[derive(Debug, PartialEq)]
+pub struct Rejection {
+    /// Sequence number of the row, its position among the submitted rows counting from 0.
+    pub seq: u64,
+    pub transaction: Transaction,
+    pub error: ExecutionError,
+}
+
+/// Engine split into shards that apply transactions on their own threads.
+///
+/// Ordering contract: every row is routed by the client whose accounts it touches, so all rows
+/// of a client go to the same shard through a FIFO queue and are applied in the order they were
+/// submitted (per-client FIFO). Rows of different clients may be applied in any order relative to
+/// each other, which doesn't change the result because a transaction only touches one client's
+/// accounts.
+///
+/// The router remembers the shard of every row that introduced a transaction id. Rows that
+/// reference a transaction, such as disputes, go to the shard of the referenced transaction, so
+/// disputes of another client's transaction, accepted by `check_dispute_client: false`, are found.
+/// A row reusing an id first seen on another shard waits for that shard to apply the rows before
+/// it and is rejected as a duplicate if the shard logged the id, so ids are unique across shards
+/// like in a single engine.
+///
+/// Determinism: every row gets a sequence number when it is submitted and carries the latest
+/// timestamp submitted so far, so time-dependent rules see the clock of a single engine. Shards
+/// report the outcome of every row and rejections are handed out in sequence order, so balances
+/// and rejections are identical to those of a single engine applying the rows in input order.
+pub struct ShardedEngine {
+    senders: Vec<SyncSender<Job>>,
+    workers: Vec<JoinHandle<Engine>>,
+    outcomes: Receiver<(u64, Option<Rejection>)>,
+    /// Sequence number of the next submitted row.
+    next_seq: u64,
+    /// Sequence number of the next row whose outcome is handed out.
+    released: u64,
+    /// Outcomes that arrived ahead of an earlier row's.
+    reorder: BTreeMap<u64, Option<Rejection>>,
+    latest: Option<u64>,
+    /// Shard of the row each transaction id was last routed with.
+    ids: HashMap<u32, usize>,
+}
+
+impl ShardedEngine {
+    /// Starts one worker thread per engine, each engine becomes a shard.
+    pub fn new(engines: Vec<Engine>) -> Self {
+        assert!(
+            !engines.is_empty(),
+            "a sharded engine needs at least one shard"
+        );
+        let (done, outcomes) = mpsc::channel();
+        let (senders, workers) = engines
+            .into_iter()
+            .map(|mut engine| {
+                let (sender, jobs) = mpsc::sync_channel::<Job>(SHARD_QUEUE);
+                let done = done.clone();
+                let worker = thread::spawn(move || {
+                    for job in jobs {
+                        let (seq, transaction, currency, timestamp, dispute_amount) = match job {
+                            Job::Row {
+                                seq,
+                                transaction,
+                                currency,
+                                timestamp,
+                                dispute_amount,
+                            } => (seq, transaction, currency, timestamp, dispute_amount),
+                            Job::Logged(tx_id, reply) => {
+                                let archived = engine.archived_transaction(tx_id);
+                                let logged = engine.transaction(tx_id).is_some()
+                                    || archived.is_ok_and(|archived| archived.is_some());
+                                let _ = reply.send(logged);
+                                continue;
+                            }
+                        };
+                        if let Some(timestamp) = timestamp {
+                            engine.clock().observe(timestamp);
+                        }
+                        let rejection = engine
+                            .execute_row(transaction.clone(), currency, dispute_amount)
+                            .err()
+                            .map(|error| Rejection {
+                                seq,
+                                transaction,
+                                error,
+                            });
+                        // The router may have stopped listening, the shard carries on
+                        let _ = done.send((seq, rejection));
+                    }
+                    engine
+                });
+                (sender, worker)
+            })
+            .unzip();
+        ShardedEngine {
+            senders,
+            workers,
+            outcomes,
+            next_seq: 0,
+            released: 0,
+            reorder: BTreeMap::new(),
+            latest: None,
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Shard that applies all transactions of the client.
+    pub fn shard_of(&self, client_id: u16) -> usize {
+        client_id as usize % self.senders.len()
+    }
+
+    /// Queues a transaction on its client's shard, or the shard of the transaction it references,
+    /// blocking while the shard's queue is full. `timestamp` advances the clock like the
+    /// `timestamp` column does. Fails only if a shard's worker died.
+    pub fn submit(
+        &mut self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+        timestamp: Option<u64>,
+    ) -> Result<()> {
+        self.submit_row(TransactionRow {
+            transaction,
+            timestamp,
+            currency,
+            dispute_amount: None,
+        })
+    }
+
+    /// Queues an input row like `submit`, with the amount of a dispute row, see
+    /// `Engine::execute_row`.
+    pub fn submit_row(&mut self, row: TransactionRow) -> Result<()> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.latest = self.latest.max(row.timestamp);
+        let transaction = &row.transaction;
+        let tx_id = transaction.tx_id();
+        let own = self.shard_of(transaction.client_id());
+        let shard = match self.ids.get(&tx_id) {
+            Some(&shard) if transaction.references_transaction() => shard,
+            _ if transaction.references_transaction() || transaction.is_assertion() => own,
+            Some(&shard) if shard != own && self.logged(shard, tx_id)? => {
+                // The rows of the shard may already have been applied, so the router reports it
+                let rejection = Rejection {
+                    seq,
+                    transaction: row.transaction,
+                    error: ExecutionError::DuplicateTransaction,
+                };
+                self.reorder.insert(seq, Some(rejection));
+                return Ok(());
+            }
+            _ => {
+                self.ids.insert(tx_id, own);
+                own
+            }
+        };
+        self.senders[shard]
+            .send(Job::Row {
+                seq,
+                transaction: row.transaction,
+                currency: row.currency,
+                timestamp: self.latest,
+                dispute_amount: row.dispute_amount,
+            })
+            .map_err(|_| anyhow!("shard {} stopped", shard))
+    }
+
+    /// Whether the shard logged the transaction id, once it applied the rows queued before.
+    fn logged(&self, shard: usize, tx_id: u32) -> Result<bool> {
+        let (reply, answer) = mpsc::sync_channel(1);
+        self.senders[shard]
+            .send(Job::Logged(tx_id, reply))
+            .map_err(|_| anyhow!("shard {} stopped", shard))?;
+        answer
+            .recv()
+            .map_err(|_| anyhow!("shard {} stopped", shard))
+    }
+
+    /// Rejections of the rows applied since the last call, without waiting, in sequence order.
+    /// A rejection is held back until the outcomes of all rows before it are known.
+    pub fn rejections(&mut self) -> impl Iterator<Item = Rejection> + '_ {
+        for (seq, rejection) in self.outcomes.try_iter() {
+            self.reorder.insert(seq, rejection);
+        }
+        let mut released = Vec::new();
+        while let Some(rejection) = self.reorder.remove(&self.released) {
+            self.released += 1;
+            released.extend(rejection);
+        }
+        released.into_iter()
+    }
+
+    /// Waits for the shards to apply all queued transactions and returns the remaining
+    /// rejections, in sequence order, with an engine holding the accounts of all shards.
+    /// Transaction logs and dispute states stay with the shards and are not part of the merged
+    /// engine.
+    pub fn finish(mut self) -> Result<(Engine, Vec<Rejection>)> {
+        drop(self.senders);
+        let mut shards = Vec::with_capacity(self.workers.len());
+        for worker in self.workers {
+            shards.push(
+                worker
+                    .join()
+                    .map_err(|_| anyhow!("shard worker panicked"))?,
+            );
+        }
+        let mut merged = Engine::with_config(shards[0].config().clone());
+        for shard in &shards {
+            for client in shard.clients() {
+                merged.restore_account(client?)?;
+            }
+        }
+        for (seq, rejection) in self.outcomes.try_iter() {
+            self.reorder.insert(seq, rejection);
+        }
+        Ok((merged, self.reorder.into_values().flatten().collect()))
+    }
+}
+
+/// Compares the outcome of a sharded run with that of a single engine fed the same rows, writing
+/// the accounts whose balances differ to `diff` and failing if any or the rejections differ.
+pub fn verify_determinism(
+    single: &Engine,
+    single_rejections: &[Rejection],
+    sharded: &Engine,
+    sharded_rejections: &[Rejection],
+    diff: impl Write,
+) -> Result<()> {
+    let accounts = |engine: &Engine| engine.clients().collect::<io::Result<Vec<_>>>();
+    let diffs = diff_accounts(&accounts(single)?, &accounts(sharded)?);
+    if !diffs.is_empty() {
+        write_diff(diff, &diffs)?;
+    }
+    let diverged = single_rejections
+        .iter()
+        .zip(sharded_rejections)
+        .position(|(single, sharded)| single != sharded)
+        .or((single_rejections.len() != sharded_rejections.len())
+            .then(|| single_rejections.len().min(sharded_rejections.len())));
+    if let Some(index) = diverged {
+        let describe = |rejection: Option<&Rejection>| match rejection {
+            Some(rejection) => format!(
+                "row {} {:?} rejected ({})",
+                rejection.seq, rejection.transaction, rejection.error
+            ),
+            None => "no rejection".to_string(),
+        };
+        warn!(
+            "Rejection {} differs: {} on one engine, {} on the shards",
+            index + 1,
+            describe(single_rejections.get(index)),
+            describe(sharded_rejections.get(index))
+        );
+    }
+    if !diffs.is_empty() || diverged.is_some() {
+        anyhow::bail!(
+            "The sharded run differs from the single-threaded run in {} accounts{}",
+            diffs.len(),
+            if diverged.is_some() {
+                " and its rejections"
+            } else {
+                ""
+            }
+        );
+    }
+    info!("The sharded run matches the single-threaded run");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::EngineConfig,
+        report::{CsvReportWriter, ReportColumns},
+    };
+
+    /// Deterministic pseudo-random sequence, so failures reproduce.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    /// Per-client scripts of deposits followed by dispute/resolve cycles and a final chargeback
+    /// or withdrawal, interleaved randomly across clients. Any reordering within a client turns
+    /// a dispute into `TransactionNotFound` or a resolve into `NonDisputedTransaction` and
+    /// changes the balances.
+    fn interleaved_transactions(seed: u64, clients: u16) -> Vec<Transaction> {
+        let mut rng = Lcg(seed);
+        let mut tx_id = 0;
+        let mut scripts: Vec<Vec<Transaction>> = (1..=clients)
+            .map(|client| {
+                let mut script = Vec::new();
+                let mut deposits = Vec::new();
+                for _ in 0..1 + rng.next(4) {
+                    tx_id += 1;
+                    deposits.push(tx_id);
+                    let amount = Decimal::new(1 + rng.next(1000) as i64, 2);
+                    script.push(Transaction::Deposit(client, tx_id, amount));
+                }
+                for &deposit in &deposits {
+                    for _ in 0..rng.next(3) {
+                        script.push(Transaction::Dispute(client, deposit));
+                        script.push(Transaction::Resolve(client, deposit));
+                    }
+                }
+                let last = deposits[deposits.len() - 1];
+                if rng.next(2) == 0 {
+                    script.push(Transaction::Dispute(client, last));
+                    script.push(Transaction::Chargeback(client, last));
+                } else {
+                    tx_id += 1;
+                    script.push(Transaction::Withdrawal(client, tx_id, Decimal::new(5, 2)));
+                }
+                script.reverse();
+                script
+            })
+            .collect();
+        let mut transactions = Vec::new();
+        while !scripts.is_empty() {
+            let idx = rng.next(scripts.len() as u64) as usize;
+            match scripts[idx].pop() {
+                Some(transaction) => transactions.push(transaction),
+                None => {
+                    scripts.swap_remove(idx);
+                }
+            }
+        }
+        transactions
+    }
+
+    #[test]
+    fn test_sharded_engine_keeps_per_client_order() {
+        for seed in 0..20 {
+            let transactions = interleaved_transactions(seed, 200);
+            let mut sequential = Engine::new();
+            let mut sequential_rejections = Vec::new();
+            let mut sharded = ShardedEngine::new((0..4).map(|_| Engine::new()).collect());
+            let mut rejections = Vec::new();
+            for (seq, transaction) in transactions.into_iter().enumerate() {
+                if let Err(error) = sequential.execute(transaction.clone()) {
+                    sequential_rejections.push((seq as u64, error));
+                }
+                sharded.submit(transaction, None, None).unwrap();
+                rejections.extend(sharded.rejections());
+            }
+            let (merged, remaining) = sharded.finish().unwrap();
+            // Rejections come in input order, whichever shard finished first
+            let rejections: Vec<_> = rejections
+                .into_iter()
+                .chain(remaining)
+                .map(|rejection| (rejection.seq, rejection.error))
+                .collect();
+            assert_eq!(rejections, sequential_rejections, "seed {}", seed);
+            assert!(
+                merged
+                    .clients()
+                    .map(Result::unwrap)
+                    .eq(sequential.clients().map(Result::unwrap)),
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn test_sharded_engine_reports_rejections() {
+        let mut sharded = ShardedEngine::new(vec![Engine::new(), Engine::new()]);
+        assert_eq!(sharded.shard_of(3), 1);
+        sharded
+            .submit(Transaction::Withdrawal(3, 1, Decimal::ONE), None, None)
+            .unwrap();
+        let (merged, rejections) = sharded.finish().unwrap();
+        assert_eq!(
+            rejections,
+            vec![Rejection {
+                seq: 0,
+                transaction: Transaction::Withdrawal(3, 1, Decimal::ONE),
+                error: ExecutionError::InsufficientFunds,
+            }]
+        );
+        assert_eq!(merged.client(3).unwrap().total, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_sharded_engine_routes_transaction_ids_across_shards() {
+        let engine = || {
+            Engine::with_config(EngineConfig {
+                check_dispute_client: false,
+                ..EngineConfig::default()
+            })
+        };
+        let transactions = [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            // Rejected, so its id stays free for a row of another shard
+            Transaction::Withdrawal(1, 2, Decimal::new(20, 0)),
+            Transaction::Deposit(2, 2, Decimal::new(5, 0)),
+            Transaction::Deposit(2, 1, Decimal::new(3, 0)),
+            // Disputes of another client's deposit reach the deposit's shard
+            Transaction::Dispute(2, 1),
+            Transaction::Dispute(1, 2),
+            Transaction::Chargeback(1, 2),
+        ];
+        let mut sequential = engine();
+        let mut sharded = ShardedEngine::new(vec![engine(), engine()]);
+        let mut sequential_rejections = Vec::new();
+        for transaction in transactions {
+            if let Err(error) = sequential.execute(transaction.clone()) {
+                sequential_rejections.push(error);
+            }
+            sharded.submit(transaction, None, None).unwrap();
+        }
+        let (merged, rejections) = sharded.finish().unwrap();
+        let errors: Vec<_> = rejections.into_iter().map(|r| r.error).collect();
+        assert_eq!(errors, sequential_rejections);
+        assert!(errors.contains(&ExecutionError::DuplicateTransaction));
+        assert!(
+            merged
+                .clients()
+                .map(Result::unwrap)
+                .eq(sequential.clients().map(Result::unwrap))
+        );
+        assert_eq!(merged.client(1).unwrap().held, Decimal::new(10, 0));
+        assert!(merged.client(2).unwrap().locked);
+    }
+
+    /// The CSV client report of the engine.
+    fn report(engine: &Engine) -> String {
+        let mut report = Vec::new();
+        let mut writer = CsvReportWriter::new(&mut report, ReportColumns::default());
+        engine.write_report(&mut writer).unwrap();
+        drop(writer);
+        String::from_utf8(report).unwrap()
+    }
+
+    #[test]
+    fn test_verify_determinism() {
+        let mut transactions = interleaved_transactions(7, 50);
+        // Overdrawing withdrawals of every client, so both runs reject rows
+        transactions.extend((1..=50).map(|client| {
+            Transaction::Withdrawal(client, 10_000 + client as u32, Decimal::new(100, 0))
+        }));
+        let mut sequential = Engine::new();
+        let mut sequential_rejections = Vec::new();
+        let mut sharded = ShardedEngine::new((0..4).map(|_| Engine::new()).collect());
+        let mut sharded_rejections = Vec::new();
+        for (seq, transaction) in transactions.into_iter().enumerate() {
+            if let Err(error) = sequential.execute(transaction.clone()) {
+                sequential_rejections.push(Rejection {
+                    seq: seq as u64,
+                    transaction: transaction.clone(),
+                    error,
+                });
+            }
+            sharded.submit(transaction, None, None).unwrap();
+            sharded_rejections.extend(sharded.rejections());
+        }
+        let (merged, remaining) = sharded.finish().unwrap();
+        sharded_rejections.extend(remaining);
+        assert_eq!(report(&merged), report(&sequential));
+        assert_eq!(sharded_rejections, sequential_rejections);
+        assert!(sequential_rejections.len() >= 50);
+        let mut diff = Vec::new();
+        verify_determinism(
+            &sequential,
+            &sequential_rejections,
+            &merged,
+            &sharded_rejections,
+            &mut diff,
+        )
+        .unwrap();
+        assert!(diff.is_empty());
+
+        // A balance that differs is reported with its account
+        let mut diverged = Engine::new();
+        diverged
+            .execute(Transaction::Deposit(1, 1, Decimal::ONE))
+            .unwrap();
+        let err = verify_determinism(
+            &diverged,
+            &sequential_rejections,
+            &merged,
+            &sharded_rejections,
+            &mut diff,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The sharded run differs from the single-threaded run in 50 accounts"
+        );
+        assert!(!diff.is_empty());
+
+        // As are rejections that differ, even with the same balances
+        let err = verify_determinism(
+            &sequential,
+            &sequential_rejections[1..],
+            &merged,
+            &sharded_rejections,
+            io::sink(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The sharded run differs from the single-threaded run in 0 accounts and its rejections"
+        );
+    }
+}
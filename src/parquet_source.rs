@@ -0,0 +1,182 @@
+use std::{fs::File, path::Path};
+
+use anyhow::{Context, Result};
+use arrow_array::{Array, ArrayRef, RecordBatch, RecordBatchReader, StringArray};
+use arrow_cast::cast;
+use arrow_schema::{DataType, TimeUnit};
+use csv::StringRecord;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+
+/// Reads transactions from a Parquet file as string records in the CSV column order
+/// (`type, client, tx, amount[, timestamp[, currency]]`), so they go through the same
+/// deserialization as CSV rows.
+///
+/// The type column may be named `type` or `ttype`. `amount`, `timestamp` and `currency` columns are optional.
+pub struct ParquetRecords {
+    batches: ParquetRecordBatchReader,
+    headers: StringRecord,
+    columns: Vec<StringArray>,
+    row: usize,
+}
+
+impl ParquetRecords {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        let batches = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+        let schema = batches.schema();
+        let has = |name: &str| schema.column_with_name(name).is_some();
+        let mut headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        // Columns are positional, a currency needs the timestamp column before it
+        if has("timestamp") || has("currency") {
+            headers.push_field("timestamp");
+        }
+        if has("currency") {
+            headers.push_field("currency");
+        }
+        Ok(ParquetRecords {
+            batches,
+            headers,
+            columns: Vec::new(),
+            row: 0,
+        })
+    }
+
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    fn load_batch(&mut self, batch: RecordBatch) -> Result<()> {
+        let column = |names: &[&str]| -> Option<&ArrayRef> {
+            names.iter().find_map(|name| batch.column_by_name(name))
+        };
+        let mut columns = Vec::with_capacity(self.headers.len());
+        for header in self.headers.iter() {
+            let names: &[&str] = if header == "type" {
+                &["type", "ttype"]
+            } else {
+                &[header]
+            };
+            let string_column = match column(names) {
+                Some(array) => to_strings(array)?,
+                None if header == "amount" || header == "timestamp" => {
+                    StringArray::new_null(batch.num_rows())
+                }
+                None => anyhow::bail!("Parquet input has no `{}` column", header),
+            };
+            columns.push(string_column);
+        }
+        self.columns = columns;
+        self.row = 0;
+        Ok(())
+    }
+}
+
+/// Casts a column to strings. Timestamps are converted to Unix seconds first.
+fn to_strings(array: &ArrayRef) -> Result<StringArray> {
+    let array = match array.data_type() {
+        DataType::Timestamp(_, _) => {
+            let seconds = cast(array, &DataType::Timestamp(TimeUnit::Second, None))?;
+            cast(&seconds, &DataType::Int64)?
+        }
+        _ => array.clone(),
+    };
+    let strings = cast(&array, &DataType::Utf8)?;
+    strings
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .cloned()
+        .context("failed to cast Parquet column to strings")
+}
+
+impl Iterator for ParquetRecords {
+    type Item = Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self
+            .columns
+            .first()
+            .is_none_or(|column| self.row >= column.len())
+        {
+            match self.batches.next()? {
+                Ok(batch) => {
+                    if let Err(err) = self.load_batch(batch) {
+                        return Some(Err(err));
+                    }
+                }
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+        let row = self.row;
+        self.row += 1;
+        let record = self
+            .columns
+            .iter()
+            .map(|column| {
+                if column.is_null(row) {
+                    ""
+                } else {
+                    column.value(row)
+                }
+            })
+            .collect();
+        Some(Ok(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::decimal::Decimal;
+    use arrow_array::{Decimal128Array, UInt16Array, UInt32Array};
+    use parquet::arrow::ArrowWriter;
+
+    use super::*;
+    use crate::transaction::{Transaction, TransactionRow};
+
+    #[test]
+    fn test_parquet_records() {
+        let path =
+            std::env::temp_dir().join(format!("transactions-{}.parquet", std::process::id()));
+        let batch = RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec!["deposit", "dispute"])) as ArrayRef,
+            ),
+            (
+                "client",
+                Arc::new(UInt16Array::from(vec![1, 1])) as ArrayRef,
+            ),
+            ("tx", Arc::new(UInt32Array::from(vec![7, 7])) as ArrayRef),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from(vec![Some(15000), None])
+                        .with_precision_and_scale(10, 4)
+                        .unwrap(),
+                ) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let records = ParquetRecords::from_path(&path).unwrap();
+        assert_eq!(records.headers().len(), 4);
+        let rows: Vec<TransactionRow> = records
+            .map(|record| record.unwrap().deserialize(None).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            rows.iter()
+                .map(|row| row.transaction.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                Transaction::Deposit(1, 7, Decimal::new(15000, 4)),
+                Transaction::Dispute(1, 7),
+            ]
+        );
+    }
+}
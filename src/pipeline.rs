@@ -0,0 +1,482 @@
+use std::{
+    fs::File,
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use csv::{StringRecord, Writer, WriterBuilder};
+use tracing::warn;
+
+use crate::decimal::Decimal;
+
+use crate::{
+    amount::AmountUnit,
+    audit::AuditLog,
+    checkpoint::CheckpointedInput,
+    client::Client,
+    cohorts::CohortReport,
+    config::DisputeAmountPolicy,
+    consolidated::Rates,
+    disputes::DisputeTracker,
+    engine::{Engine, ExecutionError},
+    impact::ImpactSummary,
+    invariants::check_step,
+    shadow::Shadow,
+    source::{SourceError, TransactionSource},
+    summary::RunSummary,
+    topk::Exposures,
+    transaction::TransactionRow,
+    windowed::WindowedMetrics,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ErrorPolicy {
+    /// Log the error as a warning and continue
+    Skip,
+    /// Stop processing on the first error with a nonzero exit code
+    Abort,
+    /// Write rejected rows with the reason to the `--rejects` CSV and continue
+    Collect,
+}
+
+/// Applies the configured error policy to rejected rows.
+pub struct RejectHandler {
+    policy: ErrorPolicy,
+    writer: Option<Writer<File>>,
+}
+
+impl RejectHandler {
+    pub fn new(policy: ErrorPolicy, path: Option<&Path>, headers: &StringRecord) -> Result<Self> {
+        let writer = match policy {
+            ErrorPolicy::Collect => {
+                let path = path.context("--rejects is required with --on-error collect")?;
+                // Rows may carry an optional trailing column the header doesn't have
+                let mut writer = WriterBuilder::new().flexible(true).from_path(path)?;
+                let mut header = headers.clone();
+                header.push_field("reason");
+                writer.write_record(&header)?;
+                Some(writer)
+            }
+            _ => None,
+        };
+        Ok(RejectHandler { policy, writer })
+    }
+
+    /// Rejects `record` for `reason`, `kind` names the error for the log.
+    pub fn reject(&mut self, record: &StringRecord, kind: &str, reason: String) -> Result<()> {
+        match self.policy {
+            ErrorPolicy::Skip => warn!(
+                kind,
+                client = record.get(1),
+                tx = record.get(2),
+                "{}",
+                reason
+            ),
+            ErrorPolicy::Abort => bail!("{} (row: {:?})", reason, record),
+            ErrorPolicy::Collect => {
+                if let Some(writer) = self.writer.as_mut() {
+                    let mut row = record.clone();
+                    row.push_field(&reason);
+                    writer.write_record(&row)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Drives raw input records through parsing, execution and the optional trackers and sinks.
+/// Shared by every input mode so they all apply the same rules.
+pub struct Pipeline {
+    pub engine: Engine,
+    pub rejects: RejectHandler,
+    pub audit: Option<AuditLog<File>>,
+    pub disputes: Option<DisputeTracker>,
+    pub cohorts: Option<CohortReport>,
+    /// Conversion rates of the consolidated report, written after the run.
+    pub rates: Option<Rates>,
+    /// What the run changes, for `--dry-run`.
+    pub impact: Option<ImpactSummary>,
+    /// Counts of the processed and rejected rows.
+    pub summary: RunSummary,
+    pub exposures: Option<Arc<Mutex<Exposures>>>,
+    pub windows: Option<WindowedMetrics>,
+    /// Reference engine every row is checked against, failing the run on a divergence.
+    pub shadow: Option<Shadow>,
+    /// Fail the run on a failed balance assertion, whatever the error policy.
+    pub strict_assertions: bool,
+    /// Check the accounts of every row's client against the invariants after the row, failing
+    /// the run on a violation, see `invariants::check_step`.
+    pub paranoid: bool,
+    /// Resolve disputes older than this before every row, see `Engine::expire_disputes`.
+    pub dispute_ttl: Option<u64>,
+    /// Input file whose checkpoint is saved with the snapshot of the run.
+    pub input: Option<CheckpointedInput>,
+    /// Unit of the amounts of the input rows, converted to whole currency units before anything
+    /// else sees them.
+    pub amount_unit: AmountUnit,
+    processed: u64,
+    invalid: u64,
+}
+
+impl Pipeline {
+    pub fn new(engine: Engine, rejects: RejectHandler) -> Self {
+        Pipeline {
+            engine,
+            rejects,
+            audit: None,
+            disputes: None,
+            cohorts: None,
+            rates: None,
+            impact: None,
+            summary: RunSummary::new(),
+            exposures: None,
+            windows: None,
+            shadow: None,
+            strict_assertions: false,
+            paranoid: false,
+            dispute_ttl: None,
+            input: None,
+            amount_unit: AmountUnit::Major,
+            processed: 0,
+            invalid: 0,
+        }
+    }
+
+    /// Number of successfully parsed rows, whether they were applied or rejected by the engine.
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    /// Number of rows taken from the input, whether they parsed or not.
+    pub fn consumed(&self) -> u64 {
+        self.processed + self.invalid
+    }
+
+    /// Parses and executes one input record. Fails only on IO errors or with the abort policy.
+    pub fn process(&mut self, record: &StringRecord) -> Result<()> {
+        match record.deserialize(None) {
+            Ok(row) => self.process_row(row, || record.clone()),
+            Err(err) => self.reject_invalid(record, &err.to_string()),
+        }
+    }
+
+    /// Parses and executes the next row of `source`. Returns `false` at the end of the input.
+    pub fn process_next(&mut self, source: &mut dyn TransactionSource) -> Result<bool> {
+        match source.next() {
+            None => Ok(false),
+            Some(Ok(row)) => {
+                let input = row.clone();
+                self.process_row(row, || source.record(&input))?;
+                Ok(true)
+            }
+            Some(Err(SourceError::Invalid { record, reason })) => {
+                self.reject_invalid(&record, &reason)?;
+                Ok(true)
+            }
+            Some(Err(SourceError::Read(err))) => Err(err),
+        }
+    }
+
+    /// Parses and executes all rows of `source`.
+    pub fn process_source(&mut self, source: &mut dyn TransactionSource) -> Result<()> {
+        while self.process_next(source)? {}
+        Ok(())
+    }
+
+    /// Rejects an input record that didn't parse, recording it in the audit trail.
+    pub fn reject_invalid(&mut self, record: &StringRecord, err: &str) -> Result<()> {
+        self.invalid += 1;
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record_invalid(record, err)?;
+        }
+        if let Some(impact) = self.impact.as_mut() {
+            impact.record_invalid();
+        }
+        self.summary.reject("invalid");
+        self.rejects.reject(
+            record,
+            "invalid",
+            format!("Failed to deserialize transaction: {}", err),
+        )
+    }
+
+    /// Executes an already parsed row. `record` returns its input record, it is only called
+    /// when the row is rejected.
+    pub fn process_row(
+        &mut self,
+        mut row: TransactionRow,
+        record: impl FnOnce() -> StringRecord,
+    ) -> Result<()> {
+        self.processed += 1;
+        self.summary.record(row.transaction.transaction_type());
+        row = self
+            .amount_unit
+            .row_to_major(row, self.engine.config().default_currency);
+        // Rounded here as well so the audit trail and trackers see the amount the engine applies
+        row.transaction = self
+            .engine
+            .config()
+            .amounts
+            .round_transaction(row.transaction);
+        if let Some(timestamp) = row.timestamp {
+            self.engine.clock().observe(timestamp);
+        }
+        self.engine.archive_old_transactions()?;
+        if let Some(ttl) = self.dispute_ttl {
+            self.expire_disputes(ttl, row.timestamp)?;
+        }
+        let dispute_amounts = self.engine.config().dispute_amounts;
+        if let Some(amount) = row.dispute_amount
+            && dispute_amounts == DisputeAmountPolicy::Warn
+        {
+            warn!(
+                client = row.transaction.client_id(),
+                tx = row.transaction.tx_id(),
+                %amount,
+                "Ignored amount of {} row",
+                row.transaction.transaction_type()
+            );
+        }
+        let before = self
+            .paranoid
+            .then(|| self.client_accounts(row.transaction.client_id()))
+            .transpose()?;
+        let result =
+            self.engine
+                .execute_row(row.transaction.clone(), row.currency, row.dispute_amount);
+        if let Some(before) = before {
+            let after = self.client_accounts(row.transaction.client_id())?;
+            let policy = self.engine.config().locked_account_policy;
+            if let Err(err) = check_step(&before, &after, &row.transaction, policy) {
+                bail!("Invariant violated: {} (row: {:?})", err, record())
+            }
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            let dispute_amount = row.dispute_amount.map(|amount| (amount, dispute_amounts));
+            audit.record(
+                &self.engine,
+                &row.transaction,
+                row.currency,
+                dispute_amount,
+                &result,
+            )?;
+        }
+        if let Some(windows) = self.windows.as_mut() {
+            windows.record(self.engine.clock().now(), &row.transaction, &result)?;
+        }
+        if let Some(impact) = self.impact.as_mut() {
+            impact.record(&self.engine, &row.transaction, row.currency, &result);
+        }
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.check(&self.engine, &row.transaction, row.currency, &result)?;
+        }
+        match result {
+            Ok(()) => {
+                if let Some(disputes) = self.disputes.as_mut() {
+                    disputes.record(&row.transaction, row.timestamp);
+                }
+                if let Some(cohorts) = self.cohorts.as_mut() {
+                    cohorts.record(&self.engine, &row.transaction)?;
+                }
+                Ok(())
+            }
+            Err(err @ ExecutionError::BalanceMismatch { .. }) if self.strict_assertions => {
+                bail!("{} (row: {:?})", err, record())
+            }
+            Err(err) => {
+                self.summary.reject(err.kind());
+                self.rejects.reject(
+                    &record(),
+                    err.kind(),
+                    format!("Failed to execute transaction: {}", err),
+                )
+            }
+        }
+    }
+
+    fn client_accounts(&self, client_id: u16) -> Result<Vec<Client>> {
+        Ok(self
+            .engine
+            .client_accounts(client_id)
+            .collect::<io::Result<_>>()?)
+    }
+
+    /// Resolves the stale disputes, recording the resolves in the audit trail and the dispute
+    /// tracker like resolve rows at `timestamp`.
+    fn expire_disputes(&mut self, ttl: u64, timestamp: Option<u64>) -> Result<()> {
+        for resolve in self.engine.expire_disputes(ttl)? {
+            if let Some(audit) = self.audit.as_mut() {
+                audit.record(&self.engine, &resolve, None, None, &Ok(()))?;
+            }
+            if let Some(disputes) = self.disputes.as_mut() {
+                disputes.record(&resolve, timestamp);
+            }
+        }
+        Ok(())
+    }
+
+    /// Posts interest to the accounts, see `Engine::accrue_interest`, recording the deposits in
+    /// the audit trail. Returns the number of posted deposits.
+    pub fn accrue_interest(&mut self, rate: Decimal, as_of: u64) -> Result<usize> {
+        let posted = self.engine.accrue_interest(rate, as_of)?;
+        if let Some(audit) = self.audit.as_mut() {
+            for (deposit, currency) in &posted {
+                audit.record(&self.engine, deposit, Some(*currency), None, &Ok(()))?;
+            }
+        }
+        Ok(posted.len())
+    }
+
+    /// Flushes the rejects, audit and windowed metrics files.
+    pub fn flush(&mut self) -> Result<()> {
+        self.rejects.finish()?;
+        if let Some(audit) = self.audit.as_mut() {
+            audit.flush()?;
+        }
+        if let Some(windows) = self.windows.as_mut() {
+            windows.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+
+    use super::*;
+    use crate::{dialect::CsvDialect, source::CsvSource};
+
+    #[test]
+    fn test_pipeline_counts_parsed_rows() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let rejects = RejectHandler::new(ErrorPolicy::Skip, None, &headers).unwrap();
+        let mut pipeline = Pipeline::new(Engine::new(), rejects);
+        for row in [
+            vec!["deposit", "1", "1", "2.0"],
+            vec!["withdrawal", "1", "2", "5.0"],
+            vec!["unknown", "1", "3", "1.0"],
+        ] {
+            pipeline.process(&StringRecord::from(row)).unwrap();
+        }
+        assert_eq!(pipeline.processed(), 2);
+        let client1 = pipeline.engine.client(1).unwrap();
+        assert_eq!(client1.total, Decimal::new(2, 0));
+    }
+
+    #[test]
+    fn test_pipeline_abort_policy() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let rejects = RejectHandler::new(ErrorPolicy::Abort, None, &headers).unwrap();
+        let mut pipeline = Pipeline::new(Engine::new(), rejects);
+        let withdrawal = StringRecord::from(vec!["withdrawal", "1", "1", "5.0"]);
+        assert!(pipeline.process(&withdrawal).is_err());
+    }
+
+    #[test]
+    fn test_reject_handler_policies() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let withdrawal = StringRecord::from(vec!["withdrawal", "1", "1", "5.0"]);
+        let mut skip = RejectHandler::new(ErrorPolicy::Skip, None, &headers).unwrap();
+        assert!(
+            skip.reject(&withdrawal, "kind", "reason".to_string())
+                .is_ok()
+        );
+        let mut abort = RejectHandler::new(ErrorPolicy::Abort, None, &headers).unwrap();
+        let err = abort
+            .reject(&withdrawal, "kind", "reason".to_string())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"reason (row: StringRecord(["withdrawal", "1", "1", "5.0"]))"#
+        );
+        assert!(RejectHandler::new(ErrorPolicy::Collect, None, &headers).is_err());
+
+        let path = std::env::temp_dir().join(format!("rejects-{}.csv", std::process::id()));
+        let rejects = RejectHandler::new(ErrorPolicy::Collect, Some(&path), &headers).unwrap();
+        let mut pipeline = Pipeline::new(Engine::new(), rejects);
+        for row in [
+            vec!["deposit", "1", "1", "2.0"],
+            vec!["withdrawal", "1", "2", "5.0"],
+            vec!["deposit", "1", "1", "1.0"],
+        ] {
+            pipeline.process(&StringRecord::from(row)).unwrap();
+        }
+        pipeline.rejects.finish().unwrap();
+        // The reason follows the columns of the row
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "type,client,tx,amount,reason\n\
+             withdrawal,1,2,5.0,Failed to execute transaction: Insufficient funds\n\
+             deposit,1,1,1.0,Failed to execute transaction: Duplicate transaction id\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_strict_assertions() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let rejects = RejectHandler::new(ErrorPolicy::Skip, None, &headers).unwrap();
+        let mut pipeline = Pipeline::new(Engine::new(), rejects);
+        pipeline
+            .process(&StringRecord::from(vec!["deposit", "1", "1", "2.0"]))
+            .unwrap();
+        let failing = StringRecord::from(vec!["assert_balance", "1", "2", "3.0"]);
+        // Skipped by the error policy unless assertions are strict
+        assert!(pipeline.process(&failing).is_ok());
+        pipeline.strict_assertions = true;
+        assert!(
+            pipeline
+                .process(&StringRecord::from(vec!["assert_balance", "1", "3", "2.0"]))
+                .is_ok()
+        );
+        assert!(pipeline.process(&failing).is_err());
+    }
+
+    #[test]
+    fn test_malformed_input_is_rejected() {
+        // Read like the CLI reads a file, through the default dialect and the CSV source
+        let input = b"type,client,tx,amount\n\
+                      deposit,1,1,1.0\n\
+                      deposit,1\n\
+                      ,,,\n\
+                      deposit,70000,2,1.0\n\
+                      deposit,-1,3,1.0\n\
+                      deposit,1,4294967296,1.0\n\
+                      deposit,1,5,abc\n\
+                      deposit,1,6,1e400\n\
+                      deposit,1,7,1000000000000000000000000000000000000000\n\
+                      withdrawal,1,8,-5\n\
+                      dispute,1,1,,not-a-timestamp\n\
+                      deposit,1,9,1.0,1,EURO\n\
+                      d\xc3\xa9p\xc3\xb4t,1,10,1.0\n\
+                      dep\xffosit,1,11,1.0\n\
+                      deposit,1\xfe,12,1.0\n\
+                      \"deposit,1,13,1.0\n\
+                      resolve,1,1,1.0,2,USD,extra,columns\n";
+        let (headers, records) = CsvDialect::default()
+            .records(std::io::Cursor::new(input.to_vec()))
+            .unwrap();
+        let mut source = CsvSource::new(headers.clone(), records);
+        let rejects = RejectHandler::new(ErrorPolicy::Skip, None, &headers).unwrap();
+        let mut pipeline = Pipeline::new(Engine::new(), rejects);
+        while pipeline.process_next(&mut source).unwrap() {}
+        // The unterminated quote takes the rest of the input as one field
+        assert_eq!(pipeline.consumed(), 16);
+        assert_eq!(pipeline.summary.rejected(), 15);
+        let client1 = pipeline.engine.client(1).unwrap();
+        assert_eq!(client1.total, Decimal::ONE);
+        assert_eq!(pipeline.engine.clients().count(), 1);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    ffi::{CString, c_char, c_int, c_void},
+    io::{self, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use libloading::Library;
+
+/// Version of the plugin C ABI. Plugins return it from `spe_plugin_abi_version`.
+pub const ABI_VERSION: u32 = 1;
+
+type VersionFn = unsafe extern "C" fn() -> u32;
+/// Opens a stream with a NUL-terminated config string, returns null on failure.
+type OpenFn = unsafe extern "C" fn(config: *const c_char) -> *mut c_void;
+/// Fills up to `len` bytes, returns the number of bytes read, 0 at the end of the input, negative on error.
+type ReadFn = unsafe extern "C" fn(handle: *mut c_void, buf: *mut u8, len: usize) -> isize;
+/// Consumes all `len` bytes, returns 0 on success.
+type WriteFn = unsafe extern "C" fn(handle: *mut c_void, data: *const u8, len: usize) -> c_int;
+/// Returns 0 on success.
+type FlushFn = unsafe extern "C" fn(handle: *mut c_void) -> c_int;
+type CloseFn = unsafe extern "C" fn(handle: *mut c_void);
+
+/// A shared library providing an input connector, a report sink or both.
+///
+/// Plugins exchange plain byte streams in the formats the engine already reads and writes, so the
+/// ABI stays small and stable:
+///
+/// * `spe_plugin_abi_version() -> u32`
+/// * input: `spe_source_open`, `spe_source_read`, `spe_source_close`, producing transactions CSV
+/// * report: `spe_sink_open`, `spe_sink_write`, `spe_sink_flush`, `spe_sink_close`, consuming the
+///   client report in the selected report format
+pub struct Plugin {
+    library: Arc<Library>,
+}
+
+impl Plugin {
+    /// Loads a plugin library and checks its ABI version. Loading runs the library's initializers,
+    /// so only load trusted plugins.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        // SAFETY: the library is trusted code chosen by the operator
+        let library = unsafe { Library::new(path) }
+            .with_context(|| format!("failed to load plugin {}", path.display()))?;
+        let plugin = Plugin {
+            library: Arc::new(library),
+        };
+        let version_fn: VersionFn = plugin.symbol("spe_plugin_abi_version")?;
+        // SAFETY: the symbol follows the plugin ABI
+        let version = unsafe { version_fn() };
+        if version != ABI_VERSION {
+            bail!(
+                "plugin {} has ABI version {}, expected {}",
+                path.display(),
+                version,
+                ABI_VERSION
+            );
+        }
+        Ok(plugin)
+    }
+
+    fn symbol<T: Copy>(&self, name: &str) -> Result<T> {
+        // SAFETY: the caller picks the function type documented for the symbol name
+        let symbol = unsafe { self.library.get::<T>(name.as_bytes()) }
+            .with_context(|| format!("plugin has no `{}` symbol", name))?;
+        Ok(*symbol)
+    }
+
+    /// Opens the plugin's input stream.
+    pub fn source(&self, config: &str) -> Result<PluginSource> {
+        let api = SourceApi {
+            open: self.symbol("spe_source_open")?,
+            read: self.symbol("spe_source_read")?,
+            close: self.symbol("spe_source_close")?,
+        };
+        PluginSource::open(api, config, Some(Arc::clone(&self.library)))
+    }
+
+    /// Opens the plugin's report sink.
+    pub fn sink(&self, config: &str) -> Result<PluginSink> {
+        let api = SinkApi {
+            open: self.symbol("spe_sink_open")?,
+            write: self.symbol("spe_sink_write")?,
+            flush: self.symbol("spe_sink_flush")?,
+            close: self.symbol("spe_sink_close")?,
+        };
+        PluginSink::open(api, config, Some(Arc::clone(&self.library)))
+    }
+}
+
+fn open_handle(open: OpenFn, config: &str) -> Result<*mut c_void> {
+    let config = CString::new(config).context("plugin config must not contain NUL bytes")?;
+    // SAFETY: `open` follows the plugin ABI and the config outlives the call
+    let handle = unsafe { open(config.as_ptr()) };
+    if handle.is_null() {
+        bail!("plugin failed to open with config {:?}", config);
+    }
+    Ok(handle)
+}
+
+#[derive(Clone, Copy)]
+struct SourceApi {
+    open: OpenFn,
+    read: ReadFn,
+    close: CloseFn,
+}
+
+/// Input stream of a plugin.
+pub struct PluginSource {
+    api: SourceApi,
+    handle: *mut c_void,
+    // Keeps the library loaded until the stream is closed
+    _library: Option<Arc<Library>>,
+}
+
+impl PluginSource {
+    fn open(api: SourceApi, config: &str, library: Option<Arc<Library>>) -> Result<Self> {
+        Ok(PluginSource {
+            api,
+            handle: open_handle(api.open, config)?,
+            _library: library,
+        })
+    }
+}
+
+// SAFETY: the ABI lets a stream be read from another thread than the one that opened it, calls
+// on one handle never overlap
+unsafe impl Send for PluginSource {}
+
+impl Read for PluginSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: the handle is open and the buffer is valid for `buf.len()` bytes
+        let read = unsafe { (self.api.read)(self.handle, buf.as_mut_ptr(), buf.len()) };
+        usize::try_from(read)
+            .map_err(|_| io::Error::other(format!("plugin read failed with code {}", read)))
+    }
+}
+
+impl Drop for PluginSource {
+    fn drop(&mut self) {
+        // SAFETY: the handle is open and not used afterwards
+        unsafe { (self.api.close)(self.handle) }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SinkApi {
+    open: OpenFn,
+    write: WriteFn,
+    flush: FlushFn,
+    close: CloseFn,
+}
+
+/// Report sink of a plugin.
+pub struct PluginSink {
+    api: SinkApi,
+    handle: *mut c_void,
+    // Keeps the library loaded until the sink is closed
+    _library: Option<Arc<Library>>,
+}
+
+impl PluginSink {
+    fn open(api: SinkApi, config: &str, library: Option<Arc<Library>>) -> Result<Self> {
+        Ok(PluginSink {
+            api,
+            handle: open_handle(api.open, config)?,
+            _library: library,
+        })
+    }
+}
+
+fn check(code: c_int, operation: &str) -> io::Result<()> {
+    match code {
+        0 => Ok(()),
+        code => Err(io::Error::other(format!(
+            "plugin {} failed with code {}",
+            operation, code
+        ))),
+    }
+}
+
+impl Write for PluginSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // SAFETY: the handle is open and the data is valid for `buf.len()` bytes
+        check(
+            unsafe { (self.api.write)(self.handle, buf.as_ptr(), buf.len()) },
+            "write",
+        )?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // SAFETY: the handle is open
+        check(unsafe { (self.api.flush)(self.handle) }, "flush")
+    }
+}
+
+impl Drop for PluginSink {
+    fn drop(&mut self) {
+        // SAFETY: the handle is open and not used afterwards
+        unsafe { (self.api.close)(self.handle) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        io::{BufRead, BufReader},
+    };
+
+    use super::*;
+
+    /// In-process stand-ins for plugin functions, the handle is a boxed cursor or byte buffer.
+    unsafe extern "C" fn source_open(config: *const c_char) -> *mut c_void {
+        let config = unsafe { CStr::from_ptr(config) }.to_bytes().to_vec();
+        Box::into_raw(Box::new(io::Cursor::new(config))) as *mut c_void
+    }
+
+    unsafe extern "C" fn source_read(handle: *mut c_void, buf: *mut u8, len: usize) -> isize {
+        let cursor = unsafe { &mut *(handle as *mut io::Cursor<Vec<u8>>) };
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        cursor.read(buf).map_or(-1, |read| read as isize)
+    }
+
+    unsafe extern "C" fn source_close(handle: *mut c_void) {
+        drop(unsafe { Box::from_raw(handle as *mut io::Cursor<Vec<u8>>) });
+    }
+
+    static SINK_OUTPUT: std::sync::Mutex<Vec<u8>> = std::sync::Mutex::new(Vec::new());
+
+    unsafe extern "C" fn sink_open(_config: *const c_char) -> *mut c_void {
+        Box::into_raw(Box::new(Vec::<u8>::new())) as *mut c_void
+    }
+
+    unsafe extern "C" fn sink_write(handle: *mut c_void, data: *const u8, len: usize) -> c_int {
+        let buffer = unsafe { &mut *(handle as *mut Vec<u8>) };
+        buffer.extend_from_slice(unsafe { std::slice::from_raw_parts(data, len) });
+        0
+    }
+
+    unsafe extern "C" fn sink_flush(handle: *mut c_void) -> c_int {
+        let buffer = unsafe { &mut *(handle as *mut Vec<u8>) };
+        SINK_OUTPUT.lock().unwrap().append(buffer);
+        0
+    }
+
+    unsafe extern "C" fn sink_close(handle: *mut c_void) {
+        drop(unsafe { Box::from_raw(handle as *mut Vec<u8>) });
+    }
+
+    #[test]
+    fn test_plugin_source() {
+        let api = SourceApi {
+            open: source_open,
+            read: source_read,
+            close: source_close,
+        };
+        let source =
+            PluginSource::open(api, "type,client,tx,amount\ndeposit,1,1,1.0\n", None).unwrap();
+        let lines: Vec<String> = BufReader::new(source)
+            .lines()
+            .map(|line| line.unwrap())
+            .collect();
+        assert_eq!(lines, vec!["type,client,tx,amount", "deposit,1,1,1.0"]);
+    }
+
+    #[test]
+    fn test_plugin_sink() {
+        let api = SinkApi {
+            open: sink_open,
+            write: sink_write,
+            flush: sink_flush,
+            close: sink_close,
+        };
+        let mut sink = PluginSink::open(api, "", None).unwrap();
+        sink.write_all(b"client,available\n").unwrap();
+        sink.write_all(b"1,1.0\n").unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+        assert_eq!(
+            SINK_OUTPUT.lock().unwrap().as_slice(),
+            b"client,available\n1,1.0\n"
+        );
+    }
+}
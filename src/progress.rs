@@ -0,0 +1,188 @@
+use std::{
+    io::{self, IsTerminal, Read, Write},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tracing::info;
+
+/// Bar of an input of known size, the bytes read with the throughput and ETA.
+const BYTES_TEMPLATE: &str = "[{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta} {msg}";
+/// Without a known input size, e.g. of stdin, only the counts and the throughput are shown.
+const COUNT_TEMPLATE: &str = "{bytes} read {bytes_per_sec} {msg}";
+
+/// The bar being drawn, which log lines suspend while they are written.
+static ACTIVE_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ProgressMode {
+    /// No progress output
+    None,
+    /// Log a line every `--progress-interval` rows
+    #[default]
+    Log,
+    /// Redraw a bar of the input bytes read with the throughput and ETA, for interactive runs
+    Bar,
+}
+
+/// Progress of a run over its input files, reported on stderr. The bar measures the bytes read
+/// from the files, compressed files by their size on disk, and is hidden when stderr isn't a
+/// terminal.
+pub struct Progress {
+    mode: ProgressMode,
+    interval: u64,
+    read: Arc<AtomicU64>,
+    bar: Option<ProgressBar>,
+}
+
+/// Reader counting the bytes read for the progress bar.
+pub struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl Progress {
+    /// `interval` is the number of rows between two lines of the log mode.
+    pub fn new(mode: ProgressMode, interval: u64) -> Self {
+        let bar = (mode == ProgressMode::Bar).then(|| {
+            let target = if io::stderr().is_terminal() {
+                ProgressDrawTarget::stderr()
+            } else {
+                ProgressDrawTarget::hidden()
+            };
+            let bar = ProgressBar::with_draw_target(None, target).with_style(style(COUNT_TEMPLATE));
+            *active_bar() = Some(bar.clone());
+            bar
+        });
+        Progress {
+            mode,
+            interval: interval.max(1),
+            read: Arc::new(AtomicU64::new(0)),
+            bar,
+        }
+    }
+
+    /// Counts the bytes read from an input of `size` bytes towards the progress.
+    pub fn track<R: Read>(&mut self, reader: R, size: u64) -> CountingReader<R> {
+        if let Some(bar) = &self.bar {
+            bar.set_length(bar.length().unwrap_or(0) + size);
+            bar.set_style(style(BYTES_TEMPLATE));
+        }
+        CountingReader {
+            inner: reader,
+            read: Arc::clone(&self.read),
+        }
+    }
+
+    /// Reports `rows` processed rows so far, called after every row.
+    pub fn update(&mut self, rows: u64) {
+        match (self.mode, &self.bar) {
+            (ProgressMode::Log, _) if rows > 0 && rows.is_multiple_of(self.interval) => {
+                info!("Processed {} transactions...", rows);
+            }
+            // The bar limits its redraws, but updating it on every row would still cost more
+            // than the drawing
+            (ProgressMode::Bar, Some(bar)) if rows.is_multiple_of(1024) => self.set(bar, rows),
+            _ => {}
+        }
+    }
+
+    /// Draws the final state of the bar and leaves it on the screen.
+    pub fn finish(&mut self, rows: u64) {
+        if let Some(bar) = self.bar.take() {
+            self.set(&bar, rows);
+            bar.finish();
+            active_bar().take();
+        }
+    }
+
+    fn set(&self, bar: &ProgressBar, rows: u64) {
+        bar.set_position(self.read.load(Ordering::Relaxed));
+        bar.set_message(format!("{} rows", rows));
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // A run that failed leaves the bar where it stopped, below its error
+        if let Some(bar) = self.bar.take() {
+            bar.abandon();
+            active_bar().take();
+        }
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("Valid progress template")
+        .progress_chars("#>.")
+}
+
+fn active_bar() -> std::sync::MutexGuard<'static, Option<ProgressBar>> {
+    ACTIVE_BAR
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Writer of the log lines to stderr, clearing the progress bar while a line is written so the
+/// two don't overwrite each other. Passed to the log subscriber as `log_writer`.
+pub struct LogWriter;
+
+pub fn log_writer() -> LogWriter {
+    LogWriter
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bar = active_bar().clone();
+        match bar {
+            Some(bar) => bar.suspend(|| io::stderr().write_all(buf))?,
+            None => io::stderr().write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_bar() {
+        let mut progress = Progress::new(ProgressMode::Bar, 1);
+        let mut reader = progress.track(&b"0123456789"[..], 4 * 1024 * 1024);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        let bar = progress.bar.clone().unwrap();
+        // The test's stderr is captured, not a terminal
+        assert!(bar.is_hidden());
+        assert_eq!(bar.length(), Some(4 * 1024 * 1024));
+        progress.update(1000);
+        assert_eq!(bar.position(), 0);
+        progress.update(1024);
+        assert_eq!(bar.position(), 10);
+        assert_eq!(bar.message(), "1024 rows");
+        progress.finish(1030);
+        assert!(bar.is_finished());
+        assert_eq!(bar.message(), "1030 rows");
+
+        let progress = Progress::new(ProgressMode::Log, 1);
+        assert!(progress.bar.is_none());
+    }
+}
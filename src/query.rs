@@ -0,0 +1,161 @@
+use std::io::{self, Write};
+
+use anyhow::Result;
+
+use crate::{client::Client, currency::Currency, decimal::Decimal, engine::Engine};
+
+/// Conditions selecting accounts from a loaded engine state. Unset conditions match every
+/// account.
+#[derive(Clone, Debug, Default)]
+pub struct AccountQuery {
+    pub client: Option<u16>,
+    pub locked_only: bool,
+    /// Only accounts whose total is above this amount.
+    pub balance_above: Option<Decimal>,
+}
+
+impl AccountQuery {
+    pub fn matches(&self, client: &Client) -> bool {
+        self.client.is_none_or(|id| id == client.id)
+            && (!self.locked_only || client.locked)
+            && self.balance_above.is_none_or(|min| client.total > min)
+    }
+
+    /// The matching accounts ordered by client and currency, rounded as in the engine's report.
+    pub fn accounts(&self, engine: &Engine) -> io::Result<Vec<Client>> {
+        let amounts = engine.config().amounts;
+        let clients: Box<dyn Iterator<Item = io::Result<Client>>> = match self.client {
+            Some(client_id) => Box::new(engine.client_accounts(client_id)),
+            None => Box::new(engine.clients()),
+        };
+        let mut matching = Vec::new();
+        for client in clients {
+            let client = amounts.round_client(&client?);
+            if self.matches(&client) {
+                matching.push(client);
+            }
+        }
+        Ok(matching)
+    }
+}
+
+/// A deposit or withdrawal under an open dispute.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisputedTransaction {
+    pub tx_id: u32,
+    pub client_id: u16,
+    pub amount: Decimal,
+    pub currency: Currency,
+    /// Amount held for the dispute, see `Engine::dispute_hold`.
+    pub hold: Decimal,
+}
+
+/// The transactions under an open dispute ordered by id, only the client's if one is given.
+pub fn disputed_transactions(
+    engine: &Engine,
+    client: Option<u16>,
+) -> io::Result<Vec<DisputedTransaction>> {
+    let mut disputed = Vec::new();
+    for logged in engine.transactions() {
+        let (transaction, currency) = logged?;
+        if client.is_some_and(|id| id != transaction.client_id()) {
+            continue;
+        }
+        if let (Some(hold), Some(amount)) = (
+            engine.dispute_hold(transaction.tx_id()),
+            transaction.amount(),
+        ) {
+            disputed.push(DisputedTransaction {
+                tx_id: transaction.tx_id(),
+                client_id: transaction.client_id(),
+                amount,
+                currency,
+                hold,
+            });
+        }
+    }
+    Ok(disputed)
+}
+
+/// Writes the disputed transactions as CSV with a `tx,client,amount,currency,hold` header.
+pub fn write_disputed<W: Write>(writer: W, disputed: &[DisputedTransaction]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["tx", "client", "amount", "currency", "hold"])?;
+    for transaction in disputed {
+        writer.write_record([
+            transaction.tx_id.to_string(),
+            transaction.client_id.to_string(),
+            transaction.amount.to_string(),
+            transaction.currency.to_string(),
+            transaction.hold.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn test_query() {
+        let mut engine = Engine::new();
+        for transaction in [
+            Transaction::Deposit(1, 1, Decimal::new(50, 0)),
+            Transaction::Deposit(2, 2, Decimal::new(200, 0)),
+            Transaction::Deposit(2, 3, Decimal::new(30, 0)),
+            Transaction::Deposit(3, 4, Decimal::new(500, 0)),
+            Transaction::Dispute(2, 3),
+            Transaction::Dispute(3, 4),
+            Transaction::Chargeback(3, 4),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let ids = |query: AccountQuery| -> Vec<u16> {
+            query
+                .accounts(&engine)
+                .unwrap()
+                .iter()
+                .map(|client| client.id)
+                .collect()
+        };
+
+        assert_eq!(ids(AccountQuery::default()), [1, 2, 3]);
+        assert_eq!(
+            ids(AccountQuery {
+                client: Some(2),
+                ..AccountQuery::default()
+            }),
+            [2]
+        );
+        assert_eq!(
+            ids(AccountQuery {
+                locked_only: true,
+                ..AccountQuery::default()
+            }),
+            [3]
+        );
+        assert_eq!(
+            ids(AccountQuery {
+                balance_above: Some(Decimal::new(100, 0)),
+                ..AccountQuery::default()
+            }),
+            [2]
+        );
+
+        let disputed = disputed_transactions(&engine, None).unwrap();
+        assert_eq!(disputed.len(), 1);
+        assert!(disputed_transactions(&engine, Some(1)).unwrap().is_empty());
+        let mut output = Vec::new();
+        write_disputed(&mut output, &disputed).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "tx,client,amount,currency,hold\n3,2,30,{},30\n",
+                engine.config().default_currency
+            )
+        );
+    }
+}
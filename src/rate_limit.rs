@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Limit of a token bucket: `burst` transactions at once, refilled at `rate` transactions per
+/// second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub rate: u32,
+    pub burst: u32,
+}
+
+/// A transaction over the rate limits, rejected without reaching the engine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimited {
+    /// How long until the transaction would be accepted, if nothing else is submitted meanwhile.
+    pub retry_after: Duration,
+    /// The client whose limit was exceeded, `None` for the global limit.
+    pub client: Option<u16>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.client {
+            Some(client) => write!(f, "Rate limit of client {} exceeded", client)?,
+            None => write!(f, "Rate limit exceeded")?,
+        }
+        write!(f, ", retry after {:.3}s", self.retry_after.as_secs_f64())
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    /// Refills the bucket up to `now` and returns how long until it holds a token, zero if it
+    /// does.
+    fn wait(&mut self, limit: RateLimit, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate as f64).min(limit.burst as f64);
+        self.updated = self.updated.max(now);
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / limit.rate as f64)
+    }
+}
+
+/// Token buckets of a server that protect the engine from bursty producers: one shared by all
+/// clients and one per client. A transaction takes a token from both, or from none when either
+/// is empty.
+pub struct RateLimiter {
+    global: Option<RateLimit>,
+    client: Option<RateLimit>,
+    // Buckets start full at the first transaction
+    bucket: Option<Bucket>,
+    clients: HashMap<u16, Bucket>,
+}
+
+impl RateLimiter {
+    /// A limiter with the global and the per-client limits, `None` leaves that side unlimited.
+    /// Rates and bursts must not be zero.
+    pub fn new(global: Option<RateLimit>, client: Option<RateLimit>) -> Self {
+        RateLimiter {
+            global,
+            client,
+            bucket: None,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Takes the tokens of a transaction of the client at `now`, or tells how long to wait.
+    pub fn acquire(&mut self, client_id: u16, now: Instant) -> Result<(), RateLimited> {
+        if let Some(limit) = self.global {
+            let bucket = self.bucket.get_or_insert_with(|| Bucket::new(limit, now));
+            let retry_after = bucket.wait(limit, now);
+            if !retry_after.is_zero() {
+                return Err(RateLimited {
+                    retry_after,
+                    client: None,
+                });
+            }
+        }
+        if let Some(limit) = self.client {
+            let bucket = self
+                .clients
+                .entry(client_id)
+                .or_insert_with(|| Bucket::new(limit, now));
+            let retry_after = bucket.wait(limit, now);
+            if !retry_after.is_zero() {
+                return Err(RateLimited {
+                    retry_after,
+                    client: Some(client_id),
+                });
+            }
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = &mut self.bucket {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(
+            Some(RateLimit { rate: 10, burst: 3 }),
+            Some(RateLimit { rate: 1, burst: 2 }),
+        );
+        assert!(limiter.acquire(1, start).is_ok());
+        assert!(limiter.acquire(1, start).is_ok());
+        // Client 1 is out of tokens, its rejection takes none from the global bucket
+        let limited = limiter.acquire(1, start).unwrap_err();
+        assert_eq!(limited.client, Some(1));
+        assert_eq!(limited.retry_after, Duration::from_secs(1));
+        assert!(limiter.acquire(2, start).is_ok());
+        let limited = limiter.acquire(3, start).unwrap_err();
+        assert_eq!(limited.client, None);
+        assert_eq!(limited.retry_after, Duration::from_millis(100));
+
+        // The buckets refill at their rates
+        assert!(
+            limiter
+                .acquire(3, start + Duration::from_millis(100))
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .acquire(1, start + Duration::from_millis(500))
+                .is_err()
+        );
+        assert!(limiter.acquire(1, start + Duration::from_secs(1)).is_ok());
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    fmt,
+    sync::{Arc, RwLock, RwLockReadGuard},
+};
+
+use crate::{
+    client::Client, decimal::Decimal, engine::ExecutionError, transaction::TransactionType,
+};
+
+/// Most custom transaction types that can be registered.
+pub const MAX_CUSTOM_TYPES: usize = 128;
+
+/// A transaction type added by a downstream crate, e.g. `bonus` or `fee_refund`, without changes
+/// to the engine. Its rows carry an amount and a new `tx` id like deposits, are logged and move
+/// the funds of their client's account, which must not be locked or closed. They can't be
+/// disputed.
+pub trait TransactionHandler: Send + Sync {
+    /// Name of the type in the `type` column.
+    fn name(&self) -> &str;
+
+    /// Change of the available and held funds of the client's account by a row with `amount`,
+    /// the total follows. An error rejects the row like a built-in transaction.
+    fn apply(
+        &self,
+        account: &Client,
+        amount: Decimal,
+    ) -> Result<(Decimal, Decimal), ExecutionError>;
+}
+
+/// A registered custom transaction type, its index in the registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CustomType(pub(crate) u8);
+
+#[derive(Debug, PartialEq)]
+pub enum RegistryError {
+    /// A built-in or registered type already has the name.
+    NameTaken(String),
+    Full,
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::NameTaken(name) => {
+                write!(f, "Transaction type {} already exists", name)
+            }
+            RegistryError::Full => write!(
+                f,
+                "No more than {} custom transaction types can be registered",
+                MAX_CUSTOM_TYPES
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Handlers of the custom types in registration order. The built-in types precede them in
+/// `types` and are applied by the engine itself.
+static HANDLERS: RwLock<Vec<Arc<dyn TransactionHandler>>> = RwLock::new(Vec::new());
+
+fn handlers() -> RwLockReadGuard<'static, Vec<Arc<dyn TransactionHandler>>> {
+    // Registration pushes a complete handler, a panicking thread leaves nothing half done
+    HANDLERS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Adds a custom transaction type for the whole process, so rows with its name parse from every
+/// input format. Register handlers before reading input or restoring state that uses them.
+pub fn register(handler: Arc<dyn TransactionHandler>) -> Result<TransactionType, RegistryError> {
+    let mut handlers = HANDLERS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let name = handler.name();
+    if TransactionType::ALL
+        .iter()
+        .any(|ttype| ttype.to_string() == name)
+        || handlers.iter().any(|other| other.name() == name)
+    {
+        return Err(RegistryError::NameTaken(name.to_string()));
+    }
+    if handlers.len() == MAX_CUSTOM_TYPES {
+        return Err(RegistryError::Full);
+    }
+    handlers.push(handler);
+    Ok(TransactionType::Custom(CustomType(
+        (handlers.len() - 1) as u8,
+    )))
+}
+
+/// The custom type registered with the name.
+pub fn custom_type(name: &str) -> Option<CustomType> {
+    handlers()
+        .iter()
+        .position(|handler| handler.name() == name)
+        .map(|idx| CustomType(idx as u8))
+}
+
+/// The custom type at the index, if one is registered there, e.g. of a stored type byte.
+pub fn by_index(idx: u8) -> Option<CustomType> {
+    ((idx as usize) < handlers().len()).then_some(CustomType(idx))
+}
+
+/// The handler of a registered custom type.
+pub fn handler(custom: CustomType) -> Arc<dyn TransactionHandler> {
+    Arc::clone(&handlers()[custom.0 as usize])
+}
+
+/// All transaction types, the built-in ones followed by the registered ones.
+pub fn types() -> Vec<TransactionType> {
+    let custom = (0..handlers().len()).map(|idx| TransactionType::Custom(CustomType(idx as u8)));
+    TransactionType::ALL.into_iter().chain(custom).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Engine, transaction::Transaction};
+
+    struct Bonus;
+
+    impl TransactionHandler for Bonus {
+        fn name(&self) -> &str {
+            "bonus"
+        }
+
+        fn apply(
+            &self,
+            account: &Client,
+            amount: Decimal,
+        ) -> Result<(Decimal, Decimal), ExecutionError> {
+            // Bonuses go to clients with funds only
+            if account.total.is_zero() {
+                return Err(ExecutionError::IneligibleTransaction);
+            }
+            Ok((amount, Decimal::ZERO))
+        }
+    }
+
+    #[test]
+    fn test_custom_transaction_type() {
+        let bonus = register(Arc::new(Bonus)).unwrap();
+        assert_eq!(bonus.to_string(), "bonus");
+        assert_eq!("bonus".parse::<TransactionType>().unwrap(), bonus);
+        assert!(types().contains(&bonus));
+        assert_eq!(
+            register(Arc::new(Bonus)),
+            Err(RegistryError::NameTaken("bonus".to_string()))
+        );
+
+        let mut engine = Engine::new();
+        let transaction = Transaction::new("bonus", 1, 1, Decimal::ONE).unwrap();
+        assert_eq!(transaction.transaction_type(), bonus);
+        assert_eq!(
+            engine.execute(transaction),
+            Err(ExecutionError::IneligibleTransaction)
+        );
+        engine
+            .execute(Transaction::Deposit(1, 2, Decimal::new(10, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::new("bonus", 1, 3, Decimal::new(5, 1)).unwrap())
+            .unwrap();
+        let client = engine.account(1, Default::default()).unwrap();
+        assert_eq!(client.available, Decimal::new(105, 1));
+        assert_eq!(client.total, Decimal::new(105, 1));
+        // Logged like a deposit, so its id is taken and it can't be disputed
+        assert_eq!(
+            engine.execute(Transaction::Deposit(1, 3, Decimal::ONE)),
+            Err(ExecutionError::DuplicateTransaction)
+        );
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 3)),
+            Err(ExecutionError::IneligibleTransaction)
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use csv::StringRecord;
+
+use crate::{client::Client, config::EngineConfig, engine::Engine};
+
+/// Number of audit columns, see `audit::AuditLog`.
+const AUDIT_COLUMNS: usize = 12;
+
+/// Read-only copy of a primary engine's accounts, kept up to date by tailing the primary's
+/// audit trail, so reporting traffic can be served without touching the ingesting instance.
+///
+/// The replica copies the balances every applied row reports for its account instead of
+/// re-executing transactions, so it doesn't need the primary's configuration. It holds accounts
+/// only: no transaction log or dispute states.
+pub struct Replica {
+    path: PathBuf,
+    /// Byte offset of the first audit row not applied yet.
+    offset: u64,
+    engine: Engine,
+    applied: u64,
+}
+
+impl Replica {
+    /// Follows the audit trail at `path` from its beginning. `multi_currency` only affects how
+    /// the replica's reports look.
+    pub fn follow<P: Into<PathBuf>>(path: P, multi_currency: bool) -> Self {
+        Replica {
+            path: path.into(),
+            offset: 0,
+            engine: Engine::with_config(EngineConfig {
+                multi_currency,
+                ..EngineConfig::default()
+            }),
+            applied: 0,
+        }
+    }
+
+    /// Applies the complete rows appended since the last poll and returns their number. A row
+    /// still being written is picked up by the next poll.
+    pub fn poll(&mut self) -> Result<usize> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            // The primary hasn't created the trail yet
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+        let Some(end) = appended.iter().rposition(|b| *b == b'\n') else {
+            return Ok(0);
+        };
+        let complete = &appended[..=end];
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(complete);
+        let mut rows = 0;
+        for rec in reader.records() {
+            let record = rec?;
+            if record.get(0) == Some("type") {
+                continue;
+            }
+            self.apply(&record)
+                .with_context(|| format!("invalid audit row {:?}", record))?;
+            rows += 1;
+        }
+        self.offset += complete.len() as u64;
+        Ok(rows)
+    }
+
+    fn apply(&mut self, record: &StringRecord) -> Result<()> {
+        if record.len() < AUDIT_COLUMNS {
+            anyhow::bail!("audit row has no account columns, written by an older version");
+        }
+        // Rejected rows change nothing, assertions on clients without an account have none
+        if &record[4] != "applied" || record[10].is_empty() {
+            return Ok(());
+        }
+        let client = Client {
+            available: record[6].parse()?,
+            held: record[7].parse()?,
+            total: record[8].parse()?,
+            locked: record[9].parse()?,
+            ..Client::with_currency(record[10].parse()?, record[11].parse()?)
+        };
+        // The lock applies to the client's accounts in all currencies
+        let others = self
+            .engine
+            .client_accounts(client.id)
+            .collect::<io::Result<Vec<Client>>>()?;
+        for other in others {
+            if other.currency != client.currency && other.locked != client.locked {
+                self.engine.restore_account(Client {
+                    locked: client.locked,
+                    ..other
+                })?;
+            }
+        }
+        self.engine.restore_account(client)?;
+        self.applied += 1;
+        Ok(())
+    }
+
+    /// The replicated state, for reports and queries.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Number of applied audit rows replicated so far.
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::decimal::Decimal;
+
+    use super::*;
+    use crate::{audit::AuditLog, currency::Currency, transaction::Transaction};
+
+    #[test]
+    fn test_replica_follows_audit() {
+        let path = std::env::temp_dir().join(format!("replica-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut primary = Engine::with_config(EngineConfig {
+            multi_currency: true,
+            ..EngineConfig::default()
+        });
+        let mut replica = Replica::follow(&path, true);
+        assert_eq!(replica.poll().unwrap(), 0);
+
+        let mut audit = AuditLog::append(&path).unwrap();
+        let mut run = |primary: &mut Engine, transaction: Transaction, currency| {
+            let result = primary.execute_in(transaction.clone(), currency);
+            audit
+                .record(primary, &transaction, currency, None, &result)
+                .unwrap();
+            audit.flush().unwrap();
+        };
+        run(
+            &mut primary,
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            None,
+        );
+        run(
+            &mut primary,
+            Transaction::Deposit(1, 2, Decimal::ONE),
+            Some(eur),
+        );
+        assert_eq!(replica.poll().unwrap(), 2);
+
+        run(
+            &mut primary,
+            Transaction::Withdrawal(1, 3, Decimal::new(50, 0)),
+            None,
+        );
+        run(&mut primary, Transaction::Dispute(1, 1), None);
+        run(&mut primary, Transaction::Chargeback(1, 1), None);
+        // A partially written row waits for the next poll
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"deposit,2,4,1")
+            .unwrap();
+        assert_eq!(replica.poll().unwrap(), 3);
+        assert_eq!(replica.applied(), 4);
+        assert!(
+            replica
+                .engine()
+                .clients()
+                .map(Result::unwrap)
+                .eq(primary.clients().map(Result::unwrap))
+        );
+        // The chargeback locked the EUR account as well
+        assert!(replica.engine().account(1, eur).unwrap().locked);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,587 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::decimal::Decimal;
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+    amount::AmountUnit, client::Client, config::EngineConfig, currency::Currency, engine::Engine,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    /// A single JSON array of client objects
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+/// How the client report writes amounts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ReportStyle {
+    /// As kept by the engine, e.g. `1234.5`
+    #[default]
+    Raw,
+    /// Rounded to four decimal places, padded with zeros and with thousands separators, e.g.
+    /// `1,234.5000`, JSON strings in the JSON formats
+    Human,
+}
+
+impl ReportStyle {
+    pub fn amount(self, amount: Decimal) -> String {
+        match self {
+            ReportStyle::Raw => amount.to_string(),
+            ReportStyle::Human => human_amount(amount),
+        }
+    }
+}
+
+/// The amount with four decimal places, however many its value carries, and commas between
+/// groups of three integer digits.
+fn human_amount(amount: Decimal) -> String {
+    let amount = amount.round_dp(4);
+    let digits = amount.abs().to_string();
+    let (integer, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+    let mut human = String::with_capacity(digits.len() + integer.len() / 3 + 6);
+    if amount.is_sign_negative() && !amount.is_zero() {
+        human.push('-');
+    }
+    for (idx, digit) in integer.chars().enumerate() {
+        if idx > 0 && (integer.len() - idx) % 3 == 0 {
+            human.push(',');
+        }
+        human.push(digit);
+    }
+    human.push('.');
+    human.push_str(fraction);
+    human.extend(std::iter::repeat_n('0', 4 - fraction.len()));
+    human
+}
+
+/// An account as the client report shows it, its balances rounded by the engine's amount policy,
+/// see `Engine::report_iter`. The optional report columns are always filled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountReportRow {
+    pub client: u16,
+    pub currency: Currency,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub overdrawn: bool,
+    pub closed: bool,
+}
+
+impl From<&Client> for AccountReportRow {
+    fn from(client: &Client) -> Self {
+        AccountReportRow {
+            client: client.id,
+            currency: client.currency,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.locked,
+            overdrawn: client.is_overdrawn(),
+            closed: client.closed,
+        }
+    }
+}
+
+/// Sink for the client report. Clients are written one by one, `finish` completes the output.
+pub trait ReportWriter {
+    fn write_client(&mut self, client: &Client) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Optional columns and the amount style of the client report.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReportColumns {
+    /// Every row names the currency of its balances, as multi-currency engines report one row
+    /// per client and currency.
+    pub currency: bool,
+    /// Every row flags whether its available funds are below zero.
+    pub overdrawn: bool,
+    /// Every row flags whether the account was closed by a `close_account` transaction.
+    pub closed: bool,
+    pub style: ReportStyle,
+    /// Unit the balances are written in.
+    pub unit: AmountUnit,
+}
+
+impl ReportColumns {
+    /// The columns of the reports of an engine with the given configuration.
+    pub fn of(config: &EngineConfig) -> Self {
+        ReportColumns {
+            currency: config.multi_currency,
+            overdrawn: config.overdrafts,
+            closed: config.allow_admin,
+            style: ReportStyle::Raw,
+            unit: AmountUnit::Major,
+        }
+    }
+}
+
+/// Creates a report writer for the given format with the given optional columns.
+pub fn report_writer<'a, W: Write + 'a>(
+    format: ReportFormat,
+    writer: W,
+    columns: ReportColumns,
+) -> Box<dyn ReportWriter + 'a> {
+    match format {
+        ReportFormat::Csv => Box::new(CsvReportWriter::new(writer, columns)),
+        ReportFormat::Json => Box::new(JsonReportWriter::new(writer, columns)),
+        ReportFormat::Ndjson => Box::new(NdjsonReportWriter::new(writer, columns)),
+    }
+}
+
+/// Writes the client report to a file, replacing it atomically so readers never see a partial report.
+pub fn write_report_file<P: AsRef<Path>>(
+    engine: &Engine,
+    path: P,
+    format: ReportFormat,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    {
+        let file = BufWriter::new(File::create(&tmp_path)?);
+        let mut writer = report_writer(format, file, ReportColumns::of(engine.config()));
+        engine.write_report(writer.as_mut())?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// A row of a CSV client report, with the optional `currency` column of multi-currency reports
+/// and `closed` column of engines allowing admin transactions.
+#[derive(Deserialize)]
+struct ReportRow {
+    client: u16,
+    #[serde(default)]
+    currency: Option<String>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+}
+
+/// Reads the accounts of a CSV client report, with or without the `currency` column. Rows
+/// without a currency are in `default_currency`.
+pub fn read_report<R: Read>(reader: R, default_currency: Currency) -> Result<Vec<Client>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut clients = Vec::new();
+    for (line, row) in reader.deserialize::<ReportRow>().enumerate() {
+        // Line 1 is the header
+        let row = row.with_context(|| format!("invalid report row on line {}", line + 2))?;
+        let currency = match &row.currency {
+            Some(code) => code.parse::<Currency>()?,
+            None => default_currency,
+        };
+        if row.available.checked_add(row.held) != Some(row.total) {
+            bail!(
+                "client {} {}: total {} is not available plus held",
+                row.client,
+                currency,
+                row.total
+            );
+        }
+        clients.push(Client {
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            locked: row.locked,
+            closed: row.closed,
+            ..Client::with_currency(row.client, currency)
+        });
+    }
+    Ok(clients)
+}
+
+/// Seeds `engine` with the accounts of a CSV client report written by an earlier run: balances
+/// and locks, but no transactions or disputes. Returns the number of seeded accounts.
+pub fn seed_from_report<R: Read>(engine: &mut Engine, reader: R) -> Result<usize> {
+    let clients = read_report(reader, engine.config().default_currency)?;
+    let seeded = clients.len();
+    for client in clients {
+        engine.seed_account(client)?;
+    }
+    Ok(seeded)
+}
+
+/// A row of the client report with its optional columns, serialized by every report format.
+/// In JSON amounts are exact JSON numbers, `locked` is a boolean.
+#[derive(Serialize)]
+struct ClientRecord {
+    client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    available: ReportAmount,
+    held: ReportAmount,
+    total: ReportAmount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overdrawn: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    closed: Option<bool>,
+}
+
+/// An amount written in a report style. Raw amounts of JSON reports are JSON numbers with the
+/// exact digits of their string representation, all others strings.
+struct ReportAmount {
+    amount: Decimal,
+    style: ReportStyle,
+    json: bool,
+}
+
+impl Serialize for ReportAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.style {
+            ReportStyle::Raw if self.json => serde_json::Number::from_str(&self.amount.to_string())
+                .map_err(serde::ser::Error::custom)?
+                .serialize(serializer),
+            style => serializer.serialize_str(&style.amount(self.amount)),
+        }
+    }
+}
+
+impl ClientRecord {
+    fn new(client: &Client, columns: ReportColumns, json: bool) -> Self {
+        let amount = |amount| ReportAmount {
+            amount: columns.unit.from_major(amount, client.currency),
+            style: columns.style,
+            json,
+        };
+        ClientRecord {
+            client: client.id,
+            currency: columns.currency.then_some(client.currency),
+            available: amount(client.available),
+            held: amount(client.held),
+            total: amount(client.total),
+            locked: client.locked,
+            overdrawn: columns.overdrawn.then(|| client.is_overdrawn()),
+            closed: columns.closed.then_some(client.closed),
+        }
+    }
+}
+
+pub struct CsvReportWriter<W: Write> {
+    writer: csv::Writer<W>,
+    header_written: bool,
+    columns: ReportColumns,
+}
+
+impl<W: Write> CsvReportWriter<W> {
+    pub fn new(writer: W, columns: ReportColumns) -> Self {
+        CsvReportWriter {
+            // The header is written by hand, as an empty report has no row to derive it from
+            writer: csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(writer),
+            header_written: false,
+            columns,
+        }
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        if !self.header_written {
+            let mut header = vec!["client"];
+            if self.columns.currency {
+                header.push("currency");
+            }
+            header.extend(["available", "held", "total", "locked"]);
+            if self.columns.overdrawn {
+                header.push("overdrawn");
+            }
+            if self.columns.closed {
+                header.push("closed");
+            }
+            self.writer.write_record(header)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> ReportWriter for CsvReportWriter<W> {
+    fn write_client(&mut self, client: &Client) -> Result<()> {
+        self.write_header()?;
+        self.writer
+            .serialize(ClientRecord::new(client, self.columns, false))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // The header is written even for an empty report
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct JsonReportWriter<W: Write> {
+    writer: W,
+    empty: bool,
+    columns: ReportColumns,
+}
+
+impl<W: Write> JsonReportWriter<W> {
+    pub fn new(writer: W, columns: ReportColumns) -> Self {
+        JsonReportWriter {
+            writer,
+            empty: true,
+            columns,
+        }
+    }
+}
+
+impl<W: Write> ReportWriter for JsonReportWriter<W> {
+    fn write_client(&mut self, client: &Client) -> Result<()> {
+        self.writer
+            .write_all(if self.empty { b"[\n" } else { b",\n" })?;
+        self.empty = false;
+        serde_json::to_writer(
+            &mut self.writer,
+            &ClientRecord::new(client, self.columns, true),
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer
+            .write_all(if self.empty { b"[]\n" } else { b"\n]\n" })?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct NdjsonReportWriter<W: Write> {
+    writer: W,
+    columns: ReportColumns,
+}
+
+impl<W: Write> NdjsonReportWriter<W> {
+    pub fn new(writer: W, columns: ReportColumns) -> Self {
+        NdjsonReportWriter { writer, columns }
+    }
+}
+
+impl<W: Write> ReportWriter for NdjsonReportWriter<W> {
+    fn write_client(&mut self, client: &Client) -> Result<()> {
+        serde_json::to_writer(
+            &mut self.writer,
+            &ClientRecord::new(client, self.columns, true),
+        )?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{EngineConfig, PreHistoryPolicy},
+        engine::ExecutionError,
+        transaction::Transaction,
+    };
+
+    use super::*;
+
+    fn clients() -> Vec<Client> {
+        let mut client1 = Client::new(1);
+        client1.available = Decimal::new(15, 1);
+        client1.total = Decimal::new(15, 1);
+        let mut client2 = Client::new(2);
+        client2.locked = true;
+        vec![client1, client2]
+    }
+
+    fn render(format: ReportFormat, clients: &[Client]) -> String {
+        render_with(format, clients, ReportColumns::default())
+    }
+
+    fn render_with_currency(format: ReportFormat, clients: &[Client]) -> String {
+        let columns = ReportColumns {
+            currency: true,
+            ..ReportColumns::default()
+        };
+        render_with(format, clients, columns)
+    }
+
+    fn render_with(format: ReportFormat, clients: &[Client], columns: ReportColumns) -> String {
+        let mut output = Vec::new();
+        {
+            let mut writer = report_writer(format, &mut output, columns);
+            for client in clients {
+                writer.write_client(client).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_csv_report() {
+        assert_eq!(
+            render(ReportFormat::Csv, &clients()),
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,0,0,0,true\n"
+        );
+        assert_eq!(
+            render(ReportFormat::Csv, &[]),
+            "client,available,held,total,locked\n"
+        );
+    }
+
+    #[test]
+    fn test_currency_column() {
+        let mut clients = clients();
+        clients[1].currency = "EUR".parse().unwrap();
+        assert_eq!(
+            render_with_currency(ReportFormat::Csv, &clients),
+            "client,currency,available,held,total,locked\n1,USD,1.5,0,1.5,false\n2,EUR,0,0,0,true\n"
+        );
+        assert_eq!(
+            render_with_currency(ReportFormat::Ndjson, &clients[1..]),
+            "{\"client\":2,\"currency\":\"EUR\",\"available\":0,\"held\":0,\"total\":0,\"locked\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_overdrawn_column() {
+        let mut clients = clients();
+        clients[0].available = Decimal::new(-5, 1);
+        clients[0].total = Decimal::new(-5, 1);
+        let columns = ReportColumns {
+            overdrawn: true,
+            ..ReportColumns::default()
+        };
+        assert_eq!(
+            render_with(ReportFormat::Csv, &clients, columns),
+            "client,available,held,total,locked,overdrawn
+1,-0.5,0,-0.5,false,true
+2,0,0,0,true,false
+"
+        );
+        assert_eq!(
+            render_with(ReportFormat::Ndjson, &clients[..1], columns),
+            "{\"client\":1,\"available\":-0.5,\"held\":0,\"total\":-0.5,\"locked\":false,\"overdrawn\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_json_report() {
+        assert_eq!(
+            render(ReportFormat::Json, &clients()),
+            "[\n{\"client\":1,\"available\":1.5,\"held\":0,\"total\":1.5,\"locked\":false},\n\
+             {\"client\":2,\"available\":0,\"held\":0,\"total\":0,\"locked\":true}\n]\n"
+        );
+        assert_eq!(render(ReportFormat::Json, &[]), "[]\n");
+    }
+
+    #[test]
+    fn test_ndjson_report() {
+        assert_eq!(
+            render(ReportFormat::Ndjson, &clients()),
+            "{\"client\":1,\"available\":1.5,\"held\":0,\"total\":1.5,\"locked\":false}\n\
+             {\"client\":2,\"available\":0,\"held\":0,\"total\":0,\"locked\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_human_report_style() {
+        let mut clients = clients();
+        clients[0].available = Decimal::new(-1234567891, 4);
+        clients[0].held = Decimal::new(1_000_000, 0);
+        clients[0].total = Decimal::new(9_876_543_210_900, 8);
+        let columns = ReportColumns {
+            style: ReportStyle::Human,
+            ..ReportColumns::default()
+        };
+        assert_eq!(
+            render_with(ReportFormat::Csv, &clients, columns),
+            "client,available,held,total,locked\n\
+             1,\"-123,456.7891\",\"1,000,000.0000\",\"98,765.4321\",false\n\
+             2,0.0000,0.0000,0.0000,true\n"
+        );
+        assert_eq!(
+            render_with(ReportFormat::Ndjson, &clients[1..], columns),
+            "{\"client\":2,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"locked\":true}\n"
+        );
+    }
+
+    /// Accepts writes but fails to flush, like a closed pipe behind a buffer.
+    struct FailingFlush;
+
+    impl Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn test_seed_from_report() {
+        let report = render_with_currency(ReportFormat::Csv, &clients());
+        let mut engine = Engine::new();
+        assert_eq!(seed_from_report(&mut engine, report.as_bytes()).unwrap(), 2);
+        assert_eq!(
+            engine.clients().map(Result::unwrap).collect::<Vec<_>>(),
+            clients()
+        );
+        // Day two: new transactions apply on top, disputes of day one's are pre-history
+        engine
+            .execute(Transaction::Deposit(1, 10, Decimal::ONE))
+            .unwrap();
+        assert_eq!(engine.client(1).unwrap().total, Decimal::new(25, 1));
+        assert_eq!(
+            engine.execute(Transaction::Dispute(1, 3)),
+            Err(ExecutionError::PreHistoryTransaction)
+        );
+        assert!(engine.execute(Transaction::Dispute(1, 10)).is_ok());
+
+        let mut engine = Engine::with_config(EngineConfig {
+            pre_history: PreHistoryPolicy::Ignore,
+            ..EngineConfig::default()
+        });
+        seed_from_report(
+            &mut engine,
+            "client,available,held,total,locked\n1,1,0,1,false\n".as_bytes(),
+        )
+        .unwrap();
+        assert!(engine.execute(Transaction::Chargeback(1, 3)).is_ok());
+        assert_eq!(engine.client(1).unwrap().total, Decimal::ONE);
+
+        let inconsistent = "client,available,held,total,locked\n1,1,1,1,false\n";
+        assert!(seed_from_report(&mut Engine::new(), inconsistent.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_flush_error_is_returned() {
+        for format in [ReportFormat::Csv, ReportFormat::Json, ReportFormat::Ndjson] {
+            let mut writer = report_writer(format, FailingFlush, ReportColumns::default());
+            for client in &clients() {
+                writer.write_client(client).unwrap();
+            }
+            assert!(writer.finish().is_err());
+        }
+    }
+}
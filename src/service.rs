@@ -0,0 +1,465 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::decimal::Decimal;
+use tokio::sync::Mutex;
+
+use crate::{
+    archive::{Archive, ArchivePolicy},
+    audit::AuditLog,
+    client::Client,
+    clock::Clock,
+    config::EngineConfig,
+    currency::Currency,
+    engine::{Engine, ExecutionError},
+    rate_limit::{RateLimited, RateLimiter},
+    topk::{CurrencyExposures, Exposures},
+    transaction::Transaction,
+    windowed::{Window, WindowedMetrics},
+};
+
+/// Notification sink called with every transaction outcome and the engine state after it.
+pub type Notifier =
+    Box<dyn FnMut(&Engine, &Transaction, Option<Currency>, &Result<(), ExecutionError>) + Send>;
+
+#[derive(Debug)]
+pub enum ServiceError {
+    /// The engine rejected the transaction, the state is unchanged.
+    Rejected(ExecutionError),
+    /// The archive or the audit trail failed. The transaction may have been applied.
+    Storage(anyhow::Error),
+    /// The transaction exceeded the rate limits and didn't reach the engine.
+    RateLimited(RateLimited),
+}
+
+impl Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::Rejected(err) => write!(f, "Transaction rejected: {}", err),
+            ServiceError::Storage(err) => write!(f, "Storage failure: {}", err),
+            ServiceError::RateLimited(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+/// Counters of a running service, readable at any time without waiting for the engine.
+#[derive(Default)]
+pub struct ServiceMetrics {
+    applied: AtomicU64,
+    rejected: AtomicU64,
+    throttled: AtomicU64,
+    exposures: Option<Arc<std::sync::Mutex<Exposures>>>,
+    windows: Option<std::sync::Mutex<WindowedMetrics>>,
+}
+
+impl ServiceMetrics {
+    pub fn applied(&self) -> u64 {
+        self.applied.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Transactions turned away by the rate limits, not counted as rejected.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Biggest clients by total balance per currency, empty unless enabled with
+    /// `EngineServiceBuilder::top_k`.
+    pub fn top_by_total(&self) -> BTreeMap<Currency, Vec<(u16, Decimal)>> {
+        self.top(|exposures| exposures.by_total.top())
+    }
+
+    /// Biggest clients by held amount per currency, empty unless enabled with
+    /// `EngineServiceBuilder::top_k`.
+    pub fn top_by_disputed(&self) -> BTreeMap<Currency, Vec<(u16, Decimal)>> {
+        self.top(|exposures| exposures.by_disputed.top())
+    }
+
+    fn top(
+        &self,
+        top: impl Fn(&CurrencyExposures) -> Vec<(u16, Decimal)>,
+    ) -> BTreeMap<Currency, Vec<(u16, Decimal)>> {
+        self.exposures
+            .as_ref()
+            .map(|exposures| {
+                exposures
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .by_currency()
+                    .map(|(currency, exposures)| (currency, top(exposures)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Width of the metrics windows in seconds, `None` unless enabled with
+    /// `EngineServiceBuilder::windowed_metrics`.
+    pub fn window_width(&self) -> Option<u64> {
+        self.windows.as_ref().map(|windows| {
+            windows
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .width()
+        })
+    }
+
+    /// Copies the retained windows, oldest first.
+    pub fn windows(&self) -> Vec<Window> {
+        self.windows
+            .as_ref()
+            .map(|windows| {
+                windows
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .windows()
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+struct State {
+    engine: Engine,
+    audit: Option<AuditLog<Box<dyn Write + Send>>>,
+    notifiers: Vec<Notifier>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    metrics: ServiceMetrics,
+    limiter: Option<std::sync::Mutex<RateLimiter>>,
+}
+
+/// Ready-made wiring of the engine, its storage, metrics and notification sinks for tokio services.
+/// Cheap to clone, all clones share the same state.
+///
+/// Concurrency contract:
+///
+/// * Transactions are applied one at a time under a `tokio::sync::Mutex`. Concurrent callers are
+///   served in the order they started waiting, and a transaction's archive step, execution, audit
+///   row and notifications complete before the next transaction starts.
+/// * Notifiers and engine hooks run synchronously under that lock. They must be quick and must not
+///   block, hand slow work to a channel instead.
+/// * Metrics are atomics and can be read while transactions are applied.
+/// * Reads (`client`, `clients`) wait for the lock like writes, so they always see the state
+///   between two transactions.
+/// * Rate limits are checked before waiting for the lock, so throttled callers return at once.
+#[derive(Clone)]
+pub struct EngineService {
+    inner: Arc<Inner>,
+}
+
+/// Assembles an `EngineService`. Every component is optional.
+pub struct EngineServiceBuilder {
+    engine: Engine,
+    audit: Option<AuditLog<Box<dyn Write + Send>>>,
+    top_k: usize,
+    windows: Option<WindowedMetrics>,
+    notifiers: Vec<Notifier>,
+    limiter: Option<RateLimiter>,
+}
+
+impl EngineServiceBuilder {
+    /// Moves transactions that fell out of the dispute window to archive segments.
+    pub fn archive(mut self, policy: ArchivePolicy) -> std::io::Result<Self> {
+        self.engine.set_archive(Archive::new(policy)?);
+        Ok(self)
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.engine.set_clock(clock);
+        self
+    }
+
+    /// Writes an audit row for every transaction. `write_header` is false when appending to an
+    /// existing trail.
+    pub fn audit<W: Write + Send + 'static>(
+        mut self,
+        writer: W,
+        write_header: bool,
+    ) -> anyhow::Result<Self> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        self.audit = Some(AuditLog::new(writer, write_header)?);
+        Ok(self)
+    }
+
+    /// Tracks the `k` biggest exposures in the metrics, 0 disables tracking.
+    pub fn top_k(mut self, k: usize) -> Self {
+        self.top_k = k;
+        self
+    }
+
+    /// Aggregates transactions in tumbling windows on the engine clock.
+    pub fn windowed_metrics(mut self, windows: WindowedMetrics) -> Self {
+        self.windows = Some(windows);
+        self
+    }
+
+    pub fn notify(mut self, notifier: Notifier) -> Self {
+        self.notifiers.push(notifier);
+        self
+    }
+
+    /// Turns away transactions over the limits with `ServiceError::RateLimited`.
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    pub fn build(mut self) -> EngineService {
+        let exposures = (self.top_k > 0).then(|| Exposures::track(&mut self.engine, self.top_k));
+        EngineService {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    engine: self.engine,
+                    audit: self.audit,
+                    notifiers: self.notifiers,
+                }),
+                metrics: ServiceMetrics {
+                    exposures,
+                    windows: self.windows.map(std::sync::Mutex::new),
+                    ..ServiceMetrics::default()
+                },
+                limiter: self.limiter.map(std::sync::Mutex::new),
+            }),
+        }
+    }
+}
+
+impl EngineService {
+    pub fn builder(config: EngineConfig) -> EngineServiceBuilder {
+        Self::from_engine(Engine::with_config(config))
+    }
+
+    /// Starts from an already set up engine, e.g. one restored from a snapshot.
+    pub fn from_engine(engine: Engine) -> EngineServiceBuilder {
+        EngineServiceBuilder {
+            engine,
+            audit: None,
+            top_k: 0,
+            windows: None,
+            notifiers: Vec::new(),
+            limiter: None,
+        }
+    }
+
+    /// Applies a transaction in the given currency, `None` stands for the default currency.
+    pub async fn execute(
+        &self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+    ) -> Result<(), ServiceError> {
+        self.submit(transaction, currency).await.map(|_| ())
+    }
+
+    /// Applies a transaction like `execute` and returns the affected account as this transaction
+    /// left it, `None` for an assertion on a client without an account.
+    pub async fn submit(
+        &self,
+        transaction: Transaction,
+        currency: Option<Currency>,
+    ) -> Result<Option<Client>, ServiceError> {
+        if let Some(limiter) = self.inner.limiter.as_ref() {
+            let acquired = limiter
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .acquire(transaction.client_id(), Instant::now());
+            if let Err(limited) = acquired {
+                self.inner.metrics.throttled.fetch_add(1, Ordering::Relaxed);
+                return Err(ServiceError::RateLimited(limited));
+            }
+        }
+        let mut guard = self.inner.state.lock().await;
+        let state = &mut *guard;
+        state
+            .engine
+            .archive_old_transactions()
+            .map_err(|err| ServiceError::Storage(err.into()))?;
+        let result = state.engine.execute_in(transaction.clone(), currency);
+        let counter = match result {
+            Ok(()) => &self.inner.metrics.applied,
+            Err(_) => &self.inner.metrics.rejected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(audit) = state.audit.as_mut() {
+            audit
+                .record(&state.engine, &transaction, currency, None, &result)
+                .map_err(ServiceError::Storage)?;
+        }
+        if let Some(windows) = self.inner.metrics.windows.as_ref() {
+            let now = state.engine.clock().now();
+            windows
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .record(now, &transaction, &result)
+                .map_err(ServiceError::Storage)?;
+        }
+        for notifier in state.notifiers.iter_mut() {
+            notifier(&state.engine, &transaction, currency, &result);
+        }
+        result.map_err(ServiceError::Rejected)?;
+        Ok(state.engine.affected_account(&transaction, currency))
+    }
+
+    pub async fn client(&self, client_id: u16) -> Option<Client> {
+        self.inner.state.lock().await.engine.client(client_id)
+    }
+
+    /// Returns the client's account in the given currency, `None` stands for the default currency.
+    pub async fn account(&self, client_id: u16, currency: Option<Currency>) -> Option<Client> {
+        let state = self.inner.state.lock().await;
+        let currency = currency.unwrap_or(state.engine.config().default_currency);
+        state.engine.account(client_id, currency)
+    }
+
+    /// Copies all client accounts ordered by client id and currency.
+    pub async fn clients(&self) -> io::Result<Vec<Client>> {
+        self.inner.state.lock().await.engine.clients().collect()
+    }
+
+    pub fn metrics(&self) -> &ServiceMetrics {
+        &self.inner.metrics
+    }
+
+    /// Flushes the audit trail and the windowed metrics sink.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        if let Some(audit) = self.inner.state.lock().await.audit.as_mut() {
+            audit.flush()?;
+        }
+        if let Some(windows) = self.inner.metrics.windows.as_ref() {
+            windows
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .flush()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the open metrics window and flushes, when the service stops.
+    pub async fn finish(&self) -> anyhow::Result<()> {
+        if let Some(windows) = self.inner.metrics.windows.as_ref() {
+            windows
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .finish()?;
+        }
+        self.flush().await
+    }
+
+    /// Runs a closure with exclusive access to the engine, e.g. to write the report.
+    pub async fn with_engine<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> R {
+        f(&mut self.inner.state.lock().await.engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+    use crate::rate_limit::RateLimit;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Audit sink shared with the test.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<StdMutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_engine_service() {
+        let audit = SharedBuffer::default();
+        let notified = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&notified);
+        let service = EngineService::builder(EngineConfig::default())
+            .audit(audit.clone(), false)
+            .unwrap()
+            .top_k(1)
+            .notify(Box::new(move |_, _, _, _| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }))
+            .build();
+        block_on(async {
+            let handles: Vec<_> = (1..=3)
+                .map(|tx_id| {
+                    let service = service.clone();
+                    tokio::spawn(async move {
+                        service
+                            .execute(Transaction::Deposit(1, tx_id, Decimal::new(5, 0)), None)
+                            .await
+                    })
+                })
+                .collect();
+            for handle in handles {
+                assert!(handle.await.unwrap().is_ok());
+            }
+            let rejected = service
+                .execute(Transaction::Withdrawal(2, 4, Decimal::ONE), None)
+                .await;
+            assert!(matches!(
+                rejected,
+                Err(ServiceError::Rejected(ExecutionError::InsufficientFunds))
+            ));
+            service.flush().await.unwrap();
+            assert_eq!(service.client(1).await.unwrap().total, Decimal::new(15, 0));
+            assert_eq!(service.clients().await.unwrap().len(), 2);
+        });
+        let metrics = service.metrics();
+        assert_eq!((metrics.applied(), metrics.rejected()), (3, 1));
+        assert_eq!(
+            metrics.top_by_total(),
+            BTreeMap::from([(Currency::USD, vec![(1, Decimal::new(15, 0))])])
+        );
+        assert_eq!(notified.load(Ordering::Relaxed), 4);
+        let audit = String::from_utf8(audit.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(audit.lines().count(), 4);
+
+        // A throttled transaction doesn't reach the engine
+        let service = EngineService::builder(EngineConfig::default())
+            .rate_limit(RateLimiter::new(
+                None,
+                Some(RateLimit { rate: 1, burst: 1 }),
+            ))
+            .build();
+        block_on(async {
+            let deposit = |tx_id| Transaction::Deposit(1, tx_id, Decimal::ONE);
+            assert!(service.execute(deposit(1), None).await.is_ok());
+            let throttled = service.execute(deposit(2), None).await;
+            assert!(
+                matches!(throttled, Err(ServiceError::RateLimited(limited)) if limited.client == Some(1))
+            );
+            assert_eq!(service.client(1).await.unwrap().total, Decimal::ONE);
+        });
+        let metrics = service.metrics();
+        assert_eq!((metrics.applied(), metrics.throttled()), (1, 1));
+    }
+}
@@ -0,0 +1,652 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    client::Client,
+    config::{ClosePolicy, DisputeAmountPolicy, DisputePolicy, EngineConfig, LockedAccountPolicy},
+    currency::Currency,
+    decimal::Decimal,
+    engine::{Engine, ExecutionError},
+    registry,
+    transaction::Transaction,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DisputeState {
+    Open,
+    Closed,
+    /// Closed by a chargeback that wasn't reversed yet.
+    ChargedBack,
+}
+
+/// Reference implementation of the default accounting rules, kept deliberately naive: plain maps,
+/// every transaction in memory, no archive, log or events. Fed the same rows as the engine, it
+/// flags any difference in outcomes or balances.
+pub struct Shadow {
+    config: EngineConfig,
+    /// Balances are compared on every `interval`th row.
+    interval: u64,
+    rows: u64,
+    /// Available and held funds per client and currency.
+    balances: BTreeMap<(u16, Currency), (Decimal, Decimal)>,
+    locked: BTreeSet<u16>,
+    closed: BTreeSet<u16>,
+    transactions: HashMap<u32, (Transaction, Currency)>,
+    disputes: HashMap<u32, DisputeState>,
+    pending_withdrawals: HashMap<u32, Decimal>,
+    refunds: HashMap<u32, Decimal>,
+}
+
+/// A difference between the engine and the shadow.
+#[derive(Debug, PartialEq)]
+pub enum Divergence {
+    /// The engine and the shadow disagree on whether or why a row is rejected.
+    Outcome {
+        row: u64,
+        transaction: Transaction,
+        engine: Result<(), ExecutionError>,
+        shadow: Result<(), ExecutionError>,
+    },
+    /// Balances of an account differ, `None` for an account one side doesn't have.
+    Balance {
+        row: u64,
+        client_id: u16,
+        currency: Currency,
+        engine: Option<Client>,
+        shadow: Option<Client>,
+    },
+}
+
+fn outcome(result: &Result<(), ExecutionError>) -> String {
+    match result {
+        Ok(()) => "applied".to_string(),
+        Err(err) => format!("rejected ({})", err),
+    }
+}
+
+fn balances(client: &Option<Client>) -> String {
+    match client {
+        Some(client) => format!(
+            "available {}, held {}, total {}, locked {}",
+            client.available, client.held, client.total, client.locked
+        ),
+        None => "no account".to_string(),
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Divergence::Outcome {
+                row,
+                transaction,
+                engine,
+                shadow,
+            } => write!(
+                f,
+                "Shadow divergence at row {}: {:?} was {} by the engine and {} by the shadow",
+                row,
+                transaction,
+                outcome(engine),
+                outcome(shadow)
+            ),
+            Divergence::Balance {
+                row,
+                client_id,
+                currency,
+                engine,
+                shadow,
+            } => write!(
+                f,
+                "Shadow divergence at row {}: client {} {} has {} in the engine and {} in the shadow",
+                row,
+                client_id,
+                currency,
+                balances(engine),
+                balances(shadow)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+impl Shadow {
+    /// A shadow of an empty engine with `config`, comparing balances every `interval` rows.
+    /// Fails for settings the reference rules don't implement.
+    pub fn new(config: &EngineConfig, interval: u64) -> Result<Self> {
+        if config.idempotent_duplicates
+            || config.dispute_policy != DisputePolicy::AllowNegative
+            || config.close_policy != ClosePolicy::Reject
+            || matches!(
+                config.dispute_amounts,
+                DisputeAmountPolicy::Reject | DisputeAmountPolicy::Honor
+            )
+        {
+            bail!(
+                "The shadow engine only implements the default dispute, duplicate and closure rules"
+            );
+        }
+        if config.fees.is_some()
+            || config.limits.is_some()
+            || config.clients.is_some()
+            || config.kyc_deposit_limit.is_some()
+            || config.overdrafts
+        {
+            bail!(
+                "The shadow engine doesn't implement fees, withdrawal limits, overdrafts and KYC gating"
+            );
+        }
+        Ok(Shadow {
+            config: config.clone(),
+            interval: interval.max(1),
+            rows: 0,
+            balances: BTreeMap::new(),
+            locked: BTreeSet::new(),
+            closed: BTreeSet::new(),
+            transactions: HashMap::new(),
+            disputes: HashMap::new(),
+            pending_withdrawals: HashMap::new(),
+            refunds: HashMap::new(),
+        })
+    }
+
+    /// Applies a row the engine executed with `result`, and compares all balances on every
+    /// `interval`th row.
+    pub fn check(
+        &mut self,
+        engine: &Engine,
+        transaction: &Transaction,
+        currency: Option<Currency>,
+        result: &Result<(), ExecutionError>,
+    ) -> Result<(), Box<Divergence>> {
+        let shadow = self.execute(transaction, currency);
+        if shadow != *result {
+            return Err(Box::new(Divergence::Outcome {
+                row: self.rows,
+                transaction: transaction.clone(),
+                engine: result.clone(),
+                shadow,
+            }));
+        }
+        if self.rows.is_multiple_of(self.interval) {
+            self.compare(engine)?;
+        }
+        Ok(())
+    }
+
+    /// Compares the balances of every account. Accounts the engine opened for rejected rows
+    /// don't count as long as they are empty.
+    pub fn compare(&self, engine: &Engine) -> Result<(), Box<Divergence>> {
+        for (&(client_id, currency), &(available, held)) in &self.balances {
+            let shadow = Client {
+                available,
+                held,
+                // An overflowing total can't match the engine, which rejects it
+                total: available.saturating_add(held),
+                locked: self.locked.contains(&client_id),
+                closed: self.closed.contains(&client_id),
+                ..Client::with_currency(client_id, currency)
+            };
+            let actual = engine.account(client_id, currency);
+            if actual.as_ref() != Some(&shadow) {
+                return Err(self.balance_divergence(actual, Some(shadow)));
+            }
+        }
+        // Unreadable accounts are missing, which the loop above reports
+        for client in engine.clients().flatten() {
+            let key = (client.id, client.currency);
+            let empty = client.total.is_zero() && client.held.is_zero();
+            if !empty && !self.balances.contains_key(&key) {
+                return Err(self.balance_divergence(Some(client), None));
+            }
+        }
+        Ok(())
+    }
+
+    fn balance_divergence(
+        &self,
+        engine: Option<Client>,
+        shadow: Option<Client>,
+    ) -> Box<Divergence> {
+        let (client_id, currency) = engine
+            .as_ref()
+            .or(shadow.as_ref())
+            .map(|client| (client.id, client.currency))
+            .unwrap_or_default();
+        Box::new(Divergence::Balance {
+            row: self.rows,
+            client_id,
+            currency,
+            engine,
+            shadow,
+        })
+    }
+
+    /// Applies a row to the shadow state, returning whether it was applied.
+    pub fn execute(
+        &mut self,
+        transaction: &Transaction,
+        currency: Option<Currency>,
+    ) -> Result<(), ExecutionError> {
+        self.rows += 1;
+        if transaction.is_admin() && !self.config.allow_admin {
+            return Err(ExecutionError::AdminNotAllowed);
+        }
+        let row_currency = currency;
+        let currency = currency.unwrap_or(self.config.default_currency);
+        if !self.config.multi_currency && currency != self.config.default_currency {
+            return Err(ExecutionError::UnsupportedCurrency);
+        }
+        let transaction = self.config.amounts.round_transaction(transaction.clone());
+        self.config.validation.validate(&transaction)?;
+        let tx_id = transaction.tx_id();
+        if !transaction.references_transaction()
+            && !transaction.is_assertion()
+            && self.transactions.contains_key(&tx_id)
+        {
+            return Err(ExecutionError::DuplicateTransaction);
+        }
+        let client_id = transaction.client_id();
+        let owner = match self.transactions.get(&tx_id) {
+            Some((referenced, _)) if transaction.references_transaction() => referenced.client_id(),
+            _ => client_id,
+        };
+        if !transaction.is_assertion() && self.closed.contains(&owner) {
+            return Err(ExecutionError::AccountClosed);
+        }
+        match transaction {
+            Transaction::Deposit(_, _, amount) => {
+                self.unlocked(client_id)?;
+                self.move_funds(client_id, currency, amount, Decimal::ZERO)?;
+            }
+            Transaction::Withdrawal(_, _, amount) => {
+                self.unlocked(client_id)?;
+                self.funded(client_id, currency, amount)?;
+                self.move_funds(client_id, currency, -amount, Decimal::ZERO)?;
+            }
+            Transaction::WithdrawalHold(_, _, amount) => {
+                self.unlocked(client_id)?;
+                self.funded(client_id, currency, amount)?;
+                self.move_funds(client_id, currency, -amount, amount)?;
+                self.pending_withdrawals.insert(tx_id, amount);
+            }
+            Transaction::WithdrawalSettle(..) | Transaction::WithdrawalCancel(..) => {
+                let Some(&amount) = self.pending_withdrawals.get(&tx_id) else {
+                    return Err(ExecutionError::NoPendingWithdrawal);
+                };
+                let (hold, hold_currency) = &self.transactions[&tx_id];
+                if hold.client_id() != client_id {
+                    return Err(ExecutionError::ClientMismatch);
+                }
+                if row_currency.is_some_and(|currency| currency != *hold_currency) {
+                    return Err(ExecutionError::CurrencyMismatch);
+                }
+                let released = match transaction {
+                    Transaction::WithdrawalCancel(..) => amount,
+                    _ => Decimal::ZERO,
+                };
+                self.move_funds(client_id, *hold_currency, released, -amount)?;
+                self.pending_withdrawals.remove(&tx_id);
+                return Ok(());
+            }
+            Transaction::Dispute(..) | Transaction::Resolve(..) | Transaction::Chargeback(..) => {
+                match (self.disputes.get(&tx_id), &transaction) {
+                    (Some(DisputeState::Closed | DisputeState::ChargedBack), _) => {
+                        return Err(ExecutionError::DisputeClosed);
+                    }
+                    (Some(DisputeState::Open), Transaction::Dispute(..)) => {
+                        return Err(ExecutionError::AlreadyDisputedTransaction);
+                    }
+                    (None, Transaction::Resolve(..) | Transaction::Chargeback(..)) => {
+                        return Err(ExecutionError::NonDisputedTransaction);
+                    }
+                    _ => {}
+                }
+                let Some((deposit, deposit_currency)) = self.transactions.get(&tx_id) else {
+                    return Err(ExecutionError::TransactionNotFound);
+                };
+                let Transaction::Deposit(owner, _, amount) = *deposit else {
+                    return Err(ExecutionError::IneligibleTransaction);
+                };
+                let deposit_currency = *deposit_currency;
+                if self.config.check_dispute_client && owner != client_id {
+                    return Err(ExecutionError::ClientMismatch);
+                }
+                if row_currency.is_some_and(|currency| currency != deposit_currency) {
+                    return Err(ExecutionError::CurrencyMismatch);
+                }
+                let amount = self.refundable(tx_id, amount)?;
+                if matches!(transaction, Transaction::Dispute(..)) && amount <= Decimal::ZERO {
+                    return Err(ExecutionError::IneligibleTransaction);
+                }
+                let allowed = match self.config.locked_account_policy {
+                    LockedAccountPolicy::Freeze => false,
+                    LockedAccountPolicy::SettleOpen => {
+                        !matches!(transaction, Transaction::Dispute(..))
+                    }
+                    LockedAccountPolicy::Allow => true,
+                };
+                if !allowed {
+                    self.unlocked(owner)?;
+                }
+                match transaction {
+                    Transaction::Dispute(..) => {
+                        self.move_funds(owner, deposit_currency, -amount, amount)?;
+                        self.disputes.insert(tx_id, DisputeState::Open);
+                    }
+                    Transaction::Resolve(..) => {
+                        self.move_funds(owner, deposit_currency, amount, -amount)?;
+                        self.disputes.insert(tx_id, DisputeState::Closed);
+                    }
+                    _ => {
+                        self.move_funds(owner, deposit_currency, Decimal::ZERO, -amount)?;
+                        self.disputes.insert(tx_id, DisputeState::ChargedBack);
+                        self.locked.insert(owner);
+                    }
+                }
+                return Ok(());
+            }
+            Transaction::ChargebackReversal(..) => {
+                if self.disputes.get(&tx_id) != Some(&DisputeState::ChargedBack) {
+                    return Err(ExecutionError::NotChargedBack);
+                }
+                let (deposit, deposit_currency) = &self.transactions[&tx_id];
+                let Transaction::Deposit(owner, _, amount) = *deposit else {
+                    return Err(ExecutionError::IneligibleTransaction);
+                };
+                let deposit_currency = *deposit_currency;
+                if self.config.check_dispute_client && owner != client_id {
+                    return Err(ExecutionError::ClientMismatch);
+                }
+                if row_currency.is_some_and(|currency| currency != deposit_currency) {
+                    return Err(ExecutionError::CurrencyMismatch);
+                }
+                let amount = self.refundable(tx_id, amount)?;
+                self.move_funds(owner, deposit_currency, amount, Decimal::ZERO)?;
+                self.disputes.insert(tx_id, DisputeState::Closed);
+                if self.config.reversal_unlocks {
+                    self.locked.remove(&owner);
+                }
+                return Ok(());
+            }
+            Transaction::Refund(_, _, refund) => {
+                if refund <= Decimal::ZERO {
+                    return Err(ExecutionError::InvalidAmount);
+                }
+                let Some((deposit, deposit_currency)) = self.transactions.get(&tx_id) else {
+                    return Err(ExecutionError::TransactionNotFound);
+                };
+                let Transaction::Deposit(owner, _, amount) = *deposit else {
+                    return Err(ExecutionError::IneligibleTransaction);
+                };
+                let deposit_currency = *deposit_currency;
+                if self.config.check_dispute_client && owner != client_id {
+                    return Err(ExecutionError::ClientMismatch);
+                }
+                if row_currency.is_some_and(|currency| currency != deposit_currency) {
+                    return Err(ExecutionError::CurrencyMismatch);
+                }
+                let refundable = match self.disputes.get(&tx_id) {
+                    Some(DisputeState::Open) => {
+                        return Err(ExecutionError::AlreadyDisputedTransaction);
+                    }
+                    // The chargeback took all that wasn't refunded
+                    Some(DisputeState::ChargedBack) => Decimal::ZERO,
+                    _ => self.refundable(tx_id, amount)?,
+                };
+                if refund > refundable {
+                    return Err(ExecutionError::RefundExceeded);
+                }
+                self.unlocked(owner)?;
+                self.funded(owner, deposit_currency, refund)?;
+                self.move_funds(owner, deposit_currency, -refund, Decimal::ZERO)?;
+                let refunded = self
+                    .refunded(tx_id)
+                    .checked_add(refund)
+                    .ok_or(ExecutionError::Overflow)?;
+                self.refunds.insert(tx_id, refunded);
+                return Ok(());
+            }
+            Transaction::Unlock(..) => {
+                self.balances.entry((client_id, currency)).or_default();
+                self.locked.remove(&client_id);
+            }
+            // Without overdrafts the limit has no effect
+            Transaction::SetOverdraft(..) => {
+                self.balances.entry((client_id, currency)).or_default();
+            }
+            Transaction::CloseAccount(..) => {
+                let held = self
+                    .balances
+                    .range((client_id, Currency::MIN)..=(client_id, Currency::MAX))
+                    .any(|(_, (_, held))| !held.is_zero());
+                if held {
+                    return Err(ExecutionError::FundsHeld);
+                }
+                self.balances.entry((client_id, currency)).or_default();
+                self.closed.insert(client_id);
+            }
+            Transaction::AdjustCredit(_, _, amount) => {
+                self.move_funds(client_id, currency, amount, Decimal::ZERO)?;
+            }
+            Transaction::Custom(_, _, amount, custom) => {
+                self.unlocked(client_id)?;
+                let (available, held) = self
+                    .balances
+                    .get(&(client_id, currency))
+                    .copied()
+                    .unwrap_or_default();
+                let account = Client {
+                    available,
+                    held,
+                    total: available
+                        .checked_add(held)
+                        .ok_or(ExecutionError::Overflow)?,
+                    ..Client::with_currency(client_id, currency)
+                };
+                let (available, held) = registry::handler(custom).apply(&account, amount)?;
+                self.move_funds(client_id, currency, available, held)?;
+            }
+            Transaction::AdjustDebit(_, _, amount) => {
+                self.funded(client_id, currency, amount)?;
+                self.move_funds(client_id, currency, -amount, Decimal::ZERO)?;
+            }
+            Transaction::AssertBalance(_, _, expected, expected_total) => {
+                let (actual, held) = self
+                    .balances
+                    .get(&(client_id, currency))
+                    .copied()
+                    .unwrap_or_default();
+                let total = actual.checked_add(held).ok_or(ExecutionError::Overflow)?;
+                if actual != expected || expected_total.is_some_and(|expected| total != expected) {
+                    return Err(ExecutionError::BalanceMismatch {
+                        expected,
+                        actual,
+                        expected_total,
+                        total,
+                    });
+                }
+                return Ok(());
+            }
+        }
+        self.transactions.insert(tx_id, (transaction, currency));
+        Ok(())
+    }
+
+    fn available(&self, client_id: u16, currency: Currency) -> Decimal {
+        self.balances
+            .get(&(client_id, currency))
+            .map(|(available, _)| *available)
+            .unwrap_or_default()
+    }
+
+    fn refunded(&self, tx_id: u32) -> Decimal {
+        self.refunds.get(&tx_id).copied().unwrap_or_default()
+    }
+
+    /// The part of a deposit that wasn't refunded.
+    fn refundable(&self, tx_id: u32, amount: Decimal) -> Result<Decimal, ExecutionError> {
+        amount
+            .checked_sub(self.refunded(tx_id))
+            .ok_or(ExecutionError::Overflow)
+    }
+
+    fn unlocked(&self, client_id: u16) -> Result<(), ExecutionError> {
+        if self.locked.contains(&client_id) {
+            return Err(ExecutionError::AccountLocked);
+        }
+        Ok(())
+    }
+
+    fn funded(
+        &self,
+        client_id: u16,
+        currency: Currency,
+        amount: Decimal,
+    ) -> Result<(), ExecutionError> {
+        if self.available(client_id, currency) < amount {
+            return Err(ExecutionError::InsufficientFunds);
+        }
+        Ok(())
+    }
+
+    fn move_funds(
+        &mut self,
+        client_id: u16,
+        currency: Currency,
+        available: Decimal,
+        held: Decimal,
+    ) -> Result<(), ExecutionError> {
+        let (old_available, old_held) = self
+            .balances
+            .get(&(client_id, currency))
+            .copied()
+            .unwrap_or_default();
+        let new_available = old_available
+            .checked_add(available)
+            .ok_or(ExecutionError::Overflow)?;
+        let new_held = old_held.checked_add(held).ok_or(ExecutionError::Overflow)?;
+        new_available
+            .checked_add(new_held)
+            .ok_or(ExecutionError::Overflow)?;
+        self.balances
+            .insert((client_id, currency), (new_available, new_held));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::currency::Currency;
+
+    use super::*;
+
+    #[test]
+    fn test_shadow_agrees_with_engine() {
+        let config = EngineConfig {
+            allow_admin: true,
+            multi_currency: true,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config.clone());
+        let mut shadow = Shadow::new(&config, 2).unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        let rows = [
+            (Transaction::Deposit(1, 1, Decimal::new(10, 0)), None),
+            (Transaction::Deposit(1, 2, Decimal::new(5, 0)), Some(eur)),
+            (Transaction::Deposit(1, 1, Decimal::ONE), None),
+            (Transaction::Withdrawal(1, 3, Decimal::new(20, 0)), None),
+            (
+                Transaction::WithdrawalHold(1, 4, Decimal::new(3, 0)),
+                Some(eur),
+            ),
+            (Transaction::WithdrawalSettle(1, 4), None),
+            (Transaction::WithdrawalCancel(1, 4), Some(eur)),
+            (Transaction::Dispute(2, 1), None),
+            (Transaction::Dispute(1, 1), None),
+            (Transaction::Chargeback(1, 1), None),
+            (Transaction::Resolve(1, 1), None),
+            (Transaction::Deposit(1, 5, Decimal::ONE), None),
+            (Transaction::Unlock(1, 6), None),
+            (Transaction::AdjustDebit(1, 7, Decimal::ONE), None),
+            (Transaction::AssertBalance(1, 8, Decimal::ZERO, None), None),
+            (
+                Transaction::AssertBalance(1, 9, Decimal::TWO, Some(Decimal::TWO)),
+                Some(eur),
+            ),
+        ];
+        for (transaction, currency) in rows {
+            let result = engine.execute_in(transaction.clone(), currency);
+            shadow
+                .check(&engine, &transaction, currency, &result)
+                .unwrap();
+        }
+        shadow.compare(&engine).unwrap();
+    }
+
+    #[test]
+    fn test_shadow_without_dispute_client_check() {
+        let config = EngineConfig {
+            check_dispute_client: false,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config.clone());
+        let mut shadow = Shadow::new(&config, 1).unwrap();
+        // Rows of client 2 settling client 1's deposits
+        let rows = [
+            Transaction::Deposit(1, 1, Decimal::new(10, 0)),
+            Transaction::Deposit(1, 2, Decimal::new(5, 0)),
+            Transaction::Dispute(2, 1),
+            Transaction::Resolve(2, 1),
+            Transaction::Dispute(2, 2),
+            Transaction::Chargeback(2, 2),
+        ];
+        for transaction in rows {
+            let result = engine.execute(transaction.clone());
+            assert_eq!(result, Ok(()));
+            shadow.check(&engine, &transaction, None, &result).unwrap();
+        }
+        shadow.compare(&engine).unwrap();
+    }
+
+    #[test]
+    fn test_shadow_flags_divergence() {
+        let mut engine = Engine::new();
+        let mut shadow = Shadow::new(engine.config(), 1).unwrap();
+        let deposit = Transaction::Deposit(1, 1, Decimal::ONE);
+        let result = engine.execute(deposit.clone());
+        shadow.check(&engine, &deposit, None, &result).unwrap();
+
+        // A row only the engine saw
+        engine
+            .execute(Transaction::Deposit(1, 2, Decimal::ONE))
+            .unwrap();
+        assert!(matches!(
+            shadow.compare(&engine).map_err(|divergence| *divergence),
+            Err(Divergence::Balance { client_id: 1, .. })
+        ));
+        let withdrawal = Transaction::Withdrawal(1, 3, Decimal::TWO);
+        let result = engine.execute(withdrawal.clone());
+        assert!(matches!(
+            shadow
+                .check(&engine, &withdrawal, None, &result)
+                .map_err(|divergence| *divergence),
+            Err(Divergence::Outcome {
+                row: 2,
+                shadow: Err(ExecutionError::InsufficientFunds),
+                ..
+            })
+        ));
+
+        let config = EngineConfig {
+            idempotent_duplicates: true,
+            ..EngineConfig::default()
+        };
+        assert!(Shadow::new(&config, 1).is_err());
+    }
+}
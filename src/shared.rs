@@ -0,0 +1,139 @@
+use std::{
+    io,
+    sync::{Arc, RwLock, RwLockReadGuard},
+};
+
+use crate::{
+    client::Client,
+    engine::{Engine, ExecutionError},
+    transaction::Transaction,
+};
+
+/// Write side of an engine shared between threads. Each transaction holds the write lock only
+/// while it is applied.
+pub struct EngineWriter {
+    engine: Arc<RwLock<Engine>>,
+}
+
+/// Read-only handle to a shared engine. Cheap to clone; queries copy the requested state out and
+/// release the lock right away, so readers never hold back ingestion for longer than a lookup.
+#[derive(Clone)]
+pub struct EngineReader {
+    engine: Arc<RwLock<Engine>>,
+}
+
+impl EngineWriter {
+    pub fn new(engine: Engine) -> Self {
+        EngineWriter {
+            engine: Arc::new(RwLock::new(engine)),
+        }
+    }
+
+    pub fn reader(&self) -> EngineReader {
+        EngineReader {
+            engine: Arc::clone(&self.engine),
+        }
+    }
+
+    pub fn execute(&mut self, transaction: Transaction) -> Result<(), ExecutionError> {
+        self.engine
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .execute(transaction)
+    }
+
+    /// Runs a closure with exclusive access to the engine, e.g. to print the final report.
+    pub fn with_engine<R>(&mut self, f: impl FnOnce(&mut Engine) -> R) -> R {
+        f(&mut self
+            .engine
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+impl EngineReader {
+    fn read(&self) -> RwLockReadGuard<'_, Engine> {
+        self.engine
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn client(&self, client_id: u16) -> Option<Client> {
+        self.read().client(client_id)
+    }
+
+    /// Copies all clients ordered by id.
+    pub fn clients(&self) -> io::Result<Vec<Client>> {
+        self.read().clients().collect()
+    }
+
+    pub fn transaction(&self, tx_id: u32) -> Option<Transaction> {
+        self.read().transaction(tx_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn test_reader_sees_writer_updates() {
+        let mut writer = EngineWriter::new(Engine::new());
+        let reader = writer.reader();
+        assert!(reader.client(1).is_none());
+        assert!(
+            writer
+                .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+                .is_ok()
+        );
+        assert_eq!(reader.client(1).unwrap().total, Decimal::new(10, 0));
+        assert_eq!(
+            reader.transaction(1),
+            Some(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+        );
+    }
+
+    #[test]
+    fn test_concurrent_readers() {
+        let mut writer = EngineWriter::new(Engine::new());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = writer.reader();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        for client in reader.clients().unwrap() {
+                            assert_eq!(
+                                Some(client.total),
+                                client.available.checked_add(client.held)
+                            );
+                        }
+                    }
+                })
+            })
+            .collect();
+        for tx_id in 0..1000 {
+            let client_id = (tx_id % 10) as u16;
+            assert!(
+                writer
+                    .execute(Transaction::Deposit(client_id, tx_id, Decimal::ONE))
+                    .is_ok()
+            );
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        let total = writer
+            .reader()
+            .clients()
+            .unwrap()
+            .iter()
+            .map(|c| c.total)
+            .try_fold(Decimal::ZERO, Decimal::checked_add)
+            .unwrap();
+        assert_eq!(total, Decimal::new(1000, 0));
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    io,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// The signal that asked the process to stop, 0 while none arrived.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Signals that stop a run gracefully.
+#[cfg(not(target_arch = "wasm32"))]
+pub const SIGNALS: [i32; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/// Installs handlers for SIGINT and SIGTERM that only record the signal, so a long run can stop
+/// reading input between rows and still write its outputs. A second signal ends the process
+/// right away.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn install() -> io::Result<()> {
+    for signal in SIGNALS {
+        // SAFETY: the handler only touches an atomic and calls the async-signal-safe `_exit`
+        unsafe {
+            signal_hook_registry::register(signal, move || {
+                if RECEIVED.swap(signal, Ordering::SeqCst) != 0 {
+                    libc::_exit(exit_code(signal));
+                }
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// WebAssembly has no signals, a run only stops at the end of its input.
+#[cfg(target_arch = "wasm32")]
+pub fn install() -> io::Result<()> {
+    Ok(())
+}
+
+/// The stop signal received so far, if any.
+pub fn received() -> Option<i32> {
+    match RECEIVED.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Exit status of a run stopped by `signal`, following the shell's 128 + signal number.
+pub fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_is_recorded() {
+        install().unwrap();
+        assert_eq!(received(), None);
+        // SAFETY: raising a signal with an installed handler
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        assert_eq!(received(), Some(libc::SIGTERM));
+        assert_eq!(exit_code(libc::SIGTERM), 143);
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::decimal::Decimal;
+use anyhow::{Context, Result, bail};
+use csv::StringRecord;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+use crate::{
+    checkpoint::InputCheckpoint, client::Client, currency::Currency, dispute::DisputeState,
+    engine::Engine, export::ExportedTransaction, report::ReportWriter, transaction::Transaction,
+};
+
+/// Version of the snapshot format, stored in its first record.
+const SNAPSHOT_VERSION: &str = "1";
+
+/// Writes the engine state as headerless CSV records:
+///
+/// * `snapshot,<version>[,<ledger>]`
+/// * `input,<rows>,<offset>,<sha256>` if saved with the checkpoint of the run's input, see
+///   `write_snapshot_with`
+/// * `client,<client>,<currency>,<available>,<held>,<total>,<locked>[,closed]` per account
+/// * `active,<client>,<time>` per client with the engine clock time of its last transaction
+/// * `tx,<type>,<client>,<tx>,<amount>,<currency>,<state>[,<held>[,<refunded>]]` per logged
+///   transaction, with the dispute state `open`, `resolved`, `charged_back`, `reversed` or empty
+///   and the amount held by an open dispute or taken by a chargeback, or `pending` for withdrawal
+///   holds not yet settled or cancelled, and the amount refunded from a deposit
+///
+/// Archived transactions are not part of the snapshot.
+pub fn write_snapshot<W: Write>(engine: &Engine, writer: W) -> Result<()> {
+    write_snapshot_with(engine, None, writer)
+}
+
+/// Writes the engine state like `write_snapshot`, with the checkpoint of the input file the state
+/// was built from so a run over the file resumes after it.
+pub fn write_snapshot_with<W: Write>(
+    engine: &Engine,
+    input: Option<&InputCheckpoint>,
+    writer: W,
+) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .has_headers(false)
+        .from_writer(writer);
+    let mut header = vec!["snapshot", SNAPSHOT_VERSION];
+    if let Some(ledger) = &engine.config().ledger {
+        header.push(ledger.as_str());
+    }
+    writer.write_record(&header)?;
+    if let Some(input) = input {
+        writer.write_record(input.record())?;
+    }
+    for client in engine.clients() {
+        writer.write_record(account_record(&client?))?;
+    }
+    for (client_id, time) in engine.activity() {
+        writer.write_record(["active", &client_id.to_string(), &time.to_string()])?;
+    }
+    for entry in engine.transactions() {
+        let (transaction, currency) = entry?;
+        writer.write_record(transaction_record(
+            &engine.transaction_state(transaction, currency),
+        ))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The `client` record of an account.
+pub(crate) fn account_record(client: &Client) -> Vec<String> {
+    let mut record = vec![
+        "client".to_string(),
+        client.id.to_string(),
+        client.currency.to_string(),
+        client.available.to_string(),
+        client.held.to_string(),
+        client.total.to_string(),
+        client.locked.to_string(),
+    ];
+    if client.closed {
+        record.push("closed".to_string());
+    }
+    record
+}
+
+/// The `tx` record of a logged transaction with its state.
+pub(crate) fn transaction_record(entry: &ExportedTransaction) -> Vec<String> {
+    let transaction = &entry.transaction;
+    let state = match entry.dispute {
+        DisputeState::None if entry.withdrawal_pending => "pending".to_string(),
+        DisputeState::None => String::new(),
+        state => state.to_string(),
+    };
+    let mut record = vec![
+        "tx".to_string(),
+        transaction.transaction_type().to_string(),
+        transaction.client_id().to_string(),
+        transaction.tx_id().to_string(),
+        transaction
+            .amount()
+            .map(|amount| amount.to_string())
+            .unwrap_or_default(),
+        entry.currency.to_string(),
+        state,
+    ];
+    if entry.dispute_amount.is_some() || entry.refunded.is_some() {
+        record.push(
+            entry
+                .dispute_amount
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+        );
+    }
+    if let Some(refunded) = entry.refunded {
+        record.push(refunded.to_string());
+    }
+    record
+}
+
+/// Parses a `client` record, the record kind is not checked.
+pub(crate) fn parse_account(record: &StringRecord) -> Result<Client> {
+    let field = |idx: usize| record.get(idx).context("missing field");
+    Ok(Client {
+        available: field(3)?.parse()?,
+        held: field(4)?.parse()?,
+        total: field(5)?.parse()?,
+        locked: field(6)?.parse()?,
+        closed: record.get(7) == Some("closed"),
+        ..Client::with_currency(field(1)?.parse()?, field(2)?.parse()?)
+    })
+}
+
+/// Parses a `tx` record, the record kind is not checked.
+pub(crate) fn parse_transaction(record: &StringRecord) -> Result<ExportedTransaction> {
+    let field = |idx: usize| record.get(idx).context("missing field");
+    let amount = match field(4)? {
+        "" => Decimal::ZERO,
+        amount => amount.parse()?,
+    };
+    let transaction = Transaction::new(field(1)?, field(2)?.parse()?, field(3)?.parse()?, amount)?;
+    let (dispute, withdrawal_pending) = match field(6)? {
+        "" => (DisputeState::None, false),
+        "pending" => (DisputeState::None, true),
+        state => (state.parse()?, false),
+    };
+    let dispute_amount = match dispute {
+        DisputeState::Open | DisputeState::ChargedBack => Some(field(7)?.parse()?),
+        _ => None,
+    };
+    let refunded = record
+        .get(8)
+        .filter(|refunded| !refunded.is_empty())
+        .map(str::parse)
+        .transpose()?;
+    Ok(ExportedTransaction {
+        transaction,
+        currency: field(5)?.parse()?,
+        dispute,
+        dispute_amount,
+        withdrawal_pending,
+        refunded,
+    })
+}
+
+/// Restores a snapshot written by `write_snapshot` into an empty engine. The engine keeps its own
+/// configuration, a snapshot of another ledger is rejected.
+pub fn read_snapshot<R: Read>(engine: &mut Engine, reader: R) -> Result<()> {
+    if engine.clients().next().is_some() || engine.transactions().next().is_some() {
+        bail!("a snapshot can only be restored into an empty engine");
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(reader);
+    let mut records = reader.records();
+    match records.next().transpose()? {
+        Some(record) if record.get(0) == Some("snapshot") => {
+            if record.get(1) != Some(SNAPSHOT_VERSION) {
+                bail!("unsupported snapshot version {:?}", record.get(1));
+            }
+            let ledger = engine
+                .config()
+                .ledger
+                .as_ref()
+                .map(|ledger| ledger.as_str());
+            if record.get(2) != ledger {
+                bail!(
+                    "snapshot belongs to ledger {:?}, not {:?}",
+                    record.get(2),
+                    ledger
+                );
+            }
+        }
+        _ => bail!("not a snapshot file"),
+    }
+    for rec in records {
+        let record = rec?;
+        restore_record(engine, &record)
+            .with_context(|| format!("invalid snapshot record {:?}", record))?;
+    }
+    Ok(())
+}
+
+fn restore_record(engine: &mut Engine, record: &StringRecord) -> Result<()> {
+    match record.get(0).context("missing field")? {
+        "client" => engine.restore_account(parse_account(record)?)?,
+        "tx" => engine.restore_transaction(&parse_transaction(record)?)?,
+        "active" => engine.restore_activity(
+            record.get(1).context("missing client")?.parse()?,
+            record.get(2).context("missing time")?.parse()?,
+        ),
+        // Read by `read_checkpoint`, it isn't engine state
+        "input" => {}
+        kind => bail!("unknown record kind {}", kind),
+    }
+    Ok(())
+}
+
+/// The input checkpoint of a snapshot written by `write_snapshot_with`, `None` if it has none.
+pub fn read_checkpoint<R: Read>(reader: R) -> Result<Option<InputCheckpoint>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(reader);
+    let mut records = reader.records();
+    match records.next().transpose()? {
+        Some(record) if record.get(0) == Some("snapshot") => {}
+        _ => bail!("not a snapshot file"),
+    }
+    match records.next().transpose()? {
+        Some(record) if record.get(0) == Some("input") => Ok(Some(
+            InputCheckpoint::parse(&record)
+                .with_context(|| format!("invalid snapshot record {:?}", record))?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Writes a gzip-compressed snapshot, replacing the file atomically.
+pub fn save<P: AsRef<Path>>(engine: &Engine, path: P) -> Result<()> {
+    save_with(engine, None, path)
+}
+
+/// Writes a gzip-compressed snapshot with an input checkpoint, replacing the file atomically.
+pub fn save_with<P: AsRef<Path>>(
+    engine: &Engine,
+    input: Option<&InputCheckpoint>,
+    path: P,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    {
+        let file = BufWriter::new(File::create(&tmp_path)?);
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        write_snapshot_with(engine, input, &mut encoder)?;
+        encoder.finish()?.flush()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Restores a gzip-compressed snapshot into an empty engine.
+pub fn load<P: AsRef<Path>>(engine: &mut Engine, path: P) -> Result<()> {
+    let path = path.as_ref();
+    let file =
+        File::open(path).with_context(|| format!("failed to open snapshot {}", path.display()))?;
+    read_snapshot(engine, GzDecoder::new(BufReader::new(file)))
+}
+
+/// Reads the input checkpoint of a gzip-compressed snapshot.
+pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<Option<InputCheckpoint>> {
+    let path = path.as_ref();
+    let file =
+        File::open(path).with_context(|| format!("failed to open snapshot {}", path.display()))?;
+    read_checkpoint(GzDecoder::new(BufReader::new(file)))
+}
+
+/// Client balances at a point in time, used to report the accounts that changed since.
+pub struct BalanceDelta {
+    baseline: BTreeMap<(u16, Currency), Client>,
+}
+
+impl BalanceDelta {
+    pub fn new(engine: &Engine) -> io::Result<Self> {
+        Ok(BalanceDelta {
+            baseline: engine
+                .clients()
+                .map(|client| client.map(|client| ((client.id, client.currency), client)))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+
+    /// Iterates over accounts that are new or whose balances or lock changed since the baseline.
+    pub fn changed<'a>(
+        &'a self,
+        engine: &'a Engine,
+    ) -> impl Iterator<Item = io::Result<Client>> + 'a {
+        engine.clients().filter(|client| match client {
+            Ok(client) => self.baseline.get(&(client.id, client.currency)) != Some(client),
+            Err(_) => true,
+        })
+    }
+
+    /// Writes the changed accounts ordered by client id.
+    pub fn write_report(&self, engine: &Engine, writer: &mut dyn ReportWriter) -> Result<()> {
+        for client in self.changed(engine) {
+            writer.write_client(&engine.config().amounts.round_client(&client?))?;
+        }
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut engine = Engine::with_config(EngineConfig {
+            multi_currency: true,
+            ..EngineConfig::default()
+        });
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(105, 1)))
+            .unwrap();
+        engine
+            .execute_in(Transaction::Deposit(1, 2, Decimal::new(3, 0)), Some(eur))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(2, 3, Decimal::new(5, 0)))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Dispute(2, 3)).unwrap();
+        engine.execute(Transaction::Chargeback(2, 3)).unwrap();
+        engine
+            .execute_in(Transaction::WithdrawalHold(1, 4, Decimal::ONE), Some(eur))
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        write_snapshot(&engine, &mut buffer).unwrap();
+        let mut restored = Engine::with_config(engine.config().clone());
+        read_snapshot(&mut restored, buffer.as_slice()).unwrap();
+
+        assert!(
+            restored
+                .clients()
+                .map(Result::unwrap)
+                .eq(engine.clients().map(Result::unwrap))
+        );
+        assert_eq!(restored.transaction_currency(2), Some(eur));
+        // Dispute states survive: tx 1 can be resolved, tx 3 can't be disputed again
+        assert!(restored.execute(Transaction::Resolve(1, 1)).is_ok());
+        assert!(restored.execute(Transaction::Dispute(2, 3)).is_err());
+        // The withdrawal hold is still pending
+        assert!(
+            restored
+                .execute(Transaction::WithdrawalSettle(1, 4))
+                .is_ok()
+        );
+        assert!(
+            restored
+                .execute(Transaction::Deposit(1, 2, Decimal::ONE))
+                .is_err()
+        );
+        assert!(read_snapshot(&mut restored, buffer.as_slice()).is_err());
+        assert!(read_snapshot(&mut Engine::new(), "client,1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_ledger() {
+        let ledger_config = |name: &str| EngineConfig {
+            ledger: Some(name.parse().unwrap()),
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(ledger_config("staging"));
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::ONE))
+            .unwrap();
+        let mut buffer = Vec::new();
+        write_snapshot(&engine, &mut buffer).unwrap();
+        assert!(buffer.starts_with(b"snapshot,1,staging\n"));
+
+        let mut restored = Engine::with_config(ledger_config("staging"));
+        assert!(read_snapshot(&mut restored, buffer.as_slice()).is_ok());
+        let mut other = Engine::with_config(ledger_config("prod"));
+        assert!(read_snapshot(&mut other, buffer.as_slice()).is_err());
+        assert!(read_snapshot(&mut Engine::new(), buffer.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_balance_delta() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(2, 2, Decimal::new(10, 0)))
+            .unwrap();
+        let delta = BalanceDelta::new(&engine).unwrap();
+        engine
+            .execute(Transaction::Withdrawal(2, 3, Decimal::new(4, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(3, 4, Decimal::new(1, 0)))
+            .unwrap();
+        let changed: Vec<u16> = delta
+            .changed(&engine)
+            .map(|client| client.unwrap().id)
+            .collect();
+        assert_eq!(changed, vec![2, 3]);
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    sync::mpsc::{self, Receiver},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Result, anyhow};
+use csv::StringRecord;
+
+use crate::{
+    dialect::CsvDialect,
+    transaction::{Transaction, TransactionRow},
+};
+
+/// Raw input records in the CSV column order (`type, client, tx, amount[, timestamp[,
+/// currency[, total]]]`), e.g. of CSV or Parquet files.
+pub type Records = Box<dyn Iterator<Item = Result<StringRecord>> + Send>;
+
+/// Columns of the CSV input, the header of sources that have none of their own.
+pub const COLUMNS: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "currency",
+    "total",
+];
+
+#[derive(Debug)]
+pub enum SourceError {
+    /// A row that isn't a valid transaction, the source continues with the next one.
+    Invalid {
+        record: StringRecord,
+        reason: String,
+    },
+    /// The input can't be read any further.
+    Read(anyhow::Error),
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceError::Invalid { reason, .. } => write!(f, "Invalid transaction: {}", reason),
+            SourceError::Read(err) => write!(f, "Failed to read input: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// A stream of parsed transaction rows the engine is driven from, so the processing loop doesn't
+/// depend on the input format. See `Pipeline::process_source`. Sources are `Send` so they can be
+/// read ahead on a thread of their own, see `ReadAhead`.
+pub trait TransactionSource: Send {
+    /// The next row, `None` at the end of the input.
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>>;
+
+    /// Header of the input records, for the rejects file.
+    fn headers(&self) -> StringRecord {
+        StringRecord::from(&COLUMNS[..])
+    }
+
+    /// The input record of `row`, the row `next` returned last, for the rejects file and the
+    /// audit trail. Rebuilt from the row unless the source keeps its raw records.
+    fn record(&self, row: &TransactionRow) -> StringRecord {
+        row.to_record()
+    }
+}
+
+/// Rows of CSV-shaped records, from a CSV reader, stdin or any other record stream.
+pub struct CsvSource {
+    headers: StringRecord,
+    records: Records,
+    last: StringRecord,
+}
+
+impl CsvSource {
+    pub fn new(headers: StringRecord, records: Records) -> Self {
+        CsvSource {
+            headers,
+            records,
+            last: StringRecord::new(),
+        }
+    }
+
+    /// Reads CSV with a header from `reader`, in the default `CsvDialect`.
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Result<Self> {
+        let (headers, records) = CsvDialect::default().records(reader)?;
+        Ok(CsvSource::new(headers, records))
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn stdin() -> Result<Self> {
+        Self::from_reader(io::stdin())
+    }
+}
+
+impl TransactionSource for CsvSource {
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>> {
+        self.last = match self.records.next()? {
+            Ok(record) => record,
+            // A record the reader skipped over, see `CsvDialect::records`
+            Err(err) => {
+                return Some(Err(err
+                    .downcast::<SourceError>()
+                    .unwrap_or_else(SourceError::Read)));
+            }
+        };
+        Some(
+            self.last
+                .deserialize(None)
+                .map_err(|err| SourceError::Invalid {
+                    record: self.last.clone(),
+                    reason: err.to_string(),
+                }),
+        )
+    }
+
+    fn headers(&self) -> StringRecord {
+        self.headers.clone()
+    }
+
+    fn record(&self, _row: &TransactionRow) -> StringRecord {
+        self.last.clone()
+    }
+}
+
+/// Rows of JSON lines, one object per line with the columns of a CSV row like
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`. Blank lines are skipped.
+pub struct JsonLinesSource<R: BufRead> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> JsonLinesSource<R> {
+    pub fn new(reader: R) -> Self {
+        JsonLinesSource {
+            reader,
+            line: String::new(),
+        }
+    }
+}
+
+impl JsonLinesSource<BufReader<File>> {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead + Send> TransactionSource for JsonLinesSource<R> {
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => return Some(Err(SourceError::Read(err.into()))),
+            }
+            let line = self.line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str(line).map_err(|err| SourceError::Invalid {
+                    record: StringRecord::from(vec![line]),
+                    reason: err.to_string(),
+                }),
+            );
+        }
+    }
+}
+
+/// Rows held in memory, e.g. built by a test or another library.
+pub struct MemorySource {
+    rows: std::vec::IntoIter<TransactionRow>,
+}
+
+impl From<Vec<TransactionRow>> for MemorySource {
+    fn from(rows: Vec<TransactionRow>) -> Self {
+        MemorySource {
+            rows: rows.into_iter(),
+        }
+    }
+}
+
+impl From<Vec<Transaction>> for MemorySource {
+    /// Transactions in the default currency without timestamps.
+    fn from(transactions: Vec<Transaction>) -> Self {
+        transactions
+            .into_iter()
+            .map(|transaction| TransactionRow {
+                transaction,
+                timestamp: None,
+                currency: None,
+                dispute_amount: None,
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+impl TransactionSource for MemorySource {
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>> {
+        self.rows.next().map(Ok)
+    }
+}
+
+/// Sources read one after another, e.g. several input files. The header is the first source's.
+pub struct Chain {
+    headers: StringRecord,
+    sources: VecDeque<Box<dyn TransactionSource>>,
+}
+
+impl Chain {
+    pub fn new(sources: Vec<Box<dyn TransactionSource>>) -> Self {
+        let headers = match sources.first() {
+            Some(source) => source.headers(),
+            None => StringRecord::from(&COLUMNS[..]),
+        };
+        Chain {
+            headers,
+            sources: sources.into(),
+        }
+    }
+}
+
+impl TransactionSource for Chain {
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>> {
+        loop {
+            match self.sources.front_mut()?.next() {
+                Some(row) => return Some(row),
+                None => {
+                    // The last source is kept for `record` of its last row
+                    if self.sources.len() == 1 {
+                        return None;
+                    }
+                    self.sources.pop_front();
+                }
+            }
+        }
+    }
+
+    fn headers(&self) -> StringRecord {
+        self.headers.clone()
+    }
+
+    fn record(&self, row: &TransactionRow) -> StringRecord {
+        match self.sources.front() {
+            Some(source) => source.record(row),
+            None => row.to_record(),
+        }
+    }
+}
+
+/// A parsed row with the input record `TransactionSource::record` returns for it.
+type ReadRow = (Result<TransactionRow, SourceError>, StringRecord);
+
+/// Rows the reader thread of a `ReadAhead` hands over at once, so the channel isn't paid per row.
+const READ_AHEAD_CHUNK: usize = 256;
+
+/// Rows of another source, read and parsed on a thread of their own while the engine applies the
+/// previous ones. Rows are handed over in chunks of up to 256, at most `capacity` rows wait in
+/// between and the reader blocks while they are unconsumed. Reading stops early when the source
+/// is dropped.
+pub struct ReadAhead {
+    headers: StringRecord,
+    chunks: Receiver<Vec<ReadRow>>,
+    chunk: std::vec::IntoIter<ReadRow>,
+    reader: Option<JoinHandle<()>>,
+    last: StringRecord,
+}
+
+impl ReadAhead {
+    pub fn spawn(mut source: Box<dyn TransactionSource>, capacity: usize) -> Result<Self> {
+        let headers = source.headers();
+        let (sender, chunks) = mpsc::sync_channel(capacity.div_ceil(READ_AHEAD_CHUNK));
+        let reader = thread::Builder::new()
+            .name("reader".to_string())
+            .spawn(move || {
+                let mut chunk = Vec::with_capacity(READ_AHEAD_CHUNK);
+                while let Some(row) = source.next() {
+                    let record = match &row {
+                        Ok(row) => source.record(row),
+                        Err(_) => StringRecord::new(),
+                    };
+                    let unreadable = matches!(row, Err(SourceError::Read(_)));
+                    chunk.push((row, record));
+                    if unreadable {
+                        break;
+                    }
+                    if chunk.len() == READ_AHEAD_CHUNK {
+                        let full =
+                            std::mem::replace(&mut chunk, Vec::with_capacity(READ_AHEAD_CHUNK));
+                        if sender.send(full).is_err() {
+                            return;
+                        }
+                    }
+                }
+                let _ = sender.send(chunk);
+            })?;
+        Ok(ReadAhead {
+            headers,
+            chunks,
+            chunk: Vec::new().into_iter(),
+            reader: Some(reader),
+            last: StringRecord::new(),
+        })
+    }
+}
+
+impl TransactionSource for ReadAhead {
+    fn next(&mut self) -> Option<Result<TransactionRow, SourceError>> {
+        loop {
+            if let Some((row, record)) = self.chunk.next() {
+                self.last = record;
+                return Some(row);
+            }
+            match self.chunks.recv() {
+                Ok(chunk) => self.chunk = chunk.into_iter(),
+                // The reader is done, a panic must not pass for the end of the input
+                Err(_) => {
+                    return match self.reader.take()?.join() {
+                        Ok(()) => None,
+                        Err(_) => Some(Err(SourceError::Read(anyhow!(
+                            "input reader thread panicked"
+                        )))),
+                    };
+                }
+            }
+        }
+    }
+
+    fn headers(&self) -> StringRecord {
+        self.headers.clone()
+    }
+
+    fn record(&self, _row: &TransactionRow) -> StringRecord {
+        self.last.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+
+    use super::*;
+
+    fn rows(source: &mut dyn TransactionSource) -> Vec<Result<Transaction, String>> {
+        std::iter::from_fn(|| source.next())
+            .map(|row| {
+                row.map(|row| row.transaction)
+                    .map_err(|err| err.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transaction_sources() {
+        let deposit = Transaction::Deposit(1, 1, Decimal::new(15, 1));
+        let dispute = Transaction::Dispute(1, 1);
+
+        let mut csv = CsvSource::from_reader(
+            "type, client, tx, amount\ndeposit,1,1,1.5\nbogus,1,2,\n dispute , 1 ,\t1, \n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let parsed = rows(&mut csv);
+        assert_eq!(parsed[0], Ok(deposit.clone()));
+        assert!(parsed[1].is_err());
+        assert_eq!(parsed[2], Ok(dispute.clone()));
+        assert_eq!(csv.headers().len(), 4);
+
+        let mut json = JsonLinesSource::new(
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1.5\"}\n\n\
+             {\"type\": \"dispute\", \"client\": 1, \"tx\": 1}\n"
+                .as_bytes(),
+        );
+        assert_eq!(rows(&mut json), [Ok(deposit.clone()), Ok(dispute.clone())]);
+
+        let mut chain = Chain::new(vec![
+            Box::new(MemorySource::from(vec![deposit.clone()])),
+            Box::new(MemorySource::from(vec![dispute.clone()])),
+        ]);
+        assert_eq!(rows(&mut chain), [Ok(deposit.clone()), Ok(dispute)]);
+        let row = TransactionRow {
+            transaction: deposit,
+            timestamp: None,
+            currency: None,
+            dispute_amount: None,
+        };
+        assert_eq!(
+            chain.record(&row),
+            StringRecord::from(vec!["deposit", "1", "1", "1.5"])
+        );
+    }
+
+    #[test]
+    fn test_csv_source_invalid_utf8() {
+        let input = b"type,client,tx,amount\ndep\xffosit,1,1,1.5\ndispute,1,1\n".to_vec();
+        let mut csv = CsvSource::from_reader(std::io::Cursor::new(input)).unwrap();
+        match csv.next() {
+            Some(Err(SourceError::Invalid { record, reason })) => {
+                assert_eq!(
+                    record,
+                    StringRecord::from(vec!["dep\u{fffd}osit", "1", "1", "1.5"])
+                );
+                assert!(reason.contains("invalid utf-8"), "{}", reason);
+            }
+            other => panic!("expected an invalid row, got {:?}", other),
+        }
+        // The rows after it are still read
+        assert_eq!(
+            csv.next().unwrap().unwrap().transaction,
+            Transaction::Dispute(1, 1)
+        );
+        assert!(csv.next().is_none());
+    }
+
+    #[test]
+    fn test_read_ahead() {
+        let csv = CsvSource::from_reader(
+            "type,client,tx,amount\ndeposit,1,1,1.5\nbogus,1,2,\nwithdrawal,1,3,0.5\n".as_bytes(),
+        )
+        .unwrap();
+        let mut source = ReadAhead::spawn(Box::new(csv), 1).unwrap();
+        assert_eq!(source.headers().len(), 4);
+        let deposit = source.next().unwrap().unwrap();
+        assert_eq!(
+            deposit.transaction,
+            Transaction::Deposit(1, 1, Decimal::new(15, 1))
+        );
+        assert!(matches!(
+            source.next(),
+            Some(Err(SourceError::Invalid { .. }))
+        ));
+        let withdrawal = source.next().unwrap().unwrap();
+        assert_eq!(
+            source.record(&withdrawal),
+            StringRecord::from(vec!["withdrawal", "1", "3", "0.5"])
+        );
+        assert!(source.next().is_none());
+        assert!(source.next().is_none());
+    }
+}
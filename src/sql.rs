@@ -0,0 +1,244 @@
+use std::{io, path::Path};
+
+use crate::decimal::ToPrimitive;
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, params, types::ValueRef};
+
+use crate::{dispute::DisputeState, engine::Engine};
+
+const SCHEMA: &str = "
+    CREATE TABLE clients (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        available REAL NOT NULL,
+        held REAL NOT NULL,
+        total REAL NOT NULL,
+        locked INTEGER NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE transactions (
+        tx INTEGER PRIMARY KEY,
+        type TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        amount REAL,
+        currency TEXT NOT NULL
+    );
+    CREATE TABLE disputes (
+        tx INTEGER PRIMARY KEY,
+        client INTEGER NOT NULL,
+        amount REAL,
+        currency TEXT NOT NULL,
+        status TEXT NOT NULL
+    );
+";
+
+/// Read-only snapshot of the engine state in an in-memory SQLite database for ad-hoc queries.
+///
+/// Tables:
+///
+/// * `clients(client, currency, available, held, total, locked)`
+/// * `transactions(tx, type, client, amount, currency)` with the logged deposits and withdrawals
+/// * `disputes(tx, client, amount, currency, status)` with status `open`, `resolved`,
+///   `charged_back` or `reversed`
+/// * `client_metadata` with the columns of the client metadata CSV, if loaded
+///
+/// Amounts are stored as SQLite reals, so aggregates are floating point. The client report stays
+/// the source of exact balances. Archived transactions are not included.
+pub struct SqlView {
+    connection: Connection,
+}
+
+impl SqlView {
+    pub fn from_engine(engine: &Engine) -> Result<Self> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.execute_batch(SCHEMA)?;
+        let tx = connection.transaction()?;
+        {
+            let mut insert = tx.prepare("INSERT INTO clients VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for client in engine.clients() {
+                let client = client?;
+                insert.execute(params![
+                    client.id,
+                    client.currency.as_str(),
+                    client.available.to_f64(),
+                    client.held.to_f64(),
+                    client.total.to_f64(),
+                    client.locked,
+                ])?;
+            }
+            let mut insert = tx.prepare("INSERT INTO transactions VALUES (?1, ?2, ?3, ?4, ?5)")?;
+            let mut insert_dispute =
+                tx.prepare("INSERT INTO disputes VALUES (?1, ?2, ?3, ?4, ?5)")?;
+            for entry in engine.transactions() {
+                let (transaction, currency) = entry?;
+                let tx_id = transaction.tx_id();
+                let amount = transaction.amount().and_then(|amount| amount.to_f64());
+                insert.execute(params![
+                    tx_id,
+                    transaction.transaction_type().to_string(),
+                    transaction.client_id(),
+                    amount,
+                    currency.as_str(),
+                ])?;
+                let status = match engine.dispute_state(tx_id) {
+                    DisputeState::None => continue,
+                    state => state.to_string(),
+                };
+                insert_dispute.execute(params![
+                    tx_id,
+                    transaction.client_id(),
+                    amount,
+                    currency.as_str(),
+                    status,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(SqlView { connection })
+    }
+
+    /// Loads a client metadata CSV with a header into the `client_metadata` table. The `client`
+    /// column is stored as an integer so it joins with the other tables, all others as text.
+    pub fn load_client_metadata<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers: Vec<String> = reader
+            .headers()?
+            .iter()
+            .map(|header| header.trim().to_string())
+            .collect();
+        if !headers.iter().any(|header| header == "client") {
+            bail!("client metadata has no `client` column");
+        }
+        let columns: Vec<String> = headers
+            .iter()
+            .map(|header| match header.as_str() {
+                "client" => "\"client\" INTEGER".to_string(),
+                name => format!("\"{}\" TEXT", name.replace('"', "\"\"")),
+            })
+            .collect();
+        let placeholders = vec!["?"; headers.len()].join(", ");
+        let tx = self.connection.transaction()?;
+        tx.execute_batch(&format!(
+            "CREATE TABLE client_metadata ({});",
+            columns.join(", ")
+        ))?;
+        {
+            let mut insert = tx.prepare(&format!(
+                "INSERT INTO client_metadata VALUES ({})",
+                placeholders
+            ))?;
+            for rec in reader.records() {
+                let record = rec?;
+                let values: Vec<&str> = record.iter().map(str::trim).collect();
+                insert
+                    .execute(rusqlite::params_from_iter(values))
+                    .with_context(|| format!("invalid client metadata record {:?}", record))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Runs a query and writes the result as CSV with the column names as header.
+    pub fn query<W: io::Write>(&self, sql: &str, writer: W) -> Result<()> {
+        let mut statement = self.connection.prepare(sql)?;
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(statement.column_names())?;
+        let columns = statement.column_count();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let mut record = Vec::with_capacity(columns);
+            for idx in 0..columns {
+                record.push(match row.get_ref(idx)? {
+                    ValueRef::Null => String::new(),
+                    ValueRef::Integer(value) => value.to_string(),
+                    ValueRef::Real(value) => value.to_string(),
+                    ValueRef::Text(value) | ValueRef::Blob(value) => {
+                        String::from_utf8_lossy(value).into_owned()
+                    }
+                });
+            }
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+
+    use super::*;
+    use crate::transaction::Transaction;
+
+    fn query(view: &SqlView, sql: &str) -> String {
+        let mut output = Vec::new();
+        view.query(sql, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_engine_tables() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(2, 2, Decimal::new(5, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(2, 3, Decimal::new(3, 0)))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Dispute(2, 2)).unwrap();
+        engine.execute(Transaction::Chargeback(2, 2)).unwrap();
+
+        let view = SqlView::from_engine(&engine).unwrap();
+        assert_eq!(
+            query(
+                &view,
+                "SELECT client, held, locked FROM clients ORDER BY client"
+            ),
+            "client,held,locked\n1,10,0\n2,0,1\n"
+        );
+        assert_eq!(
+            query(&view, "SELECT tx, status FROM disputes ORDER BY tx"),
+            "tx,status\n1,open\n2,charged_back\n"
+        );
+        assert_eq!(
+            query(
+                &view,
+                "SELECT count(*) AS n, sum(amount) AS s FROM transactions"
+            ),
+            "n,s\n3,18\n"
+        );
+        let mut output = Vec::new();
+        assert!(view.query("SELECT * FROM missing", &mut output).is_err());
+    }
+
+    #[test]
+    fn test_client_metadata_join() {
+        let path = std::env::temp_dir().join(format!("sql-metadata-{}.csv", std::process::id()));
+        std::fs::write(&path, "client,country\n1,DE\n2,FR\n3,DE\n").unwrap();
+        let mut engine = Engine::new();
+        for (client_id, tx_id) in [(1, 1), (2, 2), (3, 3)] {
+            engine
+                .execute(Transaction::Deposit(client_id, tx_id, Decimal::new(2, 0)))
+                .unwrap();
+        }
+        engine.execute(Transaction::Dispute(3, 3)).unwrap();
+
+        let mut view = SqlView::from_engine(&engine).unwrap();
+        view.load_client_metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            query(
+                &view,
+                "SELECT m.country, sum(c.held) AS held FROM clients c \
+                 JOIN client_metadata m USING (client) GROUP BY m.country ORDER BY m.country"
+            ),
+            "country,held\nDE,2\nFR,0\n"
+        );
+    }
+}
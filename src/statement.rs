@@ -0,0 +1,238 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{
+    client::Client,
+    decimal::Decimal,
+    engine::Engine,
+    events::{Event, EventKind},
+    transaction::Transaction,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum StatementFormat {
+    #[default]
+    Csv,
+    /// Standalone HTML page with print styles, for saving as PDF from a browser
+    Html,
+}
+
+/// One applied transaction on a statement with the balances it left behind.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementEntry {
+    pub transaction: Transaction,
+    /// Amount moved: the transaction amount, or the held amount for dispute operations.
+    pub amount: Decimal,
+    pub balance: Client,
+}
+
+/// Account statement of one client: the balances at the start, every applied transaction with
+/// the running balance of its account, and the balances at the end.
+pub struct Statement {
+    client_id: u16,
+    opening: Vec<Client>,
+    entries: Vec<StatementEntry>,
+}
+
+impl Statement {
+    /// Registers an event sink that records the client's transactions from now on. The client's
+    /// current accounts, e.g. restored from a snapshot, are the opening balances.
+    pub fn track(engine: &mut Engine, client_id: u16) -> io::Result<Arc<Mutex<Statement>>> {
+        let statement = Arc::new(Mutex::new(Statement {
+            client_id,
+            opening: engine
+                .client_accounts(client_id)
+                .collect::<io::Result<_>>()?,
+            entries: Vec::new(),
+        }));
+        let sink = Arc::clone(&statement);
+        engine.add_event_sink(Box::new(move |event: &Event| {
+            sink.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .record(event)
+        }));
+        Ok(statement)
+    }
+
+    fn record(&mut self, event: &Event) {
+        // The lock shows in the balance of the chargeback that caused it
+        if event.after.id != self.client_id || event.kind == EventKind::AccountLocked {
+            return;
+        }
+        let amount = event.transaction.amount().unwrap_or_else(|| {
+            let held = event.after.held.checked_sub(event.before.held);
+            held.map(|held| held.abs()).unwrap_or_default()
+        });
+        self.entries.push(StatementEntry {
+            transaction: event.transaction.clone(),
+            amount,
+            balance: event.after.clone(),
+        });
+    }
+
+    pub fn entries(&self) -> &[StatementEntry] {
+        &self.entries
+    }
+
+    /// Writes the statement with the client's accounts in `engine` as closing balances.
+    pub fn write<W: Write>(
+        &self,
+        engine: &Engine,
+        format: StatementFormat,
+        writer: W,
+    ) -> Result<()> {
+        let mut opening = self.opening.clone();
+        let closing: Vec<Client> = engine
+            .client_accounts(self.client_id)
+            .collect::<io::Result<_>>()?;
+        // Accounts opened during the period start from zero
+        for account in &closing {
+            if !opening.iter().any(|open| open.currency == account.currency) {
+                opening.push(Client::with_currency(account.id, account.currency));
+            }
+        }
+        opening.sort_by_key(|account| account.currency);
+        let mut rows = Vec::with_capacity(opening.len() + self.entries.len() + closing.len());
+        rows.extend(
+            opening
+                .iter()
+                .map(|account| row("opening", "", None, account)),
+        );
+        rows.extend(self.entries.iter().map(|entry| {
+            row(
+                &entry.transaction.transaction_type().to_string(),
+                &entry.transaction.tx_id().to_string(),
+                Some(entry.amount),
+                &entry.balance,
+            )
+        }));
+        rows.extend(
+            closing
+                .iter()
+                .map(|account| row("closing", "", None, account)),
+        );
+        match format {
+            StatementFormat::Csv => write_csv(&rows, writer),
+            StatementFormat::Html => write_html(self.client_id, &rows, writer),
+        }
+    }
+}
+
+const COLUMNS: [&str; 8] = [
+    "entry",
+    "tx",
+    "currency",
+    "amount",
+    "available",
+    "held",
+    "total",
+    "locked",
+];
+
+fn row(entry: &str, tx: &str, amount: Option<Decimal>, balance: &Client) -> [String; 8] {
+    [
+        entry.to_string(),
+        tx.to_string(),
+        balance.currency.to_string(),
+        amount.map(|amount| amount.to_string()).unwrap_or_default(),
+        balance.available.to_string(),
+        balance.held.to_string(),
+        balance.total.to_string(),
+        balance.locked.to_string(),
+    ]
+}
+
+fn write_csv<W: Write>(rows: &[[String; 8]], writer: W) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(COLUMNS)?;
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_html<W: Write>(client_id: u16, rows: &[[String; 8]], mut writer: W) -> Result<()> {
+    // Cells hold numbers, currency codes and fixed words only, nothing to escape
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(
+        writer,
+        "<html><head><meta charset=\"utf-8\"><title>Statement of client {}</title>",
+        client_id
+    )?;
+    writeln!(
+        writer,
+        "<style>body{{font-family:sans-serif}}table{{border-collapse:collapse;width:100%}}\
+         th,td{{border:1px solid #999;padding:2px 6px}}td:nth-child(n+4){{text-align:right}}\
+         tr{{page-break-inside:avoid}}thead{{display:table-header-group}}</style></head><body>"
+    )?;
+    writeln!(writer, "<h1>Statement of client {}</h1>", client_id)?;
+    writeln!(writer, "<table><thead><tr>")?;
+    for column in COLUMNS {
+        write!(writer, "<th>{}</th>", column)?;
+    }
+    writeln!(writer, "</tr></thead><tbody>")?;
+    for row in rows {
+        write!(writer, "<tr>")?;
+        for cell in row {
+            write!(writer, "<td>{}</td>", cell)?;
+        }
+        writeln!(writer, "</tr>")?;
+    }
+    writeln!(writer, "</tbody></table></body></html>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statement() {
+        let mut engine = Engine::new();
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(10, 0)))
+            .unwrap();
+        let statement = Statement::track(&mut engine, 1).unwrap();
+        for transaction in [
+            Transaction::Deposit(1, 2, Decimal::new(5, 0)),
+            Transaction::Deposit(2, 3, Decimal::new(7, 0)),
+            Transaction::Withdrawal(1, 4, Decimal::new(3, 0)),
+            Transaction::Dispute(1, 2),
+            Transaction::Chargeback(1, 2),
+        ] {
+            engine.execute(transaction).unwrap();
+        }
+        let mut output = Vec::new();
+        statement
+            .lock()
+            .unwrap()
+            .write(&engine, StatementFormat::Csv, &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "entry,tx,currency,amount,available,held,total,locked\n\
+             opening,,USD,,10,0,10,false\n\
+             deposit,2,USD,5,15,0,15,false\n\
+             withdrawal,4,USD,3,12,0,12,false\n\
+             dispute,2,USD,5,7,5,12,false\n\
+             chargeback,2,USD,5,7,0,7,true\n\
+             closing,,USD,,7,0,7,true\n"
+        );
+
+        let mut html = Vec::new();
+        statement
+            .lock()
+            .unwrap()
+            .write(&engine, StatementFormat::Html, &mut html)
+            .unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<td>chargeback</td><td>2</td><td>USD</td><td>5</td>"));
+    }
+}
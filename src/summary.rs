@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, io::Write, time::Duration};
+
+use anyhow::Result;
+use serde_json::json;
+
+use crate::transaction::TransactionType;
+
+/// Exit status of a run whose rows were all applied.
+pub const EXIT_CLEAN: i32 = 0;
+/// Exit status of a run that completed with rejected rows.
+pub const EXIT_PARTIAL: i32 = 2;
+
+/// Counts of a run for orchestration: the parsed rows by transaction type, the rejected rows by
+/// error kind, the duration and the throughput.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunSummary {
+    rows: BTreeMap<TransactionType, u64>,
+    rejects: BTreeMap<&'static str, u64>,
+    duration: Duration,
+}
+
+impl RunSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accounts a parsed row, whether it is applied or rejected.
+    pub fn record(&mut self, transaction_type: TransactionType) {
+        *self.rows.entry(transaction_type).or_default() += 1;
+    }
+
+    /// Accounts a rejected row by the kind of its error, `invalid` for rows that didn't parse.
+    pub fn reject(&mut self, kind: &'static str) {
+        *self.rejects.entry(kind).or_default() += 1;
+    }
+
+    /// Sets the time the run took.
+    pub fn finish(&mut self, duration: Duration) {
+        self.duration = duration;
+    }
+
+    /// Number of parsed rows.
+    pub fn processed(&self) -> u64 {
+        self.rows.values().sum()
+    }
+
+    /// Number of rows rejected by the parser or the engine.
+    pub fn rejected(&self) -> u64 {
+        self.rejects.values().sum()
+    }
+
+    /// `EXIT_PARTIAL` if any row was rejected, `EXIT_CLEAN` otherwise.
+    pub fn exit_code(&self) -> i32 {
+        if self.rejected() > 0 {
+            EXIT_PARTIAL
+        } else {
+            EXIT_CLEAN
+        }
+    }
+
+    /// Writes the summary as a single line JSON object.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        let seconds = self.duration.as_secs_f64();
+        let throughput = if seconds > 0.0 {
+            self.processed() as f64 / seconds
+        } else {
+            0.0
+        };
+        let rows: BTreeMap<String, u64> = self
+            .rows
+            .iter()
+            .map(|(transaction_type, count)| (transaction_type.to_string(), *count))
+            .collect();
+        let summary = json!({
+            "status": if self.rejected() > 0 { "partial" } else { "clean" },
+            "processed": self.processed(),
+            "rejected": self.rejected(),
+            "transactions": rows,
+            "rejects": self.rejects,
+            "duration_secs": seconds,
+            "rows_per_sec": throughput.round(),
+        });
+        serde_json::to_writer(&mut writer, &summary)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_summary() {
+        let mut summary = RunSummary::new();
+        assert_eq!(summary.exit_code(), EXIT_CLEAN);
+        summary.record(TransactionType::Deposit);
+        summary.record(TransactionType::Deposit);
+        summary.record(TransactionType::Withdrawal);
+        summary.reject("insufficient_funds");
+        summary.reject("invalid");
+        summary.finish(Duration::from_secs(2));
+        assert_eq!(summary.processed(), 3);
+        assert_eq!(summary.rejected(), 2);
+        assert_eq!(summary.exit_code(), EXIT_PARTIAL);
+
+        let mut output = Vec::new();
+        summary.write_json(&mut output).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "status": "partial",
+                "processed": 3,
+                "rejected": 2,
+                "transactions": {"deposit": 2, "withdrawal": 1},
+                "rejects": {"insufficient_funds": 1, "invalid": 1},
+                "duration_secs": 2.0,
+                "rows_per_sec": 2.0,
+            })
+        );
+    }
+}
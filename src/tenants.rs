@@ -0,0 +1,254 @@
+use std::{collections::BTreeSet, fmt::Display, io, io::Write};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{engine::Engine, export::ImportError};
+
+/// What a merge does with a client id that has accounts on both sides with different histories.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum CollisionPolicy {
+    /// Fail the merge, leaving the target unchanged
+    #[default]
+    Reject,
+    /// Import the source client under the lowest client id free on both sides
+    Remap,
+}
+
+/// How a colliding client id was handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollisionOutcome {
+    /// Both sides have the same accounts and transactions, the client was not imported again.
+    Identical,
+    /// The source client was imported under another id.
+    Remapped,
+}
+
+impl Display for CollisionOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollisionOutcome::Identical => write!(f, "identical"),
+            CollisionOutcome::Remapped => write!(f, "remapped"),
+        }
+    }
+}
+
+/// A source client id that already had accounts in the target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Collision {
+    pub source_client: u16,
+    /// Id of the client in the merged engine.
+    pub target_client: u16,
+    pub outcome: CollisionOutcome,
+}
+
+/// Result of a merge: the number of imported clients and the collisions it ran into.
+#[derive(Debug, Default, PartialEq)]
+pub struct MergeReport {
+    pub imported: usize,
+    pub collisions: Vec<Collision>,
+}
+
+impl MergeReport {
+    /// Writes the collisions as CSV with the columns `source_client,target_client,outcome`.
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["source_client", "target_client", "outcome"])?;
+        for collision in &self.collisions {
+            writer.write_record([
+                collision.source_client.to_string(),
+                collision.target_client.to_string(),
+                collision.outcome.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum MergeError {
+    /// The client has different histories on both sides and collisions are rejected.
+    Collision(u16),
+    /// Every client id is taken on one side or the other.
+    NoFreeClientId,
+    /// The target can't take a source client, e.g. a transaction id is taken on both sides.
+    Import(u16, ImportError),
+    Io(io::Error),
+}
+
+impl Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::Collision(client_id) => write!(
+                f,
+                "Client {} has different histories in both engines",
+                client_id
+            ),
+            MergeError::NoFreeClientId => write!(f, "No free client id to remap to"),
+            MergeError::Import(client_id, err) => {
+                write!(f, "Failed to merge client {}: {}", client_id, err)
+            }
+            MergeError::Io(err) => write!(f, "Failed to read transactions: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl From<io::Error> for MergeError {
+    fn from(err: io::Error) -> Self {
+        MergeError::Io(err)
+    }
+}
+
+/// Merges the clients of `source`, e.g. another tenant's engine, into `target` with their
+/// transactions and dispute states. A client id with accounts on both sides is never summed: an
+/// identical client is skipped, a different one is handled by `policy`. All clients are checked
+/// before the first is imported, so a failed merge leaves `target` unchanged.
+pub fn merge_engines(
+    target: &mut Engine,
+    source: &Engine,
+    policy: CollisionPolicy,
+) -> Result<MergeReport, MergeError> {
+    let client_ids = |engine: &Engine| {
+        engine
+            .clients()
+            .map(|client| client.map(|client| client.id))
+            .collect::<io::Result<BTreeSet<u16>>>()
+    };
+    let source_ids = client_ids(source)?;
+    let mut taken = client_ids(target)?;
+    taken.extend(&source_ids);
+    let mut report = MergeReport::default();
+    let mut imports = Vec::new();
+    for &client_id in &source_ids {
+        let Some(export) = source.export_client(client_id)? else {
+            continue;
+        };
+        let Some(existing) = target.export_client(client_id)? else {
+            imports.push(export);
+            continue;
+        };
+        if existing == export {
+            report.collisions.push(Collision {
+                source_client: client_id,
+                target_client: client_id,
+                outcome: CollisionOutcome::Identical,
+            });
+            continue;
+        }
+        if policy == CollisionPolicy::Reject {
+            return Err(MergeError::Collision(client_id));
+        }
+        let free = (0..=u16::MAX)
+            .find(|id| !taken.contains(id))
+            .ok_or(MergeError::NoFreeClientId)?;
+        taken.insert(free);
+        report.collisions.push(Collision {
+            source_client: client_id,
+            target_client: free,
+            outcome: CollisionOutcome::Remapped,
+        });
+        imports.push(export.with_client_id(free));
+    }
+    for export in &imports {
+        target
+            .check_import(export)
+            .map_err(|err| MergeError::Import(export.client_id, err))?;
+    }
+    for export in &imports {
+        target
+            .import_client(export)
+            .map_err(|err| MergeError::Import(export.client_id, err))?;
+    }
+    report.imported = imports.len();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{decimal::Decimal, engine::ExecutionError, transaction::Transaction};
+
+    use super::*;
+
+    fn engine(transactions: &[Transaction]) -> Engine {
+        let mut engine = Engine::new();
+        for transaction in transactions {
+            engine.execute(transaction.clone()).unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn test_merge_remaps_colliding_clients() {
+        let shared = Transaction::Deposit(1, 1, Decimal::new(10, 0));
+        let mut target = engine(&[shared.clone(), Transaction::Deposit(2, 2, Decimal::ONE)]);
+        let source = engine(&[
+            shared,
+            Transaction::Deposit(2, 3, Decimal::new(5, 0)),
+            Transaction::Dispute(2, 3),
+            Transaction::Deposit(4, 4, Decimal::TWO),
+        ]);
+        let report = merge_engines(&mut target, &source, CollisionPolicy::Remap).unwrap();
+        assert_eq!(
+            report,
+            MergeReport {
+                imported: 2,
+                collisions: vec![
+                    Collision {
+                        source_client: 1,
+                        target_client: 1,
+                        outcome: CollisionOutcome::Identical,
+                    },
+                    Collision {
+                        source_client: 2,
+                        target_client: 0,
+                        outcome: CollisionOutcome::Remapped,
+                    },
+                ],
+            }
+        );
+        // Nothing was summed: client 2 kept its own balance, the source's moved to client 0
+        assert_eq!(target.client(1).unwrap().total, Decimal::new(10, 0));
+        assert_eq!(target.client(2).unwrap().total, Decimal::ONE);
+        assert_eq!(target.client(0).unwrap().held, Decimal::new(5, 0));
+        assert_eq!(target.client(4).unwrap().total, Decimal::TWO);
+        // The remapped client's open dispute came along under its new id
+        assert!(target.execute(Transaction::Resolve(0, 3)).is_ok());
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "source_client,target_client,outcome\n1,1,identical\n2,0,remapped\n"
+        );
+    }
+
+    #[test]
+    fn test_failed_merge_leaves_target_unchanged() {
+        let mut target = engine(&[Transaction::Deposit(1, 1, Decimal::ONE)]);
+        let source = engine(&[
+            Transaction::Deposit(1, 2, Decimal::ONE),
+            Transaction::Deposit(3, 3, Decimal::ONE),
+        ]);
+        assert!(matches!(
+            merge_engines(&mut target, &source, CollisionPolicy::Reject),
+            Err(MergeError::Collision(1))
+        ));
+
+        // Client 3 is free, but its transaction id is taken in the target
+        let source = engine(&[
+            Transaction::Deposit(3, 3, Decimal::ONE),
+            Transaction::Deposit(5, 1, Decimal::ONE),
+        ]);
+        assert!(matches!(
+            merge_engines(&mut target, &source, CollisionPolicy::Remap),
+            Err(MergeError::Import(
+                5,
+                ImportError::Rejected(ExecutionError::DuplicateTransaction)
+            ))
+        ));
+        assert_eq!(target.clients().count(), 1);
+    }
+}
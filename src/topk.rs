@@ -0,0 +1,220 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::decimal::Decimal;
+
+use crate::{client::Client, currency::Currency, engine::Engine, registry};
+
+/// How many candidates are tracked per reported entry. Extra candidates keep the result
+/// accurate when tracked values shrink while untracked clients stay idle.
+const CANDIDATE_SLACK: usize = 4;
+
+/// Approximate streaming top-K of client values.
+///
+/// Only a bounded set of candidates is kept. A client enters the set when it is updated with a
+/// value above the current minimum, so clients evicted earlier are reconsidered on their next update.
+pub struct TopK {
+    k: usize,
+    capacity: usize,
+    candidates: BTreeMap<u16, Decimal>,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> Self {
+        TopK {
+            k,
+            capacity: k.saturating_mul(CANDIDATE_SLACK),
+            candidates: BTreeMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, client_id: u16, value: Decimal) {
+        if value <= Decimal::ZERO {
+            self.candidates.remove(&client_id);
+            return;
+        }
+        if let Some(current) = self.candidates.get_mut(&client_id) {
+            *current = value;
+            return;
+        }
+        if self.candidates.len() < self.capacity {
+            self.candidates.insert(client_id, value);
+            return;
+        }
+        let min = self
+            .candidates
+            .iter()
+            .min_by_key(|(_, value)| **value)
+            .map(|(client_id, value)| (*client_id, *value));
+        if let Some((min_client_id, min_value)) = min
+            && value > min_value
+        {
+            self.candidates.remove(&min_client_id);
+            self.candidates.insert(client_id, value);
+        }
+    }
+
+    /// Returns up to `k` clients ordered by value, highest first.
+    pub fn top(&self) -> Vec<(u16, Decimal)> {
+        let mut top: Vec<(u16, Decimal)> = self
+            .candidates
+            .iter()
+            .map(|(client_id, value)| (*client_id, *value))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(self.k);
+        top
+    }
+}
+
+/// Biggest exposures in one currency: clients by total balance and by disputed (held) amount.
+pub struct CurrencyExposures {
+    pub by_total: TopK,
+    pub by_disputed: TopK,
+}
+
+/// Biggest exposures seen during processing, ranked per currency since amounts in different
+/// currencies don't compare.
+pub struct Exposures {
+    k: usize,
+    currencies: BTreeMap<Currency, CurrencyExposures>,
+}
+
+impl Exposures {
+    pub fn new(k: usize) -> Self {
+        Exposures {
+            k,
+            currencies: BTreeMap::new(),
+        }
+    }
+
+    /// Takes the balances of an account into the ranking of its currency.
+    pub fn update(&mut self, client: &Client) {
+        let k = self.k;
+        let exposures =
+            self.currencies
+                .entry(client.currency)
+                .or_insert_with(|| CurrencyExposures {
+                    by_total: TopK::new(k),
+                    by_disputed: TopK::new(k),
+                });
+        exposures.by_total.update(client.id, client.total);
+        exposures.by_disputed.update(client.id, client.held);
+    }
+
+    /// The exposures of every currency that had an account, in currency order.
+    pub fn by_currency(&self) -> impl Iterator<Item = (Currency, &CurrencyExposures)> {
+        self.currencies
+            .iter()
+            .map(|(currency, exposures)| (*currency, exposures))
+    }
+
+    /// Registers engine hooks that keep the returned exposures up to date.
+    pub fn track(engine: &mut Engine, k: usize) -> Arc<Mutex<Exposures>> {
+        let exposures = Arc::new(Mutex::new(Exposures::new(k)));
+        for transaction_type in registry::types() {
+            let exposures = Arc::clone(&exposures);
+            engine.register_hook(
+                transaction_type,
+                Box::new(move |_, client| {
+                    exposures
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .update(client);
+                }),
+            );
+        }
+        exposures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::EngineConfig, transaction::Transaction};
+
+    #[test]
+    fn test_top_k_orders_and_truncates() {
+        let mut top_k = TopK::new(2);
+        top_k.update(1, Decimal::new(10, 0));
+        top_k.update(2, Decimal::new(30, 0));
+        top_k.update(3, Decimal::new(20, 0));
+        assert_eq!(
+            top_k.top(),
+            vec![(2, Decimal::new(30, 0)), (3, Decimal::new(20, 0))]
+        );
+        top_k.update(2, Decimal::ZERO);
+        assert_eq!(
+            top_k.top(),
+            vec![(3, Decimal::new(20, 0)), (1, Decimal::new(10, 0))]
+        );
+    }
+
+    #[test]
+    fn test_top_k_evicts_smallest_candidate() {
+        let mut top_k = TopK::new(1);
+        for client_id in 1..=4 {
+            top_k.update(client_id, Decimal::new(client_id as i64, 0));
+        }
+        top_k.update(5, Decimal::new(100, 0));
+        assert_eq!(top_k.candidates.len(), 4);
+        assert!(!top_k.candidates.contains_key(&1));
+        assert_eq!(top_k.top(), vec![(5, Decimal::new(100, 0))]);
+    }
+
+    #[test]
+    fn test_exposures_track_engine() {
+        let mut engine = Engine::new();
+        let exposures = Exposures::track(&mut engine, 10);
+        assert!(
+            engine
+                .execute(Transaction::Deposit(1, 1, Decimal::new(5, 0)))
+                .is_ok()
+        );
+        assert!(
+            engine
+                .execute(Transaction::Deposit(2, 2, Decimal::new(7, 0)))
+                .is_ok()
+        );
+        assert!(engine.execute(Transaction::Dispute(1, 1)).is_ok());
+        let exposures = exposures.lock().unwrap();
+        let (currency, usd) = exposures.by_currency().next().unwrap();
+        assert_eq!(currency, Currency::USD);
+        assert_eq!(
+            usd.by_total.top(),
+            vec![(2, Decimal::new(7, 0)), (1, Decimal::new(5, 0))]
+        );
+        assert_eq!(usd.by_disputed.top(), vec![(1, Decimal::new(5, 0))]);
+    }
+
+    #[test]
+    fn test_exposures_per_currency() {
+        let mut engine = Engine::with_config(EngineConfig {
+            multi_currency: true,
+            ..Default::default()
+        });
+        let exposures = Exposures::track(&mut engine, 10);
+        let eur = "EUR".parse().unwrap();
+        for (client_id, tx_id, amount, currency) in
+            [(1, 1, 5, Currency::USD), (1, 2, 9, eur), (2, 3, 7, eur)]
+        {
+            let deposit = Transaction::Deposit(client_id, tx_id, Decimal::new(amount, 0));
+            assert!(engine.execute_in(deposit, Some(currency)).is_ok());
+        }
+        let exposures = exposures.lock().unwrap();
+        let tops: Vec<_> = exposures
+            .by_currency()
+            .map(|(currency, exposures)| (currency, exposures.by_total.top()))
+            .collect();
+        // The accounts of a client rank in their own currency only
+        assert_eq!(
+            tops,
+            vec![
+                (eur, vec![(1, Decimal::new(9, 0)), (2, Decimal::new(7, 0))]),
+                (Currency::USD, vec![(1, Decimal::new(5, 0))]),
+            ]
+        );
+    }
+}
@@ -1,8 +1,14 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-use rust_decimal::Decimal;
+use crate::decimal::Decimal;
+use csv::StringRecord;
 use serde::Deserialize;
 
+use crate::{
+    currency::Currency,
+    registry::{self, CustomType},
+};
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Transaction {
     Deposit(u16, u32, Decimal),
@@ -10,6 +16,118 @@ pub enum Transaction {
     Dispute(u16, u32),
     Resolve(u16, u32),
     Chargeback(u16, u32),
+    /// Administrative: reopens a locked account.
+    Unlock(u16, u32),
+    /// Administrative: manual correction crediting the available funds.
+    AdjustCredit(u16, u32, Decimal),
+    /// Administrative: manual correction debiting the available funds.
+    AdjustDebit(u16, u32, Decimal),
+    /// Reconciliation checkpoint: expected available and, if given, total funds of the client.
+    /// Moves no money.
+    AssertBalance(u16, u32, Decimal, Option<Decimal>),
+    /// First phase of a two-phase withdrawal: reserves the amount in the held funds.
+    WithdrawalHold(u16, u32, Decimal),
+    /// Debits the held funds of the withdrawal hold with the given id.
+    WithdrawalSettle(u16, u32),
+    /// Releases the held funds of the withdrawal hold with the given id back to available.
+    WithdrawalCancel(u16, u32),
+    /// Re-credits the amount of the charged-back deposit with the given id, after the merchant
+    /// won the representment.
+    ChargebackReversal(u16, u32),
+    /// Administrative: sets how far withdrawals may take the client's available funds below zero,
+    /// zero revokes the overdraft.
+    SetOverdraft(u16, u32, Decimal),
+    /// Administrative: closes all accounts of the client for good, see `ClosePolicy`.
+    CloseAccount(u16, u32),
+    /// Merchant-initiated return of part or all of the deposit with the given id, debiting the
+    /// available funds.
+    Refund(u16, u32, Decimal),
+    /// A type of the registry, applied by its `registry::TransactionHandler`.
+    Custom(u16, u32, Decimal, CustomType),
+}
+
+/// Transaction kind without its payload, used to key per-type behavior such as engine hooks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Unlock,
+    AdjustCredit,
+    AdjustDebit,
+    AssertBalance,
+    WithdrawalHold,
+    WithdrawalSettle,
+    WithdrawalCancel,
+    ChargebackReversal,
+    SetOverdraft,
+    CloseAccount,
+    Refund,
+    Custom(CustomType),
+}
+
+impl TransactionType {
+    /// The built-in types, `registry::types` adds the registered ones.
+    pub const ALL: [TransactionType; 16] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Unlock,
+        TransactionType::AdjustCredit,
+        TransactionType::AdjustDebit,
+        TransactionType::AssertBalance,
+        TransactionType::WithdrawalHold,
+        TransactionType::WithdrawalSettle,
+        TransactionType::WithdrawalCancel,
+        TransactionType::ChargebackReversal,
+        TransactionType::SetOverdraft,
+        TransactionType::CloseAccount,
+        TransactionType::Refund,
+    ];
+}
+
+impl Display for TransactionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Unlock => "unlock",
+            TransactionType::AdjustCredit => "adjust_credit",
+            TransactionType::AdjustDebit => "adjust_debit",
+            TransactionType::AssertBalance => "assert_balance",
+            TransactionType::WithdrawalHold => "withdrawal_hold",
+            TransactionType::WithdrawalSettle => "withdrawal_settle",
+            TransactionType::WithdrawalCancel => "withdrawal_cancel",
+            TransactionType::ChargebackReversal => "chargeback_reversal",
+            TransactionType::SetOverdraft => "set_overdraft",
+            TransactionType::CloseAccount => "close_account",
+            TransactionType::Refund => "refund",
+            TransactionType::Custom(custom) => {
+                return f.write_str(registry::handler(*custom).name());
+            }
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = TransactionError;
+
+    /// Parses the name used in the `type` column, of a built-in or a registered type.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TransactionType::ALL
+            .into_iter()
+            .find(|ttype| ttype.to_string() == s)
+            .or_else(|| registry::custom_type(s).map(TransactionType::Custom))
+            .ok_or(TransactionError::UnknownType)
+    }
 }
 
 #[derive(Debug)]
@@ -25,6 +143,8 @@ impl Display for TransactionError {
     }
 }
 
+impl std::error::Error for TransactionError {}
+
 impl Transaction {
     pub fn new(
         ttype: &str,
@@ -38,33 +158,344 @@ impl Transaction {
             "dispute" => Ok(Transaction::Dispute(client, tx)),
             "resolve" => Ok(Transaction::Resolve(client, tx)),
             "chargeback" => Ok(Transaction::Chargeback(client, tx)),
-            _ => Err(TransactionError::UnknownType),
+            "unlock" => Ok(Transaction::Unlock(client, tx)),
+            "adjust_credit" => Ok(Transaction::AdjustCredit(client, tx, amount)),
+            "adjust_debit" => Ok(Transaction::AdjustDebit(client, tx, amount)),
+            "assert_balance" => Ok(Transaction::AssertBalance(client, tx, amount, None)),
+            "withdrawal_hold" => Ok(Transaction::WithdrawalHold(client, tx, amount)),
+            "withdrawal_settle" => Ok(Transaction::WithdrawalSettle(client, tx)),
+            "withdrawal_cancel" => Ok(Transaction::WithdrawalCancel(client, tx)),
+            "chargeback_reversal" => Ok(Transaction::ChargebackReversal(client, tx)),
+            "set_overdraft" => Ok(Transaction::SetOverdraft(client, tx, amount)),
+            "close_account" => Ok(Transaction::CloseAccount(client, tx)),
+            "refund" => Ok(Transaction::Refund(client, tx, amount)),
+            _ => registry::custom_type(ttype)
+                .map(|custom| Transaction::Custom(client, tx, amount, custom))
+                .ok_or(TransactionError::UnknownType),
+        }
+    }
+
+    pub fn client_id(&self) -> u16 {
+        match *self {
+            Transaction::Deposit(client_id, ..)
+            | Transaction::Withdrawal(client_id, ..)
+            | Transaction::Dispute(client_id, _)
+            | Transaction::Resolve(client_id, _)
+            | Transaction::Chargeback(client_id, _)
+            | Transaction::Unlock(client_id, _)
+            | Transaction::AdjustCredit(client_id, ..)
+            | Transaction::AdjustDebit(client_id, ..)
+            | Transaction::AssertBalance(client_id, ..)
+            | Transaction::WithdrawalHold(client_id, ..)
+            | Transaction::WithdrawalSettle(client_id, _)
+            | Transaction::WithdrawalCancel(client_id, _)
+            | Transaction::ChargebackReversal(client_id, _)
+            | Transaction::SetOverdraft(client_id, ..)
+            | Transaction::CloseAccount(client_id, _)
+            | Transaction::Refund(client_id, ..)
+            | Transaction::Custom(client_id, ..) => client_id,
+        }
+    }
+
+    /// The same transaction for another client.
+    pub fn with_client_id(&self, client_id: u16) -> Transaction {
+        match *self {
+            Transaction::Deposit(_, tx_id, amount) => {
+                Transaction::Deposit(client_id, tx_id, amount)
+            }
+            Transaction::Withdrawal(_, tx_id, amount) => {
+                Transaction::Withdrawal(client_id, tx_id, amount)
+            }
+            Transaction::Dispute(_, tx_id) => Transaction::Dispute(client_id, tx_id),
+            Transaction::Resolve(_, tx_id) => Transaction::Resolve(client_id, tx_id),
+            Transaction::Chargeback(_, tx_id) => Transaction::Chargeback(client_id, tx_id),
+            Transaction::Unlock(_, tx_id) => Transaction::Unlock(client_id, tx_id),
+            Transaction::AdjustCredit(_, tx_id, amount) => {
+                Transaction::AdjustCredit(client_id, tx_id, amount)
+            }
+            Transaction::AdjustDebit(_, tx_id, amount) => {
+                Transaction::AdjustDebit(client_id, tx_id, amount)
+            }
+            Transaction::AssertBalance(_, tx_id, amount, total) => {
+                Transaction::AssertBalance(client_id, tx_id, amount, total)
+            }
+            Transaction::WithdrawalHold(_, tx_id, amount) => {
+                Transaction::WithdrawalHold(client_id, tx_id, amount)
+            }
+            Transaction::WithdrawalSettle(_, tx_id) => {
+                Transaction::WithdrawalSettle(client_id, tx_id)
+            }
+            Transaction::WithdrawalCancel(_, tx_id) => {
+                Transaction::WithdrawalCancel(client_id, tx_id)
+            }
+            Transaction::ChargebackReversal(_, tx_id) => {
+                Transaction::ChargebackReversal(client_id, tx_id)
+            }
+            Transaction::SetOverdraft(_, tx_id, amount) => {
+                Transaction::SetOverdraft(client_id, tx_id, amount)
+            }
+            Transaction::CloseAccount(_, tx_id) => Transaction::CloseAccount(client_id, tx_id),
+            Transaction::Refund(_, tx_id, amount) => Transaction::Refund(client_id, tx_id, amount),
+            Transaction::Custom(_, tx_id, amount, custom) => {
+                Transaction::Custom(client_id, tx_id, amount, custom)
+            }
+        }
+    }
+
+    /// The same transaction with its amounts mapped, transactions without an amount are unchanged.
+    pub fn map_amount(self, f: impl Fn(Decimal) -> Decimal) -> Transaction {
+        match self {
+            Transaction::Deposit(client, tx, amount) => Transaction::Deposit(client, tx, f(amount)),
+            Transaction::Withdrawal(client, tx, amount) => {
+                Transaction::Withdrawal(client, tx, f(amount))
+            }
+            Transaction::AdjustCredit(client, tx, amount) => {
+                Transaction::AdjustCredit(client, tx, f(amount))
+            }
+            Transaction::AdjustDebit(client, tx, amount) => {
+                Transaction::AdjustDebit(client, tx, f(amount))
+            }
+            Transaction::AssertBalance(client, tx, amount, total) => {
+                Transaction::AssertBalance(client, tx, f(amount), total.map(&f))
+            }
+            Transaction::WithdrawalHold(client, tx, amount) => {
+                Transaction::WithdrawalHold(client, tx, f(amount))
+            }
+            Transaction::SetOverdraft(client, tx, amount) => {
+                Transaction::SetOverdraft(client, tx, f(amount))
+            }
+            Transaction::Refund(client, tx, amount) => Transaction::Refund(client, tx, f(amount)),
+            Transaction::Custom(client, tx, amount, custom) => {
+                Transaction::Custom(client, tx, f(amount), custom)
+            }
+            transaction => transaction,
+        }
+    }
+
+    pub fn tx_id(&self) -> u32 {
+        match *self {
+            Transaction::Deposit(_, tx_id, _)
+            | Transaction::Withdrawal(_, tx_id, _)
+            | Transaction::Dispute(_, tx_id)
+            | Transaction::Resolve(_, tx_id)
+            | Transaction::Chargeback(_, tx_id)
+            | Transaction::Unlock(_, tx_id)
+            | Transaction::AdjustCredit(_, tx_id, _)
+            | Transaction::AdjustDebit(_, tx_id, _)
+            | Transaction::AssertBalance(_, tx_id, ..)
+            | Transaction::WithdrawalHold(_, tx_id, _)
+            | Transaction::WithdrawalSettle(_, tx_id)
+            | Transaction::WithdrawalCancel(_, tx_id)
+            | Transaction::ChargebackReversal(_, tx_id)
+            | Transaction::SetOverdraft(_, tx_id, _)
+            | Transaction::CloseAccount(_, tx_id)
+            | Transaction::Refund(_, tx_id, _)
+            | Transaction::Custom(_, tx_id, ..) => tx_id,
+        }
+    }
+
+    /// Amount of deposits, withdrawals, withdrawal holds, adjustments, refunds and custom types and
+    /// the expected balance of assertions, dispute, settlement, unlock and closure rows carry none.
+    pub fn amount(&self) -> Option<Decimal> {
+        match *self {
+            Transaction::Deposit(_, _, amount)
+            | Transaction::Withdrawal(_, _, amount)
+            | Transaction::AdjustCredit(_, _, amount)
+            | Transaction::AdjustDebit(_, _, amount)
+            | Transaction::AssertBalance(_, _, amount, _)
+            | Transaction::WithdrawalHold(_, _, amount)
+            | Transaction::SetOverdraft(_, _, amount)
+            | Transaction::Refund(_, _, amount)
+            | Transaction::Custom(_, _, amount, _) => Some(amount),
+            _ => None,
+        }
+    }
+
+    /// Dispute, resolve and chargeback rows reference a logged transaction by its id,
+    /// all other rows introduce a new id.
+    pub fn is_dispute_operation(&self) -> bool {
+        matches!(
+            self,
+            Transaction::Dispute(..) | Transaction::Resolve(..) | Transaction::Chargeback(..)
+        )
+    }
+
+    /// Settle and cancel rows finalize the withdrawal hold with their id.
+    pub fn is_withdrawal_settlement(&self) -> bool {
+        matches!(
+            self,
+            Transaction::WithdrawalSettle(..) | Transaction::WithdrawalCancel(..)
+        )
+    }
+
+    /// Rows that reference a logged transaction by its id instead of introducing a new one.
+    pub fn references_transaction(&self) -> bool {
+        self.is_dispute_operation()
+            || self.is_withdrawal_settlement()
+            || matches!(
+                self,
+                Transaction::ChargebackReversal(..) | Transaction::Refund(..)
+            )
+    }
+
+    /// Balance assertions check the engine state instead of changing it. They are not logged, so
+    /// their ids needn't be unique.
+    pub fn is_assertion(&self) -> bool {
+        matches!(self, Transaction::AssertBalance(..))
+    }
+
+    /// Unlocks, manual adjustments, overdraft limits and account closures, accepted only when the
+    /// engine allows admin transactions.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Transaction::Unlock(..)
+                | Transaction::AdjustCredit(..)
+                | Transaction::AdjustDebit(..)
+                | Transaction::SetOverdraft(..)
+                | Transaction::CloseAccount(..)
+        )
+    }
+
+    pub fn transaction_type(&self) -> TransactionType {
+        match self {
+            Transaction::Deposit(..) => TransactionType::Deposit,
+            Transaction::Withdrawal(..) => TransactionType::Withdrawal,
+            Transaction::Dispute(..) => TransactionType::Dispute,
+            Transaction::Resolve(..) => TransactionType::Resolve,
+            Transaction::Chargeback(..) => TransactionType::Chargeback,
+            Transaction::Unlock(..) => TransactionType::Unlock,
+            Transaction::AdjustCredit(..) => TransactionType::AdjustCredit,
+            Transaction::AdjustDebit(..) => TransactionType::AdjustDebit,
+            Transaction::AssertBalance(..) => TransactionType::AssertBalance,
+            Transaction::WithdrawalHold(..) => TransactionType::WithdrawalHold,
+            Transaction::WithdrawalSettle(..) => TransactionType::WithdrawalSettle,
+            Transaction::WithdrawalCancel(..) => TransactionType::WithdrawalCancel,
+            Transaction::ChargebackReversal(..) => TransactionType::ChargebackReversal,
+            Transaction::SetOverdraft(..) => TransactionType::SetOverdraft,
+            Transaction::CloseAccount(..) => TransactionType::CloseAccount,
+            Transaction::Refund(..) => TransactionType::Refund,
+            Transaction::Custom(.., custom) => TransactionType::Custom(*custom),
         }
     }
 }
 
-impl<'de> Deserialize<'de> for Transaction {
+/// A transaction with the optional `timestamp` (Unix seconds) and `currency` columns of its input
+/// row. The optional `total` column goes into the expected total of balance assertions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionRow {
+    pub transaction: Transaction,
+    pub timestamp: Option<u64>,
+    pub currency: Option<Currency>,
+    /// Amount given on a dispute, resolve or chargeback row, which the transaction doesn't carry.
+    pub dispute_amount: Option<Decimal>,
+}
+
+#[derive(Deserialize)]
+struct TransactionRecord {
+    // The CSV input is read by position, JSON rows name the field like the CSV header
+    #[serde(rename = "type", alias = "ttype")]
+    ttype: String,
+    client: u16,
+    tx: u32,
+    // Rows without an amount may end after `tx`
+    #[serde(default)]
+    amount: Option<Decimal>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    currency: Option<String>,
+    #[serde(default)]
+    total: Option<Decimal>,
+}
+
+impl<'de> Deserialize<'de> for TransactionRow {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        #[derive(Deserialize)]
-        struct TransactionRecord {
-            ttype: String,
-            client: u16,
-            tx: u32,
-            amount: Option<Decimal>,
-        }
         let record = TransactionRecord::deserialize(deserializer)?;
-        let amount = record.amount.unwrap_or(Decimal::ZERO).round_dp(4);
-        Transaction::new(&record.ttype, record.client, record.tx, amount)
-            .map_err(serde::de::Error::custom)
+        let mut row = TransactionRow::new(
+            &record.ttype,
+            record.client,
+            record.tx,
+            record.amount,
+            record.timestamp,
+            record.currency.as_deref(),
+        )
+        .map_err(serde::de::Error::custom)?;
+        // Only assertions expect a total, other rows ignore the column
+        if let Transaction::AssertBalance(.., total) = &mut row.transaction {
+            *total = record.total;
+        }
+        Ok(row)
+    }
+}
+
+impl TransactionRow {
+    /// A row from its parsed columns, e.g. of a columnar input.
+    pub fn new(
+        ttype: &str,
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+        timestamp: Option<u64>,
+        currency: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        // Kept exact, the engine rounds amounts with its `AmountPolicy`
+        let transaction = Transaction::new(ttype, client, tx, amount.unwrap_or(Decimal::ZERO))?;
+        let currency = currency.map(|code| code.parse()).transpose()?;
+        let dispute_amount = amount.filter(|_| transaction.is_dispute_operation());
+        Ok(TransactionRow {
+            transaction,
+            timestamp,
+            currency,
+            dispute_amount,
+        })
+    }
+
+    /// The row as a record in the CSV column order, without trailing empty optional columns.
+    pub fn to_record(&self) -> StringRecord {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        let mut fields = vec![
+            self.transaction.transaction_type().to_string(),
+            self.transaction.client_id().to_string(),
+            self.transaction.tx_id().to_string(),
+            optional(
+                self.transaction
+                    .amount()
+                    .or(self.dispute_amount)
+                    .map(|amount| amount.to_string()),
+            ),
+            optional(self.timestamp.map(|timestamp| timestamp.to_string())),
+            optional(self.currency.map(|currency| currency.to_string())),
+            optional(match self.transaction {
+                Transaction::AssertBalance(.., total) => total.map(|total| total.to_string()),
+                _ => None,
+            }),
+        ];
+        while fields.len() > 4 && fields.last().is_some_and(String::is_empty) {
+            fields.pop();
+        }
+        StringRecord::from(fields)
+    }
+}
+
+impl<'de> Deserialize<'de> for Transaction {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        TransactionRow::deserialize(deserializer).map(|row| row.transaction)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::{amount::AmountPolicy, dialect::CsvDialect};
 
     #[test]
     fn test_deposit_transaction_creation() {
@@ -166,11 +597,173 @@ chargeback,5,104,";
             Transaction::Deposit(1, 100, Decimal::new(100000, 4))
         );
         assert_eq!(
-            transactions[1],
+            AmountPolicy::default().round_transaction(transactions[1].clone()),
             Transaction::Withdrawal(2, 101, Decimal::new(51235, 4))
         );
         assert_eq!(transactions[2], Transaction::Dispute(3, 102));
         assert_eq!(transactions[3], Transaction::Resolve(4, 103));
         assert_eq!(transactions[4], Transaction::Chargeback(5, 104));
     }
+
+    #[test]
+    fn test_admin_transaction_creation() {
+        let amount = Decimal::new(25, 1);
+        assert_eq!(
+            Transaction::new("unlock", 1, 200, Decimal::ZERO).unwrap(),
+            Transaction::Unlock(1, 200)
+        );
+        assert_eq!(
+            Transaction::new("adjust_credit", 1, 201, amount).unwrap(),
+            Transaction::AdjustCredit(1, 201, amount)
+        );
+        assert_eq!(
+            Transaction::new("adjust_debit", 1, 202, amount).unwrap(),
+            Transaction::AdjustDebit(1, 202, amount)
+        );
+        assert!(Transaction::AdjustDebit(1, 202, amount).is_admin());
+        assert!(!Transaction::Deposit(1, 203, amount).is_admin());
+    }
+
+    #[test]
+    fn test_transaction_type_names_round_trip() {
+        for ttype in TransactionType::ALL {
+            assert_eq!(ttype.to_string().parse::<TransactionType>().unwrap(), ttype);
+        }
+        assert!("Deposit".parse::<TransactionType>().is_err());
+    }
+
+    #[test]
+    fn test_transaction_row_timestamp_deserialization() {
+        let csv_data = "ttype,client,tx,amount,timestamp
+deposit,1,100,10.00,1700000000
+dispute,1,100,,1700000060
+resolve,1,100,2.5,";
+
+        let mut reader = csv::Reader::from_reader(csv_data.as_bytes());
+        let rows = reader
+            .records()
+            .map(|rec| rec.unwrap().deserialize(None).unwrap())
+            .collect::<Vec<TransactionRow>>();
+        assert_eq!(rows[0].timestamp, Some(1700000000));
+        assert_eq!(rows[1].transaction, Transaction::Dispute(1, 100));
+        assert_eq!(rows[1].timestamp, Some(1700000060));
+        assert_eq!(rows[2].timestamp, None);
+        // Only dispute operations keep the amount aside
+        assert_eq!(rows[0].dispute_amount, None);
+        assert_eq!(rows[1].dispute_amount, None);
+        assert_eq!(rows[2].dispute_amount, Some(Decimal::new(25, 1)));
+    }
+
+    #[test]
+    fn test_transaction_row_currency_deserialization() {
+        let csv_data = "ttype,client,tx,amount,timestamp,currency
+deposit,1,100,10.00,,eur
+deposit,1,101,10.00,1700000000,
+deposit,1,102,10.00,,EURO";
+
+        let mut reader = csv::Reader::from_reader(csv_data.as_bytes());
+        let rows = reader
+            .records()
+            .map(|rec| rec.unwrap().deserialize::<TransactionRow>(None))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows[0].as_ref().unwrap().currency,
+            Some("EUR".parse().unwrap())
+        );
+        assert_eq!(rows[1].as_ref().unwrap().currency, None);
+        assert!(rows[2].is_err());
+    }
+
+    #[test]
+    fn test_transaction_row_total_deserialization() {
+        let csv_data = "ttype,client,tx,amount,timestamp,currency,total
+assert_balance,1,100,10.00,,,12.50
+assert_balance,1,101,10.00
+deposit,1,102,10.00,,,12.50";
+
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv_data.as_bytes());
+        let rows = reader
+            .records()
+            .map(|rec| rec.unwrap().deserialize(None).unwrap())
+            .collect::<Vec<TransactionRow>>();
+        assert_eq!(
+            rows[0].transaction,
+            Transaction::AssertBalance(1, 100, Decimal::new(1000, 2), Some(Decimal::new(1250, 2)))
+        );
+        assert_eq!(
+            rows[0].to_record(),
+            StringRecord::from(vec!["assert_balance", "1", "100", "10", "", "", "12.5"])
+        );
+        assert_eq!(
+            rows[1].transaction,
+            Transaction::AssertBalance(1, 101, Decimal::new(1000, 2), None)
+        );
+        // Other rows ignore the column
+        assert_eq!(rows[2].to_record().len(), 4);
+    }
+
+    /// The transaction of the first row of a CSV input in the given dialect.
+    fn first_row(dialect: &CsvDialect, csv_data: &str) -> anyhow::Result<TransactionRow> {
+        let (_, mut records) = dialect.records(std::io::Cursor::new(csv_data.to_string()))?;
+        Ok(records.next().unwrap()?.deserialize(None)?)
+    }
+
+    #[test]
+    fn test_type_header_padded() {
+        // Columns out of order are found by their header name
+        let row = first_row(
+            &CsvDialect::default(),
+            "client, type ,tx,amount\n1,deposit,100,10.00\n",
+        )
+        .unwrap();
+        assert_eq!(
+            row.transaction,
+            Transaction::Deposit(1, 100, Decimal::new(1000, 2))
+        );
+    }
+
+    #[test]
+    fn test_type_header_capitalized() {
+        let row = first_row(
+            &CsvDialect::default(),
+            "Client,Type,TX,Amount\n1,withdrawal,100,10.00\n",
+        )
+        .unwrap();
+        assert_eq!(
+            row.transaction,
+            Transaction::Withdrawal(1, 100, Decimal::new(1000, 2))
+        );
+    }
+
+    #[test]
+    fn test_type_header_ttype() {
+        let csv_data = "client,ttype,tx,amount\n1,deposit,100,10.00\n";
+        let row = first_row(&CsvDialect::default(), csv_data).unwrap();
+        assert_eq!(
+            row.transaction,
+            Transaction::Deposit(1, 100, Decimal::new(1000, 2))
+        );
+        let row: TransactionRow = serde_json::from_str(
+            r#"{"ttype": "deposit", "client": 1, "tx": 100, "amount": "10.00"}"#,
+        )
+        .unwrap();
+        assert_eq!(row.transaction.tx_id(), 100);
+
+        // A column name given for the type replaces the alias
+        let dialect = CsvDialect {
+            columns: BTreeMap::from([("type".to_string(), "kind".to_string())]),
+            ..CsvDialect::default()
+        };
+        let err = first_row(&dialect, csv_data).unwrap_err();
+        assert_eq!(err.to_string(), "The header has no kind column");
+        // Both names in one JSON row are ambiguous
+        assert!(
+            serde_json::from_str::<TransactionRow>(
+                r#"{"type": "deposit", "ttype": "withdrawal", "client": 1, "tx": 100}"#,
+            )
+            .is_err()
+        );
+    }
 }
@@ -0,0 +1,228 @@
+use std::{collections::BTreeMap, io};
+
+use clap::ValueEnum;
+
+use crate::{currency::Currency, transaction::Transaction};
+
+/// Storage of the logged deposits, withdrawals and admin transactions, keyed by transaction id.
+pub trait TransactionLog: Send + Sync {
+    fn get(&self, tx_id: u32) -> io::Result<Option<(Transaction, Currency)>>;
+    fn insert(&mut self, transaction: &Transaction, currency: Currency) -> io::Result<()>;
+    fn remove(&mut self, tx_id: u32) -> io::Result<()>;
+    /// Iterates over the logged transactions ordered by id.
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(Transaction, Currency)>> + '_>;
+}
+
+/// Transaction log selection for the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum TransactionLogKind {
+    /// Keep the whole log in memory
+    #[default]
+    Memory,
+    /// Keep the log on disk with a cache of recent transactions (requires the `sled` feature)
+    Disk,
+}
+
+/// The whole log in memory, the fastest option while it fits.
+#[derive(Default)]
+pub struct MemoryLog {
+    entries: BTreeMap<u32, (Transaction, Currency)>,
+}
+
+impl MemoryLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TransactionLog for MemoryLog {
+    fn get(&self, tx_id: u32) -> io::Result<Option<(Transaction, Currency)>> {
+        Ok(self.entries.get(&tx_id).cloned())
+    }
+
+    fn insert(&mut self, transaction: &Transaction, currency: Currency) -> io::Result<()> {
+        self.entries
+            .insert(transaction.tx_id(), (transaction.clone(), currency));
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_id: u32) -> io::Result<()> {
+        self.entries.remove(&tx_id);
+        Ok(())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(Transaction, Currency)>> + '_> {
+        Box::new(self.entries.values().cloned().map(Ok))
+    }
+}
+
+#[cfg(feature = "sled")]
+pub use disk::DiskLog;
+
+#[cfg(feature = "sled")]
+mod disk {
+    use std::{fs, num::NonZeroUsize, path::Path, sync::Mutex};
+
+    use crate::decimal::Decimal;
+    use lru::LruCache;
+
+    use super::*;
+    use crate::memory::DISK_PAGE_CACHE;
+    use crate::{registry, transaction::TransactionType};
+
+    /// Encoded entry: type, client, amount and currency.
+    const ENTRY_LEN: usize = 1 + 2 + 16 + 3;
+    /// Type byte of custom types, their index in the registry follows.
+    const CUSTOM_TYPE: u8 = 0x80;
+
+    /// Log in a sled database with an LRU cache of recently used transactions in memory, for inputs
+    /// whose log doesn't fit into memory. Dispute rows usually reference recent transactions, so
+    /// most lookups hit the cache.
+    ///
+    /// The database is a scratch area of a single run and is deleted when the log is dropped.
+    pub struct DiskLog {
+        db: sled::Db,
+        cache: Mutex<LruCache<u32, (Transaction, Currency)>>,
+    }
+
+    impl DiskLog {
+        /// Creates the log in `dir`, which must not contain files of another run. `cache_size` is
+        /// the number of transactions kept in memory.
+        pub fn open<P: AsRef<Path>>(dir: P, cache_size: usize) -> io::Result<Self> {
+            let dir = dir.as_ref();
+            if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("transaction log directory {} is not empty", dir.display()),
+                ));
+            }
+            let db = sled::Config::new()
+                .path(dir)
+                .temporary(true)
+                .cache_capacity(DISK_PAGE_CACHE)
+                .open()
+                .map_err(io::Error::other)?;
+            let cache_size = NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::MIN);
+            Ok(DiskLog {
+                db,
+                cache: Mutex::new(LruCache::new(cache_size)),
+            })
+        }
+
+        fn cache(&self) -> std::sync::MutexGuard<'_, LruCache<u32, (Transaction, Currency)>> {
+            // The cache holds no invariants a panicking thread could break
+            self.cache
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    fn encode(transaction: &Transaction, currency: Currency) -> [u8; ENTRY_LEN] {
+        let mut entry = [0; ENTRY_LEN];
+        entry[0] = match transaction.transaction_type() {
+            TransactionType::Custom(custom) => CUSTOM_TYPE | custom.0,
+            transaction_type => TransactionType::ALL
+                .iter()
+                .position(|t| *t == transaction_type)
+                .unwrap_or_default() as u8,
+        };
+        entry[1..3].copy_from_slice(&transaction.client_id().to_be_bytes());
+        entry[3..19].copy_from_slice(&transaction.amount().unwrap_or_default().serialize());
+        entry[19..].copy_from_slice(currency.as_str().as_bytes());
+        entry
+    }
+
+    fn decode(key: &[u8], entry: &[u8]) -> io::Result<(Transaction, Currency)> {
+        let invalid =
+            || io::Error::new(io::ErrorKind::InvalidData, "corrupt transaction log entry");
+        let tx_id = u32::from_be_bytes(key.try_into().map_err(|_| invalid())?);
+        let entry: &[u8; ENTRY_LEN] = entry.try_into().map_err(|_| invalid())?;
+        let transaction_type = match entry[0] {
+            idx if idx & CUSTOM_TYPE != 0 => {
+                TransactionType::Custom(registry::by_index(idx & !CUSTOM_TYPE).ok_or_else(invalid)?)
+            }
+            idx => *TransactionType::ALL.get(idx as usize).ok_or_else(invalid)?,
+        };
+        let client_id = u16::from_be_bytes([entry[1], entry[2]]);
+        let mut amount = [0; 16];
+        amount.copy_from_slice(&entry[3..19]);
+        let transaction = Transaction::new(
+            &transaction_type.to_string(),
+            client_id,
+            tx_id,
+            Decimal::deserialize(amount),
+        )
+        .map_err(|_| invalid())?;
+        let currency = std::str::from_utf8(&entry[19..])
+            .map_err(|_| invalid())?
+            .parse()
+            .map_err(|_| invalid())?;
+        Ok((transaction, currency))
+    }
+
+    impl TransactionLog for DiskLog {
+        fn get(&self, tx_id: u32) -> io::Result<Option<(Transaction, Currency)>> {
+            if let Some(entry) = self.cache().get(&tx_id) {
+                return Ok(Some(entry.clone()));
+            }
+            let key = tx_id.to_be_bytes();
+            let Some(value) = self.db.get(key).map_err(io::Error::other)? else {
+                return Ok(None);
+            };
+            let entry = decode(&key, &value)?;
+            self.cache().put(tx_id, entry.clone());
+            Ok(Some(entry))
+        }
+
+        fn insert(&mut self, transaction: &Transaction, currency: Currency) -> io::Result<()> {
+            let tx_id = transaction.tx_id();
+            self.db
+                .insert(tx_id.to_be_bytes(), &encode(transaction, currency))
+                .map_err(io::Error::other)?;
+            self.cache().put(tx_id, (transaction.clone(), currency));
+            Ok(())
+        }
+
+        fn remove(&mut self, tx_id: u32) -> io::Result<()> {
+            self.cache().pop(&tx_id);
+            self.db
+                .remove(tx_id.to_be_bytes())
+                .map_err(io::Error::other)?;
+            Ok(())
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = io::Result<(Transaction, Currency)>> + '_> {
+            // Big-endian keys iterate in id order
+            Box::new(self.db.iter().map(|item| {
+                let (key, value) = item.map_err(io::Error::other)?;
+                decode(&key, &value)
+            }))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_disk_log() {
+            let dir = std::env::temp_dir().join(format!("tx-log-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            let eur: Currency = "EUR".parse().unwrap();
+            let mut log = DiskLog::open(&dir, 1).unwrap();
+            let deposit = Transaction::Deposit(7, 300, Decimal::new(-12345, 4));
+            log.insert(&deposit, eur).unwrap();
+            log.insert(&Transaction::Unlock(2, 10), Currency::USD)
+                .unwrap();
+            // The deposit was evicted from the cache of one entry and is read back from disk
+            assert_eq!(log.get(300).unwrap(), Some((deposit.clone(), eur)));
+            assert_eq!(log.get(11).unwrap(), None);
+            let ids: Vec<u32> = log.iter().map(|entry| entry.unwrap().0.tx_id()).collect();
+            assert_eq!(ids, vec![10, 300]);
+            log.remove(300).unwrap();
+            assert_eq!(log.get(300).unwrap(), None);
+            drop(log);
+            let _ = fs::remove_dir_all(&dir);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use anyhow::Result;
+
+use crate::{client::Client, currency::Currency, engine::Engine};
+
+/// A field of an account whose recomputed value differs from the expected report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Discrepancy {
+    pub client_id: u16,
+    pub currency: Currency,
+    /// `available`, `held`, `total` or `locked`, or `account` for an account only one side has.
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Discrepancy {
+    fn new(client: &Client, field: &'static str, expected: String, actual: String) -> Self {
+        Discrepancy {
+            client_id: client.id,
+            currency: client.currency,
+            field,
+            expected,
+            actual,
+        }
+    }
+}
+
+/// Compares the engine's accounts, rounded as in its report, with the `expected` accounts, e.g.
+/// read with `report::read_report`. Accounts missing from the expected report are only reported
+/// if they aren't empty, as rejected rows may open empty accounts.
+pub fn verify(engine: &Engine, expected: &[Client]) -> io::Result<Vec<Discrepancy>> {
+    let amounts = engine.config().amounts;
+    let mut actual = BTreeMap::new();
+    for client in engine.clients() {
+        let client = amounts.round_client(&client?);
+        actual.insert((client.id, client.currency), client);
+    }
+    let mut discrepancies = Vec::new();
+    for expected in expected {
+        let Some(actual) = actual.remove(&(expected.id, expected.currency)) else {
+            discrepancies.push(Discrepancy::new(
+                expected,
+                "account",
+                "present".to_string(),
+                "missing".to_string(),
+            ));
+            continue;
+        };
+        for (field, expected_value, actual_value) in [
+            ("available", expected.available, actual.available),
+            ("held", expected.held, actual.held),
+            ("total", expected.total, actual.total),
+        ] {
+            if expected_value != actual_value {
+                discrepancies.push(Discrepancy::new(
+                    expected,
+                    field,
+                    expected_value.to_string(),
+                    actual_value.to_string(),
+                ));
+            }
+        }
+        if expected.locked != actual.locked {
+            discrepancies.push(Discrepancy::new(
+                expected,
+                "locked",
+                expected.locked.to_string(),
+                actual.locked.to_string(),
+            ));
+        }
+    }
+    for client in actual.values() {
+        if !client.total.is_zero() || !client.held.is_zero() || client.locked {
+            discrepancies.push(Discrepancy::new(
+                client,
+                "account",
+                "missing".to_string(),
+                "present".to_string(),
+            ));
+        }
+    }
+    // Stable, so the fields of an account keep their order
+    discrepancies.sort_by_key(|discrepancy| (discrepancy.client_id, discrepancy.currency));
+    Ok(discrepancies)
+}
+
+/// Writes the discrepancies as CSV with a `client,currency,field,expected,actual` header.
+pub fn write_discrepancies<W: Write>(writer: W, discrepancies: &[Discrepancy]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["client", "currency", "field", "expected", "actual"])?;
+    for discrepancy in discrepancies {
+        writer.write_record([
+            discrepancy.client_id.to_string().as_str(),
+            discrepancy.currency.to_string().as_str(),
+            discrepancy.field,
+            &discrepancy.expected,
+            &discrepancy.actual,
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decimal::Decimal, report::read_report, transaction::Transaction};
+
+    #[test]
+    fn test_verify() {
+        let mut engine = Engine::new();
+        for (client_id, tx_id) in [(1, 1), (2, 2), (3, 3)] {
+            engine
+                .execute(Transaction::Deposit(client_id, tx_id, Decimal::new(5, 0)))
+                .unwrap();
+        }
+        // A rejected withdrawal opens an empty account the expected report may omit
+        assert!(
+            engine
+                .execute(Transaction::Withdrawal(4, 4, Decimal::ONE))
+                .is_err()
+        );
+
+        let expected = "client,available,held,total,locked\n\
+                        1,5.0000,0.0000,5.0000,false\n\
+                        2,4.0000,0.0000,4.0000,true\n\
+                        5,1.0000,0.0000,1.0000,false\n";
+        let default_currency = engine.config().default_currency;
+        let expected = read_report(expected.as_bytes(), default_currency).unwrap();
+        let discrepancies = verify(&engine, &expected).unwrap();
+        let fields: Vec<_> = discrepancies
+            .iter()
+            .map(|discrepancy| (discrepancy.client_id, discrepancy.field))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (2, "available"),
+                (2, "total"),
+                (2, "locked"),
+                (3, "account"),
+                (5, "account")
+            ]
+        );
+
+        let mut output = Vec::new();
+        write_discrepancies(&mut output, &discrepancies[2..3]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "client,currency,field,expected,actual\n2,{},locked,true,false\n",
+                default_currency
+            )
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{config::EngineConfig, json_engine::JsonEngine};
+
+/// The `JsonEngine` exported to JavaScript, built with `wasm-pack build --features wasm`. Errors
+/// are thrown as their message.
+#[wasm_bindgen]
+pub struct WasmEngine {
+    engine: JsonEngine,
+}
+
+impl Default for WasmEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmEngine {
+    /// An engine with the default rules of a batch run.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        WasmEngine {
+            engine: JsonEngine::new(EngineConfig::default()),
+        }
+    }
+
+    /// Executes a transaction given as a JSON object, see `JsonEngine::execute_json`.
+    pub fn execute_json(&mut self, tx_json: &str) -> Result<(), String> {
+        self.engine
+            .execute_json(tx_json)
+            .map_err(|err| err.to_string())
+    }
+
+    /// The client report as a JSON array.
+    pub fn report_json(&self) -> Result<String, String> {
+        self.engine.report_json().map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasm_engine() {
+        let mut engine = WasmEngine::new();
+        engine
+            .execute_json(r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#)
+            .unwrap();
+        assert_eq!(
+            engine.execute_json(r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "3"}"#),
+            Err("Failed to execute transaction: Insufficient funds".to_string())
+        );
+        let report: serde_json::Value =
+            serde_json::from_str(&engine.report_json().unwrap()).unwrap();
+        assert_eq!(report[0]["available"].to_string(), "2.5");
+    }
+}
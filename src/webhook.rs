@@ -0,0 +1,306 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    sync::mpsc::{self, Sender},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    client::Client,
+    decimal::Decimal,
+    events::{Event, EventKind, EventSink},
+};
+
+/// Time a webhook endpoint has to accept the connection and answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where and when account notifications are posted.
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    /// Endpoints, `http://host[:port][/path]`, each receiving every notification.
+    pub urls: Vec<String>,
+    /// Total balances whose crossing in either direction is notified.
+    pub thresholds: Vec<Decimal>,
+    /// Deliveries retried after the first failed attempt.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each further one.
+    pub backoff: Duration,
+}
+
+/// JSON body of a notification: the event, the transaction and the account after it.
+#[derive(Serialize)]
+struct Notification<'a> {
+    event: &'static str,
+    tx: u32,
+    /// The crossed threshold of a `ThresholdCrossed` notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threshold: Option<String>,
+    /// `up` or `down`, for a `ThresholdCrossed` notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    direction: Option<&'static str>,
+    #[serde(flatten)]
+    account: &'a Client,
+}
+
+/// Endpoint of a webhook URL.
+#[derive(Debug)]
+struct Endpoint {
+    /// `host:port` to connect to.
+    address: String,
+    host: String,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            anyhow::bail!("Webhook URL {} is not an http:// URL", url);
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            anyhow::bail!("Webhook URL {} has no host", url);
+        }
+        let address = match authority.contains(':') {
+            true => authority.to_string(),
+            false => format!("{}:80", authority),
+        };
+        Ok(Endpoint {
+            address,
+            host: authority.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Posts a JSON body and returns the HTTP status code of the answer.
+    fn post(&self, body: &[u8]) -> io::Result<u16> {
+        let mut stream = TcpStream::connect(&self.address)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| io::Error::other(format!("invalid HTTP status line {:?}", status)))
+    }
+}
+
+/// Event sink posting a JSON notification to every webhook URL when an account gets locked, a
+/// chargeback lands or a total balance crosses a threshold. Deliveries run in order on a
+/// background thread, so the engine never waits for an endpoint. A delivery that still fails
+/// after the retries is logged and dropped. Dropping the notifier waits for the queued
+/// deliveries.
+pub struct WebhookNotifier {
+    thresholds: Vec<Decimal>,
+    queue: Option<Sender<Vec<u8>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        let endpoints = config
+            .urls
+            .iter()
+            .map(|url| Endpoint::parse(url))
+            .collect::<Result<Vec<_>>>()?;
+        let (queue, deliveries) = mpsc::channel::<Vec<u8>>();
+        let worker = std::thread::Builder::new()
+            .name("webhooks".into())
+            .spawn(move || {
+                for body in deliveries {
+                    for endpoint in &endpoints {
+                        deliver(endpoint, &body, config.retries, config.backoff);
+                    }
+                }
+            })
+            .context("failed to start the webhook thread")?;
+        Ok(WebhookNotifier {
+            thresholds: config.thresholds,
+            queue: Some(queue),
+            worker: Some(worker),
+        })
+    }
+
+    fn send(&self, notification: Notification) {
+        let body = serde_json::to_vec(&notification).expect("notifications serialize");
+        if let Some(queue) = &self.queue {
+            // The worker only stops when the queue is closed
+            let _ = queue.send(body);
+        }
+    }
+}
+
+impl EventSink for WebhookNotifier {
+    fn emit(&mut self, event: &Event) {
+        let notification = |event_name| Notification {
+            event: event_name,
+            tx: event.transaction.tx_id(),
+            threshold: None,
+            direction: None,
+            account: &event.after,
+        };
+        match event.kind {
+            EventKind::AccountLocked => self.send(notification("AccountLocked")),
+            EventKind::ChargedBack => self.send(notification("ChargedBack")),
+            _ => {}
+        }
+        // A lock or unlock follows an event of the same transaction with the same balances
+        if matches!(
+            event.kind,
+            EventKind::AccountLocked | EventKind::AccountUnlocked
+        ) {
+            return;
+        }
+        for threshold in &self.thresholds {
+            let (before, after) = (event.before.total, event.after.total);
+            if (before < *threshold) != (after < *threshold) {
+                self.send(Notification {
+                    threshold: Some(threshold.to_string()),
+                    direction: Some(if after >= *threshold { "up" } else { "down" }),
+                    ..notification("ThresholdCrossed")
+                });
+            }
+        }
+    }
+}
+
+impl Drop for WebhookNotifier {
+    fn drop(&mut self) {
+        drop(self.queue.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Posts a notification to one endpoint, retrying with exponential backoff on connection errors
+/// and answers other than 2xx.
+fn deliver(endpoint: &Endpoint, body: &[u8], retries: u32, backoff: Duration) {
+    let mut wait = backoff;
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            std::thread::sleep(wait);
+            wait = wait.saturating_mul(2);
+        }
+        match endpoint.post(body) {
+            Ok(status) if (200..300).contains(&status) => return,
+            Ok(status) => warn!(
+                "Webhook {}{} answered {}",
+                endpoint.host, endpoint.path, status
+            ),
+            Err(err) => warn!("Webhook {}{} failed: {}", endpoint.host, endpoint.path, err),
+        }
+    }
+    warn!(
+        "Dropping a notification to {}{} after {} retries",
+        endpoint.host, endpoint.path, retries
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener};
+
+    use super::*;
+    use crate::{engine::Engine, transaction::Transaction};
+
+    #[test]
+    fn test_webhook_notifier() {
+        // The endpoint fails the first delivery, which is retried
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        let endpoint = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for (attempt, stream) in listener.incoming().take(5).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                // Read until the body announced by Content-Length is complete
+                loop {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length: usize = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if body.len() == length {
+                            assert!(head.starts_with("POST /hooks HTTP/1.1"));
+                            if attempt > 0 {
+                                bodies.push(body.to_string());
+                            }
+                            break;
+                        }
+                    }
+                }
+                let status = if attempt == 0 { "500 Error" } else { "200 OK" };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+            bodies
+        });
+
+        let mut engine = Engine::new();
+        engine.add_event_sink(Box::new(
+            WebhookNotifier::new(WebhookConfig {
+                urls: vec![url],
+                thresholds: vec![Decimal::new(100, 0)],
+                retries: 1,
+                backoff: Duration::from_millis(1),
+            })
+            .unwrap(),
+        ));
+        engine
+            .execute(Transaction::Deposit(1, 1, Decimal::new(150, 0)))
+            .unwrap();
+        engine
+            .execute(Transaction::Deposit(1, 2, Decimal::new(10, 0)))
+            .unwrap();
+        engine.execute(Transaction::Dispute(1, 1)).unwrap();
+        engine.execute(Transaction::Chargeback(1, 1)).unwrap();
+        drop(engine);
+
+        let bodies = endpoint.join().unwrap();
+        let events: Vec<serde_json::Value> = bodies
+            .iter()
+            .map(|body| serde_json::from_str(body).unwrap())
+            .collect();
+        let names: Vec<&str> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "ThresholdCrossed",
+                "ChargedBack",
+                "ThresholdCrossed",
+                "AccountLocked"
+            ][..]
+        );
+        assert_eq!(events[0]["direction"], "up");
+        assert_eq!(events[2]["direction"], "down");
+        assert_eq!(events[2]["client"], 1);
+        assert_eq!(events[2]["tx"], 1);
+    }
+}
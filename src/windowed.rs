@@ -0,0 +1,208 @@
+use std::{collections::VecDeque, io::Write};
+
+use crate::decimal::Decimal;
+use anyhow::Result;
+
+use crate::{engine::ExecutionError, transaction::Transaction};
+
+/// Aggregates of one tumbling window.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Window {
+    /// Start of the window in Unix seconds.
+    pub start: u64,
+    pub deposits: u64,
+    pub deposit_volume: Decimal,
+    pub withdrawals: u64,
+    pub withdrawal_volume: Decimal,
+    pub disputes: u64,
+    pub chargebacks: u64,
+    pub rejected: u64,
+}
+
+/// Tumbling-window aggregates of applied and rejected transactions, so bursts show up that the
+/// running counters hide.
+///
+/// Windows are aligned to multiples of the window width on the engine clock. Windows without
+/// transactions are skipped. A window is closed by the first transaction of a later window, or
+/// by `finish`. The last `retention` closed windows are kept for reading, and closed windows are
+/// written to the optional CSV sink.
+pub struct WindowedMetrics {
+    width: u64,
+    retention: usize,
+    current: Option<Window>,
+    closed: VecDeque<Window>,
+    writer: Option<csv::Writer<Box<dyn Write + Send>>>,
+}
+
+impl WindowedMetrics {
+    /// `width` is the window length in seconds.
+    pub fn new(width: u64, retention: usize) -> Self {
+        WindowedMetrics {
+            width: width.max(1),
+            retention,
+            current: None,
+            closed: VecDeque::new(),
+            writer: None,
+        }
+    }
+
+    /// Writes every closed window as a CSV row to `writer`.
+    pub fn write_to<W: Write + Send + 'static>(mut self, writer: W) -> Result<Self> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record([
+            "window_start",
+            "deposits",
+            "deposit_volume",
+            "withdrawals",
+            "withdrawal_volume",
+            "disputes",
+            "chargebacks",
+            "rejected",
+        ])?;
+        self.writer = Some(writer);
+        Ok(self)
+    }
+
+    pub fn width(&self) -> u64 {
+        self.width
+    }
+
+    /// Records the outcome of a transaction executed at `now` on the engine clock.
+    pub fn record(
+        &mut self,
+        now: u64,
+        transaction: &Transaction,
+        result: &Result<(), ExecutionError>,
+    ) -> Result<()> {
+        let start = now - now % self.width;
+        // The clock never goes back, but a shared clock may be observed out of order
+        if self.current.as_ref().is_some_and(|w| start > w.start) {
+            self.close()?;
+        }
+        let window = self.current.get_or_insert_with(|| Window {
+            start,
+            ..Window::default()
+        });
+        if result.is_err() {
+            window.rejected += 1;
+            return Ok(());
+        }
+        match *transaction {
+            Transaction::Deposit(_, _, amount) => {
+                window.deposits += 1;
+                window.deposit_volume = window.deposit_volume.saturating_add(amount);
+            }
+            Transaction::Withdrawal(_, _, amount) => {
+                window.withdrawals += 1;
+                window.withdrawal_volume = window.withdrawal_volume.saturating_add(amount);
+            }
+            Transaction::Dispute(..) => window.disputes += 1,
+            Transaction::Chargeback(..) => window.chargebacks += 1,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let Some(window) = self.current.take() else {
+            return Ok(());
+        };
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_record([
+                window.start.to_string(),
+                window.deposits.to_string(),
+                window.deposit_volume.to_string(),
+                window.withdrawals.to_string(),
+                window.withdrawal_volume.to_string(),
+                window.disputes.to_string(),
+                window.chargebacks.to_string(),
+                window.rejected.to_string(),
+            ])?;
+        }
+        self.closed.push_back(window);
+        if self.closed.len() > self.retention {
+            self.closed.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Iterates over the retained closed windows and the open one, oldest first.
+    pub fn windows(&self) -> impl Iterator<Item = &Window> {
+        self.closed.iter().chain(self.current.as_ref())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the open window and flushes the CSV sink, at the end of a run.
+    pub fn finish(&mut self) -> Result<()> {
+        self.close()?;
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_windowed_metrics() {
+        let output = SharedBuffer::default();
+        let mut metrics = WindowedMetrics::new(60, 1)
+            .write_to(output.clone())
+            .unwrap();
+        let ten = Decimal::new(10, 0);
+        metrics
+            .record(100, &Transaction::Deposit(1, 1, ten), &Ok(()))
+            .unwrap();
+        metrics
+            .record(110, &Transaction::Deposit(1, 2, ten), &Ok(()))
+            .unwrap();
+        metrics
+            .record(
+                119,
+                &Transaction::Withdrawal(1, 3, ten),
+                &Err(ExecutionError::InsufficientFunds),
+            )
+            .unwrap();
+        metrics
+            .record(200, &Transaction::Dispute(1, 1), &Ok(()))
+            .unwrap();
+        metrics
+            .record(300, &Transaction::Chargeback(1, 1), &Ok(()))
+            .unwrap();
+        // Retention 1: the first window is only in the CSV
+        let starts: Vec<u64> = metrics.windows().map(|w| w.start).collect();
+        assert_eq!(starts, vec![180, 300]);
+        metrics.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(output.0.lock().unwrap().clone()).unwrap(),
+            "window_start,deposits,deposit_volume,withdrawals,withdrawal_volume,disputes,chargebacks,rejected
+60,2,20,0,0,0,0,1
+180,0,0,0,0,1,0,0
+300,0,0,0,0,0,1,0
+"
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_simple-payment-engine");
+
+/// A deposit, an overdrawing withdrawal, a row of an unknown type and a deposit of another client.
+const INPUT: &str = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,9.0
+bogus,1,3,1.0
+deposit,2,4,3.0
+";
+
+/// A file in the temporary directory, unique to the test and the process.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cli-{}-{}", std::process::id(), name))
+}
+
+fn run(input: &str, args: &[&str]) -> Output {
+    // Numbered, tests may run the same arguments at the same time
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let path = temp_path(&format!(
+        "input-{}.csv",
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&path, input).unwrap();
+    let output = Command::new(BIN).arg(&path).args(args).output().unwrap();
+    fs::remove_file(path).unwrap();
+    output
+}
+
+#[test]
+fn test_on_error_skip() {
+    let output = run(INPUT, &["--on-error", "skip"]);
+    // Partial: the rejected rows are skipped and the rest applied
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,5,0,5,false\n2,3,0,3,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Failed to execute transaction: Insufficient funds"));
+}
+
+#[test]
+fn test_on_error_abort() {
+    let output = run(INPUT, &["--on-error", "abort"]);
+    assert_eq!(output.status.code(), Some(1));
+    // Stopped at the withdrawal, before the report
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "Error: Failed to execute transaction: Insufficient funds \
+         (row: StringRecord([\"withdrawal\", \"1\", \"2\", \"9.0\"]))"
+    ));
+    assert!(!stderr.contains("Unknown transaction type"));
+}
+
+#[test]
+fn test_on_error_collect() {
+    let rejects = temp_path("rejects.csv");
+    let output = run(
+        INPUT,
+        &[
+            "--on-error",
+            "collect",
+            "--rejects",
+            rejects.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,5,0,5,false\n2,3,0,3,false\n"
+    );
+    assert_eq!(
+        fs::read_to_string(&rejects).unwrap(),
+        "type,client,tx,amount,reason\n\
+         withdrawal,1,2,9.0,Failed to execute transaction: Insufficient funds\n\
+         bogus,1,3,1.0,\"Failed to deserialize transaction: CSV deserialize error: record 3 \
+         (line: 4, byte: 57): Unknown transaction type\"\n"
+    );
+    fs::remove_file(rejects).unwrap();
+
+    // Collecting needs the file to collect into
+    let output = run(INPUT, &["--on-error", "collect"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_sharded_run_matches_sequential() {
+    // Deposits, disputes, chargebacks and overdrawing withdrawals of many clients, interleaved
+    let mut input = String::from("type,client,tx,amount\n");
+    for client in 1..=40 {
+        input.push_str(&format!("deposit,{},{},{}.5\n", client, client, client));
+    }
+    for client in 1..=40 {
+        let tx = 100 + client;
+        input.push_str(&format!(
+            "withdrawal,{},{},{}.0\n",
+            client,
+            tx,
+            client % 7 * 10
+        ));
+        if client % 3 == 0 {
+            input.push_str(&format!("dispute,{},{},\n", client, client));
+        }
+        if client % 6 == 0 {
+            input.push_str(&format!("chargeback,{},{},\n", client, client));
+        }
+    }
+    let sequential = run(&input, &[]);
+    let sharded = run(&input, &["--shards", "4", "--verify-determinism"]);
+    assert_eq!(sequential.status.code(), Some(2));
+    assert_eq!(sharded.status.code(), sequential.status.code());
+    assert_eq!(sharded.stdout, sequential.stdout);
+    // The sharded run logs the client and tx fields as numbers, the pipeline as the row's text
+    let rejections = |output: &Output| {
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter_map(|line| line.split_once("Failed to execute transaction: "))
+            .map(|(_, rejection)| rejection.replace('"', ""))
+            .collect::<Vec<_>>()
+    };
+    assert!(!rejections(&sequential).is_empty());
+    assert_eq!(rejections(&sharded), rejections(&sequential));
+    let stderr = String::from_utf8(sharded.stderr).unwrap();
+    assert!(stderr.contains("The sharded run matches the single-threaded run"));
+
+    let output = run(&input, &["--verify-determinism"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("--verify-determinism requires --shards above 1")
+    );
+}
+
+#[test]
+fn test_consolidated_report() {
+    let rates = temp_path("rates.csv");
+    fs::write(&rates, "currency,rate\nEUR,2\n").unwrap();
+    let report = temp_path("consolidated.csv");
+    let input = "type,client,tx,amount,timestamp,currency
+deposit,1,1,10.0,,EUR
+deposit,1,2,5.0,,USD
+deposit,2,3,2.0,,USD
+";
+    let output = run(
+        input,
+        &[
+            "--multi-currency",
+            "--consolidated-report",
+            report.to_str().unwrap(),
+            "--base-currency",
+            "USD",
+            "--rates",
+            rates.to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success());
+    // The per-currency rows stay in the report
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,EUR,10,0,10,false\n\
+         1,USD,5,0,5,false\n\
+         2,USD,2,0,2,false\n"
+    );
+    assert_eq!(
+        fs::read_to_string(&report).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,USD,25,0,25,false\n\
+         2,USD,2,0,2,false\n"
+    );
+    fs::remove_file(report).unwrap();
+    fs::remove_file(rates).unwrap();
+
+    // Converting needs the base currency and the rates
+    let output = run(
+        input,
+        &["--multi-currency", "--consolidated-report", "x.csv"],
+    );
+    assert!(!output.status.success());
+}
+
+/// A dispute without the trailing amount field, a deposit without its amount and a row with an
+/// extra field.
+const RAGGED_INPUT: &str = "type,client,tx,amount
+deposit,1,1,1.0
+dispute,1,1
+deposit,1,2
+deposit,2,3,2.0,,USD,,extra
+deposit,2,4,2.0
+";
+
+const RAGGED_REPORT: &str = "client,available,held,total,locked\n1,0,1,1,false\n2,4,0,4,false\n";
+
+/// A deposit of client 1 disputed by client 2.
+const MISMATCHED_DISPUTE: &str = "type,client,tx,amount
+deposit,1,1,10.0
+dispute,2,1,
+";
+
+#[test]
+fn test_dispute_client_unchecked_by_default() {
+    for args in [&[][..], &["--shadow", "1"]] {
+        let output = run(MISMATCHED_DISPUTE, args);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "client,available,held,total,locked\n1,0,10,10,false\n"
+        );
+    }
+}
+
+#[test]
+fn test_check_dispute_client() {
+    let output = run(MISMATCHED_DISPUTE, &["--check-dispute-client"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,10,0,10,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Transaction belongs to a different client"));
+}
+
+#[test]
+fn test_ragged_rows_skip() {
+    for args in [&["--on-error", "skip"][..], &["--shards", "2"]] {
+        let output = run(RAGGED_INPUT, args);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), RAGGED_REPORT);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("Failed to execute transaction: Invalid transaction amount"));
+    }
+}
+
+#[test]
+fn test_ragged_rows_abort() {
+    let output = run(RAGGED_INPUT, &["--on-error", "abort"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "Error: Failed to execute transaction: Invalid transaction amount \
+         (row: StringRecord([\"deposit\", \"1\", \"2\"]))"
+    ));
+}
+
+#[test]
+fn test_ragged_rows_collect() {
+    let rejects = temp_path("ragged-rejects.csv");
+    let output = run(
+        RAGGED_INPUT,
+        &[
+            "--on-error",
+            "collect",
+            "--rejects",
+            rejects.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), RAGGED_REPORT);
+    assert_eq!(
+        fs::read_to_string(&rejects).unwrap(),
+        "type,client,tx,amount,reason\n\
+         deposit,1,2,Failed to execute transaction: Invalid transaction amount\n"
+    );
+    fs::remove_file(rejects).unwrap();
+}